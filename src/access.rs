@@ -0,0 +1,125 @@
+// Who may print: an allowlist of client addresses and networks, and a shared
+// secret clients send before their data. Both keep stray jobs from other
+// machines on a shared lab network out of the receipt.
+//
+// The secret preamble is the secret followed by LF, as the first bytes of
+// every connection; it is stripped before the data is parsed:
+//
+//   printf 'lab-secret\n' | cat - job.bin | nc printer 9100
+
+use anyhow::{anyhow, Result};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// How long a client has to send the secret preamble
+const PREAMBLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessControl {
+    /// Clients allowed to connect, everyone when empty
+    pub allowlist: Vec<Network>,
+    /// Preamble clients must send first, none when empty
+    pub secret: String,
+}
+
+/// An address, or a network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl AccessControl {
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        self.allowlist.is_empty() || self.allowlist.iter().any(|net| net.contains(addr))
+    }
+}
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let full = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix == full {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl std::str::FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("'{}' isn't an IP address", addr))?;
+        let full = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= full)
+                .ok_or_else(|| anyhow!("invalid prefix length in '{}'", text))?,
+            None => full,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Comma- or whitespace-separated addresses and networks
+pub fn parse_allowlist(text: &str) -> Result<Vec<Network>> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+pub fn format_allowlist(allowlist: &[Network]) -> String {
+    allowlist
+        .iter()
+        .map(Network::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn prefix_matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = (usize::from(prefix / 8), prefix % 8);
+    if net[..bytes] != addr[..bytes] {
+        return false;
+    }
+    bits == 0 || (net[bytes] ^ addr[bytes]) >> (8 - bits) == 0
+}
+
+/// Read the secret preamble, returning whether the client sent the right one
+pub async fn check_preamble(socket: &mut TcpStream, secret: &str) -> bool {
+    let mut preamble = vec![0; secret.len() + 1];
+    let read = tokio::time::timeout(PREAMBLE_TIMEOUT, socket.read_exact(&mut preamble)).await;
+    matches!(read, Ok(Ok(_)))
+        && preamble[..secret.len()] == *secret.as_bytes()
+        && preamble[secret.len()] == b'\n'
+}
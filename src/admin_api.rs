@@ -0,0 +1,649 @@
+// HTTP admin API so test harnesses can drive and inspect the emulator
+//
+//   GET    /api/status          listener state, paper size, connections, element count
+//   GET    /api/elements        current receipt elements as JSON
+//   DELETE /api/elements        clear the receipt
+//   GET    /api/paper-size      current paper size
+//   PUT    /api/paper-size      body: "58mm" or "80mm"
+//   GET    /api/printer-status  simulated status flags
+//   PUT    /api/printer-status  body: JSON object with the flags to change
+//   GET    /api/paper-roll      roll length, near-end threshold and paper used
+//   PUT    /api/paper-roll      body: {"length_m": meters (0 = endless), "near_end_m": meters,
+//                                "used_mm": millimeters}
+//   POST   /api/paper-roll/load load a full roll, clearing the paper sensors
+//   GET    /api/drawer-kicks    drawer kicks: time, source, command, pin, on/off ms
+//   DELETE /api/drawer-kicks    clear the drawer log
+//   GET    /api/nv-memory       NV user memory records (GS ( C) by key code, as byte arrays
+//   DELETE /api/nv-memory       erase the NV user memory
+//   GET    /api/custom-values   print settings saved with GS ( M, null if none, and whether
+//                                ESC @ loads them
+//   DELETE /api/custom-values   erase them, ESC @ loading the defaults again
+//   GET    /api/customer-display  the customer display's lines, cursor, mode and brightness
+//   DELETE /api/customer-display  reset the customer display
+//   GET    /api/maintenance     maintenance counters: paper fed (mm), lines printed and cuts,
+//                                each since the last reset and in total
+//   POST   /api/maintenance/reset  clear the resettable counters, as GS g 0 does
+//   GET    /api/receive-buffer  receive buffer capacity, print speed and line speed
+//   PUT    /api/receive-buffer  body: {"capacity": bytes (0 = unlimited), "drain_rate": bytes/s,
+//                                "baud_rate": serial line speed (0 = network speed),
+//                                "response_chunk": bytes per response write (0 = whole),
+//                                "response_delay_ms": pause between response writes}
+//   GET    /api/export/text     receipt text, one line per text element
+//   GET    /api/export/csv      receipt text lines with their receipt and formatting, one row
+//                                per text element
+//   GET    /api/export/json     paper size, jobs with their times, reads, lint warnings,
+//                                notes and tags, and elements with their receive times
+//   GET    /api/export/png      the receipt as an image, one pixel per printer dot
+//   GET    /api/export/gif      the receipt printing out, animated (see animation.rs)
+//   GET    /api/export/pdf      every job parsed again, a page per receipt under its metadata
+//   POST   /api/export/pdf      body: JSON array of job ids; those jobs, as above
+//   GET    /api/export/trace    each job parsed again: its commands' byte ranges and the
+//                                elements they produced, numbered within the job
+//   GET    /api/export/disassembly  each job's commands as text: offset, hex, command and
+//                                parameters (see disassembly.rs)
+//   GET    /healthz             liveness: 503 once the listener failed to bind
+//   GET    /readyz              readiness: 503 unless the listener is accepting print jobs
+//
+// Deliberately minimal HTTP/1.1: one request per connection, no chunked bodies.
+
+use crate::{
+    animation, disassembly, pdf, render, trace_job, AppState, Job, ListenerStatus, PaperRoll,
+    PaperSize, ReceiptElement, ReceiveBufferSettings, SimulatedStatus, DOTS_PER_INCH,
+};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: &Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn ok(value: Value) -> Self {
+        Self::json(200, &value)
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &json!({ "error": message }))
+    }
+
+    fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn csv(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/csv; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn png(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "image/png",
+            body,
+        }
+    }
+
+    fn gif(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "image/gif",
+            body,
+        }
+    }
+
+    fn pdf(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/pdf",
+            body,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "Error",
+        }
+    }
+}
+
+pub async fn run(addr: String, state: AppState) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("ERROR: Failed to bind admin API to {}: {}", addr, e);
+            return;
+        }
+    };
+    println!("Admin API listening on http://{}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_request(socket, state).await {
+                        eprintln!("Admin API error: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Admin API: error accepting connection: {}", e),
+        }
+    }
+}
+
+async fn handle_request(mut socket: TcpStream, state: AppState) -> Result<()> {
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if request.len() > MAX_HEADER_BYTES {
+            return write_response(&mut socket, Response::error(413, "headers too large")).await;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_BYTES {
+        return write_response(&mut socket, Response::error(413, "body too large")).await;
+    }
+
+    while request.len() < header_end + content_length {
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&chunk[..n]);
+    }
+    let body_end = (header_end + content_length).min(request.len());
+    let body = &request[header_end..body_end];
+
+    let response = route(&method, &path, body, &state);
+    // Most requests change something the window shows
+    state.request_repaint();
+    write_response(&mut socket, response).await
+}
+
+async fn write_response(socket: &mut TcpStream, response: Response) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(&response.body).await?;
+    socket.flush().await?;
+    Ok(())
+}
+
+fn route(method: &str, path: &str, body: &[u8], state: &AppState) -> Response {
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "/api/status") => {
+            let listener = state.listener_status.lock().unwrap().clone();
+            let connections: Vec<String> = state
+                .connections
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|connection| format!("Connected: {}", connection.addr))
+                .collect();
+            let elements = state.elements.lock().unwrap().len();
+            let paper_size = *state.paper_size.lock().unwrap();
+            let printer_status = *state.simulated_status.borrow();
+            Response::ok(json!({
+                "listener": listener,
+                "paper_size": paper_size,
+                "connections": connections,
+                "elements": elements,
+                "printer_status": printer_status,
+            }))
+        }
+        ("GET", "/healthz") => health(state, |listener| {
+            !matches!(listener, ListenerStatus::Failed(_))
+        }),
+        ("GET", "/readyz") => health(state, |listener| {
+            matches!(listener, ListenerStatus::Listening(_))
+        }),
+        ("GET", "/api/elements") => {
+            let elements = state.elements.lock().unwrap();
+            Response::ok(json!(*elements))
+        }
+        ("DELETE", "/api/elements") => {
+            state.clear_receipt();
+            Response::ok(json!({ "cleared": true }))
+        }
+        ("GET", "/api/paper-size") => {
+            let paper_size = *state.paper_size.lock().unwrap();
+            Response::ok(json!(paper_size))
+        }
+        ("PUT", "/api/paper-size") => match serde_json::from_slice::<PaperSize>(body) {
+            Ok(paper_size) => {
+                state.set_paper_size(paper_size);
+                Response::ok(json!(paper_size))
+            }
+            Err(_) => Response::error(400, "expected \"58mm\" or \"80mm\""),
+        },
+        ("GET", "/api/printer-status") => {
+            let status = *state.simulated_status.borrow();
+            Response::ok(json!(status))
+        }
+        ("PUT", "/api/printer-status") => {
+            let status = *state.simulated_status.borrow();
+            match update_status(status, body) {
+                Ok(updated) => {
+                    state.replace_simulated_status(updated);
+                    Response::ok(json!(updated))
+                }
+                Err(message) => Response::error(400, &message),
+            }
+        }
+        ("GET", "/api/paper-roll") => {
+            let roll = *state.paper_roll.lock().unwrap();
+            Response::ok(json!(roll))
+        }
+        ("PUT", "/api/paper-roll") => match serde_json::from_slice::<PaperRoll>(body) {
+            Ok(roll) if roll.length_m < 0.0 || roll.near_end_m < 0.0 || roll.used_mm < 0.0 => {
+                Response::error(400, "lengths can't be negative")
+            }
+            Ok(roll) => {
+                *state.paper_roll.lock().unwrap() = roll;
+                Response::ok(json!(roll))
+            }
+            Err(e) => Response::error(400, &format!("invalid paper roll: {}", e)),
+        },
+        ("POST", "/api/paper-roll/load") => {
+            state.load_paper_roll();
+            let roll = *state.paper_roll.lock().unwrap();
+            Response::ok(json!(roll))
+        }
+        ("GET", "/api/drawer-kicks") => {
+            let log = state.drawer_log.lock().unwrap();
+            Response::ok(json!(*log))
+        }
+        ("DELETE", "/api/drawer-kicks") => {
+            state.drawer_log.lock().unwrap().clear();
+            Response::ok(json!({ "cleared": true }))
+        }
+        ("GET", "/api/nv-memory") => {
+            let memory = state.nv_memory.lock().unwrap();
+            Response::ok(json!(memory.records()))
+        }
+        ("DELETE", "/api/nv-memory") => {
+            state.nv_memory.lock().unwrap().clear();
+            Response::ok(json!({ "cleared": true }))
+        }
+        ("GET", "/api/custom-values") => {
+            let values = state.custom_values.lock().unwrap();
+            Response::ok(json!({
+                "saved": values.saved(),
+                "load_at_init": values.load_at_init(),
+            }))
+        }
+        ("DELETE", "/api/custom-values") => {
+            state.custom_values.lock().unwrap().clear();
+            Response::ok(json!({ "cleared": true }))
+        }
+        ("GET", "/api/customer-display") => {
+            let display = state.customer_display.lock().unwrap();
+            let (column, row) = display.cursor();
+            Response::ok(json!({
+                "lines": display.lines(),
+                "cursor": { "column": column, "row": row, "visible": display.cursor_visible },
+                "mode": display.mode,
+                "brightness": display.brightness,
+                "blink": display.blink,
+            }))
+        }
+        ("DELETE", "/api/customer-display") => {
+            *state.customer_display.lock().unwrap() = Default::default();
+            Response::ok(json!({ "cleared": true }))
+        }
+        ("GET", "/api/maintenance") => {
+            let counters = *state.maintenance.lock().unwrap();
+            Response::ok(json!(counters))
+        }
+        ("POST", "/api/maintenance/reset") => {
+            let mut counters = state.maintenance.lock().unwrap();
+            counters.reset_all();
+            Response::ok(json!(*counters))
+        }
+        ("GET", "/api/receive-buffer") => {
+            let settings = *state.receive_buffer.lock().unwrap();
+            Response::ok(json!(settings))
+        }
+        ("PUT", "/api/receive-buffer") => {
+            match serde_json::from_slice::<ReceiveBufferSettings>(body) {
+                Ok(settings) if settings.drain_rate == 0 => {
+                    Response::error(400, "drain_rate must be at least 1 byte/s")
+                }
+                Ok(settings) => {
+                    *state.receive_buffer.lock().unwrap() = settings;
+                    Response::ok(json!(settings))
+                }
+                Err(e) => Response::error(400, &format!("invalid settings: {}", e)),
+            }
+        }
+        ("GET", "/api/export/text") => {
+            let elements = state.elements.lock().unwrap();
+            Response::text(receipt_text(&elements))
+        }
+        ("GET", "/api/export/csv") => {
+            let elements = state.elements.lock().unwrap();
+            Response::csv(receipt_csv(&elements))
+        }
+        ("GET", "/api/export/json") => {
+            let paper_size = *state.paper_size.lock().unwrap();
+            let jobs: Vec<Value> = state
+                .jobs
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|job| {
+                    json!({
+                        "id": job.id,
+                        "source": job.source,
+                        "bytes": job.raw.len(),
+                        "received_at": job.received_at,
+                        "finished_at": job.finished_at,
+                        "arrivals": job.arrivals,
+                        "warnings": job.warnings,
+                        "note": job.note,
+                        "tags": job.tags,
+                    })
+                })
+                .collect();
+            let elements = state.elements.lock().unwrap();
+            let drawer_kicks = state.drawer_log.lock().unwrap().clone();
+            let element_times = state.element_times.lock().unwrap();
+            Response::ok(json!({
+                "paper_size": paper_size,
+                "jobs": jobs,
+                "drawer_kicks": drawer_kicks,
+                "elements": *elements,
+                "element_times": *element_times,
+            }))
+        }
+        ("GET", "/api/export/trace") => {
+            let options = state.parse_options();
+            let jobs = state.jobs.lock().unwrap().clone();
+            // Parsing every job again takes a while; see the png export
+            let jobs: Vec<Value> = tokio::task::block_in_place(|| {
+                jobs.iter()
+                    .map(
+                        |job| match trace_job(&job.raw, &options.for_source(&job.source)) {
+                            Ok(trace) => {
+                                json!({ "id": job.id, "source": job.source, "trace": trace })
+                            }
+                            Err(e) => json!({
+                                "id": job.id,
+                                "source": job.source,
+                                "error": format!("{:#}", e),
+                            }),
+                        },
+                    )
+                    .collect()
+            });
+            Response::ok(json!(jobs))
+        }
+        ("GET", "/api/export/disassembly") => {
+            let options = state.parse_options();
+            let jobs = state.jobs.lock().unwrap().clone();
+            let jobs: Vec<String> = tokio::task::block_in_place(|| {
+                jobs.iter()
+                    .map(|job| {
+                        let title = format!("job {} from {}", job.id, job.source);
+                        let options = options.for_source(&job.source);
+                        disassembly::disassemble(&title, &job.raw, &job.warnings, &options)
+                            .unwrap_or_else(|e| format!("// {}: {:#}\n", title, e))
+                    })
+                    .collect()
+            });
+            Response::text(jobs.join("\n"))
+        }
+        ("GET", "/api/export/png") => {
+            let elements = state.elements.lock().unwrap().clone();
+            let paper_size = *state.paper_size.lock().unwrap();
+            // Rendering a long receipt takes a moment; let the runtime move
+            // other connections off this thread meanwhile
+            let image = tokio::task::block_in_place(|| {
+                render::render_elements_to_image(&elements, paper_size, DOTS_PER_INCH)
+            });
+            let mut png = Vec::new();
+            match image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png) {
+                Ok(()) => Response::png(png),
+                Err(e) => Response::error(500, &format!("encoding the image: {}", e)),
+            }
+        }
+        ("GET", "/api/export/gif") => {
+            let elements = state.elements.lock().unwrap().clone();
+            let paper_size = *state.paper_size.lock().unwrap();
+            let gif = tokio::task::block_in_place(|| {
+                let image = render::render_elements_to_image(&elements, paper_size, DOTS_PER_INCH);
+                let mut gif = Vec::new();
+                animation::write_gif(
+                    &image,
+                    DOTS_PER_INCH,
+                    animation::DEFAULT_SPEED,
+                    animation::DEFAULT_FPS,
+                    &mut gif,
+                )
+                .map(|_| gif)
+            });
+            match gif {
+                Ok(gif) => Response::gif(gif),
+                Err(e) => Response::error(500, &format!("encoding the animation: {:#}", e)),
+            }
+        }
+        ("GET", "/api/export/pdf") => {
+            let jobs = state.jobs.lock().unwrap().clone();
+            jobs_pdf(state, &jobs)
+        }
+        ("POST", "/api/export/pdf") => match serde_json::from_slice::<Vec<u64>>(body) {
+            Ok(ids) => {
+                let jobs = state.jobs.lock().unwrap();
+                let missing: Vec<String> = ids
+                    .iter()
+                    .filter(|id| !jobs.iter().any(|job| job.id == **id))
+                    .map(u64::to_string)
+                    .collect();
+                if !missing.is_empty() {
+                    return Response::error(404, &format!("no job {}", missing.join(", ")));
+                }
+                let selected: Vec<_> = ids
+                    .iter()
+                    .filter_map(|id| jobs.iter().find(|job| job.id == *id).cloned())
+                    .collect();
+                drop(jobs);
+                jobs_pdf(state, &selected)
+            }
+            Err(e) => Response::error(400, &format!("expected an array of job ids: {}", e)),
+        },
+        (
+            _,
+            "/api/status"
+            | "/api/elements"
+            | "/api/paper-size"
+            | "/api/printer-status"
+            | "/api/paper-roll"
+            | "/api/paper-roll/load"
+            | "/api/drawer-kicks"
+            | "/api/nv-memory"
+            | "/api/custom-values"
+            | "/api/customer-display"
+            | "/api/maintenance"
+            | "/api/maintenance/reset"
+            | "/api/receive-buffer"
+            | "/api/export/text"
+            | "/api/export/csv"
+            | "/api/export/json"
+            | "/api/export/png"
+            | "/api/export/gif"
+            | "/api/export/pdf"
+            | "/api/export/trace"
+            | "/api/export/disassembly"
+            | "/healthz"
+            | "/readyz",
+        ) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+/// Listener state, open connections and when the last job finished (null
+/// before the first), with 200 if `healthy` holds for the listener and 503
+/// otherwise, for container orchestration to act on
+fn health(state: &AppState, healthy: impl Fn(&ListenerStatus) -> bool) -> Response {
+    let listener = state.listener_status.lock().unwrap().clone();
+    let connections = state.connections.lock().unwrap().len();
+    let last_job_at = match state.last_job_at.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(millis),
+    };
+    let healthy = healthy(&listener);
+    Response::json(
+        if healthy { 200 } else { 503 },
+        &json!({
+            "status": if healthy { "ok" } else { "unavailable" },
+            "listener": listener,
+            "connections": connections,
+            "last_job_at": last_job_at,
+        }),
+    )
+}
+
+/// The jobs as a PDF, a page per receipt
+fn jobs_pdf(state: &AppState, jobs: &[Job]) -> Response {
+    let options = state.parse_options();
+    match tokio::task::block_in_place(|| pdf::jobs_pdf(jobs, &options)) {
+        Ok(pdf) => Response::pdf(pdf),
+        Err(e) => Response::error(500, &format!("writing the PDF: {:#}", e)),
+    }
+}
+
+/// Apply the flags present in a JSON object, leaving the others untouched
+fn update_status(
+    status: SimulatedStatus,
+    body: &[u8],
+) -> std::result::Result<SimulatedStatus, String> {
+    let changes: serde_json::Map<String, Value> =
+        serde_json::from_slice(body).map_err(|e| format!("invalid JSON object: {}", e))?;
+    status.merged(changes)
+}
+
+fn receipt_text(elements: &[ReceiptElement]) -> String {
+    let mut text = String::new();
+    for element in elements {
+        match element {
+            ReceiptElement::Text { content, .. } => text.push_str(content),
+            ReceiptElement::Separator => {}
+            ReceiptElement::PaperCut { cut_type } => {
+                text.push_str(&format!("--- {} ---", cut_type))
+            }
+            _ => continue,
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Text elements as CSV rows, with the receipt they belong to (empty before
+/// the first receipt) and their formatting
+fn receipt_csv(elements: &[ReceiptElement]) -> String {
+    let mut csv = String::from(
+        "receipt,line,text,alignment,bold,underline,double_width,double_height,inverted,font\n",
+    );
+    let mut receipt = None;
+    let mut line = 0;
+    for element in elements {
+        match element {
+            ReceiptElement::ReceiptStart { id, .. } => {
+                receipt = Some(*id);
+                line = 0;
+            }
+            ReceiptElement::Text {
+                content,
+                alignment,
+                bold,
+                underline,
+                double_width,
+                double_height,
+                inverted,
+                font,
+                ..
+            } => {
+                line += 1;
+                csv.push_str(&format!(
+                    "{},{},{},{:?},{},{},{},{},{},{}\n",
+                    receipt.map_or(String::new(), |id| id.to_string()),
+                    line,
+                    csv_field(content),
+                    alignment,
+                    bold,
+                    underline,
+                    double_width,
+                    double_height,
+                    inverted,
+                    font
+                ));
+            }
+            _ => {}
+        }
+    }
+    csv
+}
+
+/// Quote a field containing commas, quotes or line breaks (RFC 4180)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,103 @@
+// Print animation: a rendered receipt revealed from the top as the paper
+// would come out of a printer feeding at a given speed, as an animated GIF
+// for release notes and documentation of receipt changes.
+//
+// The first frame is the empty canvas; each later one holds only the strip
+// printed since the one before, drawn over it, so long receipts stay small.
+// A dark line under the last strip stands for the print head. The finished
+// receipt stays up for a moment before the animation starts over.
+//
+// For video, convert the GIF: ffmpeg -i receipt.gif -pix_fmt yuv420p receipt.mp4
+
+use anyhow::{Context, Result};
+use image::{Rgba, RgbaImage};
+use std::io::Write;
+
+/// Paper feed speed unless given, in mm/s, that of common 80 mm printers
+pub const DEFAULT_SPEED: f64 = 150.0;
+
+/// Frames per second unless given
+pub const DEFAULT_FPS: u32 = 20;
+
+/// Viewers slow frames shorter than this (hundredths of a second) down
+const MIN_DELAY: u16 = 2;
+
+/// How long the finished receipt stays up, in hundredths of a second
+const HOLD: u16 = 200;
+
+/// Paper not printed yet: the window background
+const UNPRINTED: Rgba<u8> = Rgba([210, 210, 210, 255]);
+
+const HEAD: Rgba<u8> = Rgba([60, 60, 60, 255]);
+const HEAD_HEIGHT: u32 = 3;
+
+/// Palette reduction speed, 1 (best) to 30 (fastest); only frames with more
+/// than 256 colors, like dithered photos, need it
+const QUANTIZE_SPEED: i32 = 10;
+
+/// Write the animation of `image`, rendered at `dpi`, printing at `speed`
+/// mm/s, returning the number of frames
+pub fn write_gif(
+    image: &RgbaImage,
+    dpi: u32,
+    speed: f64,
+    fps: u32,
+    out: impl Write,
+) -> Result<usize> {
+    let too_large = || {
+        format!(
+            "a {}x{} receipt is too large for a GIF (65535 pixels at most)",
+            image.width(),
+            image.height()
+        )
+    };
+    let width = u16::try_from(image.width()).with_context(too_large)?;
+    let height = u16::try_from(image.height()).with_context(too_large)?;
+    let mut encoder = gif::Encoder::new(out, width, height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    let delay = u16::try_from(100 / fps.max(1))
+        .unwrap_or(u16::MAX)
+        .max(MIN_DELAY);
+    // Dot rows fed per frame
+    let rows = (speed / 25.4 * f64::from(dpi) * f64::from(delay) / 100.0)
+        .ceil()
+        .max(1.0) as u32;
+
+    let mut blank = RgbaImage::from_pixel(image.width(), image.height(), UNPRINTED);
+    let mut frame = gif::Frame::from_rgba_speed(width, height, blank.as_mut(), QUANTIZE_SPEED);
+    frame.delay = delay;
+    encoder.write_frame(&frame)?;
+    let mut frames = 1;
+
+    let mut printed = 0;
+    while printed < image.height() {
+        let end = (printed + rows).min(image.height());
+        let last = end == image.height();
+        let head_end = if last {
+            end
+        } else {
+            (end + HEAD_HEIGHT).min(image.height())
+        };
+        let mut strip = RgbaImage::from_fn(image.width(), head_end - printed, |x, y| {
+            if printed + y < end {
+                *image.get_pixel(x, printed + y)
+            } else {
+                HEAD
+            }
+        });
+        let mut frame = gif::Frame::from_rgba_speed(
+            width,
+            strip.height() as u16,
+            strip.as_mut(),
+            QUANTIZE_SPEED,
+        );
+        frame.top = printed as u16;
+        frame.dispose = gif::DisposalMethod::Keep;
+        frame.delay = if last { HOLD } else { delay };
+        encoder.write_frame(&frame)?;
+        frames += 1;
+        printed = end;
+    }
+    Ok(frames)
+}
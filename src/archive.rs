@@ -0,0 +1,47 @@
+// Receipt archive: receipts cleared from the window without the user asking
+// (auto-clear when a new connection prints, or the oldest beyond the history
+// limits) are saved first, each batch to a folder of its own named by the
+// time it was archived. The folders have the layout of a session directory,
+// so one can be opened again with ESCPRESSO_SESSION:
+//
+//   <dir>/20261016-142501-123/session.json
+//   <dir>/20261016-142501-123/job-00012.bin
+
+use crate::{format_timestamp, session::Session, Job, PaperSize, ReceiptElement};
+use anyhow::Result;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub dir: PathBuf,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("escpresso-archive"),
+        }
+    }
+}
+
+impl Archive {
+    /// Save receipts and the jobs that printed them, returning their folder
+    pub fn save(
+        &self,
+        at: u64,
+        paper_size: PaperSize,
+        jobs: &[Job],
+        elements: &[ReceiptElement],
+        element_times: &[u64],
+    ) -> Result<PathBuf> {
+        // "2026-10-16 14:25:01" -> "20261016-142501", plus milliseconds so
+        // receipts archived within a second don't share a folder
+        let time = format_timestamp(at)
+            .replace(['-', ':'], "")
+            .replace(' ', "-");
+        let dir = self.dir.join(format!("{}-{:03}", time, at % 1000));
+        let last_job_id = jobs.iter().map(|job| job.id).max().unwrap_or(0);
+        Session::open(&dir)?.save(paper_size, jobs, last_job_id, elements, element_times)?;
+        Ok(dir)
+    }
+}
@@ -0,0 +1,101 @@
+// Audit log: every connection and job as a line of JSON, appended to a file
+// that survives restarts, so an overnight test run can be checked the next
+// morning with jq or a spreadsheet. Events:
+//
+//   {"event":"connected","at":...,"source":"192.168.1.20:51234"}
+//   {"event":"rejected","at":...,"source":...,"reason":"not in the allowlist"}
+//   {"event":"job","at":...,"id":12,"source":...,"bytes":1834,"received_at":...,
+//    "duration_ms":210,"elements":31,"commands":{"ESC @":1,"GS V":1,...},
+//    "warnings":0,"capture":".../20261016-142501-job00012-....bin","images":null}
+//   {"event":"disconnected","at":...,"source":...,"bytes":1834,"duration_ms":5012}
+//
+// Times are milliseconds since the Unix epoch.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent<'a> {
+    Connected {
+        source: &'a str,
+    },
+    Rejected {
+        source: &'a str,
+        reason: &'a str,
+    },
+    Job {
+        id: u64,
+        source: &'a str,
+        bytes: usize,
+        received_at: u64,
+        duration_ms: u64,
+        elements: usize,
+        /// Times each command was processed, by name
+        commands: &'a BTreeMap<String, usize>,
+        warnings: usize,
+        /// Raw capture file
+        capture: Option<&'a Path>,
+        /// Folder the job's images were dumped to
+        images: Option<&'a Path>,
+    },
+    Disconnected {
+        source: &'a str,
+        bytes: u64,
+        duration_ms: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    at: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent<'a>,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Append to the log at `path`, creating it if needed
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, at: u64, event: &AuditEvent) {
+        let result = serde_json::to_vec(&Line { at, event })
+            .map_err(anyhow::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                // One write per line, so lines of concurrent connections
+                // don't interleave
+                Ok(self.file.lock().unwrap().write_all(&line)?)
+            });
+        if let Err(e) = result {
+            eprintln!(
+                "ERROR: Failed to write the audit log {}: {:#}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// 1D barcode encoding for GS k
+//
+// Symbols are encoded into run lengths: alternating bar and space widths,
+// starting with a bar, measured in half modules so that wide elements of
+// two-width symbologies (CODE39, ITF, CODABAR) can be 2.5 modules wide as
+// on Epson printers. Data the printer would reject returns an error.
+
+use anyhow::{bail, Result};
+
+/// An encoded barcode ready to draw
+pub struct Encoded {
+    /// Symbology name, e.g. "CODE128"
+    pub symbology: &'static str,
+    /// Human readable interpretation printed above/below the bars
+    pub text: String,
+    /// Bar/space widths in half modules, starting with a bar
+    pub widths: Vec<u8>,
+}
+
+/// Encode GS k data. `m` is the barcode system from either function A
+/// (0-6, NUL terminated) or function B (65-73, length prefixed).
+pub fn encode(m: u8, data: &[u8]) -> Result<Encoded> {
+    let (symbology, (text, widths)) = match m {
+        0 | 65 => ("UPC-A", upc_a(data)?),
+        1 | 66 => ("UPC-E", upc_e(data)?),
+        2 | 67 => ("EAN13", ean13(data)?),
+        3 | 68 => ("EAN8", ean8(data)?),
+        4 | 69 => ("CODE39", code39(data)?),
+        5 | 70 => ("ITF", itf(data)?),
+        6 | 71 => ("CODABAR", codabar(data)?),
+        72 => ("CODE93", code93(data)?),
+        73 => ("CODE128", code128(data)?),
+        _ => bail!("unsupported barcode system {}", m),
+    };
+    Ok(Encoded {
+        symbology,
+        text,
+        widths,
+    })
+}
+
+type Symbol = (String, Vec<u8>);
+
+/// Appends elements given as module counts (single-width symbologies)
+fn push_modules(widths: &mut Vec<u8>, modules: &str) {
+    widths.extend(modules.bytes().map(|m| (m - b'0') * 2));
+}
+
+/// Appends elements given as narrow/wide flags ('0' narrow, '1' wide)
+fn push_narrow_wide(widths: &mut Vec<u8>, pattern: &str) {
+    widths.extend(pattern.bytes().map(|b| if b == b'1' { 5 } else { 2 }));
+}
+
+/// Appends an element list as module bits ('1' bar, '0' space), merging
+/// with the previous element when it is the same color
+fn push_bits(widths: &mut Vec<u8>, bits: &str) {
+    for bit in bits.bytes() {
+        let is_bar = bit == b'1';
+        // Elements alternate bar/space starting with a bar
+        let last_is_bar = widths.len() % 2 == 1;
+        if !widths.is_empty() && last_is_bar == is_bar {
+            *widths.last_mut().unwrap() += 2;
+        } else {
+            widths.push(2);
+        }
+    }
+}
+
+fn digits(data: &[u8]) -> Result<Vec<u8>> {
+    data.iter()
+        .map(|&b| match b {
+            b'0'..=b'9' => Ok(b - b'0'),
+            _ => bail!("non-digit {:?} in numeric barcode", b as char),
+        })
+        .collect()
+}
+
+/// Modulo 10 check digit used by UPC and EAN (weights 3,1 from the right)
+fn ean_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| d as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// Digits with the check digit appended, or verified if already present
+fn with_check_digit(data: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut d = digits(data)?;
+    if d.len() == len - 1 {
+        d.push(ean_check_digit(&d));
+    } else if d.len() != len {
+        bail!("expected {} or {} digits, got {}", len - 1, len, d.len());
+    } else if ean_check_digit(&d[..len - 1]) != d[len - 1] {
+        bail!("wrong check digit");
+    }
+    Ok(d)
+}
+
+const EAN_L: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+    "0110111", "0001011",
+];
+
+fn ean_r(d: u8) -> String {
+    EAN_L[d as usize]
+        .bytes()
+        .map(|b| if b == b'1' { '0' } else { '1' })
+        .collect()
+}
+
+fn ean_g(d: u8) -> String {
+    ean_r(d).chars().rev().collect()
+}
+
+/// Left-half parity for EAN-13 by leading digit ('G' = even parity)
+const EAN13_PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+    "LGGLGL",
+];
+
+fn ean13_digits(d: &[u8]) -> Vec<u8> {
+    let mut widths = Vec::new();
+    let parity = EAN13_PARITY[d[0] as usize].as_bytes();
+    push_bits(&mut widths, "101");
+    for (i, &digit) in d[1..7].iter().enumerate() {
+        if parity[i] == b'G' {
+            push_bits(&mut widths, &ean_g(digit));
+        } else {
+            push_bits(&mut widths, EAN_L[digit as usize]);
+        }
+    }
+    push_bits(&mut widths, "01010");
+    for &digit in &d[7..13] {
+        push_bits(&mut widths, &ean_r(digit));
+    }
+    push_bits(&mut widths, "101");
+    widths
+}
+
+fn text_of(d: &[u8]) -> String {
+    d.iter().map(|d| (b'0' + d) as char).collect()
+}
+
+fn ean13(data: &[u8]) -> Result<Symbol> {
+    let d = with_check_digit(data, 13)?;
+    Ok((text_of(&d), ean13_digits(&d)))
+}
+
+fn upc_a(data: &[u8]) -> Result<Symbol> {
+    // UPC-A is EAN-13 with a leading zero
+    let d = with_check_digit(data, 12)?;
+    let mut ean = vec![0];
+    ean.extend_from_slice(&d);
+    Ok((text_of(&d), ean13_digits(&ean)))
+}
+
+fn ean8(data: &[u8]) -> Result<Symbol> {
+    let d = with_check_digit(data, 8)?;
+    let mut widths = Vec::new();
+    push_bits(&mut widths, "101");
+    for &digit in &d[..4] {
+        push_bits(&mut widths, EAN_L[digit as usize]);
+    }
+    push_bits(&mut widths, "01010");
+    for &digit in &d[4..] {
+        push_bits(&mut widths, &ean_r(digit));
+    }
+    push_bits(&mut widths, "101");
+    Ok((text_of(&d), widths))
+}
+
+/// UPC-E parity for number system 0 by check digit ('E' = even parity)
+const UPC_E_PARITY: [&str; 10] = [
+    "EEEOOO", "EEOEOO", "EEOOEO", "EEOOOE", "EOEEOO", "EOOEEO", "EOOOEE", "EOEOEO", "EOEOOE",
+    "EOOEOE",
+];
+
+fn upc_e(data: &[u8]) -> Result<Symbol> {
+    // 6 digits (number system 0), 7 (with number system) or 8 (with check digit)
+    let mut d = digits(data)?;
+    if d.len() == 6 {
+        d.insert(0, 0);
+    }
+    if d.len() != 7 && d.len() != 8 {
+        bail!("expected 6 to 8 digits, got {}", d.len());
+    }
+    let number_system = d[0];
+    if number_system > 1 {
+        bail!("UPC-E number system must be 0 or 1");
+    }
+
+    // The check digit is that of the equivalent UPC-A code
+    let [d1, d2, d3, d4, d5, d6] = [d[1], d[2], d[3], d[4], d[5], d[6]];
+    let expanded = match d6 {
+        0..=2 => vec![number_system, d1, d2, d6, 0, 0, 0, 0, d3, d4, d5],
+        3 => vec![number_system, d1, d2, d3, 0, 0, 0, 0, 0, d4, d5],
+        4 => vec![number_system, d1, d2, d3, d4, 0, 0, 0, 0, 0, d5],
+        _ => vec![number_system, d1, d2, d3, d4, d5, 0, 0, 0, 0, d6],
+    };
+    let check = ean_check_digit(&expanded);
+    if d.len() == 8 && d[7] != check {
+        bail!("wrong check digit");
+    }
+    d.truncate(7);
+    d.push(check);
+
+    let parity = UPC_E_PARITY[check as usize].as_bytes();
+    let mut widths = Vec::new();
+    push_bits(&mut widths, "101");
+    for (i, &digit) in d[1..7].iter().enumerate() {
+        // Number system 1 inverts the parity pattern
+        let even = (parity[i] == b'E') != (number_system == 1);
+        if even {
+            push_bits(&mut widths, &ean_g(digit));
+        } else {
+            push_bits(&mut widths, EAN_L[digit as usize]);
+        }
+    }
+    push_bits(&mut widths, "010101");
+    Ok((text_of(&d), widths))
+}
+
+const CODE39_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%*";
+const CODE39: [&str; 44] = [
+    "000110100",
+    "100100001",
+    "001100001",
+    "101100000",
+    "000110001",
+    "100110000",
+    "001110000",
+    "000100101",
+    "100100100",
+    "001100100",
+    "100001001",
+    "001001001",
+    "101001000",
+    "000011001",
+    "100011000",
+    "001011000",
+    "000001101",
+    "100001100",
+    "001001100",
+    "000011100",
+    "100000011",
+    "001000011",
+    "101000010",
+    "000010011",
+    "100010010",
+    "001010010",
+    "000000111",
+    "100000110",
+    "001000110",
+    "000010110",
+    "110000001",
+    "011000001",
+    "111000000",
+    "010010001",
+    "110010000",
+    "011010000",
+    "010000101",
+    "110000100",
+    "011000100",
+    "010101000",
+    "010100010",
+    "010001010",
+    "000101010",
+    "010010100",
+];
+
+fn code39(data: &[u8]) -> Result<Symbol> {
+    // The printer adds the '*' start/stop characters if they are missing
+    let body = data
+        .strip_prefix(b"*")
+        .map(|d| d.strip_suffix(b"*").unwrap_or(d))
+        .unwrap_or(data);
+    let text = format!("*{}*", String::from_utf8_lossy(body));
+
+    let mut widths = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let Some(index) = CODE39_CHARS.find(c) else {
+            bail!("character {:?} not in CODE39", c);
+        };
+        if i > 0 {
+            widths.push(2); // narrow inter-character gap
+        }
+        push_narrow_wide(&mut widths, CODE39[index]);
+    }
+    Ok((text, widths))
+}
+
+const ITF: [&str; 10] = [
+    "00110", "10001", "01001", "11000", "00101", "10100", "01100", "00011", "10010", "01010",
+];
+
+fn itf(data: &[u8]) -> Result<Symbol> {
+    let d = digits(data)?;
+    if d.is_empty() || d.len() % 2 != 0 {
+        bail!("ITF needs an even number of digits");
+    }
+    let mut widths = Vec::new();
+    push_narrow_wide(&mut widths, "0000");
+    for pair in d.chunks(2) {
+        // First digit in the bars, second in the spaces
+        let bars = ITF[pair[0] as usize].as_bytes();
+        let spaces = ITF[pair[1] as usize].as_bytes();
+        for i in 0..5 {
+            let interleaved = [bars[i], spaces[i]];
+            push_narrow_wide(&mut widths, std::str::from_utf8(&interleaved).unwrap());
+        }
+    }
+    push_narrow_wide(&mut widths, "100");
+    Ok((text_of(&d), widths))
+}
+
+const CODABAR_CHARS: &str = "0123456789-$:/.+ABCD";
+const CODABAR: [&str; 20] = [
+    "0000011", "0000110", "0001001", "1100000", "0010010", "1000010", "0100001", "0100100",
+    "0110000", "1001000", "0001100", "0011000", "1000101", "1010001", "1010100", "0010101",
+    "0011010", "0101001", "0001011", "0001110",
+];
+
+fn codabar(data: &[u8]) -> Result<Symbol> {
+    let text = String::from_utf8_lossy(data).to_ascii_uppercase();
+    let is_start_stop = |c: Option<char>| matches!(c, Some('A'..='D'));
+    if text.len() < 2 || !is_start_stop(text.chars().next()) || !is_start_stop(text.chars().last())
+    {
+        bail!("CODABAR must start and end with A-D");
+    }
+    let mut widths = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let Some(index) = CODABAR_CHARS.find(c) else {
+            bail!("character {:?} not in CODABAR", c);
+        };
+        if i > 0 {
+            widths.push(2);
+        }
+        push_narrow_wide(&mut widths, CODABAR[index]);
+    }
+    Ok((text, widths))
+}
+
+const CODE93_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+// 43 data characters, 4 shift characters, then start/stop
+const CODE93: [&str; 48] = [
+    "131112", "111213", "111312", "111411", "121113", "121212", "121311", "111114", "131211",
+    "141111", "211113", "211212", "211311", "221112", "221211", "231111", "112113", "112212",
+    "112311", "122112", "132111", "111123", "111222", "111321", "121122", "131121", "212112",
+    "212211", "211122", "211221", "221121", "222111", "112122", "112221", "122121", "123111",
+    "121131", "311112", "311211", "321111", "112131", "113121", "211131", "121221", "312111",
+    "311121", "122211", "111141",
+];
+const CODE93_START_STOP: usize = 47;
+
+fn code93(data: &[u8]) -> Result<Symbol> {
+    let text = String::from_utf8_lossy(data).into_owned();
+    let mut values = Vec::with_capacity(text.len() + 2);
+    for c in text.chars() {
+        match CODE93_CHARS.find(c) {
+            Some(value) => values.push(value),
+            None => bail!("character {:?} not in CODE93", c),
+        }
+    }
+
+    // Two check characters, C (weights up to 20) then K (up to 15)
+    for max_weight in [20, 15] {
+        let sum: usize = values
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, &v)| v * (i % max_weight + 1))
+            .sum();
+        values.push(sum % 47);
+    }
+
+    let mut widths = Vec::new();
+    push_modules(&mut widths, CODE93[CODE93_START_STOP]);
+    for value in values {
+        push_modules(&mut widths, CODE93[value]);
+    }
+    push_modules(&mut widths, CODE93[CODE93_START_STOP]);
+    push_modules(&mut widths, "1"); // termination bar
+    Ok((text, widths))
+}
+
+const CODE128: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum CodeSet {
+    A,
+    B,
+    C,
+}
+
+/// CODE128 as sent to ESC/POS printers: data starts with "{A", "{B" or
+/// "{C" to pick the code set, "{" introduces further control codes
+/// ("{S" shift, "{1".."{4" FNC1-4, "{{" a literal brace), and in code set C
+/// every byte (0-99) is one codeword.
+fn code128(data: &[u8]) -> Result<Symbol> {
+    let mut set = match data {
+        [b'{', b'A', ..] => CodeSet::A,
+        [b'{', b'B', ..] => CodeSet::B,
+        [b'{', b'C', ..] => CodeSet::C,
+        _ => bail!("CODE128 data must start with {{A, {{B or {{C"),
+    };
+    let mut values = vec![match set {
+        CodeSet::A => 103,
+        CodeSet::B => 104,
+        CodeSet::C => 105,
+    }];
+    let mut text = String::new();
+    let mut shift = false;
+
+    let mut i = 2;
+    while i < data.len() {
+        let byte = data[i];
+        i += 1;
+        if byte == b'{' && i < data.len() && data[i] != b'{' {
+            let code = data[i];
+            i += 1;
+            let value = match (code, set) {
+                (b'A', CodeSet::A) | (b'B', CodeSet::B) | (b'C', CodeSet::C) => continue,
+                (b'A', _) => {
+                    set = CodeSet::A;
+                    101
+                }
+                (b'B', _) => {
+                    set = CodeSet::B;
+                    100
+                }
+                (b'C', _) => {
+                    set = CodeSet::C;
+                    99
+                }
+                (b'S', CodeSet::A | CodeSet::B) => {
+                    shift = true;
+                    98
+                }
+                (b'1', _) => 102,
+                (b'2', CodeSet::A | CodeSet::B) => 97,
+                (b'3', CodeSet::A | CodeSet::B) => 96,
+                (b'4', CodeSet::A) => 101,
+                (b'4', CodeSet::B) => 100,
+                _ => bail!("invalid CODE128 control {{{}", code as char),
+            };
+            values.push(value);
+            continue;
+        }
+        if byte == b'{' {
+            i += 1; // "{{" is a literal brace
+        }
+
+        // A shift applies to the next character only
+        let effective = match (set, shift) {
+            (CodeSet::A, true) => CodeSet::B,
+            (CodeSet::B, true) => CodeSet::A,
+            (set, _) => set,
+        };
+        shift = false;
+        let value = match effective {
+            CodeSet::A => match byte {
+                0x00..=0x1F => byte as usize + 64,
+                0x20..=0x5F => byte as usize - 32,
+                _ => bail!("byte 0x{:02X} not in CODE128 set A", byte),
+            },
+            CodeSet::B => match byte {
+                0x20..=0x7F => byte as usize - 32,
+                _ => bail!("byte 0x{:02X} not in CODE128 set B", byte),
+            },
+            CodeSet::C => match byte {
+                0..=99 => byte as usize,
+                _ => bail!("byte {} not in CODE128 set C", byte),
+            },
+        };
+        values.push(value);
+        match effective {
+            CodeSet::C => text.push_str(&format!("{:02}", byte)),
+            _ if byte >= 0x20 => text.push(byte as char),
+            _ => text.push(' '),
+        }
+    }
+
+    let checksum = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| v * i.max(1))
+        .sum::<usize>()
+        % 103;
+    values.push(checksum);
+    values.push(106);
+
+    let mut widths = Vec::new();
+    for value in values {
+        push_modules(&mut widths, CODE128[value]);
+    }
+    Ok((text, widths))
+}
@@ -0,0 +1,106 @@
+// Raw capture: the bytes of every job written to a file of its own as the job
+// ends, named by receive time, job number and connection, so any job in the
+// history can be replayed or attached to a bug report. Once the captures take
+// more than the size limit, the oldest are deleted.
+//
+//   <dir>/20261016-142501-job00012-192.168.1.20_51234.bin
+
+use crate::format_timestamp;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Size limit unless ESCPRESSO_CAPTURE_MAX_MB or the settings change it
+pub const DEFAULT_MAX_MB: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawCapture {
+    pub dir: PathBuf,
+    /// Captures are deleted, oldest first, beyond this many megabytes
+    pub max_mb: u64,
+}
+
+impl Default for RawCapture {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("escpresso-captures"),
+            max_mb: DEFAULT_MAX_MB,
+        }
+    }
+}
+
+impl RawCapture {
+    /// Write a job's bytes, returning the file's path
+    pub fn save(&self, job_id: u64, source: &str, received_at: u64, raw: &[u8]) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        // "2026-10-16 14:25:01" -> "20261016-142501"
+        let time = format_timestamp(received_at)
+            .replace(['-', ':'], "")
+            .replace(' ', "-");
+        let source: String = source
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = self
+            .dir
+            .join(format!("{}-job{:05}-{}.bin", time, job_id, source));
+        std::fs::write(&path, raw).with_context(|| format!("writing {}", path.display()))?;
+        self.prune(&path)?;
+        Ok(path)
+    }
+
+    /// Delete the oldest captures until they fit the size limit, sparing
+    /// `keep` even if it's bigger on its own
+    fn prune(&self, keep: &Path) -> Result<()> {
+        let mut captures = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".bin") && name.contains("-job") {
+                captures.push((name, entry.path(), entry.metadata()?.len()));
+            }
+        }
+        // Names start with the receive time
+        captures.sort();
+        let mut total: u64 = captures.iter().map(|(_, _, len)| len).sum();
+        for (_, path, len) in captures {
+            if total <= self.max_mb * 1024 * 1024 {
+                break;
+            }
+            if path != keep {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("deleting {}", path.display()))?;
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Show a capture in the platform's file manager
+pub fn reveal(path: &Path) -> Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("explorer");
+        command.arg(format!("/select,{}", path.display()));
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(path));
+        command
+    };
+    command
+        .spawn()
+        .with_context(|| format!("running {}", command.get_program().to_string_lossy()))?;
+    Ok(())
+}
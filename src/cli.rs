@@ -0,0 +1,754 @@
+// Command-line subcommands. Without one, the emulator window starts.
+//
+//   escpresso render <input.bin> -o <out.png> [--paper 58mm|80mm] [--dpi <n>]
+//                    [--code-page <n>] [--code-page-file <n>=<mapping.txt>]
+//                    [--columns <A/B/C>] [--so-double-width] [--control-glyphs]
+//                    [--compare <golden.png>] [--threshold <fraction>] [--diff <diff.png>]
+//   escpresso demo [--host <host:port>] [-o <out.bin>]
+//   escpresso bench <input.bin> [--runs <n>] [--min-mbps <n>]
+//   escpresso trace <input.bin> [-o <trace.json>] [--paper 58mm|80mm] [--code-page <n>]
+//                   [--columns <A/B/C>]
+//   escpresso disasm <input.bin> [-o <out.txt>] [--paper 58mm|80mm] [--code-page <n>]
+//                    [--columns <A/B/C>]
+//   escpresso asm <input.txt> [-o <out.bin>] [--host <host:port>]
+//   escpresso watch <input.bin> [-o <out.png>] [--paper 58mm|80mm]
+//   escpresso animate <input.bin> -o <out.gif> [--paper 58mm|80mm] [--code-page <n>]
+//                     [--columns <A/B/C>] [--speed <mm/s>] [--fps <n>]
+//   escpresso pdf <input.bin>... -o <out.pdf> [--paper 58mm|80mm] [--code-page <n>]
+//                 [--columns <A/B/C>]
+//
+// --code-page-file decodes ESC t n with a mapping file (see codepages.rs),
+// for clone printers with their own tables; it can be given several times.
+// render, trace, disasm, animate and pdf take it like --code-page.
+//
+// --so-double-width makes SO select double width for the rest of the line
+// and SI and DC4 cancel it, as on legacy printers; trace, disasm, animate
+// and pdf take it too.
+//
+// --columns sets the columns per line of Fonts A, B and C, for printers that
+// don't fit the Epson counts at their paper width, e.g. 42/56 (0 or left out:
+// the font's own count).
+//
+// animate writes the receipt printing out as an animated GIF (see
+// animation.rs). pdf puts each receipt of each file, as separated by full
+// cuts, on a page of its own, under the file name, size and warning count
+// (see pdf.rs).
+//
+// watch shows the file in the emulator window, or renders it to -o, again
+// each time it changes, until interrupted.
+//
+// render prints the job's lint warnings to stderr. trace writes which byte
+// ranges were which commands and produced which elements, as JSON, to the
+// file or stdout. disasm writes the job's commands as text, one a line with
+// offset, hex and parameters (see disassembly.rs); asm turns it back into
+// bytes, edited or not, and writes them to a file or sends them to a printer.
+// bench parses a capture
+// repeatedly and reports the parser's throughput and the commands it spent
+// the most time in.
+//
+// Exit codes: 0 success, 1 the render differs from the golden image or the
+// throughput is below --min-mbps, 2 usage or I/O error.
+
+use crate::{
+    animation, demo, disassembly, parse_columns, parse_job, pdf, profile_job, render, trace_job,
+    CustomCodePage, CustomCodePages, PaperSize, ParseOptions, DOTS_PER_INCH,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str =
+    "usage: escpresso render <input.bin> -o <out.png> [--paper 58mm|80mm] [--dpi <n>]
+                        [--code-page <n>] [--code-page-file <n>=<mapping.txt>]
+                        [--columns <A/B/C>] [--so-double-width] [--control-glyphs]
+                        [--compare <golden.png>] [--threshold <fraction>] [--diff <diff.png>]
+       escpresso demo [--host <host:port>] [-o <out.bin>]
+       escpresso bench <input.bin> [--runs <n>] [--min-mbps <n>]
+       escpresso trace <input.bin> [-o <trace.json>] [--paper 58mm|80mm] [--code-page <n>]
+                       [--columns <A/B/C>]
+       escpresso disasm <input.bin> [-o <out.txt>] [--paper 58mm|80mm] [--code-page <n>]
+                        [--columns <A/B/C>]
+       escpresso asm <input.txt> [-o <out.bin>] [--host <host:port>]
+       escpresso watch <input.bin> [-o <out.png>] [--paper 58mm|80mm]
+       escpresso animate <input.bin> -o <out.gif> [--paper 58mm|80mm] [--code-page <n>]
+                         [--columns <A/B/C>] [--speed <mm/s>] [--fps <n>]
+       escpresso pdf <input.bin>... -o <out.pdf> [--paper 58mm|80mm] [--code-page <n>]
+                     [--columns <A/B/C>]";
+
+/// Where `demo` sends its jobs unless --host is given
+const DEFAULT_DEMO_HOST: &str = "127.0.0.1:9100";
+
+/// Fraction of differing pixels tolerated by --compare unless --threshold is given
+const DEFAULT_THRESHOLD: f64 = 0.0;
+
+/// Times bench parses the input unless --runs is given
+const DEFAULT_BENCH_RUNS: u32 = 20;
+
+/// Commands bench lists, slowest first
+const BENCH_SLOWEST: usize = 10;
+
+/// How often watch checks the file for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Run the subcommand in `args` (without the program name), returning the
+/// process exit code
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "render" => render(&args[1..]),
+        "demo" => demo(&args[1..]),
+        "bench" => bench(&args[1..]),
+        "trace" => trace(&args[1..]),
+        "disasm" => disasm(&args[1..]),
+        "asm" => asm(&args[1..]),
+        "watch" => watch(&args[1..]),
+        "animate" => animate(&args[1..]),
+        "pdf" => pdf(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        other => Err(anyhow!("unknown command '{}'\n{}", other, USAGE)),
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("ERROR: {:#}", e);
+        2
+    })
+}
+
+struct RenderArgs {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    paper_size: PaperSize,
+    /// Output resolution, one pixel per dot at the printer's
+    dpi: u32,
+    /// ESC t number in effect until the job selects one
+    code_page: u8,
+    /// Columns per line of Fonts A, B and C, 0 for their own
+    columns: [u16; 3],
+    custom_code_pages: CustomCodePages,
+    /// SO and SI switch one-line double width
+    so_double_width: bool,
+    /// Print stray control characters as symbols
+    control_glyphs: bool,
+    compare: Option<PathBuf>,
+    threshold: f64,
+    diff: Option<PathBuf>,
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs> {
+    let mut input = None;
+    let mut parsed = RenderArgs {
+        input: PathBuf::new(),
+        output: None,
+        paper_size: PaperSize::Size80mm,
+        dpi: DOTS_PER_INCH,
+        code_page: 0,
+        columns: [0; 3],
+        custom_code_pages: CustomCodePages::new(),
+        so_double_width: false,
+        control_glyphs: false,
+        compare: None,
+        threshold: DEFAULT_THRESHOLD,
+        diff: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => parsed.output = Some(value()?.into()),
+            "--paper" => parsed.paper_size = parse_paper_size(value()?)?,
+            "--dpi" => {
+                let text = value()?;
+                parsed.dpi = text
+                    .parse()
+                    .ok()
+                    .filter(|dpi| (50..=1200).contains(dpi))
+                    .ok_or_else(|| anyhow!("invalid resolution '{}', expected 50 to 1200", text))?;
+            }
+            "--code-page" => parsed.code_page = parse_code_page(value()?)?,
+            "--columns" => parsed.columns = parse_columns(value()?)?,
+            "--code-page-file" => load_code_page(value()?, &mut parsed.custom_code_pages)?,
+            "--so-double-width" => parsed.so_double_width = true,
+            "--control-glyphs" => parsed.control_glyphs = true,
+            "--compare" => parsed.compare = Some(value()?.into()),
+            "--threshold" => {
+                let text = value()?;
+                parsed.threshold = text
+                    .parse()
+                    .ok()
+                    .filter(|t: &f64| (0.0..=1.0).contains(t))
+                    .ok_or_else(|| anyhow!("invalid threshold '{}', expected 0 to 1", text))?;
+            }
+            "--diff" => parsed.diff = Some(value()?.into()),
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    parsed.input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    if parsed.output.is_none() && parsed.compare.is_none() {
+        bail!("nothing to do: give -o and/or --compare\n{}", USAGE);
+    }
+    Ok(parsed)
+}
+
+fn parse_paper_size(text: &str) -> Result<PaperSize> {
+    match text {
+        "58mm" => Ok(PaperSize::Size58mm),
+        "80mm" => Ok(PaperSize::Size80mm),
+        other => bail!("unknown paper size '{}', expected 58mm or 80mm", other),
+    }
+}
+
+fn parse_code_page(text: &str) -> Result<u8> {
+    text.parse()
+        .map_err(|_| anyhow!("invalid code page '{}', expected 0 to 255", text))
+}
+
+/// Load the mapping file of "<n>=<path>" as ESC t n
+fn load_code_page(text: &str, code_pages: &mut CustomCodePages) -> Result<()> {
+    let (number, path) = text
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected <n>=<mapping file>, got '{}'", text))?;
+    let number = parse_code_page(number)?;
+    let page = CustomCodePage::load(Path::new(path))?;
+    code_pages.insert(number, Arc::new(page));
+    Ok(())
+}
+
+fn render(args: &[String]) -> Result<i32> {
+    let args = parse_render_args(args)?;
+    let data =
+        std::fs::read(&args.input).with_context(|| format!("reading {}", args.input.display()))?;
+    let options = ParseOptions {
+        paper_size: args.paper_size,
+        code_page: args.code_page,
+        columns: args.columns,
+        custom_code_pages: args.custom_code_pages,
+        so_double_width: args.so_double_width,
+        control_glyphs: args.control_glyphs,
+        ..ParseOptions::default()
+    };
+    let job = parse_job(&data, &options)?;
+    for warning in &job.warnings {
+        eprintln!(
+            "warning: {} at byte {}: {}",
+            args.input.display(),
+            warning.offset,
+            warning.message
+        );
+    }
+    let image = render::render_elements_to_image(&job.elements, args.paper_size, args.dpi);
+
+    if let Some(output) = &args.output {
+        image
+            .save(output)
+            .with_context(|| format!("writing {}", output.display()))?;
+    }
+
+    let Some(golden) = &args.compare else {
+        return Ok(0);
+    };
+    let expected = image::open(golden)
+        .with_context(|| format!("reading {}", golden.display()))?
+        .to_rgba8();
+    let (difference, diff_image) = render::compare(&image, &expected);
+    if let Some(diff) = &args.diff {
+        diff_image
+            .save(diff)
+            .with_context(|| format!("writing {}", diff.display()))?;
+    }
+    if image.dimensions() != expected.dimensions() {
+        println!(
+            "{}: size {}x{} differs from {} ({}x{})",
+            args.input.display(),
+            image.width(),
+            image.height(),
+            golden.display(),
+            expected.width(),
+            expected.height()
+        );
+        return Ok(1);
+    }
+    if difference > args.threshold {
+        println!(
+            "{}: {:.4}% of pixels differ from {} (threshold {:.4}%)",
+            args.input.display(),
+            difference * 100.0,
+            golden.display(),
+            args.threshold * 100.0
+        );
+        return Ok(1);
+    }
+    println!("{}: matches {}", args.input.display(), golden.display());
+    Ok(0)
+}
+
+/// Send the demo jobs to a printer, one connection each, or write them to a
+/// file with -o
+fn demo(args: &[String]) -> Result<i32> {
+    let mut host = DEFAULT_DEMO_HOST.to_string();
+    let mut output: Option<PathBuf> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--host" => host = value()?.clone(),
+            "-o" | "--output" => output = Some(value()?.into()),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let jobs = demo::jobs();
+    if let Some(output) = &output {
+        let data: Vec<u8> = jobs.iter().flat_map(|job| job.data.clone()).collect();
+        std::fs::write(output, data).with_context(|| format!("writing {}", output.display()))?;
+        println!("Wrote {} demo jobs to {}", jobs.len(), output.display());
+        return Ok(0);
+    }
+    for job in &jobs {
+        let mut stream =
+            TcpStream::connect(&host).with_context(|| format!("connecting to {}", host))?;
+        stream
+            .write_all(&job.data)
+            .with_context(|| format!("sending {} to {}", job.name, host))?;
+        println!("Sent {} ({} bytes)", job.name, job.data.len());
+    }
+    Ok(0)
+}
+
+/// Parse a capture repeatedly and report throughput, then the commands that
+/// took the most time
+fn bench(args: &[String]) -> Result<i32> {
+    let mut input = None;
+    let mut runs = DEFAULT_BENCH_RUNS;
+    let mut min_mbps = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--runs" => {
+                let text = value()?;
+                runs = text
+                    .parse()
+                    .ok()
+                    .filter(|&runs| runs > 0)
+                    .ok_or_else(|| anyhow!("invalid run count '{}'", text))?;
+            }
+            "--min-mbps" => {
+                let text = value()?;
+                min_mbps = Some(
+                    text.parse::<f64>()
+                        .map_err(|_| anyhow!("invalid throughput '{}'", text))?,
+                );
+            }
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    let data = std::fs::read(&input).with_context(|| format!("reading {}", input.display()))?;
+    let options = ParseOptions::default();
+
+    // Once untimed, so the first timed run doesn't pay for warming up
+    parse_job(&data, &options)?;
+    let started = Instant::now();
+    for _ in 0..runs {
+        parse_job(&data, &options)?;
+    }
+    let per_run = started.elapsed() / runs;
+
+    // Timing each command slows parsing down, so it gets runs of its own
+    let started = Instant::now();
+    let mut profile = profile_job(&data, &options)?;
+    for _ in 1..runs {
+        for (name, (count, time)) in profile_job(&data, &options)? {
+            let entry = profile.entry(name).or_default();
+            entry.0 += count;
+            entry.1 += time;
+        }
+    }
+    let profiled_run = started.elapsed() / runs;
+    let commands = profile.values().map(|(count, _)| count).sum::<usize>() / runs as usize;
+    let seconds = per_run.as_secs_f64().max(1e-9);
+    let mbps = data.len() as f64 / seconds / 1_000_000.0;
+    println!(
+        "{}: {} bytes, {} commands",
+        input.display(),
+        data.len(),
+        commands
+    );
+    println!(
+        "  {} runs, {:.3} ms each: {:.1} MB/s, {:.0} commands/s",
+        runs,
+        per_run.as_secs_f64() * 1000.0,
+        mbps,
+        commands as f64 / seconds
+    );
+
+    let mut slowest: Vec<(String, usize, Duration)> = profile
+        .into_iter()
+        .map(|(name, (count, time))| (name, count / runs as usize, time / runs))
+        .collect();
+    slowest.sort_by_key(|&(_, _, time)| std::cmp::Reverse(time));
+    let total: Duration = slowest.iter().map(|(_, _, time)| *time).sum();
+    let profiled = profiled_run.max(total).as_secs_f64().max(1e-9);
+    println!(
+        "Slowest commands (time per run; {:.0}% of the time goes to text and \
+         control characters):",
+        (profiled - total.as_secs_f64()) / profiled * 100.0
+    );
+    for (name, count, time) in slowest.iter().take(BENCH_SLOWEST) {
+        println!(
+            "  {:<10} {:>7} x {:>10.3} ms  {:>9.2} us each",
+            name,
+            count,
+            time.as_secs_f64() * 1000.0,
+            time.as_secs_f64() * 1_000_000.0 / (*count).max(1) as f64
+        );
+    }
+
+    match min_mbps {
+        Some(min) if mbps < min => {
+            println!("{:.1} MB/s is below the minimum of {:.1} MB/s", mbps, min);
+            Ok(1)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Write which bytes of a job were which commands and produced which
+/// elements
+fn trace(args: &[String]) -> Result<i32> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut options = ParseOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.into()),
+            "--paper" => options.paper_size = parse_paper_size(value()?)?,
+            "--code-page" => options.code_page = parse_code_page(value()?)?,
+            "--columns" => options.columns = parse_columns(value()?)?,
+            "--code-page-file" => load_code_page(value()?, &mut options.custom_code_pages)?,
+            "--so-double-width" => options.so_double_width = true,
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    let data = std::fs::read(&input).with_context(|| format!("reading {}", input.display()))?;
+    let json = serde_json::to_vec_pretty(&trace_job(&data, &options)?)?;
+    match &output {
+        Some(output) => {
+            std::fs::write(output, json).with_context(|| format!("writing {}", output.display()))?
+        }
+        None => std::io::stdout().write_all(&json)?,
+    }
+    Ok(0)
+}
+
+/// Write a job's commands as disassembly text
+fn disasm(args: &[String]) -> Result<i32> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut options = ParseOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.into()),
+            "--paper" => options.paper_size = parse_paper_size(value()?)?,
+            "--code-page" => options.code_page = parse_code_page(value()?)?,
+            "--columns" => options.columns = parse_columns(value()?)?,
+            "--code-page-file" => load_code_page(value()?, &mut options.custom_code_pages)?,
+            "--so-double-width" => options.so_double_width = true,
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    let data = std::fs::read(&input).with_context(|| format!("reading {}", input.display()))?;
+    let warnings = parse_job(&data, &options)?.warnings;
+    let text = disassembly::disassemble(&input.display().to_string(), &data, &warnings, &options)?;
+    match &output {
+        Some(output) => {
+            std::fs::write(output, text).with_context(|| format!("writing {}", output.display()))?
+        }
+        None => std::io::stdout().write_all(text.as_bytes())?,
+    }
+    Ok(0)
+}
+
+/// Turn disassembly text back into a job, written to a file or sent to a
+/// printer
+fn asm(args: &[String]) -> Result<i32> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut host = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--host" => host = Some(value()?.clone()),
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    if output.is_none() && host.is_none() {
+        bail!("asm needs -o, --host or both\n{}", USAGE);
+    }
+    let text =
+        std::fs::read_to_string(&input).with_context(|| format!("reading {}", input.display()))?;
+    let data = disassembly::assemble(&text).with_context(|| input.display().to_string())?;
+    if let Some(output) = &output {
+        std::fs::write(output, &data).with_context(|| format!("writing {}", output.display()))?;
+        println!("Wrote {} bytes to {}", data.len(), output.display());
+    }
+    if let Some(host) = &host {
+        let mut stream =
+            TcpStream::connect(host).with_context(|| format!("connecting to {}", host))?;
+        stream
+            .write_all(&data)
+            .with_context(|| format!("sending to {}", host))?;
+        println!("Sent {} bytes to {}", data.len(), host);
+    }
+    Ok(0)
+}
+
+/// Render a job and write it printing out as an animated GIF
+fn animate(args: &[String]) -> Result<i32> {
+    let mut input = None;
+    let mut output: Option<PathBuf> = None;
+    let mut options = ParseOptions::default();
+    let mut speed = animation::DEFAULT_SPEED;
+    let mut fps = animation::DEFAULT_FPS;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.into()),
+            "--paper" => options.paper_size = parse_paper_size(value()?)?,
+            "--code-page" => options.code_page = parse_code_page(value()?)?,
+            "--columns" => options.columns = parse_columns(value()?)?,
+            "--code-page-file" => load_code_page(value()?, &mut options.custom_code_pages)?,
+            "--so-double-width" => options.so_double_width = true,
+            "--speed" => {
+                let text = value()?;
+                speed = text
+                    .parse()
+                    .ok()
+                    .filter(|speed| (1.0..=1000.0).contains(speed))
+                    .ok_or_else(|| anyhow!("invalid speed '{}', expected 1 to 1000 mm/s", text))?;
+            }
+            "--fps" => {
+                let text = value()?;
+                fps = text
+                    .parse()
+                    .ok()
+                    .filter(|fps| (1..=50).contains(fps))
+                    .ok_or_else(|| anyhow!("invalid frame rate '{}', expected 1 to 50", text))?;
+            }
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    let input = input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?;
+    let output = output.ok_or_else(|| anyhow!("missing -o <out.gif>\n{}", USAGE))?;
+    let data = std::fs::read(&input).with_context(|| format!("reading {}", input.display()))?;
+    let job = parse_job(&data, &options)?;
+    let image = render::render_elements_to_image(&job.elements, options.paper_size, DOTS_PER_INCH);
+    let file =
+        std::fs::File::create(&output).with_context(|| format!("writing {}", output.display()))?;
+    let frames = animation::write_gif(
+        &image,
+        DOTS_PER_INCH,
+        speed,
+        fps,
+        std::io::BufWriter::new(file),
+    )
+    .with_context(|| format!("writing {}", output.display()))?;
+    println!(
+        "{}: {} frames, {:.1} s of printing",
+        output.display(),
+        frames,
+        f64::from(image.height()) / f64::from(DOTS_PER_INCH) * 25.4 / speed
+    );
+    Ok(0)
+}
+
+/// Render jobs into one PDF, a page each
+fn pdf(args: &[String]) -> Result<i32> {
+    let mut inputs = Vec::new();
+    let mut output: Option<PathBuf> = None;
+    let mut options = ParseOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.into()),
+            "--paper" => options.paper_size = parse_paper_size(value()?)?,
+            "--code-page" => options.code_page = parse_code_page(value()?)?,
+            "--columns" => options.columns = parse_columns(value()?)?,
+            "--code-page-file" => load_code_page(value()?, &mut options.custom_code_pages)?,
+            "--so-double-width" => options.so_double_width = true,
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        bail!("missing input file\n{}", USAGE);
+    }
+    let output = output.ok_or_else(|| anyhow!("missing -o <out.pdf>\n{}", USAGE))?;
+    let mut pages = Vec::new();
+    for input in &inputs {
+        let data = std::fs::read(input).with_context(|| format!("reading {}", input.display()))?;
+        let job = parse_job(&data, &options)?;
+        let name = input.file_name().unwrap_or(input.as_os_str());
+        let header = [
+            name.to_string_lossy().into_owned(),
+            format!("{} bytes, {} warning(s)", data.len(), job.warnings.len()),
+        ];
+        pages.extend(pdf::receipt_pages(
+            &header,
+            &job.elements,
+            options.paper_size,
+        ));
+    }
+    let pdf = pdf::write_pdf(&pages, DOTS_PER_INCH)?;
+    std::fs::write(&output, pdf).with_context(|| format!("writing {}", output.display()))?;
+    println!("{}: {} page(s)", output.display(), pages.len());
+    Ok(0)
+}
+
+struct WatchArgs {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    paper_size: PaperSize,
+}
+
+fn parse_watch_args(args: &[String]) -> Result<WatchArgs> {
+    let mut input = None;
+    let mut output = None;
+    let mut paper_size = PaperSize::Size80mm;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.into()),
+            "--paper" => paper_size = parse_paper_size(value()?)?,
+            _ if arg.starts_with('-') => bail!("unknown option '{}'\n{}", arg, USAGE),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{}'\n{}", arg, USAGE),
+        }
+    }
+    Ok(WatchArgs {
+        input: input.ok_or_else(|| anyhow!("missing input file\n{}", USAGE))?,
+        output,
+        paper_size,
+    })
+}
+
+/// The file and paper size `args` ask to watch in the window: `watch`
+/// without -o. Anything else, bad arguments included, is left to `run`.
+pub fn window_watch(args: &[String]) -> Option<(PathBuf, PaperSize)> {
+    if args.first().map(String::as_str) != Some("watch") {
+        return None;
+    }
+    let args = parse_watch_args(&args[1..]).ok()?;
+    match args.output {
+        Some(_) => None,
+        None => Some((args.input, args.paper_size)),
+    }
+}
+
+/// Call `changed` with the file's contents now and after every change to
+/// it; never returns. A missing file is waited for.
+pub fn watch_file(path: &Path, mut changed: impl FnMut(Vec<u8>)) -> ! {
+    let mut last = None;
+    loop {
+        let modified = std::fs::metadata(path)
+            .ok()
+            .map(|meta| (meta.modified().ok(), meta.len()));
+        if modified.is_some() && modified != last {
+            last = modified;
+            match std::fs::read(path) {
+                Ok(data) => changed(data),
+                Err(e) => eprintln!("ERROR: reading {}: {}", path.display(), e),
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Render the file to -o again whenever it changes
+fn watch(args: &[String]) -> Result<i32> {
+    let args = parse_watch_args(args)?;
+    let Some(output) = args.output else {
+        bail!("watch without -o shows the file in the window\n{}", USAGE);
+    };
+    let options = ParseOptions {
+        paper_size: args.paper_size,
+        ..ParseOptions::default()
+    };
+    println!("Watching {}, Ctrl+C to stop", args.input.display());
+    watch_file(&args.input, |data| {
+        let result = parse_job(&data, &options).and_then(|job| {
+            for warning in &job.warnings {
+                eprintln!("warning: at byte {}: {}", warning.offset, warning.message);
+            }
+            let image =
+                render::render_elements_to_image(&job.elements, args.paper_size, DOTS_PER_INCH);
+            image
+                .save(&output)
+                .with_context(|| format!("writing {}", output.display()))
+        });
+        match result {
+            Ok(()) => println!(
+                "Rendered {} ({} bytes) to {}",
+                args.input.display(),
+                data.len(),
+                output.display()
+            ),
+            Err(e) => eprintln!("ERROR: {:#}", e),
+        }
+    })
+}
@@ -0,0 +1,193 @@
+// Single-byte code pages that encoding_rs doesn't provide.
+// Bytes below 0x80 are ASCII; the tables cover 0x80-0xFF.
+//
+// Clone printers often ship proprietary tables under some ESC t number;
+// those are loaded from mapping files in the format of the unicode.org
+// tables, one byte per line with its character and an optional comment:
+//
+//   0x80    0x00C7  # LATIN CAPITAL LETTER C WITH CEDILLA
+//   0x81            # undefined
+//
+// Bytes below 0x80 are ignored, as they print ASCII; bytes left out or
+// undefined decode to U+FFFD.
+//
+// A client that selects the wrong code page can be given the right one
+// instead: its ESC t commands are ignored, as if the printer's DIP switches
+// had been set for it, with rules by client address or network:
+//
+//   192.168.1.20=16, 10.0.0.0/8=2
+
+use crate::access::Network;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A code page loaded from a mapping file
+#[derive(Debug)]
+pub struct CustomCodePage {
+    pub path: PathBuf,
+    pub table: [char; 128],
+}
+
+impl CustomCodePage {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let table = parse_mapping(&text).with_context(|| format!("in {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            table,
+        })
+    }
+
+    /// File name, for display
+    pub fn name(&self) -> String {
+        self.path.file_name().map_or_else(
+            || self.path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    }
+}
+
+/// Custom code pages by ESC t number, taking precedence over the built-in
+/// ones
+pub type CustomCodePages = BTreeMap<u8, Arc<CustomCodePage>>;
+
+/// The code page clients in a network print with, whatever ESC t they send
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForcedCodePage {
+    pub network: Network,
+    pub code_page: u8,
+}
+
+/// Comma- or whitespace-separated network=code page rules
+pub fn parse_forced_code_pages(text: &str) -> Result<Vec<ForcedCodePage>> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (network, code_page) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("'{}' isn't network=code page", entry))?;
+            let code_page = code_page
+                .parse()
+                .map_err(|_| anyhow!("invalid code page in '{}'", entry))?;
+            Ok(ForcedCodePage {
+                network: network.parse()?,
+                code_page,
+            })
+        })
+        .collect()
+}
+
+pub fn format_forced_code_pages(rules: &[ForcedCodePage]) -> String {
+    rules
+        .iter()
+        .map(|rule| format!("{}={}", rule.network, rule.code_page))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The code page forced for a job's source, by the first rule matching its
+/// address; sources that aren't clients (files, the composer) have none
+pub fn forced_code_page(rules: &[ForcedCodePage], source: &str) -> Option<u8> {
+    let addr: IpAddr = source.parse::<SocketAddr>().ok()?.ip();
+    rules
+        .iter()
+        .find(|rule| rule.network.contains(addr))
+        .map(|rule| rule.code_page)
+}
+
+/// The high half of a code page from a mapping file
+fn parse_mapping(text: &str) -> Result<[char; 128]> {
+    let mut table = [char::REPLACEMENT_CHARACTER; 128];
+    let mut mapped = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(byte) = fields.next() else {
+            continue;
+        };
+        let byte = hex(byte)
+            .and_then(|byte| u8::try_from(byte).ok())
+            .with_context(|| format!("line {}: invalid byte '{}'", number + 1, byte))?;
+        let Some(character) = fields.next() else {
+            // Undefined
+            continue;
+        };
+        let character = hex(character)
+            .and_then(char::from_u32)
+            .with_context(|| format!("line {}: invalid character '{}'", number + 1, character))?;
+        if byte >= 0x80 {
+            table[usize::from(byte - 0x80)] = character;
+            mapped += 1;
+        }
+    }
+    if mapped == 0 {
+        bail!("no mappings for bytes 0x80 to 0xFF");
+    }
+    Ok(table)
+}
+
+/// A hexadecimal number written 0x00C7 or U+00C7
+fn hex(text: &str) -> Option<u32> {
+    let digits = ["0x", "0X", "U+", "u+"]
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix))?;
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Decode bytes with one of the tables below
+pub fn decode(table: &[char; 128], bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b < 0x80 {
+                b as char
+            } else {
+                table[(b - 0x80) as usize]
+            }
+        })
+        .collect()
+}
+
+/// PC862 (Hebrew): Hebrew letters at 0x80-0x9A, the rest as PC437
+pub static CP862: [char; 128] = [
+    '\u{05D0}', '\u{05D1}', '\u{05D2}', '\u{05D3}', '\u{05D4}', '\u{05D5}', '\u{05D6}', '\u{05D7}',
+    '\u{05D8}', '\u{05D9}', '\u{05DA}', '\u{05DB}', '\u{05DC}', '\u{05DD}', '\u{05DE}', '\u{05DF}',
+    '\u{05E0}', '\u{05E1}', '\u{05E2}', '\u{05E3}', '\u{05E4}', '\u{05E5}', '\u{05E6}', '\u{05E7}',
+    '\u{05E8}', '\u{05E9}', '\u{05EA}', '\u{00A2}', '\u{00A3}', '\u{00A5}', '\u{20A7}', '\u{0192}',
+    '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}', '\u{00F1}', '\u{00D1}', '\u{00AA}', '\u{00BA}',
+    '\u{00BF}', '\u{2310}', '\u{00AC}', '\u{00BD}', '\u{00BC}', '\u{00A1}', '\u{00AB}', '\u{00BB}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{2561}', '\u{2562}', '\u{2556}',
+    '\u{2555}', '\u{2563}', '\u{2551}', '\u{2557}', '\u{255D}', '\u{255C}', '\u{255B}', '\u{2510}',
+    '\u{2514}', '\u{2534}', '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{255E}', '\u{255F}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}', '\u{256C}', '\u{2567}',
+    '\u{2568}', '\u{2564}', '\u{2565}', '\u{2559}', '\u{2558}', '\u{2552}', '\u{2553}', '\u{256B}',
+    '\u{256A}', '\u{2518}', '\u{250C}', '\u{2588}', '\u{2584}', '\u{258C}', '\u{2590}', '\u{2580}',
+    '\u{03B1}', '\u{00DF}', '\u{0393}', '\u{03C0}', '\u{03A3}', '\u{03C3}', '\u{00B5}', '\u{03C4}',
+    '\u{03A6}', '\u{0398}', '\u{03A9}', '\u{03B4}', '\u{221E}', '\u{03C6}', '\u{03B5}', '\u{2229}',
+    '\u{2261}', '\u{00B1}', '\u{2265}', '\u{2264}', '\u{2320}', '\u{2321}', '\u{00F7}', '\u{2248}',
+    '\u{00B0}', '\u{2219}', '\u{00B7}', '\u{221A}', '\u{207F}', '\u{00B2}', '\u{25A0}', '\u{00A0}',
+];
+
+/// PC864 (Arabic): letters are stored as presentation forms, already shaped
+pub static CP864: [char; 128] = [
+    '\u{00B0}', '\u{00B7}', '\u{2219}', '\u{221A}', '\u{2592}', '\u{2500}', '\u{2502}', '\u{253C}',
+    '\u{2524}', '\u{252C}', '\u{251C}', '\u{2534}', '\u{2510}', '\u{250C}', '\u{2514}', '\u{2518}',
+    '\u{03B2}', '\u{221E}', '\u{03C6}', '\u{00B1}', '\u{00BD}', '\u{00BC}', '\u{2248}', '\u{00AB}',
+    '\u{00BB}', '\u{FEF7}', '\u{FEF8}', '\u{FFFD}', '\u{FFFD}', '\u{FEFB}', '\u{FEFC}', '\u{FFFD}',
+    '\u{00A0}', '\u{00AD}', '\u{FE82}', '\u{00A3}', '\u{00A4}', '\u{FE84}', '\u{FFFD}', '\u{FFFD}',
+    '\u{FE8E}', '\u{FE8F}', '\u{FE95}', '\u{FE99}', '\u{060C}', '\u{FE9D}', '\u{FEA1}', '\u{FEA5}',
+    '\u{0660}', '\u{0661}', '\u{0662}', '\u{0663}', '\u{0664}', '\u{0665}', '\u{0666}', '\u{0667}',
+    '\u{0668}', '\u{0669}', '\u{FED1}', '\u{061B}', '\u{FEB1}', '\u{FEB5}', '\u{FEB9}', '\u{061F}',
+    '\u{00A2}', '\u{FE80}', '\u{FE81}', '\u{FE83}', '\u{FE85}', '\u{FECA}', '\u{FE8B}', '\u{FE8D}',
+    '\u{FE91}', '\u{FE93}', '\u{FE97}', '\u{FE9B}', '\u{FE9F}', '\u{FEA3}', '\u{FEA7}', '\u{FEA9}',
+    '\u{FEAB}', '\u{FEAD}', '\u{FEAF}', '\u{FEB3}', '\u{FEB7}', '\u{FEBB}', '\u{FEBF}', '\u{FEC1}',
+    '\u{FEC5}', '\u{FECB}', '\u{FECF}', '\u{00A6}', '\u{00AC}', '\u{00F7}', '\u{00D7}', '\u{FEC9}',
+    '\u{0640}', '\u{FED3}', '\u{FED7}', '\u{FEDB}', '\u{FEDF}', '\u{FEE3}', '\u{FEE7}', '\u{FEEB}',
+    '\u{FEED}', '\u{FEEF}', '\u{FEF3}', '\u{FEBD}', '\u{FECC}', '\u{FECE}', '\u{FECD}', '\u{FEE1}',
+    '\u{FE7D}', '\u{0651}', '\u{FEE5}', '\u{FEE9}', '\u{FEEC}', '\u{FEF0}', '\u{FEF2}', '\u{FED0}',
+    '\u{FED5}', '\u{FEF5}', '\u{FEF6}', '\u{FEDD}', '\u{FED9}', '\u{FEF1}', '\u{25A0}', '\u{FFFD}',
+];
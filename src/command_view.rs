@@ -0,0 +1,142 @@
+// Command view: the receipt laid out again from the recorded jobs, with the
+// commands of each job drawn as small colored chips ([ESC a 1], [GS V 0])
+// above the elements they precede, so it's clear which commands shaped
+// which output. Line feeds and carriage returns get no chip; the lines they
+// print show where they were.
+
+use crate::{
+    command_name, trace_job, Job, ParseOptions, ReceiptElement, CR, DLE, ESC, FF, FS, GS, HT, LF,
+};
+use eframe::egui;
+
+/// Parameter bytes shown in a chip; longer commands show their length
+const CHIP_PARAMS: usize = 3;
+
+/// A command as shown in the view
+pub struct Chip {
+    pub label: String,
+    /// The command's bytes, for the chip's tooltip
+    pub bytes: Vec<u8>,
+}
+
+pub enum ViewItem {
+    /// Commands in the order they were received
+    Chips(Vec<Chip>),
+    Elements(Vec<ReceiptElement>),
+}
+
+/// Parse every job again, tracing it, and lay out its elements with the
+/// commands between them. Unless receipts are merged each job starts a
+/// receipt of its own.
+pub fn build(jobs: &[Job], options: &ParseOptions, merge: bool) -> Vec<ViewItem> {
+    let mut items = Vec::new();
+    for job in jobs {
+        let Ok(trace) = trace_job(&job.raw, &options.for_source(&job.source)) else {
+            continue;
+        };
+        let mut elements: Vec<Option<ReceiptElement>> = trace
+            .elements
+            .into_iter()
+            .map(|traced| Some(traced.element))
+            .collect();
+        if !merge {
+            items.push(ViewItem::Elements(vec![ReceiptElement::ReceiptStart {
+                id: job.id,
+                source: job.source.clone(),
+            }]));
+        }
+        let mut chips = Vec::new();
+        for step in &trace.steps {
+            let bytes = &job.raw[step.start as usize..step.end as usize];
+            let chip = match bytes.first() {
+                Some(&(LF | CR)) | None => false,
+                Some(&byte) => byte < 0x20 || byte == 0x7F,
+            };
+            if chip {
+                chips.push(Chip {
+                    label: chip_label(bytes),
+                    bytes: bytes.to_vec(),
+                });
+            }
+            if step.elements.is_empty() {
+                continue;
+            }
+            if !chips.is_empty() {
+                items.push(ViewItem::Chips(std::mem::take(&mut chips)));
+            }
+            items.push(ViewItem::Elements(
+                step.elements
+                    .iter()
+                    .filter_map(|&index| elements.get_mut(index)?.take())
+                    .collect(),
+            ));
+        }
+        if !chips.is_empty() {
+            items.push(ViewItem::Chips(chips));
+        }
+    }
+    items
+}
+
+/// Command name and its parameters, like "ESC a 1" or "GS ( k +27 bytes"
+fn chip_label(bytes: &[u8]) -> String {
+    let mut name = match bytes.first() {
+        Some(&(ESC | GS | FS | DLE)) => command_name(bytes),
+        Some(&HT) => return "HT".to_string(),
+        Some(&FF) => return "FF".to_string(),
+        Some(byte) => return format!("0x{:02X}", byte),
+        None => return String::new(),
+    };
+    let mut params = bytes.get(2..).unwrap_or_default();
+    // GS ( x and FS ( x: a function letter, then a two-byte length
+    if bytes.get(1) == Some(&b'(') {
+        if let Some(&function) = params.first() {
+            name.push(' ');
+            name.push(function as char);
+            params = params.get(3..).unwrap_or_default();
+        }
+    }
+    match params.len() {
+        0 => name,
+        n if n <= CHIP_PARAMS => {
+            let params: Vec<String> = params.iter().map(u8::to_string).collect();
+            format!("{} {}", name, params.join(" "))
+        }
+        n => format!("{} +{} bytes", name, n),
+    }
+}
+
+/// Chip colors by command family, so ESC and GS commands tell apart at a
+/// glance
+fn chip_color(bytes: &[u8]) -> egui::Color32 {
+    match bytes.first() {
+        Some(&ESC) => egui::Color32::from_rgb(60, 110, 200),
+        Some(&GS) => egui::Color32::from_rgb(30, 140, 90),
+        Some(&FS) => egui::Color32::from_rgb(130, 80, 180),
+        Some(&DLE) => egui::Color32::from_rgb(210, 110, 20),
+        _ => egui::Color32::GRAY,
+    }
+}
+
+/// One row of chips, wrapped at the paper width
+pub fn show_chips(ui: &mut egui::Ui, chips: &[Chip]) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing = egui::vec2(3.0, 2.0);
+        for chip in chips {
+            egui::Frame::none()
+                .fill(chip_color(&chip.bytes))
+                .rounding(3.0)
+                .inner_margin(egui::Margin::symmetric(3.0, 0.0))
+                .show(ui, |ui| {
+                    ui.label(
+                        egui::RichText::new(&chip.label)
+                            .small()
+                            .monospace()
+                            .color(egui::Color32::WHITE),
+                    )
+                })
+                .response
+                .on_hover_text(format!("{:02X?}", &chip.bytes[..chip.bytes.len().min(48)]));
+        }
+    });
+}
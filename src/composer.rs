@@ -0,0 +1,168 @@
+// Command composer: ESC/POS sequences written by hand (or picked from the
+// palette) and printed directly, to try out formatting without a client.
+//
+// Sequences are whitespace-separated tokens:
+//
+//   1B 40          bytes as two hex digits (0x1B also works)
+//   ESC GS LF ...  control character names
+//   E a !          a single character stands for itself
+//   "Hello\n"      text, with \n \r \t \" \\ and \xNN escapes
+//   // comment     to the end of the line
+//
+// so `ESC a 01 "Centered" LF` is the same as `1B 61 01 43 65 6E ...`.
+
+use anyhow::{anyhow, bail, Result};
+
+/// Palette entries: (group, name, sequence)
+pub const PALETTE: &[(&str, &str, &str)] = &[
+    ("Setup", "Initialize", "ESC @"),
+    ("Setup", "Code page (WPC1252)", "ESC t 10"),
+    ("Text", "Text line", "\"Hello, world\" LF"),
+    ("Text", "Bold on / off", "ESC E 01 \"Bold\" ESC E 00 LF"),
+    (
+        "Text",
+        "Underline on / off",
+        "ESC - 01 \"Underlined\" ESC - 00 LF",
+    ),
+    ("Text", "Double size", "GS ! 11 \"Double\" GS ! 00 LF"),
+    ("Text", "Inverted", "GS B 01 \" Inverted \" GS B 00 LF"),
+    ("Text", "Font B", "ESC M 01 \"Font B\" ESC M 00 LF"),
+    ("Layout", "Center", "ESC a 01"),
+    ("Layout", "Right", "ESC a 02"),
+    ("Layout", "Left", "ESC a 00"),
+    ("Layout", "Line spacing 40 dots", "ESC 3 28"),
+    ("Layout", "Default line spacing", "ESC 2"),
+    ("Layout", "Feed 3 lines", "ESC d 03"),
+    (
+        "Codes",
+        "QR code",
+        "GS ( k 04 00 31 41 32 00  // model 2\n\
+         GS ( k 03 00 31 43 06  // module size\n\
+         GS ( k 08 00 31 50 30 \"HELLO\"  // store\n\
+         GS ( k 03 00 31 51 30 LF  // print",
+    ),
+    (
+        "Codes",
+        "Barcode (CODE128)",
+        "GS h 50 GS H 02 GS k 49 08 \"{BABC123\" LF",
+    ),
+    ("Paper", "Partial cut after feed", "GS V 42 03"),
+    ("Paper", "Full cut", "GS V 00"),
+    ("Paper", "Open drawer (pin 2)", "ESC p 00 19 FA"),
+];
+
+/// Control characters by name
+pub const NAMES: &[(&str, u8)] = &[
+    ("NUL", 0x00),
+    ("SOH", 0x01),
+    ("STX", 0x02),
+    ("ETX", 0x03),
+    ("EOT", 0x04),
+    ("ENQ", 0x05),
+    ("ACK", 0x06),
+    ("BEL", 0x07),
+    ("BS", 0x08),
+    ("HT", 0x09),
+    ("LF", 0x0A),
+    ("VT", 0x0B),
+    ("FF", 0x0C),
+    ("CR", 0x0D),
+    ("SO", 0x0E),
+    ("SI", 0x0F),
+    ("DLE", 0x10),
+    ("DC1", 0x11),
+    ("DC2", 0x12),
+    ("DC3", 0x13),
+    ("DC4", 0x14),
+    ("NAK", 0x15),
+    ("SYN", 0x16),
+    ("ETB", 0x17),
+    ("CAN", 0x18),
+    ("EM", 0x19),
+    ("SUB", 0x1A),
+    ("ESC", 0x1B),
+    ("FS", 0x1C),
+    ("GS", 0x1D),
+    ("RS", 0x1E),
+    ("US", 0x1F),
+    ("DEL", 0x7F),
+];
+
+/// Bytes of a sequence, or the first token that isn't valid
+pub fn parse(source: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for line in source.lines() {
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            if rest.starts_with("//") {
+                break;
+            }
+            if let Some(quoted) = rest.strip_prefix('"') {
+                rest = parse_text(quoted, &mut bytes)?;
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                bytes.push(parse_token(&rest[..end])?);
+                rest = &rest[end..];
+            }
+            rest = rest.trim_start();
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_token(token: &str) -> Result<u8> {
+    if let Some(&(_, byte)) = NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(token))
+    {
+        return Ok(byte);
+    }
+    let mut chars = token.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii() {
+            return Ok(c as u8);
+        }
+    }
+    let hex = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .unwrap_or(token);
+    if hex.len() == 2 {
+        if let Ok(byte) = u8::from_str_radix(hex, 16) {
+            return Ok(byte);
+        }
+    }
+    bail!("'{}' isn't a hex byte, a name or a character", token)
+}
+
+/// Text up to the closing quote, returning what follows it
+fn parse_text<'a>(text: &'a str, bytes: &mut Vec<u8>) -> Result<&'a str> {
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok(&text[index + 1..]),
+            '\\' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('n') => b'\n',
+                    Some('r') => b'\r',
+                    Some('t') => b'\t',
+                    Some('"') => b'"',
+                    Some('\\') => b'\\',
+                    Some('x') => {
+                        let digits: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                        u8::from_str_radix(&digits, 16)
+                            .map_err(|_| anyhow!("invalid escape \\x{}", digits))?
+                    }
+                    Some(other) => bail!("unknown escape \\{}", other),
+                    None => bail!("text ends with a lone \\"),
+                };
+                bytes.push(escaped);
+            }
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    bail!("text \"{} isn't closed", text)
+}
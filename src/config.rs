@@ -0,0 +1,152 @@
+// Configuration file: settings that can change while escpresso runs, read
+// from the JSON file ESCPRESSO_CONFIG names at startup, after the environment
+// variables, and again whenever it is saved, so instances that are awkward to
+// restart mid test run can be reconfigured in place:
+//
+//   {
+//     "printer_status": {"profile": "ink", "code_page": 16, "columns": [42, 56, 0]},
+//     "paper_size": "58mm",
+//     "code_pages": {"255": "clone-table.txt"},
+//     "forced_code_pages": "192.168.1.20=16",
+//     "image_dump": "/tmp/images",               null turns it off
+//     "capture": "/tmp/captures",                null turns it off
+//     "capture_max_mb": 500,
+//     "archive": "/tmp/archive",
+//     "auto_clear": true,
+//     "events": "http://hooks.local/printer"     null stops publishing
+//   }
+//
+// Every setting is optional. Only the settings whose value changed since the
+// file was last read are applied, so edits made meanwhile in the window or
+// through the admin API stay unless the file changes them too; removing a
+// setting keeps its current value. "printer_status" changes only the fields
+// it has, like PUT /api/printer-status. A setting that can't be applied is
+// reported and skipped, the others still apply.
+
+use crate::{cli, codepages, AppState, RawCapture};
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub struct Config {
+    path: PathBuf,
+    /// The settings as last read
+    applied: Map<String, Value>,
+}
+
+impl Config {
+    /// Apply the file's settings, if it can be read yet
+    pub fn load(state: &AppState, path: PathBuf) -> Self {
+        let mut config = Self {
+            path,
+            applied: Map::new(),
+        };
+        match std::fs::read(&config.path) {
+            Ok(data) => config.apply(state, &data),
+            Err(e) => eprintln!("ERROR: reading {}: {}", config.path.display(), e),
+        }
+        config
+    }
+
+    /// Apply the file's settings again whenever it changes; never returns
+    pub fn watch(mut self, state: AppState) -> ! {
+        let path = self.path.clone();
+        println!("Watching {} for settings", path.display());
+        cli::watch_file(&path, |data| self.apply(&state, &data))
+    }
+
+    fn apply(&mut self, state: &AppState, data: &[u8]) {
+        let settings: Map<String, Value> = match serde_json::from_slice(data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!(
+                    "ERROR: Ignoring {}: expected a JSON object: {}",
+                    self.path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        let mut changed = 0;
+        // In name order, whatever order the file has them in
+        let mut sorted: Vec<_> = settings.iter().collect();
+        sorted.sort_by_key(|(name, _)| *name);
+        for (name, value) in sorted {
+            if self.applied.get(name) == Some(value) {
+                continue;
+            }
+            match apply_setting(state, name, value.clone()) {
+                Ok(()) => changed += 1,
+                Err(e) => eprintln!(
+                    "ERROR: Ignoring {} in {}: {:#}",
+                    name,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        self.applied = settings;
+        if changed > 0 {
+            println!(
+                "Applied {} setting(s) from {}",
+                changed,
+                self.path.display()
+            );
+            state.request_repaint();
+        }
+    }
+}
+
+fn apply_setting(state: &AppState, name: &str, value: Value) -> Result<()> {
+    match name {
+        "printer_status" => {
+            let status = state
+                .simulated_status
+                .borrow()
+                .merged(serde_json::from_value(value)?)
+                .map_err(anyhow::Error::msg)?;
+            state.replace_simulated_status(status);
+        }
+        "paper_size" => state.set_paper_size(serde_json::from_value(value)?),
+        "code_pages" => {
+            let pages: BTreeMap<u8, PathBuf> = serde_json::from_value(value)?;
+            for (number, path) in pages {
+                state.load_code_page(number, &path)?;
+            }
+            state.rerender_receipt();
+        }
+        "forced_code_pages" => {
+            let text: String = serde_json::from_value(value)?;
+            *state.forced_code_pages.lock().unwrap() = codepages::parse_forced_code_pages(&text)?;
+            state.rerender_receipt();
+        }
+        "image_dump" => *state.image_dump.lock().unwrap() = serde_json::from_value(value)?,
+        "capture" => {
+            let dir: Option<PathBuf> = serde_json::from_value(value)?;
+            let mut capture = state.capture.lock().unwrap();
+            match dir {
+                Some(dir) => capture.get_or_insert_with(RawCapture::default).dir = dir,
+                None => *capture = None,
+            }
+        }
+        // Applied in name order, so after "capture"
+        "capture_max_mb" => {
+            let max_mb = serde_json::from_value(value)?;
+            if let Some(capture) = state.capture.lock().unwrap().as_mut() {
+                capture.max_mb = max_mb;
+            }
+        }
+        "archive" => state.archive.lock().unwrap().dir = serde_json::from_value(value)?,
+        "auto_clear" => *state.auto_clear.lock().unwrap() = serde_json::from_value(value)?,
+        "events" => {
+            let target = match serde_json::from_value::<Option<String>>(value)? {
+                Some(url) => Some(url.parse()?),
+                None => None,
+            };
+            state.event_target.send_replace(target);
+        }
+        _ => bail!("unknown setting"),
+    }
+    Ok(())
+}
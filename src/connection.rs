@@ -0,0 +1,177 @@
+// What a connection sends, split into jobs. The bytes go through the parser
+// as they arrive; at each job boundary it reports (see JobBoundary) the
+// bytes, lint warnings and commands so far become a finished job, and the
+// next job starts with the rest. The TCP server and test_server are both
+// built on it:
+//
+//   let mut jobs = JobSplitter::default();
+//   jobs.receive(&mut renderer, &buffer[..n])?;
+//   socket.write_all(&renderer.take_responses()).await?;
+//   for event in jobs.take_events() { ... }
+//   ...
+//   jobs.finish(&mut renderer)?; // the connection closed
+
+use crate::lint::LintWarning;
+use crate::{unix_millis, EscPosRenderer, ReceiptElement};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// What the data received printed, in order
+#[derive(Debug)]
+pub enum JobEvent {
+    /// Elements printed by the open job
+    Elements(Vec<ReceiptElement>),
+    /// The open job ended; what follows belongs to the next
+    Finished(FinishedJob),
+}
+
+/// A job, once its boundary was reached
+#[derive(Debug)]
+pub struct FinishedJob {
+    pub raw: Vec<u8>,
+    /// At offsets into `raw`
+    pub warnings: Vec<LintWarning>,
+    /// When its first byte arrived (see unix_millis)
+    pub received_at: u64,
+    /// Each read: (milliseconds after received_at, bytes of the job by its end)
+    pub arrivals: Vec<(u64, usize)>,
+    /// Times each command was processed
+    pub commands: BTreeMap<String, usize>,
+    /// Elements it printed
+    pub elements: usize,
+}
+
+/// Splits a connection's data into jobs, for the renderer it's parsed with
+#[derive(Debug, Default)]
+pub struct JobSplitter {
+    // Connection byte offset of raw[0]
+    start: u64,
+    raw: Vec<u8>,
+    elements: usize,
+    // Offsets are into the connection's stream until the job is finished
+    warnings: Vec<LintWarning>,
+    received_at: Option<u64>,
+    commands: BTreeMap<String, usize>,
+    // Each read: (unix_millis, connection byte offset after it)
+    arrivals: Vec<(u64, u64)>,
+    events: Vec<JobEvent>,
+}
+
+impl JobSplitter {
+    /// Parse a read, splitting what it printed at the job boundaries in it.
+    /// What was parsed before an error is still split.
+    pub fn receive(&mut self, renderer: &mut EscPosRenderer, data: &[u8]) -> Result<()> {
+        self.raw.extend_from_slice(data);
+        self.received_at.get_or_insert_with(unix_millis);
+        self.arrivals
+            .push((unix_millis(), self.start + self.raw.len() as u64));
+        let result = renderer.process_data(data);
+        self.split(renderer);
+        result
+    }
+
+    /// End the open job, at an inactivity timeout or when the connection
+    /// closes. Text held back for more data and the line after the last
+    /// line feed print with it (see EscPosRenderer::end_job), even after an
+    /// error.
+    pub fn finish(&mut self, renderer: &mut EscPosRenderer) -> Result<()> {
+        let result = renderer.end_job();
+        self.split(renderer);
+        self.finish_job();
+        result
+    }
+
+    /// Events since the last call
+    pub fn take_events(&mut self) -> Vec<JobEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Whether the open job has received anything
+    pub fn job_started(&self) -> bool {
+        !self.raw.is_empty()
+    }
+
+    /// Bytes the connection has sent
+    pub fn bytes(&self) -> u64 {
+        self.start + self.raw.len() as u64
+    }
+
+    fn split(&mut self, renderer: &mut EscPosRenderer) {
+        let mut new_elements = renderer.take_elements();
+        let mut new_warnings = renderer.take_lint_warnings();
+        let mut new_commands = renderer.take_commands();
+        let mut taken = 0;
+        for (offset, element_index) in renderer.take_job_boundaries() {
+            // Bytes, elements and warnings after the boundary belong to the
+            // next job
+            let next_raw = self
+                .raw
+                .split_off(((offset - self.start) as usize).min(self.raw.len()));
+            let ended: Vec<_> = new_elements.drain(..element_index - taken).collect();
+            taken = element_index;
+            let split = new_warnings.partition_point(|w| w.offset < offset);
+            self.warnings.extend(new_warnings.drain(..split));
+            let split = new_commands.partition_point(|(at, _)| *at < offset);
+            self.count_commands(new_commands.drain(..split));
+            // A read across the boundary ends this job and starts the next
+            let split = self.arrivals.partition_point(|(_, end)| *end <= offset);
+            let next_arrivals = self.arrivals.split_off(split);
+            if let Some(&(at, _)) = next_arrivals.first() {
+                self.arrivals.push((at, offset));
+            }
+            self.add_elements(ended);
+            self.finish_job();
+            if !next_raw.is_empty() {
+                self.received_at = Some(unix_millis());
+            }
+            self.raw = next_raw;
+            self.arrivals = next_arrivals;
+        }
+        self.warnings.extend(new_warnings);
+        self.count_commands(new_commands.drain(..));
+        self.add_elements(new_elements);
+    }
+
+    fn count_commands(&mut self, commands: impl Iterator<Item = (u64, String)>) {
+        for (_, name) in commands {
+            *self.commands.entry(name).or_default() += 1;
+        }
+    }
+
+    fn add_elements(&mut self, elements: Vec<ReceiptElement>) {
+        if !elements.is_empty() {
+            self.elements += elements.len();
+            self.events.push(JobEvent::Elements(elements));
+        }
+    }
+
+    fn finish_job(&mut self) {
+        if self.raw.is_empty() && self.elements == 0 {
+            return;
+        }
+        let raw = std::mem::take(&mut self.raw);
+        let mut warnings = std::mem::take(&mut self.warnings);
+        for warning in &mut warnings {
+            warning.offset = warning.offset.saturating_sub(self.start);
+        }
+        let received_at = self.received_at.take().unwrap_or_else(unix_millis);
+        let arrivals = std::mem::take(&mut self.arrivals)
+            .into_iter()
+            .map(|(at, end)| {
+                (
+                    at.saturating_sub(received_at),
+                    end.saturating_sub(self.start) as usize,
+                )
+            })
+            .collect();
+        self.start += raw.len() as u64;
+        self.events.push(JobEvent::Finished(FinishedJob {
+            raw,
+            warnings,
+            received_at,
+            arrivals,
+            commands: std::mem::take(&mut self.commands),
+            elements: std::mem::take(&mut self.elements),
+        }));
+    }
+}
@@ -0,0 +1,334 @@
+// CUPS raster (application/vnd.cups-raster) and PWG raster input: what CUPS
+// filters such as rastertozj hand the printer backend, for testing a CUPS
+// driver pipeline without an ESC/POS stage. A stream is a sync word and
+// pages, each a 1796 byte header followed by the pixels:
+//
+//   "RaSt"/"tSaR"  version 1, uncompressed
+//   "RaS2"/"2SaR"  version 2 and PWG raster, run-length compressed
+//   "RaS3"/"3SaR"  version 3, uncompressed
+//
+// The reversed words are little-endian streams. Pages become 1-bit images,
+// printing where the pixels are darker than mid gray.
+
+use anyhow::{bail, Result};
+
+pub const SYNC_LEN: usize = 4;
+const HEADER_LEN: usize = 1796;
+
+// Page header fields (cups_page_header2_t), all 32-bit integers
+const CUT_MEDIA: usize = 268;
+const WIDTH: usize = 372;
+const HEIGHT: usize = 376;
+const BITS_PER_COLOR: usize = 384;
+const BITS_PER_PIXEL: usize = 388;
+const BYTES_PER_LINE: usize = 392;
+const COLOR_ORDER: usize = 396;
+const COLOR_SPACE: usize = 400;
+
+/// Limits for a page, generous for a receipt: 10 inches wide and 8 meters
+/// long at 203 dpi
+const MAX_WIDTH: usize = 2048;
+const MAX_HEIGHT: usize = 65535;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    big_endian: bool,
+    compressed: bool,
+}
+
+/// Where a raster job is at
+#[derive(Debug)]
+pub enum Stream {
+    Pages(Decoder),
+    /// A page couldn't be decoded; the rest of the job is dropped
+    Broken,
+}
+
+/// What the start of a job is
+pub enum Start {
+    /// Too short to tell
+    Incomplete,
+    Raster(Format),
+    NotRaster,
+}
+
+pub fn start(data: &[u8]) -> Start {
+    const WORDS: [(&[u8; 4], bool, bool); 6] = [
+        (b"RaSt", true, false),
+        (b"tSaR", false, false),
+        (b"RaS2", true, true),
+        (b"2SaR", false, true),
+        (b"RaS3", true, false),
+        (b"3SaR", false, false),
+    ];
+    for (word, big_endian, compressed) in WORDS {
+        if data.starts_with(word) {
+            return Start::Raster(Format {
+                big_endian,
+                compressed,
+            });
+        }
+        if data.len() < word.len() && word.starts_with(data) {
+            return Start::Incomplete;
+        }
+    }
+    Start::NotRaster
+}
+
+/// A page as a 1-bit image, most significant bit first, 1 for a dot
+pub struct Page {
+    pub width: usize,
+    pub height: usize,
+    pub bytes_per_line: usize,
+    pub data: Vec<u8>,
+    /// The header asks for the paper to be cut after the page
+    pub cut: bool,
+}
+
+/// Decodes the pages of a stream as its bytes arrive, keeping the lines
+/// decoded so far, so each byte is looked at once however the data is split
+#[derive(Debug)]
+pub struct Decoder {
+    format: Format,
+    /// The page whose pixels are arriving
+    page: Option<PageHeader>,
+    /// Its lines so far, as dots
+    dots: Vec<u8>,
+    rows: usize,
+}
+
+#[derive(Debug)]
+struct PageHeader {
+    width: usize,
+    height: usize,
+    bits_per_color: usize,
+    bytes_per_line: usize,
+    color_space: ColorSpace,
+    cut: bool,
+}
+
+impl Decoder {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            page: None,
+            dots: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Decode what's complete at the start of `data`: a page header, then
+    /// whole lines (line groups when compressed). Returns the bytes used,
+    /// 0 until more arrive, and the page once its last line is in.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(usize, Option<Page>)> {
+        let mut used = 0;
+        if self.page.is_none() {
+            if data.len() < HEADER_LEN {
+                return Ok((0, None));
+            }
+            self.page = Some(self.header(&data[..HEADER_LEN])?);
+            used = HEADER_LEN;
+        }
+        let header = self.page.as_ref().unwrap();
+        let dots_per_line = header.width.div_ceil(8);
+        let mut line = vec![0u8; header.bytes_per_line];
+        while self.rows < header.height {
+            let (len, repeat) = if self.format.compressed {
+                match decompress_group(&data[used..], header, &mut line) {
+                    Some(group) => group,
+                    None => break,
+                }
+            } else {
+                let Some(bytes) = data.get(used..used + header.bytes_per_line) else {
+                    break;
+                };
+                line.copy_from_slice(bytes);
+                (header.bytes_per_line, 1)
+            };
+            used += len;
+            let dots = header.dots(&line);
+            for _ in 0..repeat.min(header.height - self.rows) {
+                self.dots.extend_from_slice(&dots);
+                self.rows += 1;
+            }
+        }
+        if self.rows < header.height {
+            return Ok((used, None));
+        }
+        let header = self.page.take().unwrap();
+        self.rows = 0;
+        Ok((
+            used,
+            Some(Page {
+                width: header.width,
+                height: header.height,
+                bytes_per_line: dots_per_line,
+                data: std::mem::take(&mut self.dots),
+                cut: header.cut,
+            }),
+        ))
+    }
+
+    fn header(&self, data: &[u8]) -> Result<PageHeader> {
+        let field = |offset: usize| {
+            let bytes: [u8; 4] = data[offset..offset + 4].try_into().unwrap();
+            if self.format.big_endian {
+                u32::from_be_bytes(bytes) as usize
+            } else {
+                u32::from_le_bytes(bytes) as usize
+            }
+        };
+        let (width, height) = (field(WIDTH), field(HEIGHT));
+        let bits_per_color = field(BITS_PER_COLOR);
+        let bits_per_pixel = field(BITS_PER_PIXEL);
+        let bytes_per_line = field(BYTES_PER_LINE);
+        if !(1..=MAX_WIDTH).contains(&width) || !(1..=MAX_HEIGHT).contains(&height) {
+            bail!("unsupported page size {}x{}", width, height);
+        }
+        if field(COLOR_ORDER) != 0 {
+            bail!(
+                "unsupported color order {} (only chunky)",
+                field(COLOR_ORDER)
+            );
+        }
+        let color_space = ColorSpace::from_header(field(COLOR_SPACE))?;
+        let channels = color_space.channels();
+        if !(bits_per_color == 8 || bits_per_color == 1 && channels == 1)
+            || bits_per_pixel != bits_per_color * channels
+            || bytes_per_line != (width * bits_per_pixel).div_ceil(8)
+        {
+            bail!(
+                "unsupported pixel format: {} bits per color, {} per pixel, {} bytes per line",
+                bits_per_color,
+                bits_per_pixel,
+                bytes_per_line
+            );
+        }
+        Ok(PageHeader {
+            width,
+            height,
+            bits_per_color,
+            bytes_per_line,
+            color_space,
+            cut: field(CUT_MEDIA) != 0,
+        })
+    }
+}
+
+impl PageHeader {
+    /// A line of pixels as dots: where they are darker than mid gray
+    fn dots(&self, line: &[u8]) -> Vec<u8> {
+        let channels = self.color_space.channels();
+        let mut dots = vec![0u8; self.width.div_ceil(8)];
+        for x in 0..self.width {
+            let dark = if self.bits_per_color == 1 {
+                // One bit per pixel: set is a dot in K, no light in W
+                let set = line[x / 8] & (0x80 >> (x % 8)) != 0;
+                set != self.color_space.additive()
+            } else {
+                let pixel = &line[x * channels..(x + 1) * channels];
+                self.color_space.luminance(pixel) < 128
+            };
+            if dark {
+                dots[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        dots
+    }
+}
+
+/// Version 2 compression: each line group starts with a repeat count, then
+/// runs of one pixel repeated (0-127: n+1 times), literal pixels (129-255:
+/// 257-n of them) or the rest of the line white (128). Decodes the group at
+/// the start of `data` into `line`, returning its length and how many lines
+/// it stands for, or None if the data ends first.
+fn decompress_group(data: &[u8], header: &PageHeader, line: &mut [u8]) -> Option<(usize, usize)> {
+    let pixel_len = (header.bits_per_color * header.color_space.channels()).div_ceil(8);
+    let blank = if header.color_space.additive() {
+        0xFF
+    } else {
+        0
+    };
+    let repeat = usize::from(*data.first()?) + 1;
+    let mut i = 1;
+    let mut x = 0;
+    while x < line.len() {
+        let run = *data.get(i)?;
+        i += 1;
+        match run {
+            0..=127 => {
+                let pixel = data.get(i..i + pixel_len)?;
+                i += pixel_len;
+                for _ in 0..=run {
+                    let end = (x + pixel_len).min(line.len());
+                    line[x..end].copy_from_slice(&pixel[..end - x]);
+                    x = end;
+                }
+            }
+            128 => {
+                line[x..].fill(blank);
+                x = line.len();
+            }
+            _ => {
+                let len = (257 - usize::from(run)) * pixel_len;
+                let pixels = data.get(i..i + len)?;
+                i += len;
+                let end = (x + len).min(line.len());
+                line[x..end].copy_from_slice(&pixels[..end - x]);
+                x = end;
+            }
+        }
+    }
+    Some((i, repeat))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ColorSpace {
+    /// Gray, 0 is black (W, sW)
+    White,
+    /// Ink, 0 is white (K)
+    Black,
+    /// RGB, sRGB, Adobe RGB
+    Rgb,
+    Cmyk,
+}
+
+impl ColorSpace {
+    fn from_header(value: usize) -> Result<Self> {
+        Ok(match value {
+            0 | 18 => Self::White,
+            3 => Self::Black,
+            1 | 19 | 20 => Self::Rgb,
+            6 => Self::Cmyk,
+            _ => bail!("unsupported color space {}", value),
+        })
+    }
+
+    fn channels(self) -> usize {
+        match self {
+            Self::White | Self::Black => 1,
+            Self::Rgb => 3,
+            Self::Cmyk => 4,
+        }
+    }
+
+    fn additive(self) -> bool {
+        matches!(self, Self::White | Self::Rgb)
+    }
+
+    /// Brightness of an 8-bit pixel, 0 black to 255 white
+    fn luminance(self, pixel: &[u8]) -> u8 {
+        match self {
+            Self::White => pixel[0],
+            Self::Black => 255 - pixel[0],
+            Self::Rgb => {
+                let (r, g, b) = (pixel[0] as u32, pixel[1] as u32, pixel[2] as u32);
+                ((r * 299 + g * 587 + b * 114) / 1000) as u8
+            }
+            Self::Cmyk => {
+                let ink = pixel[..3].iter().map(|&c| c as u32).sum::<u32>() / 3;
+                255u32.saturating_sub(ink + pixel[3] as u32) as u8
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Customized printer control values (GS ( M): print settings saved to the
+// printer's user storage area, loaded back on request and, once selected,
+// by ESC @ and at power on (a new connection), for drivers that configure
+// the printer once and expect it to keep the settings. Like NV user memory,
+// shared by all connections and, given a file, kept across restarts.
+
+use crate::PrinterState;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct CustomValues {
+    stored: Stored,
+    /// Saved after every change when set
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Stored {
+    /// The user storage area, empty until settings are saved to it
+    saved: Option<PrinterState>,
+    /// ESC @ and power on load the saved settings instead of the defaults
+    load_at_init: bool,
+}
+
+impl CustomValues {
+    /// Values backed by a JSON file, loaded if it exists
+    pub fn open(path: &Path) -> Result<Self> {
+        let stored = if path.exists() {
+            serde_json::from_slice(&std::fs::read(path)?)
+                .with_context(|| format!("parsing {}", path.display()))?
+        } else {
+            Stored::default()
+        };
+        Ok(Self {
+            stored,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn saved(&self) -> Option<&PrinterState> {
+        self.stored.saved.as_ref()
+    }
+
+    pub fn load_at_init(&self) -> bool {
+        self.stored.load_at_init
+    }
+
+    /// The settings ESC @ and power on start from, if not the defaults
+    pub fn initial(&self) -> Option<PrinterState> {
+        self.stored
+            .saved
+            .clone()
+            .filter(|_| self.stored.load_at_init)
+    }
+
+    pub fn save(&mut self, state: &PrinterState) {
+        self.stored.saved = Some(state.clone());
+        self.write();
+    }
+
+    pub fn set_load_at_init(&mut self, load_at_init: bool) {
+        self.stored.load_at_init = load_at_init;
+        self.write();
+    }
+
+    pub fn clear(&mut self) {
+        self.stored = Stored::default();
+        self.write();
+    }
+
+    fn write(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(&self.stored)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                std::fs::write(path, json).with_context(|| format!("writing {}", path.display()))
+            });
+        if let Err(e) = result {
+            eprintln!("ERROR: Failed to save customized values: {:#}", e);
+        }
+    }
+}
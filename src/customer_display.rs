@@ -0,0 +1,290 @@
+// Customer display: a 2×20 pole display (Epson DM-D class) on the printer's
+// pass-through port. Clients switch their data between it and the printer
+// with ESC = n (bit 0 printer, bit 1 display); the renderer hands the bytes
+// meant for the display to this emulation, which keeps the characters shown
+// and interprets the display command set:
+//
+//   BS HT LF CR      cursor left, right, down, to the start of the line
+//   HOM (0B)         cursor home;  US B: cursor to the bottom left
+//   FF / CLR (0C)    clear the display;  CAN (18): clear the cursor line
+//   US $ x y         cursor to column x, line y (from 1)
+//   US MD1/2/3       overwrite, vertical scroll, horizontal scroll mode
+//   US C n           cursor off/on;  US E n: blink;  US X n: brightness 1-4
+//   ESC Q A/B/D s CR text on the upper line, lower line or scrolling upper
+//   ESC @            initialize
+//
+// Other ESC and US commands are skipped with their usual parameter count.
+// Characters are decoded as code page 437.
+
+use crate::{BorrowFromCp437, BS, CAN, CP437_CONTROL, CR, ESC, FF, HT, LF, VT};
+use serde::Serialize;
+
+pub const COLUMNS: usize = 20;
+pub const ROWS: usize = 2;
+
+/// US: display command prefix
+const US: u8 = 0x1F;
+
+/// ESC Q strings longer than this without their CR are cut here
+const MAX_STRING: usize = 40;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayMode {
+    /// Text past the end of the bottom line continues at the top left
+    #[default]
+    Overwrite,
+    /// The lower line moves up to make room
+    VerticalScroll,
+    /// The cursor line scrolls left
+    HorizontalScroll,
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomerDisplay {
+    lines: [[char; COLUMNS]; ROWS],
+    /// Cursor position: (column, row), from 0
+    cursor: (usize, usize),
+    pub mode: DisplayMode,
+    pub cursor_visible: bool,
+    /// 1 (dimmest) to 4
+    pub brightness: u8,
+    /// Blink interval in units of 50 ms, 0 for steady
+    pub blink: u8,
+    /// Bytes of a command not complete yet
+    pending: Vec<u8>,
+}
+
+impl Default for CustomerDisplay {
+    fn default() -> Self {
+        Self {
+            lines: [[' '; COLUMNS]; ROWS],
+            cursor: (0, 0),
+            mode: DisplayMode::default(),
+            cursor_visible: false,
+            brightness: 4,
+            blink: 0,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl CustomerDisplay {
+    /// The characters shown, one string per line
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .map(|line| line.iter().collect())
+            .collect()
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// Interpret bytes sent to the display. An incomplete command at the end
+    /// is kept until the rest arrives.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let pending = std::mem::take(&mut self.pending);
+        let mut i = 0;
+        while i < pending.len() {
+            match self.command(&pending[i..]) {
+                Some(used) => i += used,
+                None => break,
+            }
+        }
+        self.pending = pending[i..].to_vec();
+    }
+
+    /// Run the command at the start of `data`, returning the bytes it took,
+    /// or None if it isn't complete
+    fn command(&mut self, data: &[u8]) -> Option<usize> {
+        match data[0] {
+            ESC => self.esc_command(data),
+            US => self.us_command(data),
+            BS => {
+                self.cursor_left();
+                Some(1)
+            }
+            HT => {
+                self.cursor_right();
+                Some(1)
+            }
+            LF => {
+                self.line_feed();
+                Some(1)
+            }
+            CR => {
+                self.cursor.0 = 0;
+                Some(1)
+            }
+            // HOM
+            VT => {
+                self.cursor = (0, 0);
+                Some(1)
+            }
+            FF => {
+                self.clear();
+                Some(1)
+            }
+            CAN => {
+                self.lines[self.cursor.1] = [' '; COLUMNS];
+                self.cursor.0 = 0;
+                Some(1)
+            }
+            0x20..=0xFF => {
+                self.put(data[0]);
+                Some(1)
+            }
+            // Other control characters do nothing
+            _ => Some(1),
+        }
+    }
+
+    fn esc_command(&mut self, data: &[u8]) -> Option<usize> {
+        match *data.get(1)? {
+            b'@' => {
+                *self = Self::default();
+                Some(2)
+            }
+            // ESC Q A/B/D string CR: a line of its own, not moving the cursor
+            b'Q' => {
+                let target = *data.get(2)?;
+                let (end, used) = match data[3..].iter().position(|&b| b == CR) {
+                    Some(length) => (3 + length, 3 + length + 1),
+                    None if data.len() > 3 + MAX_STRING => (3 + MAX_STRING, 3 + MAX_STRING),
+                    None => return None,
+                };
+                let row = usize::from(target == b'B');
+                let mut line = [' '; COLUMNS];
+                for (cell, &byte) in line.iter_mut().zip(&data[3..end]) {
+                    *cell = decode(byte);
+                }
+                self.lines[row] = line;
+                Some(used)
+            }
+            // ESC t n, ESC R n, ESC c 5 n: code page and similar settings
+            b't' | b'R' => data.get(2).map(|_| 3),
+            b'c' => data.get(3).map(|_| 4),
+            _ => Some(2),
+        }
+    }
+
+    fn us_command(&mut self, data: &[u8]) -> Option<usize> {
+        match *data.get(1)? {
+            0x01 => self.set_mode(DisplayMode::Overwrite),
+            0x02 => self.set_mode(DisplayMode::VerticalScroll),
+            0x03 => self.set_mode(DisplayMode::HorizontalScroll),
+            b'$' => {
+                let (x, y) = (*data.get(2)?, *data.get(3)?);
+                self.cursor = (
+                    usize::from(x.clamp(1, COLUMNS as u8)) - 1,
+                    usize::from(y.clamp(1, ROWS as u8)) - 1,
+                );
+                Some(4)
+            }
+            b'B' => {
+                self.cursor = (0, ROWS - 1);
+                Some(2)
+            }
+            LF => {
+                // US LF: cursor up
+                self.cursor.1 = self.cursor.1.saturating_sub(1);
+                Some(2)
+            }
+            CR => {
+                // US CR: cursor to the end of the line
+                self.cursor.0 = COLUMNS - 1;
+                Some(2)
+            }
+            b'C' => {
+                self.cursor_visible = *data.get(2)? & 1 == 1;
+                Some(3)
+            }
+            b'E' => {
+                self.blink = *data.get(2)?;
+                Some(3)
+            }
+            b'X' => {
+                self.brightness = (*data.get(2)?).clamp(1, 4);
+                Some(3)
+            }
+            b'@' => Some(2),
+            // Other US commands take one parameter
+            _ => data.get(2).map(|_| 3),
+        }
+    }
+
+    fn set_mode(&mut self, mode: DisplayMode) -> Option<usize> {
+        self.mode = mode;
+        Some(2)
+    }
+
+    fn clear(&mut self) {
+        self.lines = [[' '; COLUMNS]; ROWS];
+        self.cursor = (0, 0);
+    }
+
+    /// Write a character at the cursor and move past it
+    fn put(&mut self, byte: u8) {
+        let (column, row) = self.cursor;
+        self.lines[row][column] = decode(byte);
+        if column + 1 < COLUMNS {
+            self.cursor.0 += 1;
+            return;
+        }
+        match self.mode {
+            DisplayMode::Overwrite => {
+                self.cursor = (0, (row + 1) % ROWS);
+            }
+            DisplayMode::VerticalScroll => {
+                self.cursor.0 = 0;
+                self.line_feed();
+            }
+            DisplayMode::HorizontalScroll => {
+                // The next character goes in the last column, the line
+                // moving left to make room
+                self.lines[row].rotate_left(1);
+                self.lines[row][COLUMNS - 1] = ' ';
+            }
+        }
+    }
+
+    fn cursor_left(&mut self) {
+        match self.cursor {
+            (0, 0) => self.cursor = (COLUMNS - 1, ROWS - 1),
+            (0, row) => self.cursor = (COLUMNS - 1, row - 1),
+            (column, row) => self.cursor = (column - 1, row),
+        }
+    }
+
+    fn cursor_right(&mut self) {
+        if self.cursor.0 + 1 < COLUMNS {
+            self.cursor.0 += 1;
+        } else {
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+    }
+
+    /// Cursor down; on the bottom line the display scrolls in vertical
+    /// scroll mode and the cursor wraps to the top otherwise
+    fn line_feed(&mut self) {
+        if self.cursor.1 + 1 < ROWS {
+            self.cursor.1 += 1;
+        } else if self.mode == DisplayMode::VerticalScroll {
+            self.lines.rotate_left(1);
+            self.lines[ROWS - 1] = [' '; COLUMNS];
+        } else {
+            self.cursor.1 = 0;
+        }
+    }
+}
+
+fn decode(byte: u8) -> char {
+    String::borrow_from_cp437(&[byte], &CP437_CONTROL)
+        .chars()
+        .next()
+        .unwrap_or(' ')
+}
@@ -0,0 +1,169 @@
+// Sample jobs showing what the emulator renders: formatted text, code pages,
+// a QR code, barcodes, a raster logo and cuts. `escpresso demo` sends them to
+// a running emulator and the Demo button prints them directly, which also
+// makes them a quick smoke test after parser changes.
+
+use crate::receipt::Receipt;
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+
+pub struct DemoJob {
+    pub name: &'static str,
+    pub data: Vec<u8>,
+}
+
+pub fn jobs() -> Vec<DemoJob> {
+    vec![
+        DemoJob {
+            name: "formatted text",
+            data: formatted_text(),
+        },
+        DemoJob {
+            name: "code pages",
+            data: code_pages(),
+        },
+        DemoJob {
+            name: "QR code and barcodes",
+            data: codes(),
+        },
+        DemoJob {
+            name: "raster logo",
+            data: raster_logo(),
+        },
+    ]
+}
+
+fn formatted_text() -> Vec<u8> {
+    let mut job = Receipt::new()
+        .center()
+        .double_width()
+        .double_height()
+        .line("escpresso")
+        .normal()
+        .line("Virtual ESC/POS printer")
+        .line("")
+        .left()
+        .bold()
+        .line("Bold")
+        .normal()
+        .underline()
+        .line("Underlined")
+        .normal()
+        .inverted()
+        .line(" Inverted ")
+        .normal()
+        .double_width()
+        .line("Double width")
+        .normal()
+        .double_height()
+        .line("Double height")
+        .normal()
+        .font_b()
+        .line("Font B fits more columns on the same paper width")
+        .normal()
+        .right()
+        .line("Right aligned")
+        .left()
+        .line("");
+    // Prices at a fixed dot position with ESC $
+    for (item, price) in [
+        ("Espresso", "2.50"),
+        ("Cappuccino", "3.80"),
+        ("Croissant", "2.20"),
+    ] {
+        job = job.text(item).position(300).line(price);
+    }
+    job.bold()
+        .text("TOTAL")
+        .position(300)
+        .line("8.50")
+        .normal()
+        .partial_cut()
+        .build()
+}
+
+fn code_pages() -> Vec<u8> {
+    // Box drawing characters in CP437
+    let mut frame_top = vec![0xC9];
+    frame_top.extend([0xCD; 20]);
+    frame_top.push(0xBB);
+    let mut frame_bottom = vec![0xC8];
+    frame_bottom.extend([0xCD; 20]);
+    frame_bottom.push(0xBC);
+
+    Receipt::new()
+        .center()
+        .bold()
+        .line("Code pages")
+        .normal()
+        .left()
+        .encoded_line(16, encoding_rs::WINDOWS_1252, "Latin:   Café crème, 5 €")
+        .encoded_line(17, encoding_rs::WINDOWS_1251, "Cyrillic: Привет, мир")
+        .encoded_line(49, encoding_rs::WINDOWS_1255, "Hebrew:  שלום עולם")
+        .encoded_line(50, encoding_rs::WINDOWS_1256, "Arabic:  مرحبا بالعالم")
+        .raw(&[ESC, b't', 0])
+        .raw(&frame_top)
+        .raw(b"\n\xBA  CP437 box drawing \xBA\n")
+        .raw(&frame_bottom)
+        .raw(b"\n")
+        .partial_cut()
+        .build()
+}
+
+fn codes() -> Vec<u8> {
+    Receipt::new()
+        .center()
+        .line("Scan me")
+        .qr("https://github.com/jflaflamme/escpresso")
+        // Barcodes with the HRI text below
+        .raw(&[GS, b'h', 80, GS, b'w', 2, GS, b'H', 2])
+        .barcode(73, b"{BESCPRESSO-42")
+        .barcode(67, b"4006381333931")
+        .cut()
+        .build()
+}
+
+/// A coffee cup with steam, drawn procedurally
+fn raster_logo() -> Vec<u8> {
+    const WIDTH: usize = 192;
+    const HEIGHT: usize = 160;
+    let is_black = |x: usize, y: usize| {
+        let (x, y) = (x as f32, y as f32);
+        // Cup body: a bowl with a flat top
+        let (cx, top, bottom) = (88.0, 70.0, 140.0);
+        let half_width = 56.0 * (1.0 - ((y - top) / (bottom - top)).powi(3) * 0.35);
+        let in_body = y >= top && y <= bottom && (x - cx).abs() <= half_width;
+        // Handle: a ring on the right side
+        let ring = ((x - 148.0).powi(2) + (y - 96.0).powi(2)).sqrt();
+        let in_handle = (12.0..=20.0).contains(&ring) && x > 140.0;
+        // Saucer
+        let in_saucer = (144.0..=152.0).contains(&y) && (x - cx).abs() <= 80.0;
+        // Three wavy lines of steam
+        let in_steam = [60.0, 88.0, 116.0]
+            .iter()
+            .any(|&sx| (20.0..=60.0).contains(&y) && (x - sx - 6.0 * (y / 7.0).sin()).abs() <= 3.0);
+        in_body || in_handle || in_saucer || in_steam
+    };
+
+    let bytes_per_line = WIDTH / 8;
+    let mut raster = vec![0u8; bytes_per_line * HEIGHT];
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            if is_black(x, y) {
+                raster[y * bytes_per_line + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    Receipt::new()
+        .center()
+        .raster(bytes_per_line as u16, HEIGHT as u16, &raster)
+        .double_width()
+        .double_height()
+        .line("escpresso")
+        .normal()
+        .line("Fresh receipts, brewed daily")
+        .cut()
+        .build()
+}
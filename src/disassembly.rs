@@ -0,0 +1,334 @@
+// Disassembly: a job's command stream as text, one command or run of text a
+// line, for reading captures and attaching to vendor support tickets:
+//
+//   // escpresso disassembly: receipt.bin, 61 bytes
+//   00000000  1B 40                    ESC @                  // Initialize printer
+//   00000002  1B 61 01                 ESC a 01               // Justification
+//   00000005  48 65 6C 6C 6F           "Hello"
+//   0000000A  0A                       LF                     // Print and line feed
+//   0000000B  1D 76 30 00 30 00 20 00  GS v 30 00 30 00 20 00 // Raster image
+//                                      00 7E 0xFF 81 ...
+//
+// Each line has the offset, the first bytes in hex, and the command written
+// as the command composer reads it (see composer.rs): control character names,
+// the command letter, then every parameter and data byte in hex, long ones
+// carried on to lines of their own. Text is quoted, with \xNN for bytes
+// outside printable ASCII. Lint warnings follow the line they're about.
+//
+// assemble reads it back: the offset and hex columns are dropped and the rest
+// parsed by the composer, so a captured job can be edited by hand (a code
+// page changed, a command deleted or added as composer text, without offset)
+// and replayed. The hex column isn't checked against the command.
+
+use crate::{composer, trace_job, LintWarning, ParseOptions, DLE, ESC, FS, GS};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+
+/// What a disassembly starts with
+const HEADER: &str = "// escpresso disassembly: ";
+
+/// Bytes shown in the hex column
+const HEX_BYTES: usize = 8;
+
+/// Where the command starts on a line
+const TEXT_COLUMN: usize = 8 + 2 + HEX_BYTES * 3 + 1;
+
+/// Commands are padded to this width before their description
+const COMMAND_WIDTH: usize = 22;
+
+/// Parameter bytes, or text characters, per continuation line
+const BYTES_PER_LINE: usize = 16;
+
+/// What commands do, by their first bytes; the longest match wins
+const DESCRIPTIONS: &[(&[u8], &str)] = &[
+    (b"\x09", "Horizontal tab"),
+    (b"\x0A", "Print and line feed"),
+    (b"\x0C", "Form feed"),
+    (b"\x0D", "Print and carriage return"),
+    (b"\x18", "Cancel print data in page mode"),
+    (b"\x1B@", "Initialize printer"),
+    (b"\x1B!", "Select print mode"),
+    (b"\x1BE", "Bold on/off"),
+    (b"\x1BG", "Double-strike on/off"),
+    (b"\x1B-", "Underline mode"),
+    (b"\x1BM", "Select character font"),
+    (b"\x1BR", "Select international character set"),
+    (b"\x1Bt", "Select code table"),
+    (b"\x1Br", "Select color"),
+    (b"\x1B{", "Upside-down printing"),
+    (b"\x1BV", "90 degree rotation"),
+    (b"\x1B%", "User-defined character set"),
+    (b"\x1B%-12345X", "Universal Exit Language (PJL)"),
+    (b"@PJL", "PJL command"),
+    (b"\x1B&", "Define user-defined characters"),
+    (b"\x1B?", "Cancel user-defined characters"),
+    (b"\x1B ", "Right-side character spacing"),
+    (b"\x1B2", "Default line spacing"),
+    (b"\x1B3", "Set line spacing"),
+    (b"\x1Ba", "Justification"),
+    (b"\x1B$", "Absolute print position"),
+    (b"\x1B\\", "Relative print position"),
+    (b"\x1BD", "Set horizontal tab positions"),
+    (b"\x1Bd", "Print and feed n lines"),
+    (b"\x1BJ", "Print and feed n dots"),
+    (b"\x1B*", "Bit image"),
+    (b"\x1Bp", "Generate pulse (cash drawer)"),
+    (b"\x1BS", "Select standard mode"),
+    (b"\x1BL", "Select page mode"),
+    (b"\x1BT", "Print direction in page mode"),
+    (b"\x1BU", "Unidirectional printing"),
+    (b"\x1BW", "Print area in page mode"),
+    (b"\x1B\x0C", "Print data in page mode"),
+    (b"\x1B<", "Return home"),
+    (b"\x1B=", "Select peripheral device"),
+    (b"\x1Bc3", "Paper sensors to output paper-end signals"),
+    (b"\x1Bc4", "Paper sensors to stop printing"),
+    (b"\x1Bc5", "Panel buttons on/off"),
+    (b"\x1Bi", "Partial cut (obsolete)"),
+    (b"\x1Bm", "Partial cut (obsolete)"),
+    (b"\x1Bu", "Transmit peripheral device status"),
+    (b"\x1Bv", "Transmit paper sensor status"),
+    (b"\x1D!", "Select character size"),
+    (b"\x1DB", "White/black reverse printing"),
+    (b"\x1DL", "Set left margin"),
+    (b"\x1DW", "Set print area width"),
+    (b"\x1DT", "Move to beginning of print line"),
+    (b"\x1DP", "Set motion units"),
+    (b"\x1Dv0", "Raster image"),
+    (b"\x1DV", "Cut paper"),
+    (b"\x1Da", "Automatic Status Back"),
+    (b"\x1DH", "HRI printing position"),
+    (b"\x1Dh", "Barcode height"),
+    (b"\x1Dw", "Barcode module width"),
+    (b"\x1Dk", "Print barcode"),
+    (b"\x1D(A", "Execute test print"),
+    (b"\x1D(C", "NV user memory"),
+    (b"\x1D(D", "Enable/disable real-time commands"),
+    (b"\x1D(E", "User setup commands"),
+    (b"\x1D(K", "Print control"),
+    (b"\x1D(L", "Graphics"),
+    (b"\x1D8L", "Graphics (extended size)"),
+    (b"\x1D(M", "Customize printer control values"),
+    (b"\x1D(k", "2D code (QR)"),
+    (b"\x1Dg0", "Initialize maintenance counter"),
+    (b"\x1Dg2", "Transmit maintenance counter"),
+    (b"\x1Dr", "Transmit status"),
+    (b"\x1DI", "Transmit printer ID"),
+    (b"\x1C.", "Cancel Kanji character mode"),
+    (b"\x1Cp", "Print NV bit image"),
+    (b"\x1Cq", "Define NV bit image"),
+    (b"\x1CC", "Select Kanji character code system"),
+    (b"\x1C&", "Select Kanji character mode"),
+    (b"\x1C!", "Set Kanji print mode"),
+    (b"\x1C(", "Extended Kanji commands"),
+    (b"\x10\x04", "Real-time status transmission"),
+    (b"\x10\x05", "Real-time request to printer"),
+    (b"\x10\x14", "Real-time command"),
+];
+
+/// The job as disassembly text, under a comment naming it
+pub fn disassemble(
+    title: &str,
+    data: &[u8],
+    warnings: &[LintWarning],
+    options: &ParseOptions,
+) -> Result<String> {
+    let trace = trace_job(data, options)?;
+    let mut out = String::new();
+    writeln!(out, "{}{}, {} bytes", HEADER, title, data.len())?;
+    let mut position = 0;
+    for step in &trace.steps {
+        let (start, end) = (step.start as usize, step.end as usize);
+        if start == end {
+            continue;
+        }
+        // Bytes the parser passed over without a step of their own
+        if start > position {
+            write_line(&mut out, position, &data[position..start], "not parsed")?;
+        }
+        let bytes = &data[start..end];
+        let description = if step.command == "text" {
+            ""
+        } else {
+            describe(bytes)
+        };
+        write_line(&mut out, start, bytes, description)?;
+        for warning in warnings
+            .iter()
+            .filter(|warning| (start..end).contains(&(warning.offset as usize)))
+        {
+            writeln!(
+                out,
+                "{:w$}// warning: {}",
+                "",
+                warning.message,
+                w = TEXT_COLUMN
+            )?;
+        }
+        position = end;
+    }
+    if position < data.len() {
+        write_line(&mut out, position, &data[position..], "incomplete command")?;
+    }
+    Ok(out)
+}
+
+/// Whether a file is disassembly text rather than a capture
+pub fn is_disassembly(data: &[u8]) -> bool {
+    data.starts_with(HEADER.as_bytes())
+}
+
+/// The bytes of disassembly text, possibly edited
+pub fn assemble(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let command = strip_columns(line);
+        bytes.extend(composer::parse(command).with_context(|| format!("line {}", number + 1))?);
+    }
+    Ok(bytes)
+}
+
+/// A line without its offset and hex columns, if it has them
+fn strip_columns(line: &str) -> &str {
+    let Some(offset) = line.get(..8) else {
+        return line;
+    };
+    let Some(rest) = line[8..].strip_prefix("  ") else {
+        return line;
+    };
+    if !offset.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return line;
+    }
+    // The hex column ends where two spaces separate it from the command
+    let (hex, command) = rest.split_once("  ").unwrap_or((rest, ""));
+    if hex
+        .split(' ')
+        .all(|byte| byte.len() == 2 && byte.bytes().all(|b| b.is_ascii_hexdigit()))
+    {
+        command
+    } else {
+        line
+    }
+}
+
+/// A command with its offset and hex column, carried on to more lines if
+/// it's long
+fn write_line(out: &mut String, offset: usize, bytes: &[u8], description: &str) -> Result<()> {
+    let hex: Vec<String> = bytes
+        .iter()
+        .take(HEX_BYTES)
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let mut lines = command_lines(bytes).into_iter();
+    let first = lines.next().unwrap_or_default();
+    write!(
+        out,
+        "{:08X}  {:h$} {}",
+        offset,
+        hex.join(" "),
+        first,
+        h = HEX_BYTES * 3
+    )?;
+    if !description.is_empty() {
+        let padding = COMMAND_WIDTH.saturating_sub(first.len());
+        write!(out, "{:p$} // {}", "", description, p = padding)?;
+    }
+    writeln!(out)?;
+    for line in lines {
+        writeln!(out, "{:w$}{}", "", line, w = TEXT_COLUMN)?;
+    }
+    Ok(())
+}
+
+/// The bytes in composer syntax, split into lines
+fn command_lines(bytes: &[u8]) -> Vec<String> {
+    match bytes.first() {
+        Some(&(0x20..=0x7E | 0x80..=0xFF)) => text_lines(bytes),
+        Some(&(ESC | GS | FS | DLE)) => {
+            let mut name = vec![token(bytes[0])];
+            let mut head = 1;
+            if let Some(&command) = bytes.get(1) {
+                name.push(token(command));
+                head = 2;
+                // GS ( x and FS ( x name their function too
+                if command == b'(' {
+                    if let Some(&function) = bytes.get(2) {
+                        name.push(token(function));
+                        head = 3;
+                    }
+                }
+            }
+            let mut lines = hex_lines(&bytes[head..]);
+            match lines.first_mut() {
+                Some(first) => *first = format!("{} {}", name.join(" "), first),
+                None => lines.push(name.join(" ")),
+            }
+            lines
+        }
+        Some(&byte) => {
+            let mut lines = hex_lines(&bytes[1..]);
+            lines.insert(0, token(byte));
+            vec![lines.join(" ")]
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Parameter and data bytes in hex, BYTES_PER_LINE to a line
+fn hex_lines(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|&byte| match byte {
+                    // FF alone is the form feed
+                    0xFF => "0xFF".to_string(),
+                    byte => format!("{:02X}", byte),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+/// Text quoted, long runs split into several quoted strings
+fn text_lines(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_LINE * 3)
+        .map(|chunk| {
+            let mut quoted = String::from("\"");
+            for &byte in chunk {
+                match byte {
+                    b'"' => quoted.push_str("\\\""),
+                    b'\\' => quoted.push_str("\\\\"),
+                    0x20..=0x7E => quoted.push(byte as char),
+                    _ => quoted.push_str(&format!("\\x{:02X}", byte)),
+                }
+            }
+            quoted.push('"');
+            quoted
+        })
+        .collect()
+}
+
+/// A command byte as the composer reads it: the character itself, a control
+/// character name, or hex
+fn token(byte: u8) -> String {
+    if let Some((name, _)) = composer::NAMES.iter().find(|(_, named)| *named == byte) {
+        return name.to_string();
+    }
+    match byte {
+        // A quote would start text
+        0x21..=0x7E if byte != b'"' => (byte as char).to_string(),
+        _ => format!("0x{:02X}", byte),
+    }
+}
+
+fn describe(bytes: &[u8]) -> &'static str {
+    DESCRIPTIONS
+        .iter()
+        .filter(|(prefix, _)| bytes.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or("", |(_, description)| description)
+}
@@ -0,0 +1,138 @@
+// Saving the images of each job (raster graphics, QR codes, barcodes) as PNG
+// files, one folder per job:
+//
+//   <dir>/job-00001/001-raster.png
+//   <dir>/job-00001/002-qr.png
+//
+// Images are drawn from the decoded element data, one pixel per dot and
+// without the preview's layout, so bit order or width problems in an image
+// pipeline show up as they were received.
+
+use crate::ReceiptElement;
+use anyhow::{Context, Result};
+use image::{GrayImage, Luma};
+use qrcode::{Color as QrColor, QrCode};
+use std::path::{Path, PathBuf};
+
+const BLACK: Luma<u8> = Luma([0]);
+
+/// Modules of white around QR codes so the saved images scan
+const QR_QUIET_ZONE: usize = 4;
+
+/// Whether an element is an image that gets saved
+pub fn is_image(element: &ReceiptElement) -> bool {
+    matches!(
+        element,
+        ReceiptElement::RasterImage { .. }
+            | ReceiptElement::QrCode { .. }
+            | ReceiptElement::Barcode { .. }
+    )
+}
+
+/// Save the images among `elements`, returning how many were written
+pub fn save_job_images(dir: &Path, job_id: u64, elements: &[ReceiptElement]) -> Result<usize> {
+    let images: Vec<_> = elements.iter().filter_map(element_image).collect();
+    if images.is_empty() {
+        return Ok(0);
+    }
+    let job_dir = job_dir(dir, job_id);
+    std::fs::create_dir_all(&job_dir).with_context(|| format!("creating {}", job_dir.display()))?;
+    for (n, (kind, image)) in (1..).zip(&images) {
+        let path = job_dir.join(format!("{:03}-{}.png", n, kind));
+        image
+            .save(&path)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(images.len())
+}
+
+/// Folder a job's images are saved to
+pub fn job_dir(dir: &Path, job_id: u64) -> PathBuf {
+    dir.join(format!("job-{:05}", job_id))
+}
+
+fn element_image(element: &ReceiptElement) -> Option<(&'static str, GrayImage)> {
+    match element {
+        ReceiptElement::RasterImage {
+            width,
+            height,
+            data,
+            bytes_per_line,
+            ..
+        } => Some((
+            "raster",
+            raster_image(*width, *height, *bytes_per_line, data),
+        )),
+        ReceiptElement::QrCode { data, size, .. } => {
+            // Drawn the way the preview does, skipping what it can't encode
+            let qr = QrCode::new(data.as_bytes()).ok()?;
+            Some(("qr", qr_image(&qr, (*size).max(1))))
+        }
+        ReceiptElement::Barcode {
+            widths,
+            module_width,
+            height,
+            ..
+        } => Some(("barcode", barcode_image(widths, *module_width, *height))),
+        _ => None,
+    }
+}
+
+/// MSB first, a set bit is a black dot
+fn raster_image(width: usize, height: usize, bytes_per_line: usize, data: &[u8]) -> GrayImage {
+    let mut image = GrayImage::from_pixel(width.max(1) as u32, height.max(1) as u32, Luma([255]));
+    for y in 0..height {
+        for x in 0..width {
+            let byte = data.get(y * bytes_per_line + x / 8).copied().unwrap_or(0);
+            if byte & (0x80 >> (x % 8)) != 0 {
+                image.put_pixel(x as u32, y as u32, BLACK);
+            }
+        }
+    }
+    image
+}
+
+fn qr_image(qr: &QrCode, module_size: usize) -> GrayImage {
+    let modules = qr.width();
+    let side = (modules + 2 * QR_QUIET_ZONE) * module_size;
+    let colors = qr.to_colors();
+    GrayImage::from_fn(side as u32, side as u32, |x, y| {
+        let module = |p: u32| (p as usize / module_size).checked_sub(QR_QUIET_ZONE);
+        match (module(x), module(y)) {
+            (Some(mx), Some(my)) if mx < modules && my < modules => {
+                match colors[my * modules + mx] {
+                    QrColor::Dark => BLACK,
+                    QrColor::Light => Luma([255]),
+                }
+            }
+            _ => Luma([255]),
+        }
+    })
+}
+
+/// Bars and spaces alternate, starting with a bar, in half modules
+fn barcode_image(widths: &[u8], module_width: u8, height: u8) -> GrayImage {
+    let half_module = module_width.max(1) as f32 / 2.0;
+    let length: f32 = widths.iter().map(|&w| w as f32 * half_module).sum();
+    let mut image = GrayImage::from_pixel(
+        (length.ceil() as u32).max(1),
+        u32::from(height.max(1)),
+        Luma([255]),
+    );
+    let mut position = 0.0;
+    for (i, &w) in widths.iter().enumerate() {
+        let end = position + w as f32 * half_module;
+        if i % 2 == 0 {
+            // Dots whose centers fall inside the bar
+            let first = (position - 0.5).ceil().max(0.0) as u32;
+            let last = (end - 0.5).ceil() as u32;
+            for x in first..last.min(image.width()) {
+                for y in 0..image.height() {
+                    image.put_pixel(x, y, BLACK);
+                }
+            }
+        }
+        position = end;
+    }
+    image
+}
@@ -0,0 +1,424 @@
+// Printer events published for store automation to react to: a job printed,
+// the paper cut, the drawer kicked, an error raised or cleared. Each event is
+// a JSON object sent to an MQTT broker or POSTed to a webhook, whichever
+// ESCPRESSO_EVENTS, or later the "events" setting of the configuration file,
+// names:
+//
+//   mqtt://[user:password@]host[:port][/prefix]   topic <prefix>/<event>,
+//                                                 prefix "escpresso" by default
+//   http://host[:port]/path                       one POST per event
+//
+//   {"event":"job","at":...,"id":12,"source":"192.168.1.20:51234","bytes":1834,
+//    "received_at":...,"duration_ms":210,"warnings":0}
+//   {"event":"cut","at":...,"source":...,"cut_type":"PARTIAL CUT"}
+//   {"event":"drawer_kick","at":...,"source":...,"command":"ESC p","pin":2,
+//    "on_ms":100,"off_ms":200}
+//   {"event":"error","at":...,"condition":"cover_open","active":true}
+//
+// Times are milliseconds since the Unix epoch. MQTT messages are QoS 0; the
+// broker connection is retried every few seconds, and events raised while it
+// is down are dropped, as a real printer's wouldn't queue either.
+
+use crate::{status::SimulatedStatus, unix_millis};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+
+const MQTT_PORT: u16 = 1883;
+const DEFAULT_PREFIX: &str = "escpresso";
+/// Seconds the broker waits for a packet before dropping the client. A ping
+/// goes out at half this when there's nothing to publish.
+const MQTT_KEEPALIVE: u16 = 60;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PrinterEvent {
+    Job {
+        id: u64,
+        source: String,
+        bytes: usize,
+        received_at: u64,
+        duration_ms: u64,
+        warnings: usize,
+    },
+    Cut {
+        source: String,
+        cut_type: String,
+    },
+    DrawerKick {
+        source: String,
+        command: &'static str,
+        pin: u8,
+        on_ms: u32,
+        off_ms: u32,
+    },
+    Error {
+        condition: &'static str,
+        active: bool,
+    },
+}
+
+impl PrinterEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Job { .. } => "job",
+            Self::Cut { .. } => "cut",
+            Self::DrawerKick { .. } => "drawer_kick",
+            Self::Error { .. } => "error",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    at: u64,
+    #[serde(flatten)]
+    event: &'a PrinterEvent,
+}
+
+/// Where events go
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Mqtt {
+        /// host:port
+        broker: String,
+        credentials: Option<(String, String)>,
+        prefix: String,
+    },
+    Webhook {
+        /// host:port
+        host: String,
+        path: String,
+    },
+}
+
+impl std::str::FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .context("expected mqtt://host or http://host/path")?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        let (credentials, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => {
+                let (name, password) = user.split_once(':').unwrap_or((user, ""));
+                (Some((name.to_string(), password.to_string())), host)
+            }
+            None => (None, authority),
+        };
+        if host.is_empty() {
+            bail!("no host in '{}'", url);
+        }
+        let with_port = |port: u16| {
+            // A port is there unless the host ends with an IPv6 bracket
+            if host
+                .rsplit_once(':')
+                .is_some_and(|(_, p)| !p.ends_with(']'))
+            {
+                host.to_string()
+            } else {
+                format!("{}:{}", host, port)
+            }
+        };
+        match scheme {
+            "mqtt" => {
+                let prefix = path.trim_matches('/');
+                Ok(Self::Mqtt {
+                    broker: with_port(MQTT_PORT),
+                    credentials,
+                    prefix: if prefix.is_empty() {
+                        DEFAULT_PREFIX.to_string()
+                    } else {
+                        prefix.to_string()
+                    },
+                })
+            }
+            "http" if credentials.is_none() => Ok(Self::Webhook {
+                host: with_port(80),
+                path: if path.is_empty() { "/" } else { path }.to_string(),
+            }),
+            "http" => bail!("webhook URLs don't take credentials"),
+            _ => bail!("unsupported scheme '{}', expected mqtt or http", scheme),
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mqtt { broker, prefix, .. } => write!(f, "mqtt://{}/{}", broker, prefix),
+            Self::Webhook { host, path } => write!(f, "http://{}{}", host, path),
+        }
+    }
+}
+
+/// Hands events to the publishing task without blocking the caller
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: mpsc::UnboundedSender<(&'static str, String)>,
+}
+
+impl EventBus {
+    /// The bus and the receiving end for `run`
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<(&'static str, String)>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+
+    pub fn publish(&self, event: PrinterEvent) {
+        let message = Message {
+            at: unix_millis(),
+            event: &event,
+        };
+        match serde_json::to_string(&message) {
+            // Fails only once the publishing task is gone
+            Ok(json) => _ = self.sender.send((event.name(), json)),
+            Err(e) => eprintln!("ERROR: Failed to encode {} event: {}", event.name(), e),
+        }
+    }
+}
+
+/// Publish events to the target, following changes to it, until the bus is
+/// dropped. Without a target they're dropped.
+pub async fn run(
+    mut targets: watch::Receiver<Option<Target>>,
+    mut events: mpsc::UnboundedReceiver<(&'static str, String)>,
+) {
+    loop {
+        let target = targets.borrow_and_update().clone();
+        tokio::select! {
+            () = publish(target.as_ref(), &mut events) => return,
+            changed = targets.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                if let Some(target) = target.filter(|_| targets.borrow().is_none()) {
+                    println!("Stopped publishing printer events to {}", target);
+                }
+            }
+        }
+    }
+}
+
+/// Publish events to `target` until the bus is dropped
+async fn publish(
+    target: Option<&Target>,
+    events: &mut mpsc::UnboundedReceiver<(&'static str, String)>,
+) {
+    let Some(target) = target else {
+        while events.recv().await.is_some() {}
+        return;
+    };
+    println!("Publishing printer events to {}", target);
+    match target {
+        Target::Mqtt { .. } => {
+            let mut reported = false;
+            loop {
+                match mqtt_connect(target).await {
+                    Ok(stream) => {
+                        println!("Connected to MQTT broker for {}", target);
+                        reported = false;
+                        match mqtt_session(stream, target, events).await {
+                            Ok(()) => return,
+                            Err(e) => eprintln!("ERROR: MQTT broker connection lost: {:#}", e),
+                        }
+                    }
+                    // Once per outage rather than at every retry
+                    Err(e) if !reported => {
+                        eprintln!("ERROR: Can't reach MQTT broker, retrying: {:#}", e);
+                        reported = true;
+                    }
+                    Err(_) => {}
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                // What came up meanwhile is dropped rather than sent late
+                while events.try_recv().is_ok() {}
+            }
+        }
+        Target::Webhook { host, path } => {
+            while let Some((name, json)) = events.recv().await {
+                if let Err(e) = post(host, path, &json).await {
+                    eprintln!("ERROR: Failed to post {} event: {:#}", name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Publish an error event whenever an error condition of the simulated
+/// status is raised or cleared, by the GUI, the API, a schedule or a job
+pub async fn publish_errors(bus: EventBus, mut status: watch::Receiver<SimulatedStatus>) {
+    let conditions = |status: &SimulatedStatus| {
+        [
+            ("offline", status.offline),
+            ("cover_open", status.cover_open),
+            ("paper_out", status.paper_out),
+            ("cutter_error", status.cutter_error),
+            ("unrecoverable_error", status.unrecoverable_error),
+        ]
+    };
+    let mut before = conditions(&status.borrow_and_update());
+    while status.changed().await.is_ok() {
+        let now = conditions(&status.borrow_and_update());
+        for ((condition, was), (_, active)) in before.iter().zip(now) {
+            if *was != active {
+                bus.publish(PrinterEvent::Error { condition, active });
+            }
+        }
+        before = now;
+    }
+}
+
+/// Append an MQTT remaining length: 7 bits a byte, low first
+fn push_length(packet: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            return;
+        }
+        packet.push(byte | 0x80);
+    }
+}
+
+fn push_string(body: &mut Vec<u8>, text: &[u8]) {
+    body.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    body.extend_from_slice(text);
+}
+
+/// A packet of the given type and flags around `body`
+fn mqtt_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    push_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Connect to the broker, send CONNECT (MQTT 3.1.1, clean session) and wait
+/// for CONNACK
+async fn mqtt_connect(target: &Target) -> Result<TcpStream> {
+    let Target::Mqtt {
+        broker,
+        credentials,
+        ..
+    } = target
+    else {
+        unreachable!("not an MQTT target");
+    };
+    let mut stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(broker))
+        .await
+        .context("timed out")?
+        .with_context(|| format!("connecting to {}", broker))?;
+    let mut body = Vec::new();
+    push_string(&mut body, b"MQTT");
+    body.push(4); // protocol level: 3.1.1
+    let flags = match credentials {
+        Some(_) => 0xC2, // user name, password, clean session
+        None => 0x02,
+    };
+    body.push(flags);
+    body.extend_from_slice(&MQTT_KEEPALIVE.to_be_bytes());
+    push_string(
+        &mut body,
+        format!("escpresso-{}", std::process::id()).as_bytes(),
+    );
+    if let Some((user, password)) = credentials {
+        push_string(&mut body, user.as_bytes());
+        push_string(&mut body, password.as_bytes());
+    }
+    stream.write_all(&mqtt_packet(0x10, &body)).await?;
+
+    let mut connack = [0; 4];
+    tokio::time::timeout(TIMEOUT, stream.read_exact(&mut connack))
+        .await
+        .context("no CONNACK from broker")??;
+    if connack[0] != 0x20 {
+        bail!("expected CONNACK, got packet type 0x{:02X}", connack[0]);
+    }
+    match connack[3] {
+        0 => Ok(stream),
+        4 | 5 => bail!("broker refused the user name or password"),
+        code => bail!("broker refused the connection (code {})", code),
+    }
+}
+
+/// Publish events until the bus is dropped (Ok) or the connection fails
+async fn mqtt_session(
+    mut stream: TcpStream,
+    target: &Target,
+    events: &mut mpsc::UnboundedReceiver<(&'static str, String)>,
+) -> Result<()> {
+    let Target::Mqtt { prefix, .. } = target else {
+        unreachable!("not an MQTT target");
+    };
+    let mut ping = tokio::time::interval(Duration::from_secs(u64::from(MQTT_KEEPALIVE) / 2));
+    ping.tick().await;
+    let mut incoming = [0; 256];
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some((name, json)) = event else {
+                    // Polite goodbye: DISCONNECT
+                    _ = stream.write_all(&[0xE0, 0]).await;
+                    return Ok(());
+                };
+                let mut body = Vec::new();
+                push_string(&mut body, format!("{}/{}", prefix, name).as_bytes());
+                body.extend_from_slice(json.as_bytes());
+                stream.write_all(&mqtt_packet(0x30, &body)).await?;
+                ping.reset();
+            }
+            _ = ping.tick() => {
+                stream.write_all(&[0xC0, 0]).await?; // PINGREQ
+            }
+            // Only PINGRESP comes back for QoS 0; read it to notice a close
+            read = stream.read(&mut incoming) => {
+                if read? == 0 {
+                    bail!("closed by the broker");
+                }
+            }
+        }
+    }
+}
+
+/// POST an event to a webhook, expecting a 2xx answer
+async fn post(host: &str, path: &str, json: &str) -> Result<()> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(host)
+            .await
+            .with_context(|| format!("connecting to {}", host))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host,
+            json.len(),
+            json
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let status_line = String::from_utf8_lossy(&response)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        match status_line.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => bail!("webhook answered '{}'", status_line),
+        }
+    };
+    tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .context("timed out")?
+}
@@ -0,0 +1,3657 @@
+// The ESC/POS parser: EscPosRenderer turns the bytes of a job into
+// ReceiptElements, keeping PrinterState between commands, and parse_bytes
+// does it in one go for a complete job. The escpresso app (GUI, TCP server,
+// admin API and CLI) is built on it; other tools and tests can embed it the
+// same way:
+//
+//   let elements = escpresso::parse_bytes(b"\x1b@Hello\n", &ParseOptions::default());
+
+pub mod access;
+pub mod barcode;
+pub mod codepages;
+pub mod composer;
+pub mod connection;
+pub mod cups_raster;
+pub mod custom_values;
+pub mod customer_display;
+pub mod disassembly;
+pub mod lint;
+pub mod maintenance;
+pub mod nv_memory;
+pub mod paper_roll;
+pub mod pjl;
+pub mod receipt;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod rtl;
+pub mod scripting;
+pub mod self_test;
+pub mod status;
+pub mod test_server;
+
+use anyhow::Result;
+use codepage_437::{BorrowFromCp437, CP437_CONTROL};
+use codepages::{CustomCodePages, ForcedCodePage};
+use custom_values::CustomValues;
+use customer_display::CustomerDisplay;
+use encoding_rs::Encoding;
+use lint::{LintWarning, Linter};
+use maintenance::MaintenanceCounters;
+use nv_memory::NvMemory;
+use scripting::ScriptHooks;
+use serde::{Deserialize, Serialize};
+use status::SimulatedStatus;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const ESC: u8 = 0x1B;
+pub const GS: u8 = 0x1D;
+pub const FS: u8 = 0x1C;
+pub const DLE: u8 = 0x10;
+pub const LF: u8 = 0x0A;
+pub const FF: u8 = 0x0C;
+pub const CR: u8 = 0x0D;
+pub const HT: u8 = 0x09;
+pub const CAN: u8 = 0x18;
+pub const DC2: u8 = 0x12;
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const ETX: u8 = 0x03;
+pub const EOT: u8 = 0x04;
+pub const ENQ: u8 = 0x05;
+pub const ACK: u8 = 0x06;
+pub const BEL: u8 = 0x07;
+pub const BS: u8 = 0x08;
+pub const VT: u8 = 0x0B;
+pub const SO: u8 = 0x0E;
+pub const SI: u8 = 0x0F;
+pub const DC1: u8 = 0x11;
+pub const DC3: u8 = 0x13;
+pub const DC4: u8 = 0x14;
+pub const ETB: u8 = 0x17;
+pub const RS: u8 = 0x1E;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PaperSize {
+    #[serde(rename = "58mm")]
+    Size58mm,
+    #[serde(rename = "80mm")]
+    Size80mm,
+}
+
+impl PaperSize {
+    pub fn width_px(&self) -> f32 {
+        // Printable area width (print head), not full paper
+        // 80mm paper: 72mm print head = 576 dots (48 cols * 12 dots)
+        // 58mm paper: 48mm print head = 384 dots (32 cols * 12 dots)
+        (self.chars_per_line() as f32) * 12.0
+    }
+
+    /// Columns per line in Font A
+    pub fn chars_per_line(&self) -> usize {
+        match self {
+            PaperSize::Size58mm => 32,
+            PaperSize::Size80mm => 48,
+        }
+    }
+
+    /// Columns per line in the given font (Font B: 42 on 58mm, 64 on 80mm)
+    pub fn columns(&self, font: u8) -> usize {
+        self.width_px() as usize / font_width_dots(font) as usize
+    }
+
+    /// Columns per line in the given font, or the count set for it in
+    /// `columns` (see SimulatedStatus::columns)
+    pub fn font_columns(&self, font: u8, columns: &[u16; 3]) -> usize {
+        match columns.get(usize::from(font)) {
+            Some(&n) if n > 0 => usize::from(n),
+            _ => self.columns(font),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            PaperSize::Size58mm => "58mm",
+            PaperSize::Size80mm => "80mm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ReceiptElement {
+    Text {
+        content: String,
+        bold: bool,
+        underline: bool,
+        double_width: bool,
+        double_height: bool,
+        inverted: bool,
+        alignment: Alignment,
+        density: u8,
+        offset: u16,
+        left_margin: u16,
+        character_spacing: u8,
+        double_strike: bool,
+        font: u8,
+        print_area_width: u16,
+        // Set when ESC $ / ESC \ moved the print position mid-line;
+        // each run is drawn at its own position instead of by alignment
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        runs: Vec<TextRun>,
+        // Set when the line was wider than the print area and the printer
+        // wrapped the rest onto the next line
+        #[serde(default, skip_serializing_if = "is_false")]
+        overflow: bool,
+        // Bytes the code page can't print, or that look like text in
+        // another encoding, described for the warning sign
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        decoding_problem: Option<String>,
+        // Character cell width in dots when the font's columns per line were
+        // set, 0 for the font's own
+        #[serde(default, skip_serializing_if = "is_zero")]
+        cell_width: u16,
+        // GS ! character size, 1 to 8 times the normal width and height;
+        // double_width and double_height tell whether they're above 1.
+        // Elements saved before sizes were kept have none.
+        #[serde(default = "one", skip_serializing_if = "is_one")]
+        width_multiplier: u8,
+        #[serde(default = "one", skip_serializing_if = "is_one")]
+        height_multiplier: u8,
+    },
+    RasterImage {
+        width: usize, // Width in pixels (for display)
+        height: usize,
+        data: Vec<u8>,
+        offset: u16,
+        density: u8,
+        alignment: Alignment,
+        bytes_per_line: usize, // Actual bytes per line from command (for data reading)
+        print_area_width: u16,
+        #[serde(default)]
+        left_margin: u16,
+    },
+    QrCode {
+        data: String,
+        size: usize,
+        alignment: Alignment,
+        offset: u16,
+        print_area_width: u16,
+        #[serde(default)]
+        left_margin: u16,
+    },
+    Barcode {
+        symbology: String,
+        text: String,
+        widths: Vec<u8>, // Bar/space widths in half modules, starting with a bar
+        module_width: u8,
+        height: u8,
+        hri_position: u8, // 0=none, 1=above, 2=below, 3=both
+        rotated: bool,    // ESC V: drawn 90° clockwise
+        alignment: Alignment,
+        left_margin: u16,
+        print_area_width: u16,
+    },
+    PaperCut {
+        cut_type: String,
+    },
+    CashDrawer {
+        pin: u8,
+        on_time: u8,
+        off_time: u8,
+    },
+    Separator,
+    FormFeed,
+    JobTags {
+        tags: Vec<String>,
+    },
+    // A command the parser doesn't know, consumed without effect. Shown as
+    // a marker in the preview when enabled, never printed.
+    UnknownCommand {
+        bytes: Vec<u8>,
+    },
+    // Starts the receipt of one connection, inserted by the app (see its
+    // AppState::append_elements)
+    ReceiptStart {
+        id: u64,
+        source: String,
+    },
+}
+
+/// Part of a line printed from an explicit horizontal position (in dots)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextRun {
+    pub x: u16,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+// Serialized for the settings saved with GS ( M; the encoding follows the
+// code page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrinterState {
+    pub bold: bool,
+    pub underline: bool,
+    // Character size (ESC !, GS !), 1 to 8 times the normal
+    pub width_multiplier: u8,
+    // SO on legacy printers: double width until the line is printed
+    pub one_line_double_width: bool,
+    pub height_multiplier: u8,
+    pub inverted: bool,
+    pub alignment: Alignment,
+    pub print_density: u8,
+    #[serde(skip)]
+    pub encoding: &'static Encoding,
+    pub code_page: u8,
+    pub horizontal_offset: u16,
+    pub left_margin: u16,
+    pub print_area_width: u16,
+    pub line_spacing: u8,
+    pub character_spacing: u8,
+    pub double_strike: bool,
+    pub font: u8,      // 0=Font A, 1=Font B, etc.
+    pub rotated: bool, // ESC V 90° clockwise rotation
+    pub barcode_height: u8,
+    pub barcode_module_width: u8,
+    pub barcode_hri_position: u8,
+    // GS P x y: motion units of 1/x and 1/y inch (0 = one dot)
+    pub motion_unit_x: u8,
+    pub motion_unit_y: u8,
+    // ESC D: tab stops in dots from the start of the line, ascending
+    pub tab_stops: Vec<u16>,
+}
+
+/// Code pages offered as the startup default, with their ESC t numbers
+pub const CODE_PAGES: &[(u8, &str)] = &[
+    (0, "PC437 (USA, Standard Europe)"),
+    (2, "PC850 (Multilingual)"),
+    (16, "WPC1252 (Western European)"),
+    (17, "PC866 (Cyrillic #2)"),
+    (18, "PC852 (Latin 2)"),
+    (19, "PC858 (Euro)"),
+    (36, "PC862 (Hebrew)"),
+    (37, "PC864 (Arabic)"),
+    (49, "WPC1255 (Hebrew)"),
+    (50, "WPC1256 (Arabic)"),
+];
+
+/// Name of an ESC t code page for messages
+fn code_page_name(n: u8, custom: &CustomCodePages) -> String {
+    if let Some(page) = custom.get(&n) {
+        return format!("{} (custom)", page.name());
+    }
+    CODE_PAGES
+        .iter()
+        .find(|(code_page, _)| *code_page == n)
+        .map_or_else(|| format!("Code page {}", n), |(_, name)| name.to_string())
+}
+
+/// Tab stops ESC D can set
+const MAX_TAB_STOPS: usize = 32;
+
+/// Print resolution; a motion unit of one dot is 1/203 inch
+pub const DOTS_PER_INCH: u32 = 203;
+
+/// Convert n motion units of 1/unit inch to dots, rounded
+fn motion_to_dots(n: u32, unit: u8) -> u32 {
+    match unit {
+        0 => n,
+        unit => (n * DOTS_PER_INCH + u32::from(unit) / 2) / u32::from(unit),
+    }
+}
+
+/// Character cell width in dots for ESC M / ESC ! font selection
+pub fn font_width_dots(font: u8) -> u16 {
+    match font {
+        1 => 9,  // Font B (9x17)
+        2 => 8,  // Font C (if used)
+        _ => 12, // Font A (12x24)
+    }
+}
+
+impl PrinterState {
+    /// Horizontal distance in motion units (ESC $, GS L, ...) to dots
+    fn x_dots(&self, n: u16) -> u16 {
+        motion_to_dots(n.into(), self.motion_unit_x).min(u16::MAX.into()) as u16
+    }
+
+    /// Vertical distance in motion units (ESC 3, ...) to dots
+    fn y_dots(&self, n: u8) -> u8 {
+        motion_to_dots(n.into(), self.motion_unit_y).min(u8::MAX.into()) as u8
+    }
+
+    /// Select character code table n (ESC t n)
+    fn select_code_page(&mut self, n: u8) {
+        self.code_page = n;
+        // Map codepage numbers to encoding_rs encodings
+        // Note: CP437 (codepage 0), PC862 and PC864 are handled
+        // specially in decode_text()
+        self.encoding = match n {
+            0 => encoding_rs::WINDOWS_1252,  // CP437 (handled specially)
+            1 => encoding_rs::WINDOWS_1252,  // Katakana (approximation)
+            2 => encoding_rs::WINDOWS_1252,  // CP850
+            3 => encoding_rs::WINDOWS_1252,  // CP860
+            4 => encoding_rs::WINDOWS_1252,  // CP863
+            5 => encoding_rs::WINDOWS_1252,  // CP865
+            16 => encoding_rs::WINDOWS_1252, // Windows-1252 (Western European)
+            17 => encoding_rs::IBM866,       // CP866 (Cyrillic)
+            18 => encoding_rs::WINDOWS_1250, // CP852 -> Windows-1250 (Central European)
+            19 => encoding_rs::WINDOWS_1252, // CP858 (like CP850 with Euro)
+            20 => encoding_rs::SHIFT_JIS,    // Shift JIS (Japanese)
+            21 => encoding_rs::SHIFT_JIS,
+            36 => encoding_rs::WINDOWS_1255, // PC862 (handled specially)
+            37 => encoding_rs::WINDOWS_1256, // PC864 (handled specially)
+            49 => encoding_rs::WINDOWS_1255, // Windows-1255 (Hebrew)
+            50 => encoding_rs::WINDOWS_1256, // Windows-1256 (Arabic)
+            255 => encoding_rs::SHIFT_JIS,
+            _ => encoding_rs::WINDOWS_1252, // Default fallback
+        };
+    }
+}
+
+impl Default for PrinterState {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: false,
+            width_multiplier: 1,
+            one_line_double_width: false,
+            height_multiplier: 1,
+            inverted: false,
+            alignment: Alignment::Left,
+            print_density: 4,
+            encoding: encoding_rs::UTF_8,
+            code_page: 0,
+            horizontal_offset: 0,
+            left_margin: 0,
+            print_area_width: 0, // 0 = use default (full width)
+            line_spacing: 30,    // Default: 1/6 inch = ~30 dots at 203 DPI
+            character_spacing: 0,
+            double_strike: false,
+            font: 0, // Default: Font A
+            rotated: false,
+            barcode_height: 162,
+            barcode_module_width: 3,
+            barcode_hri_position: 0,
+            motion_unit_x: 0,
+            motion_unit_y: 0,
+            // Every 8 Font A characters
+            tab_stops: (1..=MAX_TAB_STOPS as u16)
+                .map(|n| n * 8 * font_width_dots(0))
+                .collect(),
+        }
+    }
+}
+
+// GS a n bits and the ASB frame bits whose change they report:
+// (enable bit, frame byte, bits)
+const ASB_TRIGGERS: [(u8, usize, u8); 4] = [
+    (0x01, 0, 0x04), // Drawer kick-out connector
+    (0x02, 0, 0x68), // Online/offline, cover, feed button
+    (0x04, 1, 0x6C), // Errors
+    (0x08, 2, 0x0F), // Roll paper sensor
+];
+
+// Bytes of an unknown command kept for its marker; the rest are left out
+const MAX_UNKNOWN_COMMAND_BYTES: usize = 64;
+
+// Graphics memory sizes reported to GS ( L queries (TM-T88 class printer)
+const NV_GRAPHICS_CAPACITY: usize = 256 * 1024;
+const DOWNLOAD_GRAPHICS_CAPACITY: usize = 192 * 1024;
+
+/// GS ( L / GS ( C capacity response: header 0x37, identifier, decimal
+/// digits, NUL
+fn capacity_response(identifier: u8, bytes: usize) -> Vec<u8> {
+    let mut response = vec![0x37, identifier];
+    response.extend_from_slice(bytes.to_string().as_bytes());
+    response.push(0x00);
+    response
+}
+
+/// Enlarge MSB-first raster data by whole factors (GS v 0 double width/height).
+/// Returns the new data, width, height and bytes per line.
+fn scale_raster(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bytes_per_line: usize,
+    scale_x: usize,
+    scale_y: usize,
+) -> (Vec<u8>, usize, usize, usize) {
+    if scale_x == 1 && scale_y == 1 {
+        return (data.to_vec(), width, height, bytes_per_line);
+    }
+    let new_width = width * scale_x;
+    let new_bytes_per_line = new_width.div_ceil(8);
+    let new_height = height.min(data.len() / bytes_per_line.max(1)) * scale_y;
+    let mut scaled = vec![0u8; new_bytes_per_line * new_height];
+    fill_rows(&mut scaled, new_bytes_per_line, |y, line| {
+        let row = &data[y / scale_y * bytes_per_line..][..bytes_per_line];
+        for x in 0..width {
+            if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                for dx in 0..scale_x {
+                    let nx = x * scale_x + dx;
+                    line[nx / 8] |= 0x80 >> (nx % 8);
+                }
+            }
+        }
+    });
+    (scaled, new_width, new_height, new_bytes_per_line)
+}
+
+/// Keep the leftmost `width` dots of each row of MSB-first raster data,
+/// clearing the bits past them in the last byte
+fn clip_raster(data: &[u8], bytes_per_line: usize, width: usize) -> Vec<u8> {
+    let new_bytes_per_line = width.div_ceil(8);
+    let mask = match width % 8 {
+        0 => 0xFF,
+        bits => 0xFF << (8 - bits),
+    };
+    let mut clipped = Vec::with_capacity(data.len() / bytes_per_line.max(1) * new_bytes_per_line);
+    for row in data.chunks(bytes_per_line.max(1)) {
+        let start = clipped.len();
+        clipped.extend(row.iter().take(new_bytes_per_line));
+        clipped.resize(start + new_bytes_per_line, 0);
+        if let Some(last) = clipped.last_mut().filter(|_| new_bytes_per_line > 0) {
+            *last &= mask;
+        }
+    }
+    clipped
+}
+
+/// Images with more bytes or pixels than this are decoded on all cores
+const PARALLEL_DECODE_MIN: usize = 256 * 1024;
+
+/// Fill `out` row by row, `row_len` items a row, with `fill(y, row)`. Big
+/// images are done in bands of rows on every core, so decoding a long
+/// report image doesn't hold up a connection or the window for long.
+pub fn fill_rows<T: Send>(out: &mut [T], row_len: usize, fill: impl Fn(usize, &mut [T]) + Sync) {
+    if row_len == 0 {
+        return;
+    }
+    let threads = if out.len() >= PARALLEL_DECODE_MIN {
+        std::thread::available_parallelism().map_or(1, usize::from)
+    } else {
+        1
+    };
+    let rows = out.len() / row_len;
+    let band = rows.div_ceil(threads).max(1);
+    let fill_band = |first: usize, band: &mut [T]| {
+        for (y, row) in band.chunks_mut(row_len).enumerate() {
+            fill(first + y, row);
+        }
+    };
+    if threads == 1 {
+        fill_band(0, out);
+        return;
+    }
+    std::thread::scope(|scope| {
+        for (n, chunk) in out.chunks_mut(band * row_len).enumerate() {
+            let fill_band = &fill_band;
+            scope.spawn(move || fill_band(n * band, chunk));
+        }
+    });
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_zero(value: &u16) -> bool {
+    *value == 0
+}
+
+fn one() -> u8 {
+    1
+}
+
+fn is_one(value: &u8) -> bool {
+    *value == 1
+}
+
+/// Symbol standing for a control character: U+2400-U+241F, U+2421 for DEL
+fn control_picture(byte: u8) -> char {
+    match byte {
+        0x7F => '\u{2421}',
+        b => char::from_u32(0x2400 + u32::from(b)).unwrap_or(char::REPLACEMENT_CHARACTER),
+    }
+}
+
+/// End of the bytes from `start` that a device selected with ESC = gets:
+/// up to the next ESC =, which the printer always reads, or a trailing ESC
+/// that may turn out to be one
+fn peripheral_span_end(data: &[u8], start: usize) -> usize {
+    let mut end = start;
+    while end < data.len() {
+        if data[end] == ESC && data.get(end + 1).is_none_or(|&next| next == b'=') {
+            break;
+        }
+        end += 1;
+    }
+    end
+}
+
+/// Short human-readable name for a command sequence, e.g. "ESC a" or "GS 0x00"
+pub fn command_name(command: &[u8]) -> String {
+    let prefix = match command.first() {
+        Some(&ESC) => "ESC",
+        Some(&GS) => "GS",
+        Some(&FS) => "FS",
+        Some(&DLE) => "DLE",
+        _ => return format!("{:02X?}", command),
+    };
+    match command.get(1) {
+        Some(&cmd) if cmd.is_ascii_graphic() => format!("{} {}", prefix, cmd as char),
+        Some(&cmd) => format!("{} 0x{:02X}", prefix, cmd),
+        None => prefix.to_string(),
+    }
+}
+
+pub struct EscPosRenderer {
+    state: PrinterState,
+    current_line: Vec<u8>, // Store raw bytes, decode using current encoding when flushing
+    line_runs: Vec<(u16, Vec<u8>)>, // Earlier runs of the current line, with their x position
+    debug: bool,
+    buffer: Vec<u8>,
+    awaiting: Option<(&'static str, usize)>, // Command at buffer[0] waiting for data: (name, size)
+    elements: Vec<ReceiptElement>,
+    in_command_sequence: bool,
+    qr_data: Vec<u8>,
+    qr_size: u8,
+    qr_error_correction: u8,
+    response_queue: Vec<u8>,
+    last_was_binary: bool, // Track if last command was binary (raster, etc.)
+    hooks: Option<Arc<ScriptHooks>>,
+    nv_memory: Arc<Mutex<NvMemory>>, // GS ( C user memory, shared by connections
+    custom_values: Arc<Mutex<CustomValues>>, // GS ( M saved settings, shared by connections
+    maintenance: Arc<Mutex<MaintenanceCounters>>, // GS g counters, shared by connections
+    status: SimulatedStatus,         // Conditions reported in status responses
+    paper_size: PaperSize,           // Lines wrap at the paper (or GS W area) width
+    columns: [u16; 3],               // Columns per line set for Fonts A, B and C, 0 for their own
+    custom_code_pages: CustomCodePages, // Tables loaded from mapping files, by ESC t number
+    asb_mask: u8,                    // GS a n: status changes reported automatically
+    last_asb: [u8; 4],               // Last ASB frame sent, to detect changes
+    job_boundary: JobBoundary,
+    init_starts_job: bool, // ESC @ ends the job whatever the boundary
+    stream_offset: u64,    // Connection byte offset of buffer[0]
+    job_boundaries: Vec<(u64, usize)>, // Jobs ended: (byte offset, element index)
+    lint: Linter,
+    unknown_command: bool, // Set by handlers that skipped a command they don't know
+    drawer_pulses: Vec<DrawerPulse>, // Drawer kicks since the last take_drawer_pulses
+    command_offset: u64,   // Connection byte offset of the command being parsed
+    commands: Vec<(u64, String)>, // Commands processed since the last take_commands
+    error_recovered: bool, // DLE ENQ cleared an error since the last take_error_recovery
+    default_code_page: u8, // Code page before any ESC t, like a printer's DIP switches
+    code_page_selected: bool, // ESC t seen since the last reset
+    forced_code_page: Option<u8>, // Code page ESC t can't change, for clients that pick the wrong one
+    control_glyphs: bool,         // Print stray control characters as symbols
+    end_of_job: bool,             // No more data is coming: nothing is held back for it
+    line_overflow: bool,          // The current line is being wrapped at the print area edge
+    dc4_enabled: [bool; 2],       // GS ( D: DLE DC4 fn 1 (pulse) and fn 2 (power off) enabled
+    profile: Option<CommandProfile>, // Time spent per command, when profiling
+    trace: Option<Vec<TraceStep>>, // Each command and the elements it produced, when tracing
+    command_started: std::time::Instant, // Start of the command being parsed, when profiling
+    peripheral: u8,               // ESC = n: bit 0 printer, bit 1 customer display
+    customer_display: Arc<Mutex<CustomerDisplay>>, // Shared by connections
+    display_fed_until: u64,       // Connection byte offset the display has been sent up to
+    display_updated: bool,        // Bytes went to the display since take_display_updated
+    pjl: bool,                    // After a UEL, until the printer language is entered
+    cups_raster: Option<cups_raster::Stream>, // The job is CUPS raster instead of ESC/POS
+}
+
+impl EscPosRenderer {
+    pub fn new(debug: bool) -> Self {
+        Self {
+            state: PrinterState::default(),
+            current_line: Vec::new(),
+            line_runs: Vec::new(),
+            debug,
+            buffer: Vec::new(),
+            awaiting: None,
+            elements: Vec::new(),
+            in_command_sequence: false,
+            qr_data: Vec::new(),
+            qr_size: 3,
+            qr_error_correction: 0,
+            response_queue: Vec::new(),
+            last_was_binary: false,
+            hooks: None,
+            nv_memory: Arc::default(),
+            custom_values: Arc::default(),
+            maintenance: Arc::default(),
+            status: SimulatedStatus::default(),
+            paper_size: PaperSize::Size80mm,
+            columns: [0; 3],
+            custom_code_pages: CustomCodePages::new(),
+            asb_mask: 0,
+            last_asb: [0; 4],
+            job_boundary: JobBoundary::default(),
+            init_starts_job: false,
+            stream_offset: 0,
+            job_boundaries: Vec::new(),
+            lint: Linter::default(),
+            unknown_command: false,
+            drawer_pulses: Vec::new(),
+            command_offset: 0,
+            commands: Vec::new(),
+            error_recovered: false,
+            default_code_page: 0,
+            code_page_selected: false,
+            forced_code_page: None,
+            control_glyphs: false,
+            line_overflow: false,
+            dc4_enabled: [true; 2],
+            end_of_job: false,
+            profile: None,
+            trace: None,
+            command_started: std::time::Instant::now(),
+            peripheral: 1,
+            customer_display: Arc::default(),
+            display_fed_until: 0,
+            display_updated: false,
+            pjl: false,
+            cups_raster: None,
+        }
+    }
+
+    pub fn set_status(&mut self, status: SimulatedStatus) {
+        self.set_default_code_page(status.code_page);
+        self.set_columns(status.columns);
+        self.status = SimulatedStatus {
+            buffer_full: self.status.buffer_full,
+            ..status
+        };
+    }
+
+    /// Take a new simulated status between reads. Returns the ASB frame to
+    /// send if ASB is enabled for one of the conditions that changed.
+    pub fn status_changed(&mut self, status: SimulatedStatus) -> Option<[u8; 4]> {
+        self.set_status(status);
+        let status = self.status;
+        if self.asb_mask == 0 {
+            return None;
+        }
+        let frame = status.asb();
+        let previous = std::mem::replace(&mut self.last_asb, frame);
+        let triggered = ASB_TRIGGERS.iter().any(|&(enable, byte, bits)| {
+            self.asb_mask & enable != 0 && (frame[byte] ^ previous[byte]) & bits != 0
+        });
+        triggered.then_some(frame)
+    }
+
+    /// Report the connection's receive buffer as full (busy) or not, with an
+    /// ASB frame to send like any other status change
+    pub fn set_buffer_full(&mut self, full: bool) -> Option<[u8; 4]> {
+        if self.status.buffer_full == full {
+            return None;
+        }
+        self.status.buffer_full = full;
+        self.status_changed(self.status)
+    }
+
+    /// Reset the print settings, as ESC @ does: to the settings saved with
+    /// GS ( M if they were selected for initialization, else the defaults
+    fn initialize(&mut self) {
+        let initial = self.custom_values.lock().unwrap().initial();
+        match initial {
+            Some(state) => self.load_settings(state),
+            None => self.load_default_settings(),
+        }
+    }
+
+    fn load_default_settings(&mut self) {
+        self.state = PrinterState::default();
+        // Back to the DIP switch code page
+        self.state
+            .select_code_page(self.forced_code_page.unwrap_or(self.default_code_page));
+        self.code_page_selected = false;
+    }
+
+    /// Replace the print settings with saved ones, their code page included
+    fn load_settings(&mut self, state: PrinterState) {
+        self.state = state;
+        self.state
+            .select_code_page(self.forced_code_page.unwrap_or(self.state.code_page));
+        self.code_page_selected = true;
+    }
+
+    /// Change the code page used until ESC t selects one. Text already
+    /// printed keeps the code page it was decoded with.
+    fn set_default_code_page(&mut self, code_page: u8) {
+        self.default_code_page = code_page;
+        if !self.code_page_selected && self.forced_code_page.is_none() {
+            self.state.select_code_page(code_page);
+        }
+    }
+
+    /// Print with this code page whatever ESC t selects, or stop forcing one
+    pub fn set_forced_code_page(&mut self, forced_code_page: Option<u8>) {
+        self.forced_code_page = forced_code_page;
+        let code_page = match forced_code_page {
+            Some(code_page) => code_page,
+            None if self.code_page_selected => return,
+            None => self.default_code_page,
+        };
+        self.state.select_code_page(code_page);
+    }
+
+    /// Set the columns per line of Fonts A, B and C, 0 for their own count.
+    /// Lines already printed keep theirs.
+    fn set_columns(&mut self, columns: [u16; 3]) {
+        self.columns = columns;
+    }
+
+    /// Set the code pages loaded from mapping files. Text already printed
+    /// keeps the characters it was decoded to.
+    pub fn set_custom_code_pages(&mut self, code_pages: CustomCodePages) {
+        self.custom_code_pages = code_pages;
+    }
+
+    pub fn set_control_glyphs(&mut self, control_glyphs: bool) {
+        self.control_glyphs = control_glyphs;
+    }
+
+    pub fn set_paper_size(&mut self, paper_size: PaperSize) {
+        self.paper_size = paper_size;
+    }
+
+    pub fn set_nv_memory(&mut self, nv_memory: Arc<Mutex<NvMemory>>) {
+        self.nv_memory = nv_memory;
+    }
+
+    /// Share the GS ( M saved settings, starting from them if they were
+    /// selected for initialization, as a printer does at power on
+    pub fn set_custom_values(&mut self, custom_values: Arc<Mutex<CustomValues>>) {
+        self.custom_values = custom_values;
+        self.initialize();
+    }
+
+    pub fn set_customer_display(&mut self, display: Arc<Mutex<CustomerDisplay>>) {
+        self.customer_display = display;
+    }
+
+    pub fn set_maintenance_counters(&mut self, maintenance: Arc<Mutex<MaintenanceCounters>>) {
+        self.maintenance = maintenance;
+    }
+
+    pub fn set_hooks(&mut self, hooks: Option<Arc<ScriptHooks>>) {
+        self.hooks = hooks;
+    }
+
+    fn log_debug(&self, msg: &str) {
+        if self.debug {
+            eprintln!("[DEBUG] {}", msg);
+        }
+    }
+
+    pub fn take_elements(&mut self) -> Vec<ReceiptElement> {
+        std::mem::take(&mut self.elements)
+    }
+
+    pub fn set_job_boundary(&mut self, job_boundary: JobBoundary) {
+        self.job_boundary = job_boundary;
+    }
+
+    pub fn set_init_starts_job(&mut self, init_starts_job: bool) {
+        self.init_starts_job = init_starts_job;
+    }
+
+    /// Note that a job ends before `data[index]` (and after the elements
+    /// produced so far) if `kind` is the configured boundary, or ESC @ with
+    /// init_starts_job set
+    fn mark_job_boundary(&mut self, kind: JobBoundary, index: usize) {
+        if self.job_boundary == kind || (kind == JobBoundary::Initialize && self.init_starts_job) {
+            self.job_boundaries
+                .push((self.stream_offset + index as u64, self.elements.len()));
+            self.lint.job_ended();
+        }
+    }
+
+    /// Note that the command at `data[start]` can't be processed until the
+    /// data up to `end` has arrived
+    fn await_data(&mut self, command: &'static str, start: usize, end: usize) {
+        self.awaiting = Some((command, end - start));
+    }
+
+    /// Command whose data is still arriving: (name, bytes received, bytes it
+    /// takes)
+    pub fn receiving(&self) -> Option<(&'static str, usize, usize)> {
+        self.awaiting
+            .map(|(command, size)| (command, self.buffer.len(), size))
+    }
+
+    /// Job boundaries found since the last call, as (connection byte offset,
+    /// index into the elements not yet taken)
+    pub fn take_job_boundaries(&mut self) -> Vec<(u64, usize)> {
+        std::mem::take(&mut self.job_boundaries)
+    }
+
+    /// Lint warnings found since the last call, at connection byte offsets
+    pub fn take_lint_warnings(&mut self) -> Vec<LintWarning> {
+        self.lint.take_warnings()
+    }
+
+    /// Names of the commands processed, with their connection byte offsets
+    pub fn take_commands(&mut self) -> Vec<(u64, String)> {
+        std::mem::take(&mut self.commands)
+    }
+
+    /// Drive a kick-out pulse. The drawer on its pin opens, so status
+    /// requests later in the same data already see it open.
+    fn kick_drawer(&mut self, pulse: DrawerPulse) {
+        self.status.kick_drawer(pulse.pin);
+        self.drawer_pulses.push(pulse);
+    }
+
+    pub fn take_drawer_pulses(&mut self) -> Vec<DrawerPulse> {
+        std::mem::take(&mut self.drawer_pulses)
+    }
+
+    pub fn take_display_updated(&mut self) -> bool {
+        std::mem::take(&mut self.display_updated)
+    }
+
+    pub fn take_error_recovery(&mut self) -> bool {
+        std::mem::take(&mut self.error_recovered)
+    }
+
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.response_queue)
+    }
+
+    /// Queue a response to a status query, letting the hooks script replace it
+    fn queue_response(&mut self, query: &[u8], response: &[u8]) {
+        let replaced = self
+            .hooks
+            .as_ref()
+            .and_then(|hooks| hooks.on_status_query(query, response));
+        match replaced {
+            Some(bytes) => {
+                self.log_debug(&format!(
+                    "Script replaced response {:02X?} with {:02X?}",
+                    response, bytes
+                ));
+                self.response_queue.extend_from_slice(&bytes);
+            }
+            None => self.response_queue.extend_from_slice(response),
+        }
+    }
+
+    fn command_processed(&mut self, command: &[u8]) {
+        if let Some(profile) = &mut self.profile {
+            let (count, time) = profile.entry(command_name(command)).or_default();
+            *count += 1;
+            *time += self.command_started.elapsed();
+        }
+        if std::mem::take(&mut self.unknown_command) {
+            self.elements.push(ReceiptElement::UnknownCommand {
+                bytes: command[..command.len().min(MAX_UNKNOWN_COMMAND_BYTES)].to_vec(),
+            });
+        }
+        self.lint.command(command);
+        self.commands
+            .push((self.command_offset, command_name(command)));
+        self.run_command_hook(command);
+    }
+
+    /// Give the hooks script a look at a fully processed command
+    fn run_command_hook(&mut self, command: &[u8]) {
+        let Some(hooks) = &self.hooks else {
+            return;
+        };
+        if let Some(bytes) = hooks.on_command(&command_name(command), command) {
+            self.response_queue.extend_from_slice(&bytes);
+        }
+    }
+
+    pub fn process_data(&mut self, new_data: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(new_data);
+
+        // Taken rather than copied: an image arriving over many reads stays
+        // in the buffer until complete, and copying it on every read made
+        // receiving it quadratic
+        let data = std::mem::take(&mut self.buffer);
+        let result = self.parse_buffered(&data);
+        self.buffer = data;
+        let i = result?;
+
+        self.buffer.drain(0..i);
+        self.stream_offset += i as u64;
+
+        // Don't auto-flush at buffer end - only flush on explicit line terminators (LF, CR)
+        // This prevents fragmenting text that arrives in multiple TCP packets
+
+        Ok(())
+    }
+
+    /// The job's data is all in: print the text held back for more data
+    /// and the line after the last line feed. Incomplete commands are left
+    /// unprocessed.
+    pub fn end_job(&mut self) -> Result<()> {
+        let result = self.process_held_back();
+        self.flush_line();
+        result
+    }
+
+    /// Process what was held back for more data, now that none is coming
+    fn process_held_back(&mut self) -> Result<()> {
+        self.end_of_job = true;
+        let result = self.process_data(&[]);
+        self.end_of_job = false;
+        result
+    }
+
+    /// Process the complete commands and text at the start of the buffered
+    /// data, returning how many bytes were used
+    fn parse_buffered(&mut self, data: &[u8]) -> Result<usize> {
+        let mut i = 0;
+        self.awaiting = None;
+
+        while i < data.len() {
+            let byte = data[i];
+            let start_pos = i;
+            if self.profile.is_some() {
+                self.command_started = std::time::Instant::now();
+            }
+            self.command_offset = self.stream_offset + i as u64;
+            self.lint.set_offset(self.command_offset);
+            let elements_before = self.elements.len();
+
+            // After ESC = the bytes up to the next one go to the customer
+            // display, the printer or both
+            if self.peripheral != 1 {
+                let end = peripheral_span_end(data, i);
+                if end > i {
+                    if self.peripheral & 2 != 0 && self.command_offset >= self.display_fed_until {
+                        self.customer_display.lock().unwrap().feed(&data[i..end]);
+                        self.display_fed_until = self.stream_offset + end as u64;
+                        self.display_updated = true;
+                    }
+                    if self.peripheral & 1 == 0 {
+                        if let Some(trace) = &mut self.trace {
+                            trace.push(TraceStep {
+                                start: self.command_offset,
+                                end: self.stream_offset + end as u64,
+                                command: "customer display".to_string(),
+                                elements: Vec::new(),
+                            });
+                        }
+                        i = end;
+                        continue;
+                    }
+                }
+            }
+
+            // CUPS or PWG raster straight from a CUPS filter: the job is
+            // pages of pixels from its first byte on
+            if self.command_offset == 0 {
+                match cups_raster::start(&data[i..]) {
+                    cups_raster::Start::Incomplete => {
+                        i = start_pos;
+                        break;
+                    }
+                    cups_raster::Start::Raster(format) => {
+                        let decoder = cups_raster::Decoder::new(format);
+                        self.cups_raster = Some(cups_raster::Stream::Pages(decoder));
+                        i += cups_raster::SYNC_LEN;
+                    }
+                    cups_raster::Start::NotRaster => {}
+                }
+            }
+            if let Some(stream) = &mut self.cups_raster {
+                let fed = match stream {
+                    cups_raster::Stream::Pages(decoder) => decoder.feed(&data[i..]),
+                    cups_raster::Stream::Broken => Ok((data.len() - i, None)),
+                };
+                match fed {
+                    Ok((len, page)) => {
+                        i += len;
+                        if let Some(page) = page {
+                            self.push_raster(
+                                page.width,
+                                page.height,
+                                page.data,
+                                page.bytes_per_line,
+                            );
+                            if page.cut {
+                                self.elements.push(ReceiptElement::PaperCut {
+                                    cut_type: "FULL CUT".to_string(),
+                                });
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        self.log_debug(&format!("CUPS raster: {:#}", e));
+                        self.lint.raster_dropped(&format!("{:#}", e));
+                        self.cups_raster = Some(cups_raster::Stream::Broken);
+                        i = data.len();
+                    }
+                }
+                if i == start_pos {
+                    // Waiting for the rest of a header or line
+                    break;
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.push(TraceStep {
+                        start: self.command_offset,
+                        end: self.stream_offset + i as u64,
+                        command: "CUPS raster".to_string(),
+                        elements: (elements_before..self.elements.len()).collect(),
+                    });
+                }
+                continue;
+            }
+
+            // PJL wrapping from office printer drivers: skip the commands,
+            // answer the queries and parse the payload
+            if self.pjl || byte == ESC {
+                let len = match pjl::next(&data[i..]) {
+                    pjl::Next::Incomplete => {
+                        i = start_pos;
+                        break;
+                    }
+                    pjl::Next::Uel => {
+                        self.pjl = true;
+                        pjl::UEL.len()
+                    }
+                    pjl::Next::Command { line, len } => {
+                        self.log_debug(&format!("PJL: {}", line));
+                        if let Some(response) = pjl::response(line, &self.status) {
+                            self.queue_response(line.as_bytes(), &response);
+                        }
+                        if pjl::enters_language(line) {
+                            self.pjl = false;
+                        }
+                        len
+                    }
+                    pjl::Next::Payload => {
+                        self.pjl = false;
+                        0
+                    }
+                };
+                if len > 0 {
+                    i += len;
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(TraceStep {
+                            start: self.command_offset,
+                            end: self.stream_offset + i as u64,
+                            command: "PJL".to_string(),
+                            elements: Vec::new(),
+                        });
+                    }
+                    continue;
+                }
+            }
+
+            match byte {
+                DLE => {
+                    // Enter command sequence - block text accumulation
+                    self.in_command_sequence = true;
+                    // DLE commands (real-time status, etc.)
+                    i += 1;
+                    if i >= data.len() {
+                        i = start_pos;
+                        break;
+                    }
+                    let subcmd = data[i];
+                    i += 1;
+                    // DLE EOT 7, 8 and 18 take a second parameter, DLE DC4
+                    // 1 (pulse) and 2 (power off) take two after fn
+                    let params = match (subcmd, data.get(i)) {
+                        (EOT, Some(7 | 8 | 18)) => 2,
+                        (EOT | ENQ, _) => 1,
+                        (DC4, Some(1 | 2)) => 3,
+                        (DC4, _) => 2,
+                        _ => 0,
+                    };
+                    if i + params > data.len() {
+                        i = start_pos;
+                        break;
+                    }
+                    match subcmd {
+                        // DLE ENQ n - recover from a recoverable error and
+                        // restart printing (n = 1), after clearing the
+                        // buffered line (n = 2). Ignored without an error.
+                        ENQ => {
+                            let n = data[i];
+                            i += params;
+                            if matches!(n, 1 | 2) && self.status.recover() {
+                                if n == 2 {
+                                    self.current_line.clear();
+                                    self.line_runs.clear();
+                                }
+                                self.error_recovered = true;
+                                self.log_debug(&format!("DLE ENQ {}: recovered from error", n));
+                            } else {
+                                self.log_debug(&format!("DLE ENQ {}: no recoverable error", n));
+                            }
+                        }
+                        EOT => {
+                            // DLE EOT - real-time status
+                            let n = data[i];
+                            let a = (params == 2).then(|| data[i + 1]);
+                            i += params;
+
+                            // Queue status response: 0x12 = online, no errors
+                            // Bits 1 and 4 are fixed, the rest reflect the
+                            // simulated status for the requested type
+                            let status = match a {
+                                Some(a) => self.status.extended_status(n, a),
+                                None => Some(self.status.real_time_status(n)),
+                            };
+                            match status {
+                                Some(status) => {
+                                    self.queue_response(&data[start_pos..i], &[status]);
+                                    self.log_debug(&format!(
+                                        "DLE EOT {}: queued status response 0x{:02X}",
+                                        n, status
+                                    ));
+                                }
+                                None => self.log_debug(&format!(
+                                    "DLE EOT {} {:?}: not supported by the printer profile",
+                                    n, a
+                                )),
+                            }
+                        }
+                        // Disabled by GS ( D: read as ordinary data, which
+                        // prints nothing
+                        DC4 if matches!(data[i], 1 | 2)
+                            && !self.dc4_enabled[data[i] as usize - 1] =>
+                        {
+                            self.log_debug(&format!("DLE DC4 {}: disabled by GS ( D", data[i]));
+                            i += params;
+                        }
+                        // DLE DC4 1 m t - real-time drawer pulse of t x 100 ms
+                        DC4 if data[i] == 1 => {
+                            let (pin, t) = (data[i + 1], data[i + 2]);
+                            self.kick_drawer(DrawerPulse {
+                                command: "DLE DC4",
+                                pin: if pin == 0 { 2 } else { 5 },
+                                on_ms: u32::from(t) * 100,
+                                off_ms: u32::from(t) * 100,
+                            });
+                            i += params;
+                        }
+                        // Other DLE DC4 real-time commands
+                        DC4 => i += params,
+                        _ => {}
+                    }
+                    self.command_processed(&data[start_pos..i]);
+                    // Command processed - allow text accumulation again
+                    self.in_command_sequence = false;
+                }
+                CAN => {
+                    // Cancel print data in page mode, ignored in standard
+                    // mode (page mode isn't emulated)
+                    i += 1;
+                }
+                DC2 => {
+                    // DC2 - Cancel bold OR DC2 # n (print density for zj-58)
+                    i += 1;
+                    if i < data.len() && data[i] == b'#' {
+                        // DC2 # n - Set print density (zj-58 CUPS driver)
+                        i += 1;
+                        if i < data.len() {
+                            let density = data[i];
+                            self.state.print_density = (density / 32).min(8); // Map 0-255 to 0-8
+                            self.log_debug(&format!("DC2 #: print density={}", density));
+                            i += 1;
+                        }
+                    } else {
+                        // Standard DC2 - Cancel bold
+                        self.state.bold = false;
+                    }
+                }
+                DC1 => {
+                    // DC1 / XON - Device control / flow control
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+                DC3 => {
+                    // DC3 / XOFF - Device control / flow control
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+                DC4 if self.status.so_double_width => {
+                    // DC4 - Cancel the one-line double width of SO
+                    self.state.one_line_double_width = false;
+                    i += 1;
+                }
+                DC4 => {
+                    // DC4 - Device control (standalone, not DLE DC4)
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+                SO => {
+                    // SO - Shift Out: double width for the rest of the line
+                    // on legacy printers, ignored by Epson's
+                    if self.status.so_double_width {
+                        self.state.one_line_double_width = true;
+                    }
+                    i += 1;
+                }
+                SI => {
+                    // SI - Shift In: cancels SO
+                    if self.status.so_double_width {
+                        self.state.one_line_double_width = false;
+                    }
+                    i += 1;
+                }
+                VT => {
+                    // VT - Vertical tab
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+                SOH | STX | ETX | EOT | ENQ | ACK | BEL | ETB | RS => {
+                    // Other control characters - just skip
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+                BS => {
+                    // Backspace - remove last byte if present
+                    if !self.current_line.is_empty() {
+                        self.current_line.pop();
+                    }
+                    i += 1;
+                }
+                ESC => {
+                    // Enter command sequence - block text accumulation
+                    self.in_command_sequence = true;
+                    i += 1;
+                    if i >= data.len() {
+                        i = start_pos;
+                        break;
+                    }
+                    match self.handle_esc_command(data, i) {
+                        Ok(new_i) => {
+                            if new_i == i || new_i <= start_pos {
+                                // Handler didn't make progress - waiting for more data
+                                i = start_pos;
+                                // Keep in_command_sequence = true
+                                break;
+                            }
+                            i = new_i;
+                            self.command_processed(&data[start_pos..i]);
+                            // Command fully processed - allow text accumulation again
+                            self.in_command_sequence = false;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                GS => {
+                    // Enter command sequence - block text accumulation
+                    self.in_command_sequence = true;
+                    i += 1;
+                    if i >= data.len() {
+                        i = start_pos;
+                        break;
+                    }
+                    match self.handle_gs_command(data, i) {
+                        Ok(new_i) => {
+                            if new_i == i || new_i <= start_pos {
+                                // Handler didn't make progress - waiting for more data
+                                i = start_pos;
+                                // Keep in_command_sequence = true
+                                break;
+                            }
+                            i = new_i;
+                            self.command_processed(&data[start_pos..i]);
+                            // Command fully processed - allow text accumulation again
+                            self.in_command_sequence = false;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                FS => {
+                    // Enter command sequence - block text accumulation
+                    self.in_command_sequence = true;
+                    i += 1;
+                    if i >= data.len() {
+                        i = start_pos;
+                        break;
+                    }
+                    // FS command handling - many commands have unknown parameter counts
+                    let cmd = data[i];
+                    i += 1;
+                    match cmd {
+                        b'.' => {
+                            // FS . n - Print NV bit image - 1 parameter
+                            // Don't consume parameter if next byte is a command start
+                            if i < data.len() {
+                                let next = data[i];
+                                // Only consume if not a command byte (ESC/GS/FS/DLE)
+                                if next != ESC && next != GS && next != FS && next != DLE {
+                                    i += 1;
+                                }
+                            }
+                        }
+                        b'p' => {
+                            // FS p n m - Print NV bit image - 2 parameters
+                            if i + 1 < data.len() {
+                                i += 2;
+                            }
+                        }
+                        b'q' => {
+                            // FS q n [xL xH yL yH d1...dk] - Define NV bit image
+                            if i < data.len() {
+                                let n = data[i];
+                                i += 1;
+                                if n > 0 && i + 4 < data.len() {
+                                    let xl = data[i] as usize;
+                                    let xh = data[i + 1] as usize;
+                                    let yl = data[i + 2] as usize;
+                                    let yh = data[i + 3] as usize;
+                                    let width = xl + (xh << 8);
+                                    let height = yl + (yh << 8);
+                                    let data_size = width.div_ceil(8) * height;
+                                    i += 4 + data_size.min(data.len() - i);
+                                }
+                            }
+                        }
+                        b'(' => {
+                            // FS ( fn pL pH [data...] - Extended commands with length
+                            if i + 3 < data.len() {
+                                let _fn = data[i]; // function code (e.g., 'A')
+                                let p_l = data[i + 1] as usize;
+                                let p_h = data[i + 2] as usize;
+                                let len = p_l + (p_h << 8);
+                                i += 3 + len.min(data.len() - i);
+                            }
+                        }
+                        b'C' | b'g' | b'!' | b'&' | b'S' | b'-' => {
+                            // Commands with 1 parameter
+                            if i < data.len() {
+                                i += 1;
+                            }
+                        }
+                        _ => {
+                            // Unknown FS subcommands - try to consume 1-2 likely parameter bytes
+                            // Many proprietary commands use 1-2 bytes
+                            self.unknown_command = true;
+                            if i < data.len() && (data[i] < 0x1B || data[i] > 0x7E) {
+                                // Next byte doesn't look like a command start, consume it as parameter
+                                i += 1;
+                                // If it was high-bit, might be a 2-byte parameter
+                                if i < data.len()
+                                    && data[i - 1] > 0x7F
+                                    && (data[i] < 0x1B || data[i] > 0x7E)
+                                {
+                                    i += 1;
+                                }
+                            }
+                            if self.debug {
+                                self.log_debug(&format!(
+                                    "FS command 0x{:02X} - consumed {} parameter bytes",
+                                    cmd,
+                                    i - (start_pos + 2)
+                                ));
+                            }
+                        }
+                    }
+                    self.command_processed(&data[start_pos..i]);
+                    // Command processed - allow text accumulation again
+                    self.in_command_sequence = false;
+                }
+                LF => {
+                    // LF: Print and line feed - flush current line and advance
+                    self.in_command_sequence = false; // Exit command sequence, allow text again
+                    self.last_was_binary = false; // LF marks start of text content
+                    self.lint.line_ended();
+                    if self.has_pending_text() {
+                        self.flush_line();
+                        self.current_line.clear();
+                    } else if !self.elements.is_empty() {
+                        // Only add separator for blank lines if we've already printed something
+                        // This avoids extra spacing after init commands like ESC @
+                        self.elements.push(ReceiptElement::Separator);
+                    }
+                    i += 1;
+                }
+                CR => {
+                    // CR: Print and carriage return - flush current line
+                    self.in_command_sequence = false; // Exit command sequence, allow text again
+                    self.last_was_binary = false; // CR marks start of text content
+                    self.lint.line_ended();
+                    if self.has_pending_text() {
+                        self.flush_line();
+                        self.current_line.clear();
+                    }
+                    i += 1;
+                }
+                FF => {
+                    self.current_line.clear();
+                    self.line_runs.clear();
+                    // Only add FormFeed if the last element isn't already one
+                    if !matches!(self.elements.last(), Some(ReceiptElement::FormFeed)) {
+                        self.elements.push(ReceiptElement::FormFeed);
+                    }
+                    i += 1;
+                }
+                HT => {
+                    // Only add tabs if not in command sequence
+                    if !self.in_command_sequence {
+                        self.horizontal_tab();
+                    }
+                    i += 1;
+                }
+                0x20..=0x7E | 0x80..=0xFF => {
+                    // Printable characters (both ASCII and extended codepage)
+                    if i == data.len() - 1 && !self.end_of_job {
+                        break;
+                    }
+                    // Only accumulate text if we're NOT in a command sequence AND not after binary data
+                    if !self.in_command_sequence && !self.last_was_binary {
+                        if self.debug {
+                            self.log_debug(&format!(
+                                "Adding byte to line: 0x{:02X} at position {}",
+                                byte, i
+                            ));
+                        }
+                        self.push_text_byte(byte);
+                    }
+                    i += 1;
+                }
+                0x00..=0x1F | 0x7F => {
+                    // Control characters (including DEL)
+                    // Silently consume these - they're control codes, not printable text
+                    self.stray_control_byte(byte);
+                    i += 1;
+                }
+            }
+            self.lint.elements(&self.elements[elements_before..]);
+            if self.trace.is_some() {
+                self.trace_step(&data[start_pos..i], elements_before);
+            }
+        }
+
+        Ok(i)
+    }
+
+    /// Record what the bytes just parsed were and the elements they
+    /// produced. Runs of text that produced nothing are one step.
+    fn trace_step(&mut self, bytes: &[u8], elements_before: usize) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let (start, end) = (
+            self.command_offset,
+            self.command_offset + bytes.len() as u64,
+        );
+        let command = match bytes[0] {
+            ESC | GS | FS | DLE => command_name(bytes),
+            LF => "LF".to_string(),
+            CR => "CR".to_string(),
+            FF => "FF".to_string(),
+            HT => "HT".to_string(),
+            0x20..=0x7E | 0x80..=0xFF => "text".to_string(),
+            byte => format!("0x{:02X}", byte),
+        };
+        let elements: Vec<usize> = (elements_before..self.elements.len()).collect();
+        if let Some(last) = trace.last_mut() {
+            let text_run = command == "text" && last.command == "text" && last.end == start;
+            if text_run && elements.is_empty() && last.elements.is_empty() {
+                last.end = end;
+                return;
+            }
+        }
+        trace.push(TraceStep {
+            start,
+            end,
+            command,
+            elements,
+        });
+    }
+
+    /// Add a character to the current line, wrapping onto a new line first
+    /// if it would not fit, the way the printer does
+    fn push_text_byte(&mut self, byte: u8) {
+        let char_width = self.char_width_dots();
+        let end = self.print_position().saturating_add(char_width);
+        if self.has_pending_text() && end > self.text_width_dots() {
+            if self.debug {
+                self.log_debug("Line full, wrapping");
+            }
+            self.lint
+                .text_wrapped(end, self.text_width_dots(), char_width);
+            self.line_overflow = true;
+            self.flush_line();
+            self.current_line.clear();
+        }
+        self.current_line.push(byte);
+    }
+
+    /// A control character that isn't a command. The printer drops it; with
+    /// control_glyphs it goes on the line to be drawn as a symbol, so
+    /// corrupted data shows in the preview.
+    fn stray_control_byte(&mut self, byte: u8) {
+        if self.control_glyphs && !self.in_command_sequence && !self.last_was_binary {
+            self.push_text_byte(byte);
+        }
+    }
+
+    /// Pad the line with spaces up to the next tab stop. Without a stop
+    /// further on the line, HT is ignored.
+    fn horizontal_tab(&mut self) {
+        let position = self.print_position();
+        let Some(&stop) = self.state.tab_stops.iter().find(|&&stop| stop > position) else {
+            return;
+        };
+        if stop > self.text_width_dots() {
+            return;
+        }
+        let char_width = self.char_width_dots().max(1);
+        for _ in 0..(stop - position).div_ceil(char_width) {
+            self.push_text_byte(b' ');
+        }
+    }
+
+    /// Usable line width in dots: the GS W print area (or the paper width)
+    /// minus the GS L left margin
+    fn line_width_dots(&self) -> u16 {
+        let paper_width = self.paper_size.width_px() as u16;
+        let area = if self.state.print_area_width > 0 {
+            self.state.print_area_width.min(paper_width)
+        } else {
+            paper_width
+        };
+        area.saturating_sub(self.state.left_margin)
+    }
+
+    /// Add a raster image at the current position, clipped to the dots the
+    /// print head has from there to the end of the line: a real printer
+    /// drops the rest, so an image from a driver set up for wider paper
+    /// previews cut off the way it prints. Resets the ESC $ offset.
+    fn push_raster(&mut self, width: usize, height: usize, data: Vec<u8>, bytes_per_line: usize) {
+        let offset = self.state.horizontal_offset;
+        let available = usize::from(self.line_width_dots().saturating_sub(offset));
+        let (width, data, bytes_per_line) = if width > available {
+            self.lint.image_clipped(width, available);
+            (
+                available,
+                clip_raster(&data, bytes_per_line, available),
+                available.div_ceil(8),
+            )
+        } else {
+            (width, data, bytes_per_line)
+        };
+        self.elements.push(ReceiptElement::RasterImage {
+            width,
+            height,
+            data,
+            offset,
+            density: self.state.print_density,
+            alignment: self.state.alignment.clone(),
+            bytes_per_line,
+            print_area_width: self.state.print_area_width,
+            left_margin: self.state.left_margin,
+        });
+
+        // Reset offset after rendering
+        self.state.horizontal_offset = 0;
+
+        // Mark that we just processed binary data - don't treat following ASCII bytes as text
+        self.last_was_binary = true;
+    }
+
+    fn has_pending_text(&self) -> bool {
+        !self.current_line.is_empty() || !self.line_runs.is_empty()
+    }
+
+    /// Decode line bytes using the current codepage. Control characters
+    /// (only on the line with control_glyphs) become their Unicode control
+    /// pictures, e.g. ␀ and ␛.
+    fn decode_text(&self, bytes: &[u8]) -> String {
+        if !self.control_glyphs {
+            return self.decode_code_page(bytes);
+        }
+        let is_control = |b: &u8| *b < 0x20 || *b == 0x7F;
+        let mut text = String::new();
+        for chunk in bytes.chunk_by(|a, b| is_control(a) == is_control(b)) {
+            if is_control(&chunk[0]) {
+                text.extend(chunk.iter().map(|&b| control_picture(b)));
+            } else {
+                text.push_str(&self.decode_code_page(chunk));
+            }
+        }
+        text
+    }
+
+    fn decode_code_page(&self, bytes: &[u8]) -> String {
+        if let Some(page) = self.custom_code_pages.get(&self.state.code_page) {
+            codepages::decode(&page.table, bytes)
+        } else if self.state.code_page == 0 {
+            // CP437 - use codepage-437 crate
+            String::borrow_from_cp437(bytes, &CP437_CONTROL)
+        } else if self.state.code_page == 36 {
+            codepages::decode(&codepages::CP862, bytes)
+        } else if self.state.code_page == 37 {
+            codepages::decode(&codepages::CP864, bytes)
+        } else {
+            // Other codepages - use encoding_rs
+            let (decoded_cow, _encoding_used, had_errors) = self.state.encoding.decode(bytes);
+
+            if self.debug {
+                if had_errors {
+                    self.log_debug(&format!(
+                        "Decoding errors in line, codepage={}",
+                        self.state.code_page
+                    ));
+                }
+                self.log_debug(&format!("Decoded: {:?}", decoded_cow));
+            }
+
+            decoded_cow.into_owned()
+        }
+    }
+
+    /// Describe what's wrong with decoding a line's bytes in the current code
+    /// page: UTF-8 text, which prints as mojibake, or bytes without a
+    /// character
+    fn decoding_problem(&self, bytes: &[u8]) -> Option<String> {
+        let non_ascii: Vec<u8> = bytes.iter().copied().filter(|b| *b >= 0x80).collect();
+        if non_ascii.is_empty() {
+            return None;
+        }
+        let code_page = code_page_name(self.state.code_page, &self.custom_code_pages);
+        let listed = |bytes: &[u8]| {
+            let mut text: Vec<String> =
+                bytes.iter().take(8).map(|b| format!("{:02X}", b)).collect();
+            if bytes.len() > 8 {
+                text.push("...".to_string());
+            }
+            text.join(" ")
+        };
+        if std::str::from_utf8(bytes).is_ok() {
+            return Some(format!(
+                "{}: bytes {} look like UTF-8 text, which this code page prints as other characters",
+                code_page,
+                listed(&non_ascii)
+            ));
+        }
+        let single_byte = matches!(self.state.code_page, 0 | 36 | 37)
+            || self.custom_code_pages.contains_key(&self.state.code_page)
+            || self.state.encoding.is_single_byte();
+        let undecodable: Vec<u8> = if single_byte {
+            let mut undecodable: Vec<u8> = non_ascii
+                .into_iter()
+                .filter(|&b| {
+                    self.decode_code_page(&[b])
+                        .contains(char::REPLACEMENT_CHARACTER)
+                })
+                .collect();
+            undecodable.dedup();
+            undecodable
+        } else if self
+            .decode_code_page(bytes)
+            .contains(char::REPLACEMENT_CHARACTER)
+        {
+            // Which bytes of a multi-byte encoding are at fault isn't
+            // worth working out
+            non_ascii
+        } else {
+            Vec::new()
+        };
+        (!undecodable.is_empty()).then(|| {
+            format!(
+                "{}: bytes {} have no character in this code page",
+                code_page,
+                listed(&undecodable)
+            )
+        })
+    }
+
+    /// Move the print position within the current line (ESC $ / ESC \).
+    /// Text already on the line becomes its own run so both parts keep
+    /// their positions.
+    fn set_print_position(&mut self, x: u16) {
+        if !self.current_line.is_empty() {
+            let run = std::mem::take(&mut self.current_line);
+            self.line_runs.push((self.state.horizontal_offset, run));
+        }
+        self.state.horizontal_offset = x;
+    }
+
+    /// Columns per line set for `font`, 0 for its own count
+    fn font_columns(&self, font: u8) -> u16 {
+        self.columns.get(usize::from(font)).copied().unwrap_or(0)
+    }
+
+    /// Character cell width of `font` in dots: its own, or the paper width
+    /// split into the columns set for it, rounded down
+    fn cell_width_dots(&self, font: u8) -> u16 {
+        match self.font_columns(font) {
+            0 => font_width_dots(font),
+            columns => (self.paper_size.width_px() as u16 / columns).max(1),
+        }
+    }
+
+    /// How many times the normal width characters print, selected for good
+    /// or, double width with SO, for the line
+    fn width_multiplier(&self) -> u8 {
+        if self.state.one_line_double_width {
+            self.state.width_multiplier.max(2)
+        } else {
+            self.state.width_multiplier
+        }
+    }
+
+    /// Horizontal advance of one character in dots
+    fn char_width_dots(&self) -> u16 {
+        let base = self.cell_width_dots(self.state.font);
+        let width = base.saturating_mul(self.width_multiplier().into());
+        width.saturating_add(self.state.character_spacing as u16)
+    }
+
+    /// Width text wraps at: the line width, but no more than the columns set
+    /// for the font, as rounding their cells down leaves room for more
+    fn text_width_dots(&self) -> u16 {
+        let line_width = self.line_width_dots();
+        match self.font_columns(self.state.font) {
+            0 => line_width,
+            columns => {
+                let columns_width = columns.saturating_mul(self.cell_width_dots(self.state.font));
+                line_width.min(columns_width.saturating_sub(self.state.left_margin))
+            }
+        }
+    }
+
+    /// Current print position in dots: start of the current run plus its text
+    fn print_position(&self) -> u16 {
+        let advance = (self.current_line.len() as u16).saturating_mul(self.char_width_dots());
+        self.state.horizontal_offset.saturating_add(advance)
+    }
+
+    /// Print the buffered line, as commands that print before they act do.
+    /// Returns whether there was anything to print.
+    fn print_buffer(&mut self) -> bool {
+        self.lint.line_ended();
+        let printed = self.has_pending_text();
+        self.flush_line();
+        self.current_line.clear();
+        self.state.one_line_double_width = false;
+        printed
+    }
+
+    fn flush_line(&mut self) {
+        if !self.has_pending_text() {
+            return;
+        }
+
+        if self.debug {
+            self.log_debug(&format!(
+                "Flushing line: {} bytes in {} run(s), codepage={}",
+                self.current_line.len(),
+                self.line_runs.len() + 1,
+                self.state.code_page
+            ));
+        }
+
+        let decoded;
+        let mut runs = Vec::new();
+        let offset;
+        let decoding_problem = {
+            let mut bytes: Vec<u8> = self
+                .line_runs
+                .iter()
+                .flat_map(|(_, run)| run)
+                .copied()
+                .collect();
+            bytes.extend_from_slice(&self.current_line);
+            self.decoding_problem(&bytes)
+        };
+        if let Some(problem) = &decoding_problem {
+            self.lint.decoding_problem(problem);
+        }
+
+        if self.line_runs.is_empty() {
+            decoded = self.decode_text(&self.current_line);
+            offset = self.state.horizontal_offset;
+        } else {
+            let mut line_runs = std::mem::take(&mut self.line_runs);
+            if !self.current_line.is_empty() {
+                line_runs.push((self.state.horizontal_offset, self.current_line.clone()));
+            }
+            for (x, bytes) in &line_runs {
+                runs.push(TextRun {
+                    x: *x,
+                    content: self.decode_text(bytes),
+                });
+            }
+            offset = runs.first().map_or(0, |run| run.x);
+
+            // Plain-text content pads each run out to its column, so exports
+            // keep the two-column layout
+            let char_width = self.char_width_dots().max(1) as usize;
+            let mut line = String::new();
+            for run in &runs {
+                let column = run.x as usize / char_width;
+                let used = line.chars().count();
+                if column > used {
+                    line.push_str(&" ".repeat(column - used));
+                } else if used > 0 {
+                    line.push(' ');
+                }
+                line.push_str(&run.content);
+            }
+            decoded = line;
+        }
+
+        self.elements.push(ReceiptElement::Text {
+            content: decoded,
+            bold: self.state.bold,
+            underline: self.state.underline,
+            double_width: self.width_multiplier() > 1,
+            double_height: self.state.height_multiplier > 1,
+            inverted: self.state.inverted,
+            alignment: self.state.alignment.clone(),
+            density: self.state.print_density,
+            offset,
+            left_margin: self.state.left_margin,
+            character_spacing: self.state.character_spacing,
+            double_strike: self.state.double_strike,
+            font: self.state.font,
+            print_area_width: self.state.print_area_width,
+            runs,
+            overflow: std::mem::take(&mut self.line_overflow),
+            decoding_problem,
+            cell_width: match self.font_columns(self.state.font) {
+                0 => 0,
+                _ => self.cell_width_dots(self.state.font),
+            },
+            width_multiplier: self.width_multiplier(),
+            height_multiplier: self.state.height_multiplier,
+        });
+
+        // Reset horizontal offset after use (ESC $ is one-time positioning)
+        self.state.horizontal_offset = 0;
+    }
+
+    fn handle_esc_command(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
+        let cmd = data[i];
+        match cmd {
+            b'@' => {
+                // The job starts with the ESC @, not after it
+                self.mark_job_boundary(JobBoundary::Initialize, i - 1);
+                self.initialize();
+                i += 1;
+            }
+            b'E' => {
+                i += 1;
+                if i < data.len() {
+                    self.state.bold = data[i] == 1;
+                    i += 1;
+                }
+            }
+            b'-' => {
+                i += 1;
+                if i < data.len() {
+                    let n = data[i];
+                    // n = 0: off, n = 1 or 2: on (with thickness)
+                    // Only consider actual values 1-2, not ASCII '1' '2'
+                    self.state.underline = n == 1 || n == 2;
+                    i += 1;
+                }
+            }
+            b'a' => {
+                i += 1;
+                if i < data.len() {
+                    self.state.alignment = match data[i] {
+                        0 => Alignment::Left,
+                        1 => Alignment::Center,
+                        2 => Alignment::Right,
+                        _ => Alignment::Left,
+                    };
+                    i += 1;
+                }
+            }
+            b'!' => {
+                i += 1;
+                if i < data.len() {
+                    let mode = data[i];
+                    // Bit 0 selects Font B, like ESC M 1
+                    self.state.font = mode & 0x01;
+                    self.state.bold = (mode & 0x08) != 0;
+                    self.state.height_multiplier = if mode & 0x10 != 0 { 2 } else { 1 };
+                    self.state.width_multiplier = if mode & 0x20 != 0 { 2 } else { 1 };
+                    self.state.underline = (mode & 0x80) != 0;
+                    i += 1;
+                }
+            }
+            b'd' => {
+                // ESC d n - Print the buffered line and feed n lines
+                i += 1;
+                if i >= data.len() {
+                    return Ok(i - 1);
+                }
+                let lines = data[i];
+                // A printed line is the first of the n
+                let printed = self.print_buffer();
+                for _ in u8::from(printed)..lines {
+                    self.elements.push(ReceiptElement::Separator);
+                }
+                i += 1;
+            }
+            b'*' => {
+                i += 1;
+                i = self.handle_raster_graphics(data, i)?;
+            }
+            b'~' => {
+                i += 1;
+                if i < data.len() {
+                    self.state.print_density = data[i].min(8);
+                    i += 1;
+                }
+            }
+            b'p' => {
+                i += 1;
+                if i + 2 >= data.len() {
+                    // Wait for m t1 t2
+                    return Ok(i - 1);
+                }
+                let pin = data[i];
+                let on_time = data[i + 1];
+                let off_time = data[i + 2];
+                self.elements.push(ReceiptElement::CashDrawer {
+                    pin,
+                    on_time,
+                    off_time,
+                });
+                // Units of 2 ms; the off time is at least the on time
+                self.kick_drawer(DrawerPulse {
+                    command: "ESC p",
+                    pin: if pin & 1 == 0 { 2 } else { 5 },
+                    on_ms: u32::from(on_time) * 2,
+                    off_ms: u32::from(off_time.max(on_time)) * 2,
+                });
+                i += 3;
+            }
+            b' ' => {
+                // ESC SP n - Set right-side character spacing
+                i += 1;
+                if i < data.len() {
+                    self.state.character_spacing = data[i];
+                    self.log_debug(&format!("ESC SP: character spacing = {}", data[i]));
+                    i += 1;
+                }
+            }
+            b'$' => {
+                // ESC $ - Set absolute horizontal print position. Positions
+                // past the print area are ignored, as on the printer.
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as u16;
+                    let nh = data[i + 1] as u16;
+                    let position = self.state.x_dots(nl + (nh << 8));
+                    if position <= self.line_width_dots() {
+                        self.set_print_position(position);
+                    }
+                    self.log_debug(&format!(
+                        "ESC $: set horizontal offset to {}",
+                        self.state.horizontal_offset
+                    ));
+                    i += 2;
+                }
+            }
+            b'\\' => {
+                // ESC \ - Set relative horizontal print position
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as i16;
+                    let nh = data[i + 1] as i16;
+                    let units = nl + (nh << 8);
+                    let relative_offset = if units < 0 {
+                        -(self.state.x_dots(units.unsigned_abs()) as i32)
+                    } else {
+                        self.state.x_dots(units as u16) as i32
+                    };
+                    // Relative to the current print position (can be
+                    // negative); ignored when it leaves the print area
+                    let position = self.print_position() as i32 + relative_offset;
+                    if (0..=i32::from(self.line_width_dots())).contains(&position) {
+                        self.set_print_position(position as u16);
+                    }
+                    self.log_debug(&format!(
+                        "ESC \\: relative offset {} -> total {}",
+                        relative_offset, self.state.horizontal_offset
+                    ));
+                    i += 2;
+                }
+            }
+            b'K' | b'L' | b'Y' | b'Z' => {
+                // ESC K/L/Y/Z - Select bit image mode
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as usize;
+                    let nh = data[i + 1] as usize;
+                    let width = nl + (nh << 8);
+                    i += 2;
+                    // Skip image data
+                    let bytes_needed = match cmd {
+                        b'K' | b'L' => width,
+                        b'Y' | b'Z' => width * 2,
+                        _ => width,
+                    };
+                    if i + bytes_needed <= data.len() {
+                        i += bytes_needed;
+                    }
+                }
+            }
+            b'D' => {
+                // ESC D n1 ... nk NUL - Set tab stops at n character widths
+                // (as the characters are set now) from the start of the line.
+                // NUL alone clears them. A value not above the previous one,
+                // or a 33rd, ends the list and is processed as normal data.
+                // Stops past the print area are set at its end.
+                let cmd_index = i;
+                i += 1;
+                let char_width = u32::from(self.char_width_dots());
+                let line_width = u32::from(self.line_width_dots());
+                let mut last = 0;
+                self.state.tab_stops.clear();
+                loop {
+                    let Some(&n) = data.get(i) else {
+                        // Wait for the rest of the list
+                        return Ok(cmd_index);
+                    };
+                    if n == 0 {
+                        i += 1;
+                        break;
+                    }
+                    let stop = u32::from(n) * char_width;
+                    if self.state.tab_stops.len() == MAX_TAB_STOPS || stop <= last {
+                        break;
+                    }
+                    last = stop;
+                    self.state.tab_stops.push(stop.min(line_width) as u16);
+                    i += 1;
+                }
+                self.log_debug(&format!("ESC D: tab stops {:?}", self.state.tab_stops));
+            }
+            b'S' | b'T' | b'U' | b'W' => {
+                // ESC S/T - Standard/page mode selection
+                // ESC U - Unidirectional printing
+                // ESC W - Set print area in page mode
+                i += 1;
+                if i < data.len() {
+                    if cmd == b'W' && i + 7 < data.len() {
+                        // W takes 8 parameters
+                        i += 8;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            b'c' => {
+                // ESC c - Paper sensor commands
+                i += 1;
+                if i + 1 < data.len() {
+                    i += 2;
+                }
+            }
+            b'i' => {
+                // ESC i - Partial cut (obsolete)
+                i += 1;
+            }
+            b's' => {
+                // ESC s - Select paper sensor(s)
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            0x06 => {
+                // ESC ACK n - Enable/disable panel buttons (or ASB in some implementations)
+                i += 1;
+                if i < data.len() {
+                    let _n = data[i];
+                    self.log_debug(&format!(
+                        "ESC ACK: n=0x{:02X} (acknowledged, not implemented)",
+                        _n
+                    ));
+                    i += 1;
+                }
+            }
+            b'u' => {
+                // ESC u - Transmit peripheral device status (obsolete)
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            b'v' => {
+                // ESC v - Transmit paper sensor status (obsolete)
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            b't' => {
+                // ESC t - Select character code table (ESC/POS standard)
+                i += 1;
+                if i < data.len() {
+                    if let Some(forced) = self.forced_code_page {
+                        self.log_debug(&format!(
+                            "ESC t: ignored codepage {}, {} is forced",
+                            data[i], forced
+                        ));
+                    } else {
+                        self.state.select_code_page(data[i]);
+                        if self.debug {
+                            self.log_debug(&format!("ESC t: selected codepage {}", data[i]));
+                        }
+                    }
+                    self.code_page_selected = true;
+                    i += 1;
+                }
+            }
+            b'M' => {
+                // ESC M n - Select character font
+                // n=0: Font A, n=1: Font B, n=2: Font C (if supported)
+                i += 1;
+                if i < data.len() {
+                    self.state.font = data[i];
+                    self.log_debug(&format!("ESC M: font = {}", data[i]));
+                    i += 1;
+                }
+            }
+            b'R' | b'r' | b'%' => {
+                // Character set, region, user-defined char mode
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            b'2' => {
+                // ESC 2 - Set default line spacing (1/6 inch = ~30 dots at 203 DPI)
+                self.state.line_spacing = 30;
+                self.log_debug("ESC 2: reset to default line spacing (30 dots)");
+                i += 1;
+            }
+            b'3' => {
+                // ESC 3 n - Set line spacing to n vertical motion units
+                i += 1;
+                if i < data.len() {
+                    self.state.line_spacing = self.state.y_dots(data[i]);
+                    self.log_debug(&format!(
+                        "ESC 3: line spacing = {} dots",
+                        self.state.line_spacing
+                    ));
+                    i += 1;
+                }
+            }
+            b'{' => {
+                // Upside down mode
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            b'G' => {
+                // ESC G n - Double-strike mode (makes text darker/bolder)
+                i += 1;
+                if i < data.len() {
+                    self.state.double_strike = data[i] != 0;
+                    self.log_debug(&format!(
+                        "ESC G: double-strike = {}",
+                        self.state.double_strike
+                    ));
+                    i += 1;
+                }
+            }
+            b'J' => {
+                // ESC J n - Print and feed n lines (used by zj-58 CUPS driver)
+                i += 1;
+                if i >= data.len() {
+                    return Ok(i - 1);
+                }
+                let lines = data[i];
+                self.log_debug(&format!("ESC J: feed {} lines", lines));
+                // The buffered line prints before the feed
+                self.print_buffer();
+                // Add line feeds as specified (each line is ~1/6 inch or ~4.23mm)
+                // Display exactly as ESC/POS specifies for accurate virtual printer behavior
+                for _ in 0..lines {
+                    self.elements.push(ReceiptElement::Separator);
+                }
+                i += 1;
+            }
+            b'V' => {
+                // ESC V n - 90° clockwise rotation (n=1/2 on, 0 off; also ASCII '0'-'2').
+                // Only barcodes are drawn rotated for now.
+                i += 1;
+                if i < data.len() {
+                    self.state.rotated = matches!(data[i], 1 | 2 | b'1' | b'2');
+                    i += 1;
+                }
+            }
+            b'(' => {
+                // ESC ( - Extended commands
+                i += 1;
+                if i + 2 < data.len() {
+                    let p_l = data[i + 1] as usize;
+                    let p_h = data[i + 2] as usize;
+                    let len = p_l + (p_h << 8);
+                    i += 3 + len;
+                }
+            }
+            b'&' => {
+                // ESC & - Define user-defined characters
+                i += 1;
+                if i + 2 < data.len() {
+                    let y = data[i] as usize;
+                    let c1 = data[i + 1] as usize;
+                    let c2 = data[i + 2] as usize;
+                    i += 3;
+                    let num_chars = if c2 >= c1 { c2 - c1 + 1 } else { 0 };
+                    let bytes_per_char = y * 12_usize.div_ceil(8);
+                    i += num_chars * bytes_per_char;
+                }
+            }
+            b'?' => {
+                // ESC ? - Cancel user-defined characters
+                i += 1;
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            b'=' => {
+                // ESC = n - Select peripheral device: bit 0 the printer,
+                // bit 1 the customer display
+                i += 1;
+                if i < data.len() {
+                    self.peripheral = data[i] & 3;
+                    i += 1;
+                }
+            }
+            b'<' => {
+                // ESC < - Return home
+                i += 1;
+            }
+            FF => {
+                // ESC FF - Print the page mode data without leaving page
+                // mode. Page mode isn't emulated, and printers ignore ESC FF
+                // in standard mode.
+                i += 1;
+            }
+            _ => {
+                // Unknown ESC command - assume it has at least 1 parameter
+                self.unknown_command = true;
+                if self.debug {
+                    self.log_debug(&format!("Unknown ESC command: 0x{:02X}", cmd));
+                }
+                i += 1;
+                // Try to consume 1 parameter byte to prevent leakage
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+        }
+        Ok(i)
+    }
+
+    fn handle_gs_command(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
+        let cmd = data[i];
+        match cmd {
+            b'8' => {
+                // GS 8 - Extended command (L = raster graphics)
+                let start_i = i - 1;
+                i += 1;
+                if i < data.len() {
+                    if data[i] == b'L' {
+                        i = self.handle_gs_8l(data, i)?;
+                    } else {
+                        // Other GS 8 subcommands (structure: GS 8 fn p1 p2 p3 p4 data...)
+                        let subcmd = data[i];
+                        i += 1; // skip subcommand
+
+                        // Read length bytes
+                        if i + 4 > data.len() {
+                            // Not enough data for length - wait for more
+                            if self.debug {
+                                self.log_debug(&format!(
+                                    "GS 8 0x{:02X}: waiting for length bytes",
+                                    subcmd
+                                ));
+                            }
+                            return Ok(start_i);
+                        }
+
+                        let p1 = data[i] as usize;
+                        let p2 = data[i + 1] as usize;
+                        let p3 = data[i + 2] as usize;
+                        let p4 = data[i + 3] as usize;
+                        let len = p1 | (p2 << 8) | (p3 << 16) | (p4 << 24);
+                        i += 4;
+
+                        // Check if we have all the data
+                        let skip = len.min(1_000_000);
+                        if i + skip > data.len() {
+                            // Not enough data - wait for more
+                            self.await_data("GS 8", start_i, i + skip);
+                            if self.debug {
+                                self.log_debug(&format!(
+                                    "GS 8 0x{:02X}: waiting for {} data bytes (have {})",
+                                    subcmd,
+                                    skip,
+                                    data.len() - i
+                                ));
+                            }
+                            return Ok(start_i);
+                        }
+
+                        // Skip all the data
+                        self.unknown_command = true;
+                        i += skip;
+                    }
+                }
+            }
+            b'V' => {
+                i += 1;
+                if i >= data.len() {
+                    // Wait for the mode byte
+                    return Ok(i - 1);
+                }
+                i = self.handle_paper_cut(data, i)?;
+            }
+            b'v' => {
+                i += 1;
+                if i < data.len() {
+                    i = self.handle_raster_graphics_gs(data, i)?;
+                }
+            }
+            b'!' => {
+                // GS ! - Select character size (width and height multipliers)
+                // Bits 0-2: width (0-7), Bits 4-6: height (0-7)
+                i += 1;
+                if i < data.len() {
+                    let mode = data[i];
+                    self.state.width_multiplier = (mode & 0x07) + 1;
+                    self.state.height_multiplier = ((mode >> 4) & 0x07) + 1;
+                    i += 1;
+                }
+            }
+            b'B' => {
+                i += 1;
+                if i < data.len() {
+                    self.state.inverted = data[i] == 1;
+                    i += 1;
+                }
+            }
+            b'L' => {
+                // GS L nL nH - Set left margin (in horizontal motion units)
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as u16;
+                    let nh = data[i + 1] as u16;
+                    self.state.left_margin = self.state.x_dots(nl + (nh << 8));
+                    self.log_debug(&format!(
+                        "GS L: left margin = {} dots",
+                        self.state.left_margin
+                    ));
+                    i += 2;
+                }
+            }
+            b'W' => {
+                // GS W nL nH - Set print area width (in horizontal motion units)
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as u16;
+                    let nh = data[i + 1] as u16;
+                    self.state.print_area_width = self.state.x_dots(nl + (nh << 8));
+                    self.log_debug(&format!(
+                        "GS W: print area width = {} dots",
+                        self.state.print_area_width
+                    ));
+                    i += 2;
+                }
+            }
+            b'T' => {
+                // GS T n - Move to the beginning of the print line: n = 0
+                // discards the buffered line, n = 1 prints it first
+                i += 1;
+                if i >= data.len() {
+                    return Ok(i - 1);
+                }
+                match data[i] {
+                    1 | b'1' => {
+                        self.print_buffer();
+                    }
+                    _ => {
+                        self.current_line.clear();
+                        self.line_runs.clear();
+                        self.lint.line_ended();
+                    }
+                }
+                self.state.horizontal_offset = 0;
+                i += 1;
+            }
+            b'P' => {
+                // GS P x y - Set horizontal and vertical motion units (1/x
+                // and 1/y inch, 0 = the default of one dot). Distances set
+                // before keep their length in dots.
+                i += 1;
+                if i + 1 >= data.len() {
+                    return Ok(i - 1);
+                }
+                self.state.motion_unit_x = data[i];
+                self.state.motion_unit_y = data[i + 1];
+                self.log_debug(&format!(
+                    "GS P: motion units 1/{} x 1/{} inch",
+                    data[i],
+                    data[i + 1]
+                ));
+                i += 2;
+            }
+            b'H' => {
+                // GS H n - HRI position (0 none, 1 above, 2 below, 3 both; also '0'-'3')
+                i += 1;
+                if i < data.len() {
+                    self.state.barcode_hri_position = data[i] & 0x03;
+                    i += 1;
+                }
+            }
+            b'h' => {
+                // GS h n - Barcode height in dots
+                i += 1;
+                if i < data.len() {
+                    if data[i] > 0 {
+                        self.state.barcode_height = data[i];
+                    }
+                    i += 1;
+                }
+            }
+            b'w' => {
+                // GS w n - Barcode module width in dots (2-6)
+                i += 1;
+                if i < data.len() {
+                    if (2..=6).contains(&data[i]) {
+                        self.state.barcode_module_width = data[i];
+                    }
+                    i += 1;
+                }
+            }
+            b'k' => {
+                // GS k m ... - Print barcode. Waits for all of the data:
+                // a barcode printed from part of it would be wrong.
+                let command_start = i;
+                i += 1;
+                if i >= data.len() {
+                    return Ok(command_start);
+                }
+                let barcode_type = data[i];
+                i += 1;
+                let payload;
+                if barcode_type <= 6 {
+                    // Function A: data is NUL terminated
+                    let Some(len) = data[i..].iter().position(|&byte| byte == 0) else {
+                        return Ok(command_start);
+                    };
+                    payload = &data[i..i + len];
+                    i += len + 1;
+                } else {
+                    // Function B: length-prefixed data
+                    let Some(&len) = data.get(i) else {
+                        return Ok(command_start);
+                    };
+                    let len = len as usize;
+                    if i + 1 + len > data.len() {
+                        return Ok(command_start);
+                    }
+                    payload = &data[i + 1..i + 1 + len];
+                    i += 1 + len;
+                }
+                self.print_barcode(barcode_type, payload);
+            }
+            b'(' => {
+                // Extended commands
+                i += 1;
+                if i >= data.len() {
+                    return Ok(i - 1);
+                }
+                let subcmd = data[i];
+                if subcmd == b'k' {
+                    // QR Code commands
+                    i = self.handle_qr_code(data, i)?;
+                } else if subcmd == b'L' {
+                    // Graphics commands
+                    i = self.handle_gs_paren_l(data, i)?;
+                } else if subcmd == b'A' {
+                    i = self.handle_test_print(data, i)?;
+                } else if subcmd == b'C' {
+                    i = self.handle_nv_user_memory(data, i)?;
+                } else if subcmd == b'D' {
+                    i = self.handle_real_time_enable(data, i)?;
+                } else if subcmd == b'M' {
+                    i = self.handle_custom_values(data, i)?;
+                } else {
+                    // Other extended commands
+                    if i + 2 >= data.len() {
+                        return Ok(i - 1);
+                    }
+                    let p_l = data[i + 1] as usize;
+                    let p_h = data[i + 2] as usize;
+                    let len = p_l + (p_h << 8);
+                    if i + 3 + len > data.len() {
+                        return Ok(i - 1);
+                    }
+                    i += 3 + len;
+                }
+            }
+            b'a' => {
+                // GS a n - Enable/disable Automatic Status Back (ASB)
+                // n bits specify which status types to report automatically
+                i += 1;
+                if i < data.len() {
+                    let asb_flags = data[i];
+                    self.log_debug(&format!("GS a: ASB flags=0x{:02X}", asb_flags));
+                    self.asb_mask = asb_flags & 0x0F;
+
+                    // If ASB is enabled (n != 0), send 4-byte ASB status immediately
+                    if asb_flags != 0 {
+                        // Healthy printer frame, see SimulatedStatus::asb for errors:
+                        // ASB format (4 bytes):
+                        // Byte 0: 0x10 = binary 00010000
+                        //   Bit 0,1 = 0 (fixed)
+                        //   Bit 2 = 0 (drawer pin LOW)
+                        //   Bit 3 = 0 (online)
+                        //   Bit 4 = 1 (fixed)
+                        //   Bit 5 = 0 (cover closed)
+                        //   Bit 6 = 0 (not feeding paper)
+                        //   Bit 7 = 0 (fixed)
+                        // Byte 1: 0x00 = all OK (no errors, not waiting)
+                        // Byte 2: 0x00 = paper sensors OK (paper present)
+                        // Byte 3: 0x00 = reserved
+                        let frame = self.status.asb();
+                        self.last_asb = frame;
+                        self.queue_response(&data[i - 2..=i], &frame);
+                        self.log_debug(&format!("GS a: queued 4-byte ASB status {:02X?}", frame));
+                    }
+                    i += 1;
+                }
+            }
+            b'g' => {
+                // GS g 0 m nL nH - Initialize maintenance counter n
+                // GS g 2 m nL nH - Transmit maintenance counter n
+                if i + 4 >= data.len() {
+                    return Ok(i - 1);
+                }
+                let function = data[i + 1];
+                let n = u16::from(data[i + 3]) | u16::from(data[i + 4]) << 8;
+                let mut counters = self.maintenance.lock().unwrap();
+                match function {
+                    0 | 48 => {
+                        counters.reset(n);
+                    }
+                    2 | 50 => {
+                        // Header 0x5F, the value in decimal digits, NUL.
+                        // Counters that aren't kept read 0.
+                        let value = counters.value(n).unwrap_or(0);
+                        drop(counters);
+                        let mut response = vec![0x5F];
+                        response.extend_from_slice(value.to_string().as_bytes());
+                        response.push(0x00);
+                        self.queue_response(&data[i - 1..i + 5], &response);
+                    }
+                    _ => {}
+                }
+                self.log_debug(&format!("GS g {}: counter {}", function, n));
+                i += 5;
+            }
+            b'I' => {
+                // GS I n - Transmit printer ID information
+                // Response format: 0x5f + "string" + 0x00 (block data format)
+                i += 1;
+                if i < data.len() {
+                    let n = data[i];
+                    self.log_debug(&format!("GS I: query type=0x{:02X}", n));
+
+                    // Queue response based on query type (block data format)
+                    match n {
+                        0x42 => {
+                            // Manufacturer name (0x42 = 66)
+                            // Send in block data format: 0x5f + "CITIZEN" + 0x00
+                            // (use CITIZEN not EPSON so receiptio switches to 'escpos' mode)
+                            self.queue_response(&data[i - 2..=i], b"\x5fCITIZEN\x00");
+                            self.log_debug("GS I 0x42: sent manufacturer 'CITIZEN' (block data)");
+                        }
+                        0x43 => {
+                            // Model name (0x43 = 67)
+                            // Send in block data format: 0x5f + "CT-S310" + 0x00
+                            self.queue_response(&data[i - 2..=i], b"\x5fCT-S310\x00");
+                            self.log_debug("GS I 0x43: sent model 'CT-S310' (block data)");
+                        }
+                        _ => {
+                            self.log_debug(&format!("GS I: unknown query type 0x{:02X}", n));
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            b'r' => {
+                // GS r n - Transmit status
+                i += 1;
+                if i < data.len() {
+                    let n = data[i];
+                    self.log_debug(&format!("GS r: transmit status n=0x{:02X}", n));
+
+                    // Send 1-byte status response
+                    // Status byte format: bit pattern must have (value & 0x90) === 0
+                    // 0x08 = 00001000 (online, paper present, no errors)
+                    //   Bit 3 = 1: paper present
+                    //   Bit 4 = 0: online (not offline)
+                    //   Bit 7 = 0: (required by receiptio)
+                    let status = self.status.transmit_status(n);
+                    self.queue_response(&data[i - 2..=i], &[status]);
+                    self.log_debug(&format!("GS r: queued status response 0x{:02X}", status));
+                    i += 1;
+                }
+            }
+            b'$' => {
+                // GS $ nL nH - Set absolute vertical print position
+                // Used by receiptio for positioning each line
+                i += 1;
+                if i + 1 < data.len() {
+                    let nl = data[i] as u16;
+                    let nh = data[i + 1] as u16;
+                    let vertical_pos = nl + (nh << 8);
+                    self.log_debug(&format!("GS $: set vertical position to {}", vertical_pos));
+                    // VirtualESC renders sequentially, so we acknowledge but don't use this
+                    i += 2;
+                }
+            }
+            0x00 | 0x80 | 0xF7 => {
+                // Additional GS commands found in real data
+                self.unknown_command = true;
+                i += 1;
+                // Consume likely parameter
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+            _ => {
+                // Unknown GS command - assume it has at least 1 parameter
+                self.unknown_command = true;
+                if self.debug {
+                    self.log_debug(&format!("Unknown GS command: 0x{:02X}", cmd));
+                }
+                i += 1;
+                // Try to consume 1 parameter byte to prevent leakage
+                if i < data.len() {
+                    i += 1;
+                }
+            }
+        }
+        Ok(i)
+    }
+
+    fn handle_raster_graphics(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to ESC byte, not '*' byte (i-1=*, i-2=ESC)
+
+        if i + 3 > data.len() {
+            self.log_debug("ESC * incomplete: not enough header bytes");
+            return Ok(start_i);
+        }
+
+        let m = data[i];
+        let nl = data[i + 1] as usize;
+        let nh = data[i + 2] as usize;
+        let width = nl + (nh << 8);
+        // m: 0/1 = 8-dot single/double density, 32/33 = 24-dot single/double density
+        let height = match m {
+            0 | 1 => 8,
+            32 | 33 => 24,
+            _ => 8,
+        };
+
+        let mut pos = i + 3;
+
+        // Validate dimensions
+        if width == 0 || width > 10000 {
+            self.log_debug(&format!("ESC * invalid width: {}", width));
+            return Ok(pos);
+        }
+
+        // ESC * uses COLUMN-based format, not raster!
+        // Each column is height/8 bytes (8-dot) or height/8*3 bytes (24-dot)
+        let bytes_per_column = height / 8;
+        let total_bytes = width * bytes_per_column;
+
+        self.log_debug(&format!(
+            "ESC * column-based: m={}, width={}, height={}, bytes_per_col={}, need {} bytes",
+            m, width, height, bytes_per_column, total_bytes
+        ));
+
+        if total_bytes > 1_000_000 {
+            self.log_debug("ESC * dimensions too large, skipping");
+            return Ok(pos);
+        }
+
+        if pos + total_bytes > data.len() {
+            self.await_data("ESC *", start_i, pos + total_bytes);
+            self.log_debug(&format!(
+                "ESC * incomplete: have {}, need {}",
+                data.len() - pos,
+                total_bytes
+            ));
+            return Ok(start_i);
+        }
+
+        // Additional safety check before slicing
+        if pos >= data.len() || pos + total_bytes > data.len() {
+            self.log_debug("ESC * bounds check failed");
+            return Ok(start_i);
+        }
+
+        // Flush any pending text before image
+        if self.has_pending_text() {
+            self.flush_line();
+            self.current_line.clear();
+        }
+
+        // Convert column-based data to row-based raster data for rendering
+        let column_data = &data[pos..pos + total_bytes];
+
+        // Single-density modes (0, 32) print at half the horizontal
+        // resolution: each column covers two dots
+        let (column_data, width) = if matches!(m, 0 | 32) {
+            let doubled: Vec<u8> = column_data
+                .chunks(bytes_per_column)
+                .flat_map(|column| column.iter().chain(column.iter()).copied())
+                .collect();
+            (std::borrow::Cow::Owned(doubled), width * 2)
+        } else {
+            (std::borrow::Cow::Borrowed(column_data), width)
+        };
+        let raster_data = self.column_to_raster(&column_data, width, height);
+
+        self.push_raster(width, height, raster_data, width.div_ceil(8));
+
+        pos += total_bytes;
+
+        Ok(pos)
+    }
+
+    fn column_to_raster(&self, column_data: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let bytes_per_column = height / 8;
+        let bytes_per_row = width.div_ceil(8);
+        let mut raster_data = vec![0u8; bytes_per_row * height];
+
+        // Convert column format to raster format
+        // Column format: each byte represents 8 vertical pixels in a column,
+        // the top one in the high bit
+        // Raster format: each byte represents 8 horizontal pixels in a row
+        fill_rows(&mut raster_data, bytes_per_row, |y, row| {
+            let (byte_in_col, mask) = (y / 8, 0x80 >> (y % 8));
+            for col in 0..width {
+                let col_byte = column_data.get(col * bytes_per_column + byte_in_col);
+                if col_byte.is_some_and(|byte| byte & mask != 0) {
+                    row[col / 8] |= 0x80 >> (col % 8);
+                }
+            }
+        });
+
+        raster_data
+    }
+
+    fn handle_raster_graphics_gs(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte, not 'v' byte (i-1=v, i-2=GS)
+
+        self.log_debug(&format!("GS v: entered handler at position {}", i));
+
+        if i + 6 > data.len() {
+            self.log_debug(&format!(
+                "GS v incomplete: not enough header bytes (have {}, need {})",
+                data.len() - i,
+                6
+            ));
+            return Ok(start_i);
+        }
+
+        // zj-58 format: GS v variant m xL xH yL yH [data]
+        // escRasterMode[] = "\x1dv0\0" sends: GS v '0' 0x00
+        // Then mputnum(width) and mputnum(height) send little-endian 2-byte values
+        let variant = data[i]; // '0' = 0x30
+        let m = data[i + 1]; // 0/48 normal, 1/49 double width, 2/50 double height, 3/51 both
+        let xl = data[i + 2] as usize;
+        let xh = data[i + 3] as usize;
+        let yl = data[i + 4] as usize;
+        let yh = data[i + 5] as usize;
+
+        self.log_debug(&format!(
+            "GS v: raw bytes at i: [{:02X} {:02X} {:02X} {:02X} {:02X} {:02X}]",
+            data[i],
+            data[i + 1],
+            data[i + 2],
+            data[i + 3],
+            data[i + 4],
+            data[i + 5]
+        ));
+        self.log_debug(&format!(
+            "GS v: variant=0x{:02X} m=0x{:02X}, xl=0x{:02X} xh=0x{:02X} yl=0x{:02X} yh=0x{:02X}",
+            variant, m, xl, xh, yl, yh
+        ));
+
+        let mut pos = i + 6;
+
+        // GS v 0: xL/xH are width in BYTES, yL/yH are height in DOTS (pixels)
+        let width_in_bytes = xl + (xh << 8);
+        let height = yl + (yh << 8);
+        let width = width_in_bytes * 8; // Convert bytes to pixels for rendering
+
+        // Validate dimensions
+        if width_in_bytes == 0 || height == 0 {
+            self.log_debug(&format!(
+                "GS v invalid dimensions: {} bytes x {} pixels",
+                width_in_bytes, height
+            ));
+            return Ok(pos);
+        }
+
+        if width > 10000 || height > 10000 {
+            self.log_debug(&format!(
+                "GS v dimensions too large: {}x{} pixels, attempting to skip raster data",
+                width, height
+            ));
+            // Still need to skip the raster data even if dimensions seem wrong
+            // Otherwise the raster bytes will be processed as text
+            let total_bytes = width_in_bytes * height;
+            if total_bytes > 5_000_000 {
+                self.log_debug("GS v: calculated bytes too large, cannot skip safely");
+                return Ok(start_i); // Wait for correct data or give up
+            }
+            if pos + total_bytes > data.len() {
+                self.log_debug(&format!(
+                    "GS v: not enough data to skip (need {} more bytes)",
+                    total_bytes - (data.len() - pos)
+                ));
+                return Ok(start_i); // Wait for more data
+            }
+            return Ok(pos + total_bytes); // Skip past the raster data
+        }
+
+        let total_bytes = width_in_bytes * height;
+
+        self.log_debug(&format!(
+            "GS v raster: width={} pixels ({} bytes), height={} pixels, need {} bytes",
+            width, width_in_bytes, height, total_bytes
+        ));
+
+        if total_bytes > 5_000_000 {
+            self.log_debug("GS v raster: calculated bytes too large, skipping");
+            return Ok(pos);
+        }
+
+        if pos + total_bytes > data.len() {
+            self.await_data("GS v 0", start_i, pos + total_bytes);
+            self.log_debug(&format!(
+                "GS v incomplete: have {}, need {}",
+                data.len() - pos,
+                total_bytes
+            ));
+            return Ok(start_i);
+        }
+
+        // Additional safety check before slicing
+        if pos >= data.len() || pos + total_bytes > data.len() {
+            self.log_debug("GS v bounds check failed");
+            return Ok(start_i);
+        }
+
+        // Flush any pending text before image (already cleared by caller)
+        if self.has_pending_text() {
+            self.flush_line();
+            self.current_line.clear();
+        }
+
+        // Debug: dump first 64 bytes of raster data to see the pattern
+        if self.debug {
+            let preview_len = std::cmp::min(64, total_bytes);
+            let mut hex_str = String::new();
+            for i in 0..preview_len {
+                hex_str.push_str(&format!("{:02X} ", data[pos + i]));
+                if (i + 1) % 16 == 0 {
+                    hex_str.push('\n');
+                }
+            }
+            self.log_debug(&format!(
+                "GS v raster data (first {} bytes):\n{}",
+                preview_len, hex_str
+            ));
+
+            // Also show bytes per line calculation
+            self.log_debug(&format!(
+                "Width={} pixels -> {} bytes per line, {} total lines",
+                width, width_in_bytes, height
+            ));
+        }
+
+        // GS v data is in standard raster format (row-based), NOT column format
+        // Just use the data directly, scaled up per the mode byte
+        let raster = &data[pos..pos + total_bytes];
+        let scale_x = if m & 0x01 != 0 { 2 } else { 1 };
+        let scale_y = if m & 0x02 != 0 { 2 } else { 1 };
+        let (raster_data, width, height, bytes_per_line) =
+            scale_raster(raster, width, height, width_in_bytes, scale_x, scale_y);
+
+        self.push_raster(width, height, raster_data, bytes_per_line);
+
+        pos += total_bytes;
+
+        Ok(pos)
+    }
+
+    fn handle_gs_8l(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
+        let start_i = i - 1;
+
+        // GS 8 L p1 p2 p3 p4 m fn a bx by c xL xH yL yH d1...dk
+        if i + 10 > data.len() {
+            self.log_debug("GS 8 L incomplete: not enough header bytes");
+            return Ok(start_i);
+        }
+
+        i += 1; // skip 'L'
+
+        let p1 = data[i] as u32;
+        let p2 = data[i + 1] as u32;
+        let p3 = data[i + 2] as u32;
+        let p4 = data[i + 3] as u32;
+        let data_len = p1 | (p2 << 8) | (p3 << 16) | (p4 << 24);
+
+        let m = data[i + 4];
+        let _fn = data[i + 5];
+        let _a = data[i + 6];
+        let _bx = data[i + 7];
+        let _by = data[i + 8];
+        let _c = data[i + 9];
+
+        i += 10;
+
+        if m == 48 || m == 112 {
+            if i + 4 > data.len() {
+                self.log_debug("GS 8 L incomplete: not enough dimension bytes");
+                return Ok(start_i);
+            }
+
+            let xl = data[i] as usize;
+            let xh = data[i + 1] as usize;
+            let yl = data[i + 2] as usize;
+            let yh = data[i + 3] as usize;
+
+            let width = xl | (xh << 8);
+            let height = yl | (yh << 8);
+
+            i += 4;
+
+            let image_bytes = width.div_ceil(8) * height;
+
+            self.log_debug(&format!(
+                "GS 8 L raster: m={}, width={}, height={}, need {} bytes",
+                m, width, height, image_bytes
+            ));
+
+            if data_len as usize > 100_000 || image_bytes > 5_000_000 {
+                self.log_debug("GS 8 L: dimensions too large, skipping");
+                // data_len includes m,fn,a,bx,by,c (6 bytes) which we already consumed
+                // We need to skip the remaining data_len - 6 bytes
+                let skip = (data_len as usize).saturating_sub(6);
+                if i + skip <= data.len() {
+                    return Ok(i + skip);
+                } else {
+                    // Not enough data to skip - wait for more
+                    self.await_data("GS 8 L", start_i, i + skip);
+                    return Ok(start_i);
+                }
+            }
+
+            if i + image_bytes > data.len() {
+                self.await_data("GS 8 L", start_i, i + image_bytes);
+                self.log_debug(&format!(
+                    "GS 8 L incomplete: have {}, need {}",
+                    data.len() - i,
+                    image_bytes
+                ));
+                return Ok(start_i);
+            }
+
+            if self.has_pending_text() {
+                self.flush_line();
+                self.current_line.clear();
+            }
+
+            let bytes_per_line = width.div_ceil(8);
+            self.push_raster(
+                width,
+                height,
+                data[i..i + image_bytes].to_vec(),
+                bytes_per_line,
+            );
+
+            i += image_bytes;
+        } else {
+            let skip = (data_len as usize).saturating_sub(6);
+            i += skip.min(data.len() - i);
+        }
+
+        Ok(i)
+    }
+
+    /// GS ( A pL pH n m - Execute test print: m = 2/50 the self-test page,
+    /// 3/51 the rolling pattern, on the roll paper (n). The printer resets
+    /// afterwards. The hexadecimal dump (m = 1/49) isn't emulated.
+    fn handle_test_print(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte
+
+        if i + 2 >= data.len() {
+            return Ok(start_i);
+        }
+        let len = data[i + 1] as usize | (data[i + 2] as usize) << 8;
+        let end = i + 3 + len;
+        if end > data.len() {
+            return Ok(start_i);
+        }
+        let Some(&m) = data.get(i + 4).filter(|_| len >= 2) else {
+            return Ok(end);
+        };
+        let page = match m {
+            2 | 50 => self_test::page(&self.status, self.paper_size, &self.custom_code_pages),
+            3 | 51 => self_test::rolling_pattern(self.paper_size),
+            _ => {
+                self.log_debug(&format!("GS ( A: test print {} not supported", m));
+                return Ok(end);
+            }
+        };
+        self.log_debug(&format!("GS ( A: test print {}", m));
+        self.print_buffer();
+        let options = ParseOptions {
+            paper_size: self.paper_size,
+            code_page: self.default_code_page,
+            columns: self.columns,
+            custom_code_pages: self.custom_code_pages.clone(),
+            so_double_width: self.status.so_double_width,
+            control_glyphs: false,
+            ..ParseOptions::default()
+        };
+        self.elements.extend(parse_bytes(&page, &options));
+        self.initialize();
+        Ok(end)
+    }
+
+    /// GS ( C pL pH m fn ... - NV user memory: delete (fn 0/48), store
+    /// (1/49) and transmit (2/50) a record, delete all (3/51), transmit the
+    /// capacity (4/52) and the remaining capacity (5/53)
+    fn handle_nv_user_memory(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte
+
+        if i + 2 >= data.len() {
+            return Ok(start_i);
+        }
+        let len = data[i + 1] as usize | (data[i + 2] as usize) << 8;
+        let body_start = i + 3;
+        let end = body_start + len;
+        if end > data.len() {
+            return Ok(start_i);
+        }
+
+        // body = m fn [a c1 c2 [d1...dk]]
+        let body = &data[body_start..end];
+        let Some(&function) = body.get(1) else {
+            return Ok(end);
+        };
+        self.log_debug(&format!("GS ( C: fn={}", function));
+
+        let query = &data[start_i..end];
+        let key = body.get(3..5).map(|key| [key[0], key[1]]);
+        let mut memory = self.nv_memory.lock().unwrap();
+        match (function, key) {
+            (0 | 48, Some(key)) => memory.delete(key),
+            (1 | 49, Some(key)) => {
+                let stored = memory.store(key, &body[5..]);
+                if !stored {
+                    self.log_debug("GS ( C: record doesn't fit in NV user memory");
+                }
+            }
+            // The record's data, empty if there is none
+            (2 | 50, Some(key)) => {
+                let mut response = vec![0x37, 0x43, 0x40];
+                response.extend_from_slice(memory.get(key).unwrap_or_default());
+                response.push(0x00);
+                drop(memory);
+                self.queue_response(query, &response);
+            }
+            (3 | 51, _) if body.get(2..5) == Some(b"CLR") => memory.clear(),
+            (4 | 52, _) => {
+                drop(memory);
+                self.queue_response(query, &capacity_response(0x34, nv_memory::CAPACITY));
+            }
+            (5 | 53, _) => {
+                let remaining = nv_memory::CAPACITY - memory.used();
+                drop(memory);
+                self.queue_response(query, &capacity_response(0x35, remaining));
+            }
+            _ => {}
+        }
+
+        Ok(end)
+    }
+
+    /// GS ( D pL pH m [a b]... - Enable (b = 1/49) or disable (b = 0/48) the
+    /// real-time commands DLE DC4 fn 1 (a = 1) and fn 2 (a = 2), with m = 20.
+    /// Drivers disable them around data that may contain the sequences.
+    /// DLE EOT and DLE ENQ can't be disabled, as on the printer.
+    fn handle_real_time_enable(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte
+
+        if i + 2 >= data.len() {
+            return Ok(start_i);
+        }
+        let len = data[i + 1] as usize | (data[i + 2] as usize) << 8;
+        let body_start = i + 3;
+        let end = body_start + len;
+        if end > data.len() {
+            return Ok(start_i);
+        }
+
+        // body = m a1 b1 ... ak bk
+        let body = &data[body_start..end];
+        if body.first() != Some(&20) {
+            self.log_debug("GS ( D: m must be 20");
+            return Ok(end);
+        }
+        for pair in body[1..].chunks_exact(2) {
+            let enabled = match pair[1] {
+                0 | 48 => false,
+                1 | 49 => true,
+                _ => continue,
+            };
+            if let 1 | 2 = pair[0] {
+                self.dc4_enabled[pair[0] as usize - 1] = enabled;
+                self.log_debug(&format!(
+                    "GS ( D: DLE DC4 {} {}",
+                    pair[0],
+                    if enabled { "enabled" } else { "disabled" }
+                ));
+            }
+        }
+
+        Ok(end)
+    }
+
+    /// GS ( M pL pH n m - Customize printer control values: save the print
+    /// settings to the user storage area (n = 1/49, m = 1/49), load the
+    /// defaults (n = 2/50, m = 0/48) or the saved settings (m = 1/49), or
+    /// select which of them ESC @ and power on load (n = 3/51, m likewise)
+    fn handle_custom_values(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte
+
+        if i + 2 >= data.len() {
+            return Ok(start_i);
+        }
+        let len = data[i + 1] as usize | (data[i + 2] as usize) << 8;
+        let body_start = i + 3;
+        let end = body_start + len;
+        if end > data.len() {
+            return Ok(start_i);
+        }
+
+        // body = n m
+        let body = &data[body_start..end];
+        let (Some(&function), Some(&area)) = (body.first(), body.get(1)) else {
+            return Ok(end);
+        };
+        self.log_debug(&format!("GS ( M: fn={} m={}", function, area));
+        let user = match area {
+            0 | 48 => false,
+            1 | 49 => true,
+            _ => return Ok(end),
+        };
+        match function {
+            1 | 49 if user => self.custom_values.lock().unwrap().save(&self.state),
+            2 | 50 if user => {
+                let saved = self.custom_values.lock().unwrap().saved().cloned();
+                match saved {
+                    Some(state) => self.load_settings(state),
+                    None => self.log_debug("GS ( M: no settings saved"),
+                }
+            }
+            2 | 50 => self.load_default_settings(),
+            3 | 51 => self.custom_values.lock().unwrap().set_load_at_init(user),
+            _ => {}
+        }
+
+        Ok(end)
+    }
+
+    /// GS ( L pL pH m fn ... - Graphics functions. Answers the capacity and
+    /// key code list queries utilities send before uploading logos.
+    fn handle_gs_paren_l(&mut self, data: &[u8], i: usize) -> Result<usize> {
+        let start_i = i - 2; // Point to GS byte
+
+        if i + 2 >= data.len() {
+            return Ok(start_i);
+        }
+        let len = data[i + 1] as usize | (data[i + 2] as usize) << 8;
+        let body_start = i + 3;
+        let end = body_start + len;
+        if end > data.len() {
+            self.await_data("GS ( L", start_i, end);
+            self.log_debug(&format!(
+                "GS ( L incomplete: have {}, need {}",
+                data.len() - body_start,
+                len
+            ));
+            return Ok(start_i);
+        }
+
+        // body = m fn [parameters]
+        let body = &data[body_start..end];
+        let Some(&function) = body.get(1) else {
+            return Ok(end);
+        };
+        self.log_debug(&format!("GS ( L: fn={}", function));
+
+        let query = &data[start_i..end];
+        match function {
+            // Transmit the NV graphics memory capacity
+            48 => {
+                let response = capacity_response(0x30, NV_GRAPHICS_CAPACITY);
+                self.queue_response(query, &response);
+            }
+            // Transmit the remaining capacity of the NV graphics memory
+            51 => {
+                let response = capacity_response(0x31, NV_GRAPHICS_CAPACITY);
+                self.queue_response(query, &response);
+            }
+            // Transmit the remaining capacity of the download graphics memory
+            52 => {
+                let response = capacity_response(0x32, DOWNLOAD_GRAPHICS_CAPACITY);
+                self.queue_response(query, &response);
+            }
+            // Transmit the key code list for NV (64) or download (80) graphics.
+            // Nothing is stored yet, so the list is empty.
+            64 | 80 if body.get(2..4) == Some(b"KC") => {
+                let identifier = if function == 64 { 0x72 } else { 0x73 };
+                // Status 0x40: last (and only) block of the list
+                self.queue_response(query, &[0x37, identifier, 0x40, 0x00]);
+            }
+            _ => {}
+        }
+
+        Ok(end)
+    }
+
+    fn print_barcode(&mut self, barcode_type: u8, payload: &[u8]) {
+        match barcode::encode(barcode_type, payload) {
+            Ok(encoded) => {
+                if self.has_pending_text() {
+                    self.flush_line();
+                    self.current_line.clear();
+                }
+                self.elements.push(ReceiptElement::Barcode {
+                    symbology: encoded.symbology.to_string(),
+                    text: encoded.text,
+                    widths: encoded.widths,
+                    module_width: self.state.barcode_module_width,
+                    height: self.state.barcode_height,
+                    hri_position: self.state.barcode_hri_position,
+                    rotated: self.state.rotated,
+                    alignment: self.state.alignment.clone(),
+                    left_margin: self.state.left_margin,
+                    print_area_width: self.state.print_area_width,
+                });
+            }
+            // The printer prints nothing for invalid data
+            Err(e) => self.log_debug(&format!(
+                "GS k: barcode {} not printed: {}",
+                barcode_type, e
+            )),
+        }
+    }
+
+    fn handle_qr_code(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
+        let start_i = i - 1;
+
+        // GS ( k pL pH cn fn [parameters]
+        if i + 5 > data.len() {
+            self.log_debug("GS ( k incomplete: not enough header bytes");
+            return Ok(start_i);
+        }
+
+        i += 1; // skip 'k'
+
+        let p_l = data[i] as usize;
+        let p_h = data[i + 1] as usize;
+        let param_len = p_l | (p_h << 8);
+
+        let cn = data[i + 2];
+        let fn_code = data[i + 3];
+
+        i += 4;
+
+        if cn != 49 {
+            // Not a QR code command
+            let skip = param_len.saturating_sub(2);
+            i += skip.min(data.len() - i);
+            return Ok(i);
+        }
+
+        // pL pH count cn and fn too, so the parameters end here
+        let end = i + param_len.saturating_sub(2);
+        if end > data.len() {
+            self.log_debug("GS ( k QR command incomplete");
+            return Ok(start_i);
+        }
+
+        match fn_code {
+            // Set module size
+            67 if i < end => self.qr_size = data[i],
+            // Set error correction level
+            69 if i < end => self.qr_error_correction = data[i],
+            80 => {
+                // Store QR data, after the m byte
+                self.qr_data = data[(i + 1).min(end)..end].to_vec();
+            }
+            // Print QR code
+            81 if !self.qr_data.is_empty() => {
+                if self.has_pending_text() {
+                    self.flush_line();
+                    self.current_line.clear();
+                }
+
+                let qr_string = String::from_utf8_lossy(&self.qr_data).to_string();
+                let size = (self.qr_size as usize).clamp(1, 16);
+
+                self.elements.push(ReceiptElement::QrCode {
+                    data: qr_string,
+                    size,
+                    alignment: self.state.alignment.clone(),
+                    offset: self.state.horizontal_offset,
+                    print_area_width: self.state.print_area_width,
+                    left_margin: self.state.left_margin,
+                });
+
+                // Reset horizontal offset after use
+                self.state.horizontal_offset = 0;
+
+                self.qr_data.clear();
+            }
+            // 65 (QR model), printing without data and unknown functions
+            _ => {}
+        }
+
+        Ok(end)
+    }
+
+    fn handle_paper_cut(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
+        let mode = data[i];
+        i += 1;
+        // Function B (feed and cut) has a feed amount parameter
+        if matches!(mode, 65 | 66) {
+            if i >= data.len() {
+                return Ok(i - 2);
+            }
+            i += 1;
+        }
+
+        let cut_type = match mode {
+            0 | 48 => "FULL CUT",
+            1 | 49 => "PARTIAL CUT",
+            65 => "FEED & FULL CUT",
+            66 => "FEED & PARTIAL CUT",
+            _ => "UNKNOWN CUT",
+        };
+
+        self.flush_line();
+        self.elements.push(ReceiptElement::PaperCut {
+            cut_type: cut_type.to_string(),
+        });
+        if matches!(mode, 0 | 48 | 65) {
+            self.mark_job_boundary(JobBoundary::FullCut, i);
+        }
+
+        Ok(i)
+    }
+}
+
+/// What parsing a whole job produced
+pub struct ParsedJob {
+    pub elements: Vec<ReceiptElement>,
+    pub warnings: Vec<LintWarning>,
+    pub drawer_pulses: Vec<DrawerPulse>,
+}
+
+/// Printer settings a job is parsed with
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub paper_size: PaperSize,
+    /// ESC t number in effect until the job selects one
+    pub code_page: u8,
+    /// Columns per line of Fonts A, B and C, 0 for their own
+    pub columns: [u16; 3],
+    /// Code pages loaded from mapping files, by ESC t number
+    pub custom_code_pages: CustomCodePages,
+    /// SO and SI switch one-line double width, like legacy firmware
+    pub so_double_width: bool,
+    /// Print stray control characters as symbols
+    pub control_glyphs: bool,
+    /// Code pages forced by client address, see for_source
+    pub forced_code_pages: Vec<ForcedCodePage>,
+    /// Code page the job prints with whatever ESC t selects
+    pub forced_code_page: Option<u8>,
+}
+
+impl ParseOptions {
+    /// The options for a job from `source`, with the code page forced for
+    /// that client, if any
+    pub fn for_source(&self, source: &str) -> Self {
+        Self {
+            forced_code_page: codepages::forced_code_page(&self.forced_code_pages, source),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            paper_size: PaperSize::Size80mm,
+            code_page: 0,
+            columns: [0; 3],
+            custom_code_pages: CustomCodePages::new(),
+            so_double_width: false,
+            control_glyphs: false,
+            forced_code_pages: Vec::new(),
+            forced_code_page: None,
+        }
+    }
+}
+
+fn job_renderer(options: &ParseOptions) -> EscPosRenderer {
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.set_paper_size(options.paper_size);
+    renderer.set_default_code_page(options.code_page);
+    renderer.set_columns(options.columns);
+    renderer.set_custom_code_pages(options.custom_code_pages.clone());
+    renderer.status.so_double_width = options.so_double_width;
+    renderer.set_control_glyphs(options.control_glyphs);
+    renderer.set_forced_code_page(options.forced_code_page);
+    renderer
+}
+
+/// Parse a whole job the way a connection would
+pub fn parse_job(data: &[u8], options: &ParseOptions) -> Result<ParsedJob> {
+    let mut renderer = job_renderer(options);
+    renderer.process_data(data)?;
+    renderer.end_job()?;
+    Ok(ParsedJob {
+        elements: renderer.take_elements(),
+        warnings: renderer.take_lint_warnings(),
+        drawer_pulses: renderer.take_drawer_pulses(),
+    })
+}
+
+/// Times each command was processed while parsing, and the time it took, by
+/// command name
+pub type CommandProfile = BTreeMap<String, (usize, Duration)>;
+
+/// Parse a whole job timing each command, for `escpresso bench`
+pub fn profile_job(data: &[u8], options: &ParseOptions) -> Result<CommandProfile> {
+    let mut renderer = job_renderer(options);
+    renderer.profile = Some(CommandProfile::new());
+    renderer.process_data(data)?;
+    renderer.end_job()?;
+    Ok(renderer.profile.take().unwrap_or_default())
+}
+
+/// A stretch of a job's bytes as the parser saw it: one command, a run of
+/// text or a control character
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceStep {
+    /// Byte offsets in the job, end excluded
+    pub start: u64,
+    pub end: u64,
+    /// Command name, "text" or the control character
+    pub command: String,
+    /// Indexes of the elements it produced
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub elements: Vec<usize>,
+}
+
+/// An element with the bytes it came from. A line of text comes from its
+/// first character through the step that printed it, so the formatting
+/// commands within the line are included.
+#[derive(Debug, Clone, Serialize)]
+pub struct TracedElement {
+    pub start: u64,
+    pub end: u64,
+    pub element: ReceiptElement,
+}
+
+/// Which bytes of a job produced which elements
+#[derive(Debug, Clone, Serialize)]
+pub struct JobTrace {
+    pub steps: Vec<TraceStep>,
+    pub elements: Vec<TracedElement>,
+}
+
+/// Parse a whole job, tracing every step
+pub fn trace_job(data: &[u8], options: &ParseOptions) -> Result<JobTrace> {
+    let mut renderer = job_renderer(options);
+    renderer.trace = Some(Vec::new());
+    renderer.process_data(data)?;
+    renderer.process_held_back()?;
+    let end = renderer.stream_offset;
+    let elements_before = renderer.elements.len();
+    // Text after the last line feed still prints
+    renderer.flush_line();
+    let mut steps = renderer.trace.take().unwrap_or_default();
+    if renderer.elements.len() > elements_before {
+        steps.push(TraceStep {
+            start: end,
+            end,
+            command: "end of job".to_string(),
+            elements: (elements_before..renderer.elements.len()).collect(),
+        });
+    }
+
+    let mut elements = renderer.take_elements().into_iter();
+    let mut traced = Vec::with_capacity(elements.len());
+    // Start of the text not yet printed
+    let mut line_start = None;
+    for step in &steps {
+        if step.command == "text" {
+            line_start.get_or_insert(step.start);
+        }
+        for element in elements.by_ref().take(step.elements.len()) {
+            let start = match element {
+                ReceiptElement::Text { .. } => line_start.unwrap_or(step.start),
+                _ => step.start,
+            };
+            traced.push(TracedElement {
+                start,
+                end: step.end,
+                element,
+            });
+        }
+        if !step.elements.is_empty() {
+            line_start = None;
+        }
+    }
+    Ok(JobTrace {
+        steps,
+        elements: traced,
+    })
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Receipt elements of a complete job, for one-shot decoding in tests and
+/// tools that don't need the job's warnings or a connection's state
+pub fn parse_bytes(data: &[u8], options: &ParseOptions) -> Vec<ReceiptElement> {
+    // Unknown or malformed commands are skipped rather than failing the job;
+    // after an error, what was parsed up to it is kept
+    let mut renderer = job_renderer(options);
+    match renderer.process_data(data) {
+        Ok(()) => _ = renderer.end_job(),
+        Err(_) => renderer.flush_line(),
+    }
+    renderer.take_elements()
+}
+
+/// What ends a job, besides the connection closing. Each job gets its own
+/// entry in the job list, hooks `on_job_end` call and receipt.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum JobBoundary {
+    #[default]
+    ConnectionClose,
+    FullCut,
+    Initialize,
+    // Seconds without data
+    Inactivity(u64),
+}
+
+impl std::str::FromStr for JobBoundary {
+    type Err = String;
+
+    /// `close`, `cut`, `init` or `idle:<seconds>`
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.trim() {
+            "close" => Ok(Self::ConnectionClose),
+            "cut" => Ok(Self::FullCut),
+            "init" => Ok(Self::Initialize),
+            other => other
+                .strip_prefix("idle:")
+                .and_then(|secs| secs.parse().ok())
+                .filter(|&secs| secs > 0)
+                .map(Self::Inactivity)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown job boundary '{}', expected close, cut, init or idle:<seconds>",
+                        other
+                    )
+                }),
+        }
+    }
+}
+
+/// A drawer kick-out pulse as the printer would drive it
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawerPulse {
+    pub command: &'static str,
+    /// Connector pin: 2 or 5
+    pub pin: u8,
+    pub on_ms: u32,
+    pub off_ms: u32,
+}
@@ -0,0 +1,202 @@
+// Job linting: flags commands that print fine here but are likely to cause
+// trouble on real printers or other emulators. The renderer feeds every
+// command and element of a connection through a Linter as it parses.
+
+use crate::{ReceiptElement, ESC, FS, GS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintWarning {
+    /// Byte offset of the command in the job (in the connection's stream
+    /// until the job is recorded)
+    pub offset: u64,
+    pub message: String,
+}
+
+/// Obsolete commands: (command prefix, name, replacement)
+const OBSOLETE: &[(&[u8], &str, &str)] = &[
+    (&[ESC, b'i'], "ESC i (partial cut)", "GS V"),
+    (&[ESC, b'm'], "ESC m (partial cut)", "GS V"),
+    (&[ESC, b'u'], "ESC u (peripheral status)", "GS r"),
+    (&[ESC, b'v'], "ESC v (paper sensor status)", "GS r"),
+    (&[FS, b'p'], "FS p (print NV bit image)", "GS ( L"),
+    (&[FS, b'q'], "FS q (define NV bit image)", "GS ( L"),
+    (&[GS, b'v', b'0'], "GS v 0 (raster bit image)", "GS ( L"),
+    (&[GS, b'*'], "GS * (define downloaded bit image)", "GS ( L"),
+    (&[GS, b'/'], "GS / (print downloaded bit image)", "GS ( L"),
+];
+
+#[derive(Default)]
+pub struct Linter {
+    warnings: Vec<LintWarning>,
+    /// Stream offset of the command being parsed
+    offset: u64,
+    /// ESC @ seen in the current job
+    initialized: bool,
+    warned_uninitialized: bool,
+    /// Decoding problems are reported once per job
+    warned_decoding: bool,
+    /// Something printed since the last paper feed or cut
+    printed: bool,
+    /// The last command was a cut that feeds the paper first
+    cut_feeds: bool,
+    /// The current line already wrapped at the paper edge
+    wrapped: bool,
+    /// NV graphics key codes and the number of FS q images defined on this
+    /// connection
+    nv_graphics: HashSet<[u8; 2]>,
+    nv_bit_images: u8,
+}
+
+impl Linter {
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push(LintWarning {
+            offset: self.offset,
+            message,
+        });
+    }
+
+    /// A new job starts on the same connection. What's stored in NV memory
+    /// stays defined.
+    pub fn job_ended(&mut self) {
+        self.initialized = false;
+        self.warned_uninitialized = false;
+        self.warned_decoding = false;
+    }
+
+    /// Check a fully parsed command
+    pub fn command(&mut self, command: &[u8]) {
+        for (prefix, name, replacement) in OBSOLETE {
+            if command.starts_with(prefix) {
+                self.warn(format!("{} is obsolete, use {}", name, replacement));
+            }
+        }
+        self.cut_feeds = false;
+        match command {
+            // A test print resets the printer when it's done
+            [ESC, b'@', ..] | [GS, b'(', b'A', ..] => self.initialized = true,
+            [GS, b'V', mode, ..] => self.cut_feeds = matches!(mode, 65 | 66),
+            [FS, b'q', n, ..] => self.nv_bit_images = *n,
+            [FS, b'p', n, ..] if *n > self.nv_bit_images => self.warn(format!(
+                "FS p prints NV bit image {}, which isn't defined on this connection",
+                n
+            )),
+            // GS ( L pL pH m fn ... and GS 8 L p1 p2 p3 p4 m fn ...
+            [GS, b'(', b'L', _, _, _, function, params @ ..]
+            | [GS, b'8', b'L', _, _, _, _, _, function, params @ ..] => {
+                self.graphics_command(*function, params)
+            }
+            _ => {}
+        }
+    }
+
+    fn graphics_command(&mut self, function: u8, params: &[u8]) {
+        match (function, params) {
+            // Delete all NV graphics
+            (65, _) => self.nv_graphics.clear(),
+            // Delete one
+            (66, [kc1, kc2, ..]) => {
+                self.nv_graphics.remove(&[*kc1, *kc2]);
+            }
+            // Define: a kc1 kc2 ...
+            (67, [_, kc1, kc2, ..]) => {
+                self.nv_graphics.insert([*kc1, *kc2]);
+            }
+            // Print
+            (69, [kc1, kc2, ..]) if !self.nv_graphics.contains(&[*kc1, *kc2]) => {
+                self.warn(format!(
+                    "GS ( L prints NV graphics \"{}\", which isn't defined on this connection",
+                    String::from_utf8_lossy(&[*kc1, *kc2])
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    /// Check elements as the parser produces them
+    pub fn elements(&mut self, elements: &[ReceiptElement]) {
+        for element in elements {
+            match element {
+                ReceiptElement::Text { .. }
+                | ReceiptElement::RasterImage { .. }
+                | ReceiptElement::QrCode { .. }
+                | ReceiptElement::Barcode { .. } => {
+                    if !self.initialized && !self.warned_uninitialized {
+                        self.warned_uninitialized = true;
+                        self.warn("Printing before ESC @ initialized the printer".to_string());
+                    }
+                    self.printed = true;
+                }
+                ReceiptElement::Separator | ReceiptElement::FormFeed => self.printed = false,
+                ReceiptElement::PaperCut { .. } => {
+                    if self.printed && !self.cut_feeds {
+                        self.warn(
+                            "Cut right after printing: the last lines are still below the \
+                             cutter, feed first (ESC d or GS V 65/66)"
+                                .to_string(),
+                        );
+                    }
+                    self.printed = false;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Text didn't fit the print area and wrapped onto a new line: the
+    /// next character would have ended `width` dots into the line, with
+    /// characters `char_width` dots wide (size and spacing included)
+    pub fn text_wrapped(&mut self, width: u16, line_width: u16, char_width: u16) {
+        if !self.wrapped {
+            self.wrapped = true;
+            self.warn(format!(
+                "Text reaching {} dots is wider than the {}-dot print area ({} columns at \
+                 this size) and wrapped onto the next line",
+                width,
+                line_width,
+                line_width / char_width.max(1)
+            ));
+        }
+    }
+
+    /// A raster image ran past the end of the line and was cut off
+    pub fn image_clipped(&mut self, width: usize, available: usize) {
+        self.warn(format!(
+            "Image {} dots wide doesn't fit the {} dots left on the line: the printer drops \
+             the {} dots past the edge",
+            width,
+            available,
+            width - available
+        ));
+    }
+
+    /// A CUPS raster page couldn't be decoded, so the rest of the job isn't
+    /// printed
+    pub fn raster_dropped(&mut self, error: &str) {
+        self.warn(format!(
+            "CUPS raster page not printed, nor anything after it: {}",
+            error
+        ));
+    }
+
+    /// A line's bytes don't decode cleanly in the code page
+    pub fn decoding_problem(&mut self, problem: &str) {
+        if !self.warned_decoding {
+            self.warned_decoding = true;
+            self.warn(problem.to_string());
+        }
+    }
+
+    pub fn line_ended(&mut self) {
+        self.wrapped = false;
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<LintWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+}
@@ -1,1791 +1,3152 @@
+mod admin_api;
+mod animation;
+mod archive;
+mod audit;
+mod capture;
+mod cli;
+mod command_view;
+mod config;
+mod demo;
+mod dump;
+mod events;
+mod os_print;
+mod pdf;
+mod receive_buffer;
+mod reference;
+mod rfc2217;
+mod session;
+
 use anyhow::Result;
-use codepage_437::{BorrowFromCp437, CP437_CONTROL};
+use archive::Archive;
+use audit::{AuditEvent, AuditLog};
+use capture::RawCapture;
 use eframe::egui;
-use encoding_rs::Encoding;
-use qrcode::{Color as QrColor, QrCode};
+use escpresso::access::AccessControl;
+use escpresso::codepages::{CustomCodePage, CustomCodePages, ForcedCodePage};
+use escpresso::connection::{FinishedJob, JobEvent, JobSplitter};
+use escpresso::custom_values::CustomValues;
+use escpresso::customer_display::CustomerDisplay;
+use escpresso::lint::LintWarning;
+use escpresso::maintenance::MaintenanceCounters;
+use escpresso::nv_memory::NvMemory;
+use escpresso::paper_roll::PaperRoll;
+use escpresso::render::{
+    apply_style, element_layout, install_fallback_fonts, show_receipt_elements, DARKNESS_RANGE,
+    DEFAULT_RASTER_BUDGET_MP,
+};
+use escpresso::scripting::{JobSummary, ScriptHooks};
+use escpresso::status::{Profile, ScheduledChange, SimulatedStatus};
+use escpresso::{
+    access, codepages, command_name, composer, customer_display, disassembly, parse_job,
+    profile_job, receipt, render, self_test, status, trace_job, unix_millis, Alignment,
+    DrawerPulse, EscPosRenderer, JobBoundary, JobTrace, PaperSize, ParseOptions, ReceiptElement,
+    CODE_PAGES, CR, DLE, DOTS_PER_INCH, ESC, FF, FS, GS, HT, LF,
+};
+use events::{EventBus, PrinterEvent};
+use receive_buffer::{ReceiveBuffer, ReceiveBufferSettings};
+use reference::{OverlayMode, Reference};
+use serde::Serialize;
+use session::{Saver, Session, Snapshot};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+/// How often the window redraws when nothing changes
+const IDLE_REPAINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a listener restart waits for open connections to finish their job
+/// and close before aborting them and rebinding
+const LISTENER_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Network settings for the TCP listener, changeable at runtime
+#[derive(Debug, Clone, PartialEq)]
+struct ListenerSettings {
+    // One or more addresses, separated by commas or spaces: IPv4, IPv6
+    // or host names
+    bind_address: String,
+    port: u16,
+    protocol: Protocol,
+}
 
-const ESC: u8 = 0x1B;
-const GS: u8 = 0x1D;
-const FS: u8 = 0x1C;
-const DLE: u8 = 0x10;
-const LF: u8 = 0x0A;
-const FF: u8 = 0x0C;
-const CR: u8 = 0x0D;
-const HT: u8 = 0x09;
-const CAN: u8 = 0x18;
-const DC2: u8 = 0x12;
-const SOH: u8 = 0x01;
-const STX: u8 = 0x02;
-const ETX: u8 = 0x03;
-const EOT: u8 = 0x04;
-const ENQ: u8 = 0x05;
-const ACK: u8 = 0x06;
-const BEL: u8 = 0x07;
-const BS: u8 = 0x08;
-const VT: u8 = 0x0B;
-const SO: u8 = 0x0E;
-const SI: u8 = 0x0F;
-const DC1: u8 = 0x11;
-const DC3: u8 = 0x13;
-const DC4: u8 = 0x14;
-const ETB: u8 = 0x17;
-const RS: u8 = 0x1E;
+impl Default for ListenerSettings {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0".to_string(),
+            port: 9100,
+            protocol: Protocol::default(),
+        }
+    }
+}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum PaperSize {
-    Size58mm,
-    Size80mm,
+/// What connections speak
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Protocol {
+    /// Print data as is, like port 9100 of a network printer
+    #[default]
+    Raw,
+    /// Telnet with the serial port options, like a serial device server
+    /// (see rfc2217.rs)
+    Rfc2217,
 }
 
-impl PaperSize {
-    fn width_px(&self) -> f32 {
-        // Printable area width (print head), not full paper
-        // 80mm paper: 72mm print head = 576 dots (48 cols * 12 dots)
-        // 58mm paper: 48mm print head = 384 dots (32 cols * 12 dots)
-        (self.chars_per_line() as f32) * 12.0
-    }
+impl Protocol {
+    const ALL: [Self; 2] = [Self::Raw, Self::Rfc2217];
 
-    fn chars_per_line(&self) -> usize {
+    fn label(self) -> &'static str {
         match self {
-            PaperSize::Size58mm => 32,
-            PaperSize::Size80mm => 48,
+            Self::Raw => "Raw TCP",
+            Self::Rfc2217 => "RFC 2217 (serial over telnet)",
         }
     }
+}
 
-    fn label(&self) -> &str {
-        match self {
-            PaperSize::Size58mm => "58mm",
-            PaperSize::Size80mm => "80mm",
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    /// `raw` or `rfc2217`
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.trim() {
+            "raw" => Ok(Self::Raw),
+            "rfc2217" => Ok(Self::Rfc2217),
+            other => Err(format!(
+                "unknown protocol '{}', expected raw or rfc2217",
+                other
+            )),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-enum ReceiptElement {
-    Text {
-        content: String,
-        bold: bool,
-        underline: bool,
-        double_width: bool,
-        double_height: bool,
-        inverted: bool,
-        alignment: Alignment,
-        density: u8,
-        offset: u16,
-        left_margin: u16,
-        character_spacing: u8,
-        double_strike: bool,
-        font: u8,
-        print_area_width: u16,
-    },
-    RasterImage {
-        width: usize, // Width in pixels (for display)
-        height: usize,
-        data: Vec<u8>,
-        offset: u16,
-        density: u8,
-        alignment: Alignment,
-        bytes_per_line: usize, // Actual bytes per line from command (for data reading)
-        print_area_width: u16,
-    },
-    QrCode {
-        data: String,
-        size: usize,
-        alignment: Alignment,
-        offset: u16,
-        print_area_width: u16,
-    },
-    PaperCut {
-        cut_type: String,
-    },
-    CashDrawer {
-        pin: u8,
-        on_time: u8,
-        off_time: u8,
-    },
-    Separator,
-    FormFeed,
+impl ListenerSettings {
+    /// Each bind address with the port, IPv6 ones in brackets
+    fn socket_addrs(&self) -> Vec<String> {
+        self.bind_address
+            .split([',', ' '])
+            .filter(|address| !address.is_empty())
+            .map(|address| {
+                let address = address.trim_start_matches('[').trim_end_matches(']');
+                if address.contains(':') {
+                    format!("[{}]:{}", address, self.port)
+                } else {
+                    format!("{}:{}", address, self.port)
+                }
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
-enum Alignment {
-    Left,
-    Center,
-    Right,
+/// Bind every address, or none if one fails: (address, error)
+async fn bind_all(addrs: &[String]) -> std::result::Result<Vec<TcpListener>, (String, String)> {
+    if addrs.is_empty() {
+        return Err((String::new(), "no bind address".to_string()));
+    }
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => return Err((addr.clone(), e.to_string())),
+        }
+    }
+    Ok(listeners)
+}
+
+/// Next connection on any of the listeners
+async fn accept_any(
+    listeners: &[TcpListener],
+) -> std::io::Result<(tokio::net::TcpStream, std::net::SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for listener in listeners {
+            if let std::task::Poll::Ready(accepted) = listener.poll_accept(cx) {
+                return std::task::Poll::Ready(accepted);
+            }
+        }
+        std::task::Poll::Pending
+    })
+    .await
 }
 
-#[derive(Debug)]
-struct PrinterState {
-    bold: bool,
-    underline: bool,
-    double_width: bool,
-    double_height: bool,
-    inverted: bool,
-    alignment: Alignment,
-    print_density: u8,
-    encoding: &'static Encoding,
-    code_page: u8,
-    horizontal_offset: u16,
-    left_margin: u16,
-    print_area_width: u16,
-    line_spacing: u8,
-    character_spacing: u8,
-    double_strike: bool,
-    font: u8, // 0=Font A, 1=Font B, etc.
+/// Limits on client connections, applied without restarting the listener
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ConnectionLimits {
+    // Connections beyond this many are closed right away (0 = no limit)
+    max_connections: usize,
+    // Seconds without data before a connection is closed (0 = never)
+    idle_timeout: u64,
+    // Seconds without traffic before TCP keepalive probes check that the
+    // client is still there (0 = off), for new connections
+    keepalive: u64,
 }
 
-impl Default for PrinterState {
+impl Default for ConnectionLimits {
     fn default() -> Self {
         Self {
-            bold: false,
-            underline: false,
-            double_width: false,
-            double_height: false,
-            inverted: false,
-            alignment: Alignment::Left,
-            print_density: 4,
-            encoding: encoding_rs::UTF_8,
-            code_page: 0,
-            horizontal_offset: 0,
-            left_margin: 0,
-            print_area_width: 0, // 0 = use default (full width)
-            line_spacing: 30,    // Default: 1/6 inch = ~30 dots at 203 DPI
-            character_spacing: 0,
-            double_strike: false,
-            font: 0, // Default: Font A
+            max_connections: 0,
+            idle_timeout: 0,
+            keepalive: 60,
         }
     }
 }
 
-struct EscPosRenderer {
-    state: PrinterState,
-    current_line: Vec<u8>, // Store raw bytes, decode using current encoding when flushing
-    debug: bool,
-    buffer: Vec<u8>,
-    elements: Vec<ReceiptElement>,
-    in_command_sequence: bool,
-    qr_data: Vec<u8>,
-    qr_size: u8,
-    qr_error_correction: u8,
-    response_queue: Vec<u8>,
-    last_was_binary: bool, // Track if last command was binary (raster, etc.)
+/// Time between keepalive probes once they start. The system gives up on
+/// the connection after a number of unanswered ones (9 on Linux).
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Turn on TCP keepalive, so a client that vanished without closing the
+/// connection (power loss, network gone) is noticed and its job finished
+fn set_keepalive(socket: &tokio::net::TcpStream, idle_secs: u64) -> std::io::Result<()> {
+    let keepalive = socket2::TcpKeepalive::new()
+        .with_time(Duration::from_secs(idle_secs))
+        .with_interval(KEEPALIVE_INTERVAL);
+    socket2::SockRef::from(socket).set_tcp_keepalive(&keepalive)
+}
+
+/// How much of the receipt is kept, so long soak tests don't run out of
+/// memory. Beyond either limit the oldest receipts are archived and dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct HistoryLimits {
+    // 0 = no limit
+    max_receipts: usize,
+    max_elements: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", content = "detail", rename_all = "lowercase")]
+enum ListenerStatus {
+    Starting,
+    Listening(String),
+    Draining(usize),
+    Failed(String),
+}
+
+/// Entry of the drawer log
+#[derive(Debug, Clone, Serialize)]
+struct DrawerKick {
+    /// Milliseconds since the Unix epoch
+    at: u64,
+    source: String,
+    #[serde(flatten)]
+    pulse: DrawerPulse,
+}
+
+/// An open client connection
+#[derive(Debug, Clone)]
+struct Connection {
+    addr: std::net::SocketAddr,
+    /// Big command whose data is arriving: (name, bytes received, bytes it
+    /// takes); smaller ones arrive too fast to be worth showing
+    receiving: Option<(&'static str, usize, usize)>,
+    last_data: std::time::Instant,
+}
+
+/// Commands with less data than this get no progress bar
+const PROGRESS_MIN_BYTES: usize = 16 * 1024;
+
+/// Raw bytes received over one connection
+#[derive(Debug, Clone)]
+struct Job {
+    id: u64,
+    source: String,
+    raw: Vec<u8>,
+    // Offsets are into raw
+    warnings: Vec<LintWarning>,
+    // Milliseconds since the Unix epoch: first byte received, job ended
+    received_at: u64,
+    finished_at: u64,
+    // File with the raw bytes, if captured (or restored from a session)
+    capture: Option<std::path::PathBuf>,
+    // Each read: (milliseconds after received_at, bytes received so far)
+    arrivals: Vec<(u64, usize)>,
+    // Written in the job list, or tags from the on_job_end hook, so the
+    // history can serve as a review record ("bug #1234 repro")
+    note: String,
+    tags: Vec<String>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    elements: Arc<Mutex<Vec<ReceiptElement>>>,
+    // Receive time of each element (see unix_millis), locked after elements
+    element_times: Arc<Mutex<Vec<u64>>>,
+    // Last ReceiptStart id handed out
+    receipt_ids: Arc<AtomicU64>,
+    // Last job id handed out; clearing the jobs doesn't reset it
+    job_ids: Arc<AtomicU64>,
+    // Append every connection's elements as they arrive, interleaved, instead
+    // of keeping one receipt per connection
+    merge_receipts: Arc<Mutex<bool>>,
+    // Print control characters that aren't commands as symbols instead of
+    // dropping them
+    control_glyphs: Arc<Mutex<bool>>,
+    // Code pages loaded from mapping files, by ESC t number
+    custom_code_pages: Arc<Mutex<CustomCodePages>>,
+    // Code pages clients print with whatever ESC t they send, by address
+    forced_code_pages: Arc<Mutex<Vec<ForcedCodePage>>>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+    paper_size: Arc<Mutex<PaperSize>>,
+    // Sending new settings makes the server drain its connections and rebind
+    listener: Arc<watch::Sender<ListenerSettings>>,
+    listener_status: Arc<Mutex<ListenerStatus>>,
+    connection_limits: Arc<Mutex<ConnectionLimits>>,
+    // Allowed client addresses and the secret preamble, checked on connect
+    access: Arc<Mutex<AccessControl>>,
+    job_boundary: Arc<Mutex<JobBoundary>>,
+    // ESC @ starts a new job and receipt, besides resetting the formatting,
+    // for clients that send it before every order on a connection they keep
+    init_new_receipt: Arc<Mutex<bool>>,
+    // Picked up by each new connection; reloading doesn't affect open ones
+    hooks: Arc<Mutex<Option<Arc<ScriptHooks>>>>,
+    // Connections subscribe to push ASB frames when the status changes
+    simulated_status: Arc<watch::Sender<SimulatedStatus>>,
+    // Flag changes waiting for their time, applied by run_status_scheduler
+    scheduled_status: Arc<Mutex<Vec<ScheduledChange>>>,
+    // Used up by everything printed, tripping the paper sensors as it runs out
+    paper_roll: Arc<Mutex<PaperRoll>>,
+    // Read by each connection before every socket read
+    receive_buffer: Arc<Mutex<ReceiveBufferSettings>>,
+    jobs: Arc<Mutex<Vec<Job>>>,
+    // Every drawer kick, kept until cleared in the Drawer window or the API
+    drawer_log: Arc<Mutex<Vec<DrawerKick>>>,
+    // GS ( C user memory, saved to a file when one is configured
+    nv_memory: Arc<Mutex<NvMemory>>,
+    // GS ( M saved settings, saved to a file when one is configured
+    custom_values: Arc<Mutex<CustomValues>>,
+    // Pole display behind the printer, selected with ESC = 2
+    customer_display: Arc<Mutex<CustomerDisplay>>,
+    // Paper fed, lines printed and cuts since start (GS g)
+    maintenance: Arc<Mutex<MaintenanceCounters>>,
+    // Folder each job's images are saved to as PNG, if any
+    image_dump: Arc<Mutex<Option<std::path::PathBuf>>>,
+    // Where each job's raw bytes are saved, if anywhere
+    capture: Arc<Mutex<Option<RawCapture>>>,
+    // Archive and clear the receipt when a connection first prints, so test
+    // prints don't stack up into one receipt
+    auto_clear: Arc<Mutex<bool>>,
+    archive: Arc<Mutex<Archive>>,
+    // Checked as each job ends
+    history_limits: Arc<Mutex<HistoryLimits>>,
+    // Where jobs and elements are saved when session persistence is enabled
+    session: Option<Saver>,
+    // Connections and jobs are appended to it when enabled
+    audit: Option<Arc<AuditLog>>,
+    // Jobs, cuts and drawer kicks are published to it when enabled
+    events: Option<EventBus>,
+    // Where the events go; changing it reconnects the publishing task
+    event_target: Arc<watch::Sender<Option<events::Target>>>,
+    // Finish time of the last job (see unix_millis), 0 before the first
+    last_job_at: Arc<AtomicU64>,
+    // The window, once open, woken up when the state it shows changes
+    repaint: Arc<Mutex<Option<egui::Context>>>,
 }
 
-impl EscPosRenderer {
-    fn new(debug: bool) -> Self {
+impl AppState {
+    fn new() -> Self {
+        let (listener, _) = watch::channel(ListenerSettings::default());
         Self {
-            state: PrinterState::default(),
-            current_line: Vec::new(),
-            debug,
-            buffer: Vec::new(),
-            elements: Vec::new(),
-            in_command_sequence: false,
-            qr_data: Vec::new(),
-            qr_size: 3,
-            qr_error_correction: 0,
-            response_queue: Vec::new(),
-            last_was_binary: false,
+            elements: Arc::new(Mutex::new(Vec::new())),
+            element_times: Arc::new(Mutex::new(Vec::new())),
+            receipt_ids: Arc::new(AtomicU64::new(0)),
+            job_ids: Arc::new(AtomicU64::new(0)),
+            merge_receipts: Arc::new(Mutex::new(false)),
+            control_glyphs: Arc::new(Mutex::new(false)),
+            custom_code_pages: Arc::default(),
+            forced_code_pages: Arc::default(),
+            connections: Arc::new(Mutex::new(Vec::new())),
+            paper_size: Arc::new(Mutex::new(PaperSize::Size80mm)),
+            listener: Arc::new(listener),
+            listener_status: Arc::new(Mutex::new(ListenerStatus::Starting)),
+            connection_limits: Arc::new(Mutex::new(ConnectionLimits::default())),
+            access: Arc::default(),
+            job_boundary: Arc::new(Mutex::new(JobBoundary::default())),
+            init_new_receipt: Arc::new(Mutex::new(false)),
+            hooks: Arc::new(Mutex::new(None)),
+            simulated_status: Arc::new(watch::channel(SimulatedStatus::default()).0),
+            scheduled_status: Arc::new(Mutex::new(Vec::new())),
+            paper_roll: Arc::new(Mutex::new(PaperRoll::default())),
+            receive_buffer: Arc::new(Mutex::new(ReceiveBufferSettings::default())),
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            drawer_log: Arc::new(Mutex::new(Vec::new())),
+            nv_memory: Arc::default(),
+            custom_values: Arc::default(),
+            customer_display: Arc::default(),
+            maintenance: Arc::default(),
+            image_dump: Arc::new(Mutex::new(None)),
+            capture: Arc::new(Mutex::new(Some(RawCapture::default()))),
+            auto_clear: Arc::new(Mutex::new(false)),
+            archive: Arc::default(),
+            history_limits: Arc::default(),
+            session: None,
+            audit: None,
+            events: None,
+            event_target: Arc::new(watch::channel(None).0),
+            last_job_at: Arc::default(),
+            repaint: Arc::new(Mutex::new(None)),
         }
     }
 
-    fn log_debug(&self, msg: &str) {
-        if self.debug {
-            eprintln!("[DEBUG] {}", msg);
+    /// Have the window redraw, as it only does on input otherwise
+    fn request_repaint(&self) {
+        if let Some(ctx) = &*self.repaint.lock().unwrap() {
+            ctx.request_repaint();
         }
     }
 
-    fn take_elements(&mut self) -> Vec<ReceiptElement> {
-        std::mem::take(&mut self.elements)
+    /// A connection read more data, still `receiving` a big command or not
+    fn connection_progress(
+        &self,
+        addr: std::net::SocketAddr,
+        receiving: Option<(&'static str, usize, usize)>,
+    ) {
+        let receiving = receiving.filter(|&(_, _, size)| size >= PROGRESS_MIN_BYTES);
+        let mut connections = self.connections.lock().unwrap();
+        let Some(connection) = connections.iter_mut().find(|c| c.addr == addr) else {
+            return;
+        };
+        connection.last_data = std::time::Instant::now();
+        if connection.receiving != receiving {
+            connection.receiving = receiving;
+            self.request_repaint();
+        }
     }
 
-    fn take_responses(&mut self) -> Vec<u8> {
-        std::mem::take(&mut self.response_queue)
+    fn set_listener_status(&self, status: ListenerStatus) {
+        *self.listener_status.lock().unwrap() = status;
+        self.request_repaint();
     }
 
-    fn process_data(&mut self, new_data: &[u8]) -> Result<()> {
-        self.buffer.extend_from_slice(new_data);
+    /// Clear the receipt and the jobs that produced it
+    fn clear_receipt(&self) {
+        self.elements.lock().unwrap().clear();
+        self.element_times.lock().unwrap().clear();
+        self.jobs.lock().unwrap().clear();
+        self.save_session();
+    }
 
-        let mut i = 0;
-        let data = self.buffer.clone();
+    /// Decode ESC t `number` with the mapping file at `path` from now on
+    fn load_code_page(&self, number: u8, path: &std::path::Path) -> Result<()> {
+        let page = CustomCodePage::load(path)?;
+        println!(
+            "Code page {} (ESC t {}) loaded from {}",
+            page.name(),
+            number,
+            path.display()
+        );
+        self.custom_code_pages
+            .lock()
+            .unwrap()
+            .insert(number, Arc::new(page));
+        Ok(())
+    }
 
-        while i < data.len() {
-            let byte = data[i];
-            let start_pos = i;
+    /// Switch the paper size, laying the jobs out again if it changed, as
+    /// switching in the window does
+    fn set_paper_size(&self, paper_size: PaperSize) {
+        let changed = {
+            let mut current = self.paper_size.lock().unwrap();
+            std::mem::replace(&mut *current, paper_size) != paper_size
+        };
+        if changed {
+            self.rerender_receipt();
+        }
+    }
 
-            match byte {
-                DLE => {
-                    // Enter command sequence - block text accumulation
-                    self.in_command_sequence = true;
-                    // DLE commands (real-time status, etc.)
-                    i += 1;
-                    if i >= data.len() {
-                        i = start_pos;
-                        break;
-                    }
-                    let subcmd = data[i];
-                    i += 1;
-                    match subcmd {
-                        0x04 | 0x05 => {
-                            // DLE EOT, DLE ENQ - real-time status
-                            if i < data.len() {
-                                let _n = data[i];
-                                i += 1;
-
-                                // Queue status response: 0x12 = online, no errors
-                                // Bit format: 00010010
-                                //   Bit 3 = 1: Paper present
-                                //   Bit 4 = 1: Online
-                                self.response_queue.push(0x12);
-                                self.log_debug(
-                                    "DLE EOT/ENQ: queued status response 0x12 (online, no errors)",
-                                );
-                            }
-                        }
-                        0x14 => {
-                            // DLE DC4 - real-time commands
-                            if i + 1 < data.len() {
-                                i += 2;
-                            }
-                        }
-                        _ => {}
-                    }
-                    // Command processed - allow text accumulation again
-                    self.in_command_sequence = false;
-                }
-                CAN => {
-                    // Cancel print data in page mode
-                    i += 1;
-                }
-                DC2 => {
-                    // DC2 - Cancel bold OR DC2 # n (print density for zj-58)
-                    i += 1;
-                    if i < data.len() && data[i] == b'#' {
-                        // DC2 # n - Set print density (zj-58 CUPS driver)
-                        i += 1;
-                        if i < data.len() {
-                            let density = data[i];
-                            self.state.print_density = (density / 32).min(8); // Map 0-255 to 0-8
-                            self.log_debug(&format!("DC2 #: print density={}", density));
-                            i += 1;
-                        }
-                    } else {
-                        // Standard DC2 - Cancel bold
-                        self.state.bold = false;
-                    }
-                }
-                DC1 => {
-                    // DC1 / XON - Device control / flow control
-                    i += 1;
-                }
-                DC3 => {
-                    // DC3 / XOFF - Device control / flow control
-                    i += 1;
-                }
-                DC4 => {
-                    // DC4 - Device control (standalone, not DLE DC4)
-                    i += 1;
-                }
-                SO => {
-                    // SO - Shift Out (alternate character set)
-                    i += 1;
-                }
-                SI => {
-                    // SI - Shift In (standard character set)
-                    i += 1;
-                }
-                VT => {
-                    // VT - Vertical tab
-                    i += 1;
-                }
-                SOH | STX | ETX | EOT | ENQ | ACK | BEL | ETB | RS => {
-                    // Other control characters - just skip
-                    i += 1;
-                }
-                BS => {
-                    // Backspace - remove last byte if present
-                    if !self.current_line.is_empty() {
-                        self.current_line.pop();
-                    }
-                    i += 1;
-                }
-                ESC => {
-                    // Enter command sequence - block text accumulation
-                    self.in_command_sequence = true;
-                    i += 1;
-                    if i >= data.len() {
-                        i = start_pos;
-                        break;
-                    }
-                    match self.handle_esc_command(&data, i) {
-                        Ok(new_i) => {
-                            if new_i == i || new_i <= start_pos {
-                                // Handler didn't make progress - waiting for more data
-                                i = start_pos;
-                                // Keep in_command_sequence = true
-                                break;
-                            }
-                            i = new_i;
-                            // Command fully processed - allow text accumulation again
-                            self.in_command_sequence = false;
-                        }
-                        Err(e) => return Err(e),
-                    }
-                }
-                GS => {
-                    // Enter command sequence - block text accumulation
-                    self.in_command_sequence = true;
-                    i += 1;
-                    if i >= data.len() {
-                        i = start_pos;
-                        break;
-                    }
-                    match self.handle_gs_command(&data, i) {
-                        Ok(new_i) => {
-                            if new_i == i || new_i <= start_pos {
-                                // Handler didn't make progress - waiting for more data
-                                i = start_pos;
-                                // Keep in_command_sequence = true
-                                break;
-                            }
-                            i = new_i;
-                            // Command fully processed - allow text accumulation again
-                            self.in_command_sequence = false;
-                        }
-                        Err(e) => return Err(e),
-                    }
-                }
-                FS => {
-                    // Enter command sequence - block text accumulation
-                    self.in_command_sequence = true;
-                    i += 1;
-                    if i >= data.len() {
-                        i = start_pos;
-                        break;
-                    }
-                    // FS command handling - many commands have unknown parameter counts
-                    let cmd = data[i];
-                    i += 1;
-                    match cmd {
-                        b'.' => {
-                            // FS . n - Print NV bit image - 1 parameter
-                            // Don't consume parameter if next byte is a command start
-                            if i < data.len() {
-                                let next = data[i];
-                                // Only consume if not a command byte (ESC/GS/FS/DLE)
-                                if next != ESC && next != GS && next != FS && next != DLE {
-                                    i += 1;
-                                }
-                            }
-                        }
-                        b'p' => {
-                            // FS p n m - Print NV bit image - 2 parameters
-                            if i + 1 < data.len() {
-                                i += 2;
-                            }
-                        }
-                        b'q' => {
-                            // FS q n [xL xH yL yH d1...dk] - Define NV bit image
-                            if i < data.len() {
-                                let n = data[i];
-                                i += 1;
-                                if n > 0 && i + 4 < data.len() {
-                                    let xl = data[i] as usize;
-                                    let xh = data[i + 1] as usize;
-                                    let yl = data[i + 2] as usize;
-                                    let yh = data[i + 3] as usize;
-                                    let width = xl + (xh << 8);
-                                    let height = yl + (yh << 8);
-                                    let data_size = width.div_ceil(8) * height;
-                                    i += 4 + data_size.min(data.len() - i);
-                                }
-                            }
-                        }
-                        b'(' => {
-                            // FS ( fn pL pH [data...] - Extended commands with length
-                            if i + 3 < data.len() {
-                                let _fn = data[i]; // function code (e.g., 'A')
-                                let p_l = data[i + 1] as usize;
-                                let p_h = data[i + 2] as usize;
-                                let len = p_l + (p_h << 8);
-                                i += 3 + len.min(data.len() - i);
-                            }
-                        }
-                        b'C' | b'g' | b'!' | b'&' | b'S' | b'-' => {
-                            // Commands with 1 parameter
-                            if i < data.len() {
-                                i += 1;
-                            }
-                        }
-                        _ => {
-                            // Unknown FS subcommands - try to consume 1-2 likely parameter bytes
-                            // Many proprietary commands use 1-2 bytes
-                            if i < data.len() && (data[i] < 0x1B || data[i] > 0x7E) {
-                                // Next byte doesn't look like a command start, consume it as parameter
-                                i += 1;
-                                // If it was high-bit, might be a 2-byte parameter
-                                if i < data.len()
-                                    && data[i - 1] > 0x7F
-                                    && (data[i] < 0x1B || data[i] > 0x7E)
-                                {
-                                    i += 1;
-                                }
-                            }
-                            if self.debug {
-                                self.log_debug(&format!(
-                                    "FS command 0x{:02X} - consumed {} parameter bytes",
-                                    cmd,
-                                    i - (start_pos + 2)
-                                ));
-                            }
-                        }
-                    }
-                    // Command processed - allow text accumulation again
-                    self.in_command_sequence = false;
-                }
-                LF => {
-                    // LF: Print and line feed - flush current line and advance
-                    self.in_command_sequence = false; // Exit command sequence, allow text again
-                    self.last_was_binary = false; // LF marks start of text content
-                    if !self.current_line.is_empty() {
-                        self.flush_line();
-                        self.current_line.clear();
-                    } else if !self.elements.is_empty() {
-                        // Only add separator for blank lines if we've already printed something
-                        // This avoids extra spacing after init commands like ESC @
-                        self.elements.push(ReceiptElement::Separator);
-                    }
-                    i += 1;
-                }
-                CR => {
-                    // CR: Print and carriage return - flush current line
-                    self.in_command_sequence = false; // Exit command sequence, allow text again
-                    self.last_was_binary = false; // CR marks start of text content
-                    if !self.current_line.is_empty() {
-                        self.flush_line();
-                        self.current_line.clear();
-                    }
-                    i += 1;
-                }
-                FF => {
-                    self.current_line.clear();
-                    // Only add FormFeed if the last element isn't already one
-                    if !matches!(self.elements.last(), Some(ReceiptElement::FormFeed)) {
-                        self.elements.push(ReceiptElement::FormFeed);
-                    }
-                    i += 1;
-                }
-                HT => {
-                    // Only add tabs if not in command sequence
-                    if !self.in_command_sequence {
-                        // Add 4 spaces as tab
-                        self.current_line.extend_from_slice(b"    ");
-                    }
-                    i += 1;
-                }
-                0x20..=0x7E | 0x80..=0xFF => {
-                    // Printable characters (both ASCII and extended codepage)
-                    if i == data.len() - 1 && !self.buffer.is_empty() {
-                        break;
-                    }
-                    // Only accumulate text if we're NOT in a command sequence AND not after binary data
-                    if !self.in_command_sequence && !self.last_was_binary {
-                        if self.debug {
-                            self.log_debug(&format!(
-                                "Adding byte to line: 0x{:02X} at position {}",
-                                byte, i
-                            ));
-                        }
-                        self.current_line.push(byte);
-                    }
-                    i += 1;
-                }
-                0x00..=0x1F | 0x7F => {
-                    // Control characters (including DEL)
-                    // Silently consume these - they're control codes, not printable text
-                    i += 1;
+    /// Set the simulated status, laying the jobs out again if the columns
+    /// or SO double width changed
+    fn replace_simulated_status(&self, status: SimulatedStatus) {
+        let before = self.simulated_status.send_replace(status);
+        if status.columns != before.columns || status.so_double_width != before.so_double_width {
+            self.rerender_receipt();
+        }
+    }
+
+    /// Settings jobs are parsed with outside a connection
+    fn parse_options(&self) -> ParseOptions {
+        let status = *self.simulated_status.borrow();
+        ParseOptions {
+            paper_size: *self.paper_size.lock().unwrap(),
+            code_page: status.code_page,
+            columns: status.columns,
+            custom_code_pages: self.custom_code_pages.lock().unwrap().clone(),
+            so_double_width: status.so_double_width,
+            control_glyphs: *self.control_glyphs.lock().unwrap(),
+            forced_code_pages: self.forced_code_pages.lock().unwrap().clone(),
+            forced_code_page: None,
+        }
+    }
+
+    /// Save the receipt and its jobs to the archive, then clear them. If
+    /// archiving fails they are kept.
+    fn archive_and_clear(&self) {
+        let paper_size = *self.paper_size.lock().unwrap();
+        let archive = self.archive.lock().unwrap().clone();
+        {
+            let jobs = self.jobs.lock().unwrap();
+            let elements = self.elements.lock().unwrap();
+            if jobs.is_empty() && elements.is_empty() {
+                return;
+            }
+            let element_times = self.element_times.lock().unwrap();
+            match archive.save(unix_millis(), paper_size, &jobs, &elements, &element_times) {
+                Ok(dir) => println!("Archived the receipt to {}", dir.display()),
+                Err(e) => {
+                    eprintln!("ERROR: Failed to archive the receipt, keeping it: {:#}", e);
+                    return;
                 }
             }
         }
+        self.clear_receipt();
+        self.request_repaint();
+    }
 
-        self.buffer.drain(0..i);
+    /// Lay the receipt out again from the jobs' raw bytes, at the current
+    /// paper size, so the same jobs can be compared on 58mm and 80mm paper.
+    /// Each job is parsed on its own and becomes a receipt of its own
+    /// (unless receipts are merged); elements of jobs still being received
+    /// are dropped.
+    fn rerender_receipt(&self) {
+        let options = self.parse_options();
+        let merge = *self.merge_receipts.lock().unwrap();
+        let mut elements = Vec::new();
+        let mut times = Vec::new();
+        for job in self.jobs.lock().unwrap().iter_mut() {
+            let parsed = match parse_job(&job.raw, &options.for_source(&job.source)) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("ERROR: Failed to re-render job {}: {:#}", job.id, e);
+                    continue;
+                }
+            };
+            // Width-dependent warnings (wrapping, clipped images) change too
+            job.warnings = parsed.warnings;
+            if parsed.elements.is_empty() {
+                continue;
+            }
+            if !merge {
+                let id = self.receipt_ids.fetch_add(1, Ordering::Relaxed) + 1;
+                elements.push(ReceiptElement::ReceiptStart {
+                    id,
+                    source: job.source.clone(),
+                });
+            }
+            elements.extend(parsed.elements);
+            times.resize(elements.len(), job.received_at);
+        }
+        *self.elements.lock().unwrap() = elements;
+        *self.element_times.lock().unwrap() = times;
+        self.save_session();
+        self.request_repaint();
+    }
 
-        // Don't auto-flush at buffer end - only flush on explicit line terminators (LF, CR)
-        // This prevents fragmenting text that arrives in multiple TCP packets
+    /// Add elements printed by a connection. Unless receipts are merged they
+    /// go at the end of that connection's receipt, which is started (with a
+    /// ReceiptStart element) the first time it prints, so concurrent clients
+    /// don't interleave.
+    fn append_elements(
+        &self,
+        receipt: &mut Option<u64>,
+        source: &str,
+        new_elements: Vec<ReceiptElement>,
+    ) {
+        self.request_repaint();
+        self.use_paper(&new_elements);
+        for element in &new_elements {
+            if let ReceiptElement::PaperCut { cut_type } = element {
+                self.publish(PrinterEvent::Cut {
+                    source: source.to_string(),
+                    cut_type: cut_type.clone(),
+                });
+            }
+        }
+        self.maintenance.lock().unwrap().count(&new_elements);
+        let mut elements = self.elements.lock().unwrap();
+        let mut times = self.element_times.lock().unwrap();
+        // Restored sessions may predate timestamps
+        times.resize(elements.len(), 0);
+        let now = unix_millis();
+        if *self.merge_receipts.lock().unwrap() {
+            times.resize(elements.len() + new_elements.len(), now);
+            elements.extend(new_elements);
+            return;
+        }
+        let is_start = |element: &ReceiptElement, wanted: Option<u64>| match element {
+            ReceiptElement::ReceiptStart { id, .. } => wanted.is_none_or(|wanted| *id == wanted),
+            _ => false,
+        };
+        // The receipt is gone if it was cleared meanwhile
+        let start = receipt.and_then(|id| elements.iter().position(|e| is_start(e, Some(id))));
+        let end = match start {
+            Some(start) => elements[start + 1..]
+                .iter()
+                .position(|e| is_start(e, None))
+                .map_or(elements.len(), |next| start + 1 + next),
+            None => {
+                let id = self.receipt_ids.fetch_add(1, Ordering::Relaxed) + 1;
+                *receipt = Some(id);
+                elements.push(ReceiptElement::ReceiptStart {
+                    id,
+                    source: source.to_string(),
+                });
+                times.push(now);
+                elements.len()
+            }
+        };
+        times.splice(end..end, std::iter::repeat_n(now, new_elements.len()));
+        elements.splice(end..end, new_elements);
+    }
 
+    /// Print a job generated locally rather than received over the network,
+    /// as its own receipt
+    fn print_local(&self, source: &str, data: Vec<u8>) -> Result<()> {
+        let options = self.parse_options();
+        let job = parse_job(&data, &options)?;
+        let images: Vec<_> = job
+            .elements
+            .iter()
+            .filter(|e| dump::is_image(e))
+            .cloned()
+            .collect();
+        let received_at = unix_millis();
+        self.log_drawer_pulses(source, job.drawer_pulses);
+        self.append_elements(&mut None, source, job.elements);
+        let arrivals = vec![(0, data.len())];
+        let id = self.add_job(
+            source.to_string(),
+            data,
+            job.warnings,
+            received_at,
+            arrivals,
+        );
+        self.save_job_images(id, &images);
         Ok(())
     }
 
-    fn flush_line(&mut self) {
-        if self.current_line.is_empty() {
+    fn log_drawer_pulses(&self, source: &str, pulses: Vec<DrawerPulse>) {
+        if pulses.is_empty() {
             return;
         }
+        self.request_repaint();
+        let at = unix_millis();
+        self.simulated_status.send_if_modified(|status| {
+            let mut opened = false;
+            for pulse in &pulses {
+                opened |= status.kick_drawer(pulse.pin);
+            }
+            opened
+        });
+        let mut log = self.drawer_log.lock().unwrap();
+        for pulse in pulses {
+            println!(
+                "Drawer kick from {}: {} pin {}, on {} ms, off {} ms",
+                source, pulse.command, pulse.pin, pulse.on_ms, pulse.off_ms
+            );
+            self.publish(PrinterEvent::DrawerKick {
+                source: source.to_string(),
+                command: pulse.command,
+                pin: pulse.pin,
+                on_ms: pulse.on_ms,
+                off_ms: pulse.off_ms,
+            });
+            log.push(DrawerKick {
+                at,
+                source: source.to_string(),
+                pulse,
+            });
+        }
+    }
 
-        if self.debug {
-            self.log_debug(&format!(
-                "Flushing line: {} bytes, codepage={}",
-                self.current_line.len(),
-                self.state.code_page
-            ));
+    /// A client cleared the recoverable error with DLE ENQ
+    fn recover_error(&self, source: &str) {
+        if self
+            .simulated_status
+            .send_if_modified(SimulatedStatus::recover)
+        {
+            println!("Error recovery from {}", source);
+            self.request_repaint();
         }
+    }
 
-        // Decode bytes using current codepage
-        let decoded = if self.state.code_page == 0 {
-            // CP437 - use codepage-437 crate
-            String::borrow_from_cp437(&self.current_line, &CP437_CONTROL)
-        } else {
-            // Other codepages - use encoding_rs
-            let (decoded_cow, _encoding_used, had_errors) =
-                self.state.encoding.decode(&self.current_line);
-
-            if self.debug {
-                if had_errors {
-                    self.log_debug(&format!(
-                        "Decoding errors in line, codepage={}",
-                        self.state.code_page
-                    ));
-                }
-                self.log_debug(&format!("Decoded: {:?}", decoded_cow));
+    /// Record a finished job, returning its id
+    fn add_job(
+        &self,
+        source: String,
+        raw: Vec<u8>,
+        warnings: Vec<LintWarning>,
+        received_at: u64,
+        arrivals: Vec<(u64, usize)>,
+    ) -> u64 {
+        let finished_at = unix_millis();
+        self.last_job_at.store(finished_at, Ordering::Relaxed);
+        let warning_count = warnings.len();
+        // The id is taken with the jobs locked so they stay in id order; the
+        // capture is written after the lock is released and filled in then
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let id = self.job_ids.fetch_add(1, Ordering::Relaxed) + 1;
+            jobs.push(Job {
+                id,
+                source: source.clone(),
+                raw: raw.clone(),
+                warnings,
+                received_at,
+                finished_at,
+                capture: None,
+                arrivals,
+                note: String::new(),
+                tags: Vec::new(),
+            });
+            id
+        };
+        if let Some(capture) = self.save_capture(id, &source, received_at, &raw) {
+            let mut jobs = self.jobs.lock().unwrap();
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                job.capture = Some(capture);
             }
+        }
+        self.publish(PrinterEvent::Job {
+            id,
+            source,
+            bytes: raw.len(),
+            received_at,
+            duration_ms: finished_at.saturating_sub(received_at),
+            warnings: warning_count,
+        });
+        self.apply_history_limits();
+        self.save_session();
+        self.request_repaint();
+        id
+    }
 
-            decoded_cow.into_owned()
+    /// Archive and drop the oldest receipts beyond the history limits, along
+    /// with the jobs that ended before the first receipt kept. The newest
+    /// receipt is always kept; with merged receipts, where there is just one,
+    /// its oldest elements go instead. If archiving fails nothing is dropped.
+    fn apply_history_limits(&self) {
+        let limits = *self.history_limits.lock().unwrap();
+        if limits == HistoryLimits::default() {
+            return;
+        }
+        let paper_size = *self.paper_size.lock().unwrap();
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut elements = self.elements.lock().unwrap();
+        let mut times = self.element_times.lock().unwrap();
+        // Restored sessions may predate timestamps
+        times.resize(elements.len(), 0);
+        let starts: Vec<usize> = elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches!(e, ReceiptElement::ReceiptStart { .. }))
+            .map(|(i, _)| i)
+            .collect();
+        let too_many = |evicted: usize| {
+            let receipts = starts.iter().filter(|&&start| start >= evicted).count();
+            (limits.max_receipts > 0 && receipts > limits.max_receipts)
+                || (limits.max_elements > 0 && elements.len() - evicted > limits.max_elements)
         };
+        // Elements before the first one kept
+        let mut evicted = 0;
+        for &start in starts.iter().skip(1) {
+            if !too_many(evicted) {
+                break;
+            }
+            evicted = start;
+        }
+        if starts.is_empty() && limits.max_elements > 0 {
+            evicted = elements.len().saturating_sub(limits.max_elements);
+        }
+        if evicted == 0 {
+            return;
+        }
+        let kept_since = times[evicted];
+        let ended = jobs.partition_point(|job| job.finished_at < kept_since);
+        let archive = self.archive.lock().unwrap().clone();
+        match archive.save(
+            unix_millis(),
+            paper_size,
+            &jobs[..ended],
+            &elements[..evicted],
+            &times[..evicted],
+        ) {
+            Ok(dir) => println!(
+                "History limit reached, archived {} element(s) and {} job(s) to {}",
+                evicted,
+                ended,
+                dir.display()
+            ),
+            Err(e) => {
+                eprintln!(
+                    "ERROR: Failed to archive old receipts, keeping them: {:#}",
+                    e
+                );
+                return;
+            }
+        }
+        jobs.drain(..ended);
+        elements.drain(..evicted);
+        times.drain(..evicted);
+    }
 
-        self.elements.push(ReceiptElement::Text {
-            content: decoded,
-            bold: self.state.bold,
-            underline: self.state.underline,
-            double_width: self.state.double_width,
-            double_height: self.state.double_height,
-            inverted: self.state.inverted,
-            alignment: self.state.alignment.clone(),
-            density: self.state.print_density,
-            offset: self.state.horizontal_offset,
-            left_margin: self.state.left_margin,
-            character_spacing: self.state.character_spacing,
-            double_strike: self.state.double_strike,
-            font: self.state.font,
-            print_area_width: self.state.print_area_width,
-        });
+    /// Save a job's raw bytes if capturing is enabled, returning the file
+    fn save_capture(
+        &self,
+        job_id: u64,
+        source: &str,
+        received_at: u64,
+        raw: &[u8],
+    ) -> Option<std::path::PathBuf> {
+        let capture = self.capture.lock().unwrap().clone()?;
+        capture
+            .save(job_id, source, received_at, raw)
+            .map_err(|e| eprintln!("ERROR: Failed to capture job {}: {:#}", job_id, e))
+            .ok()
+    }
 
-        // Reset horizontal offset after use (ESC $ is one-time positioning)
-        self.state.horizontal_offset = 0;
+    fn image_dump_enabled(&self) -> bool {
+        self.image_dump.lock().unwrap().is_some()
     }
 
-    fn handle_esc_command(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
-        let cmd = data[i];
-        match cmd {
-            b'@' => {
-                self.state = PrinterState::default();
-                i += 1;
+    /// Save a job's images if image dumping is enabled, returning their
+    /// folder if there were any
+    fn save_job_images(
+        &self,
+        job_id: u64,
+        elements: &[ReceiptElement],
+    ) -> Option<std::path::PathBuf> {
+        let dir = self.image_dump.lock().unwrap().clone()?;
+        match dump::save_job_images(&dir, job_id, elements) {
+            Ok(0) => None,
+            Ok(count) => {
+                println!(
+                    "Saved {} image(s) of job {} to {}",
+                    count,
+                    job_id,
+                    dir.display()
+                );
+                Some(dump::job_dir(&dir, job_id))
             }
-            b'E' => {
-                i += 1;
-                if i < data.len() {
-                    self.state.bold = data[i] == 1;
-                    i += 1;
-                }
+            Err(e) => {
+                eprintln!(
+                    "ERROR: Failed to save the images of job {}: {:#}",
+                    job_id, e
+                );
+                None
             }
-            b'-' => {
-                i += 1;
-                if i < data.len() {
-                    let n = data[i];
-                    // n = 0: off, n = 1 or 2: on (with thickness)
-                    // Only consider actual values 1-2, not ASCII '1' '2'
-                    self.state.underline = n == 1 || n == 2;
-                    i += 1;
+        }
+    }
+
+    fn publish(&self, event: PrinterEvent) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    fn audit(&self, event: AuditEvent) {
+        if let Some(audit) = &self.audit {
+            audit.record(unix_millis(), &event);
+        }
+    }
+
+    /// Add a recorded job to the audit log
+    fn audit_job(
+        &self,
+        id: u64,
+        elements: usize,
+        commands: &BTreeMap<String, usize>,
+        images: Option<&std::path::Path>,
+    ) {
+        if self.audit.is_none() {
+            return;
+        }
+        let jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter().rev().find(|job| job.id == id) else {
+            return;
+        };
+        self.audit(AuditEvent::Job {
+            id,
+            source: &job.source,
+            bytes: job.raw.len(),
+            received_at: job.received_at,
+            duration_ms: job.finished_at.saturating_sub(job.received_at),
+            elements,
+            commands,
+            warnings: job.warnings.len(),
+            capture: job.capture.as_deref(),
+            images,
+        });
+    }
+
+    /// Use up the paper roll, setting the near-end and paper-out flags when
+    /// their thresholds are crossed. Only a new roll clears them.
+    fn use_paper(&self, elements: &[ReceiptElement]) {
+        let Some(sensors) = self.paper_roll.lock().unwrap().consume(elements) else {
+            return;
+        };
+        self.simulated_status.send_if_modified(|status| {
+            let before = *status;
+            status.paper_near_end |= sensors.near_end;
+            status.paper_out |= sensors.paper_out;
+            *status != before
+        });
+    }
+
+    /// Replace the paper roll with a full one
+    fn load_paper_roll(&self) {
+        self.paper_roll.lock().unwrap().used_mm = 0.0;
+        self.simulated_status.send_if_modified(|status| {
+            let before = *status;
+            status.paper_near_end = false;
+            status.paper_out = false;
+            *status != before
+        });
+    }
+
+    /// Apply scheduled status changes whose time has come
+    fn apply_due_status_changes(&self) {
+        let now = std::time::Instant::now();
+        let mut scheduled = self.scheduled_status.lock().unwrap();
+        if !scheduled.iter().any(|change| change.due <= now) {
+            return;
+        }
+        let changed = self.simulated_status.send_if_modified(|status| {
+            let before = *status;
+            scheduled.retain(|change| {
+                if change.due > now {
+                    return true;
                 }
-            }
-            b'a' => {
-                i += 1;
-                if i < data.len() {
-                    self.state.alignment = match data[i] {
-                        0 => Alignment::Left,
-                        1 => Alignment::Center,
-                        2 => Alignment::Right,
-                        _ => Alignment::Left,
-                    };
-                    i += 1;
+                if let Some(flag) = status.flag_mut(change.flag) {
+                    *flag = change.value;
                 }
+                false
+            });
+            *status != before
+        });
+        if changed {
+            self.request_repaint();
+        }
+    }
+
+    /// Replace a job's note and tags
+    fn annotate_job(&self, id: u64, note: String, tags: Vec<String>) {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.iter_mut().find(|job| job.id == id) else {
+                return;
+            };
+            job.note = note;
+            job.tags = tags;
+        }
+        self.save_session();
+        self.request_repaint();
+    }
+
+    /// Save a copy of the jobs and the receipt in the background, so the
+    /// locks are only held while copying
+    fn save_session(&self) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let snapshot = Snapshot {
+            paper_size: *self.paper_size.lock().unwrap(),
+            jobs: self.jobs.lock().unwrap().clone(),
+            last_job_id: self.job_ids.load(Ordering::Relaxed),
+            elements: self.elements.lock().unwrap().clone(),
+            element_times: self.element_times.lock().unwrap().clone(),
+        };
+        session.save(snapshot);
+    }
+}
+
+/// Tags typed separated by commas, without blanks or repeats
+fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in text.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|known| known == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// Where an element came from, for the element tooltip: its job, its byte
+/// range in the job and the first of those bytes
+struct ElementSource {
+    job: u64,
+    start: usize,
+    end: usize,
+    job_len: usize,
+    bytes: Vec<u8>,
+}
+
+type CommandViewKey = (usize, Option<u64>, PaperSize, bool);
+
+/// Status shown while a Print… action runs
+const PRINTING: &str = "Printing…";
+
+/// Status shown while the jobs are written to a PDF
+const EXPORTING: &str = "Exporting…";
+
+struct VirtualEscPosApp {
+    state: AppState,
+    show_settings: bool,
+    show_warnings: bool,
+    show_jobs: bool,
+    show_composer: bool,
+    show_drawer_log: bool,
+    show_customer_display: bool,
+    show_element_list: bool,
+    // The receipt is in a window of its own
+    receipt_detached: bool,
+    // Element selected in the element list, moved with the arrow keys
+    element_list_selected: usize,
+    // Job whose arrival timeline is shown
+    timeline_job: Option<u64>,
+    // Job whose notes are being edited: id, tags as typed, note
+    job_notes: Option<(u64, String, String)>,
+    // Jobs ticked for the PDF export, the file and the export's progress
+    job_selection: BTreeSet<u64>,
+    pdf_path: String,
+    pdf_status: Arc<Mutex<Option<Result<String, String>>>>,
+    // Text file the ticked jobs are disassembled to, and how that went
+    disassembly_path: String,
+    disassembly_status: Option<Result<String, String>>,
+    show_reference: bool,
+    // Approved render laid over the receipt, see reference.rs
+    reference: Option<Reference>,
+    reference_mode: OverlayMode,
+    reference_opacity: f32,
+    // Path typed in the reference window, and why loading failed
+    reference_path: String,
+    reference_error: Option<String>,
+    // Sequence being written in the composer, see composer::parse
+    composer_source: String,
+    // Listener settings being edited in the settings window, applied on demand
+    listener_draft: ListenerSettings,
+    // Allowlist being edited, applied whenever it parses
+    allowlist_draft: String,
+    // Custom code page being loaded in the settings: ESC t number, mapping
+    // file, and why loading failed
+    code_page_number: u8,
+    code_page_path: String,
+    code_page_error: Option<String>,
+    // Forced code page rules being edited, applied on demand, and why they
+    // don't parse
+    forced_code_pages_draft: String,
+    forced_code_pages_error: Option<String>,
+    script_path: String,
+    script_message: Option<Result<String, String>>,
+    // Folder for job images, kept while dumping is switched off
+    image_dump_dir: String,
+    // Raw capture folder and size limit, kept while capturing is switched off
+    capture_settings: RawCapture,
+    // Capture files dropped on the window, waiting for append or replace:
+    // (file name, contents)
+    dropped_files: Vec<(String, Vec<u8>)>,
+    // Progress of the last Print… action, updated by its thread
+    print_status: Arc<Mutex<Option<Result<String, String>>>>,
+    // Wrap text at the print area edge instead of clipping it
+    wrap_print_area: bool,
+    show_unknown_commands: bool,
+    // Warning sign on lines wider than the print area or with bytes the
+    // code page can't decode
+    show_text_warnings: bool,
+    // Dashed lines at each element's GS L left margin and GS W print area
+    show_guides: bool,
+    // Tooltip with an element's properties and source bytes on hover
+    show_element_details: bool,
+    // Trace of the job the last element hovered came from, by job id and
+    // paper size, so resting the pointer doesn't parse it every frame
+    hover_trace: Option<(u64, PaperSize, JobTrace)>,
+    // Source of the element hovered, by index and element count
+    hover_source: Option<((usize, usize), Option<ElementSource>)>,
+    // Commands drawn as chips between the elements, see command_view
+    show_commands: bool,
+    // The command view and what it was built from: job count, last job id,
+    // paper size and whether receipts were merged
+    command_view: Option<(CommandViewKey, Vec<command_view::ViewItem>)>,
+    // Printer darkness setting the preview is drawn with (DARKNESS_RANGE)
+    darkness: i8,
+    // Rasters bigger than this many megapixels are shown downscaled, 0 for
+    // full resolution
+    raster_budget_mp: u32,
+    // Scheduled status change being set up: index into status::FLAGS,
+    // new value and delay in seconds
+    schedule_flag: usize,
+    schedule_value: bool,
+    schedule_delay: u64,
+}
+
+impl VirtualEscPosApp {
+    fn new(cc: &eframe::CreationContext, state: AppState) -> Self {
+        install_fallback_fonts(&cc.egui_ctx);
+        apply_style(&cc.egui_ctx);
+        *state.repaint.lock().unwrap() = Some(cc.egui_ctx.clone());
+        let listener_draft = state.listener.borrow().clone();
+        let allowlist_draft = access::format_allowlist(&state.access.lock().unwrap().allowlist);
+        let forced_code_pages_draft =
+            codepages::format_forced_code_pages(&state.forced_code_pages.lock().unwrap());
+        let script_path = state
+            .hooks
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|hooks| hooks.path().display().to_string())
+            .unwrap_or_default();
+        let image_dump_dir = state.image_dump.lock().unwrap().as_ref().map_or_else(
+            || "escpresso-images".to_string(),
+            |dir| dir.display().to_string(),
+        );
+        let capture_settings = state.capture.lock().unwrap().clone().unwrap_or_default();
+        Self {
+            state,
+            show_settings: false,
+            show_warnings: false,
+            show_jobs: false,
+            show_composer: false,
+            show_drawer_log: false,
+            show_customer_display: false,
+            receipt_detached: false,
+            show_element_list: false,
+            element_list_selected: 0,
+            timeline_job: None,
+            job_notes: None,
+            job_selection: BTreeSet::new(),
+            pdf_path: "escpresso-jobs.pdf".to_string(),
+            pdf_status: Arc::new(Mutex::new(None)),
+            disassembly_path: "escpresso-jobs.txt".to_string(),
+            disassembly_status: None,
+            show_reference: false,
+            reference: None,
+            reference_mode: OverlayMode::Overlay,
+            reference_opacity: 0.5,
+            reference_path: String::new(),
+            reference_error: None,
+            composer_source: "ESC @\n".to_string(),
+            listener_draft,
+            allowlist_draft,
+            code_page_number: 255,
+            code_page_path: String::new(),
+            code_page_error: None,
+            forced_code_pages_draft,
+            forced_code_pages_error: None,
+            script_path,
+            script_message: None,
+            image_dump_dir,
+            capture_settings,
+            dropped_files: Vec::new(),
+            print_status: Arc::new(Mutex::new(None)),
+            wrap_print_area: false,
+            show_unknown_commands: true,
+            show_text_warnings: true,
+            show_guides: false,
+            show_element_details: true,
+            hover_trace: None,
+            hover_source: None,
+            show_commands: false,
+            command_view: None,
+            darkness: 0,
+            raster_budget_mp: DEFAULT_RASTER_BUDGET_MP,
+            schedule_flag: 0,
+            schedule_value: true,
+            schedule_delay: 5,
+        }
+    }
+
+    fn load_script(&mut self) {
+        let path = self.script_path.trim();
+        self.script_message = Some(match ScriptHooks::load(std::path::Path::new(path)) {
+            Ok(hooks) => {
+                let message = format!("Loaded hooks: {}", hooks.defined_hooks().join(", "));
+                *self.state.hooks.lock().unwrap() = Some(Arc::new(hooks));
+                Ok(message)
             }
-            b'!' => {
-                i += 1;
-                if i < data.len() {
-                    let mode = data[i];
-                    self.state.bold = (mode & 0x08) != 0;
-                    self.state.double_height = (mode & 0x10) != 0;
-                    self.state.double_width = (mode & 0x20) != 0;
-                    self.state.underline = (mode & 0x80) != 0;
-                    i += 1;
-                }
+            Err(e) => Err(e.to_string()),
+        });
+    }
+
+    fn print_demo(&self, job: demo::DemoJob) {
+        let source = format!("demo: {}", job.name);
+        if let Err(e) = self.state.print_local(&source, job.data) {
+            eprintln!("ERROR: Demo job '{}' failed: {:#}", job.name, e);
+        }
+    }
+
+    fn print_self_test(&self) {
+        let status = *self.state.simulated_status.borrow();
+        let paper_size = *self.state.paper_size.lock().unwrap();
+        let custom_code_pages = self.state.custom_code_pages.lock().unwrap().clone();
+        if let Err(e) = self.state.print_local(
+            "self test",
+            self_test::page(&status, paper_size, &custom_code_pages),
+        ) {
+            eprintln!("ERROR: Self test failed: {:#}", e);
+        }
+    }
+
+    /// Code pages loaded from mapping files, for clone printers with tables
+    /// of their own
+    fn show_custom_code_pages(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Custom code page").on_hover_text(
+                "A mapping file of bytes 0x80-0xFF to Unicode, one \"0x80 0x00C7\" per line \
+                 like the unicode.org tables, used for ESC t n instead of the built-in table",
+            );
+            ui.label("ESC t");
+            ui.add(egui::DragValue::new(&mut self.code_page_number));
+            ui.add(
+                egui::TextEdit::singleline(&mut self.code_page_path)
+                    .hint_text("mapping file")
+                    .desired_width(160.0),
+            );
+            let path = self.code_page_path.trim();
+            if ui
+                .add_enabled(!path.is_empty(), egui::Button::new("Load"))
+                .clicked()
+            {
+                let result = self
+                    .state
+                    .load_code_page(self.code_page_number, std::path::Path::new(path));
+                changed = result.is_ok();
+                self.code_page_error = result.err().map(|e| format!("{:#}", e));
             }
-            b'd' => {
-                i += 1;
-                if i < data.len() {
-                    let lines = data[i];
-                    for _ in 0..lines {
-                        self.elements.push(ReceiptElement::Separator);
-                    }
-                    i += 1;
+        });
+        if let Some(error) = &self.code_page_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        let loaded = self.state.custom_code_pages.lock().unwrap().clone();
+        for (number, page) in loaded {
+            ui.horizontal(|ui| {
+                ui.label(format!("ESC t {}: {}", number, page.name()))
+                    .on_hover_text(page.path.display().to_string());
+                if ui.small_button("Remove").clicked() {
+                    self.state.custom_code_pages.lock().unwrap().remove(&number);
+                    changed = true;
                 }
-            }
-            b'*' => {
-                i += 1;
-                i = self.handle_raster_graphics(data, i)?;
-            }
-            b'~' => {
-                i += 1;
-                if i < data.len() {
-                    self.state.print_density = data[i].min(8);
-                    i += 1;
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Forced code pages").on_hover_text(
+                "Clients that select the wrong code page print with this one instead, \
+                 their ESC t ignored, like a printer with corrected DIP switches",
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.forced_code_pages_draft)
+                    .hint_text("192.168.1.20=16, 10.0.0.0/8=2")
+                    .desired_width(220.0),
+            );
+            if ui.button("Apply").clicked() {
+                match codepages::parse_forced_code_pages(&self.forced_code_pages_draft) {
+                    Ok(rules) => {
+                        *self.state.forced_code_pages.lock().unwrap() = rules;
+                        self.forced_code_pages_error = None;
+                        changed = true;
+                    }
+                    Err(e) => self.forced_code_pages_error = Some(format!("{:#}", e)),
                 }
             }
-            b'p' => {
-                i += 1;
-                if i + 2 < data.len() {
-                    let pin = data[i];
-                    let on_time = data[i + 1];
-                    let off_time = data[i + 2];
-                    self.elements.push(ReceiptElement::CashDrawer {
-                        pin,
-                        on_time,
-                        off_time,
+        });
+        if let Some(error) = &self.forced_code_pages_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        // Decode the jobs again with the new tables
+        if changed {
+            self.state.rerender_receipt();
+        }
+    }
+
+    /// Bit-level editor for the DLE EOT status bytes
+    fn show_status_overrides(&mut self, ui: &mut egui::Ui, status: &mut SimulatedStatus) {
+        ui.label("Override DLE EOT status bytes bit by bit (bit 7 on the left)");
+        egui::Grid::new("status_overrides")
+            .num_columns(3)
+            .show(ui, |ui| {
+                for (n, (name, bits)) in (1u8..).zip(status::STATUS_BITS) {
+                    let computed = SimulatedStatus {
+                        overrides: Default::default(),
+                        ..*status
+                    }
+                    .real_time_status(n);
+                    let slot = status.overrides.get_mut(n).unwrap();
+
+                    let mut enabled = slot.is_some();
+                    ui.checkbox(&mut enabled, format!("{} {}", n, name));
+                    let mut value = slot.unwrap_or(computed);
+                    ui.add_enabled_ui(enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.spacing_mut().item_spacing.x = 0.0;
+                            for bit in (0..8).rev() {
+                                let mut on = value & (1 << bit) != 0;
+                                let hint = match bits[bit] {
+                                    "" => format!("Bit {}", bit),
+                                    label => format!("Bit {}: {}", bit, label),
+                                };
+                                ui.checkbox(&mut on, "").on_hover_text(hint);
+                                if on {
+                                    value |= 1 << bit;
+                                } else {
+                                    value &= !(1 << bit);
+                                }
+                            }
+                        });
                     });
-                    i += 3;
-                }
-            }
-            b' ' => {
-                // ESC SP n - Set right-side character spacing
-                i += 1;
-                if i < data.len() {
-                    self.state.character_spacing = data[i];
-                    self.log_debug(&format!("ESC SP: character spacing = {}", data[i]));
-                    i += 1;
-                }
-            }
-            b'$' => {
-                // ESC $ - Set absolute horizontal print position
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as u16;
-                    let nh = data[i + 1] as u16;
-                    self.state.horizontal_offset = nl + (nh << 8);
-                    self.log_debug(&format!(
-                        "ESC $: set horizontal offset to {}",
-                        self.state.horizontal_offset
-                    ));
-                    i += 2;
+                    *slot = enabled.then_some(value);
+                    ui.monospace(format!("0x{:02X}", value));
+                    ui.end_row();
                 }
-            }
-            b'\\' => {
-                // ESC \ - Set relative horizontal print position
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as i16;
-                    let nh = data[i + 1] as i16;
-                    let relative_offset = nl + (nh << 8);
-                    // Add to current horizontal offset (can be negative)
-                    self.state.horizontal_offset =
-                        ((self.state.horizontal_offset as i16) + relative_offset).max(0) as u16;
-                    self.log_debug(&format!(
-                        "ESC \\: relative offset {} -> total {}",
-                        relative_offset, self.state.horizontal_offset
-                    ));
-                    i += 2;
+            });
+    }
+
+    /// Roll length and what's left of it
+    fn show_paper_roll(&mut self, ui: &mut egui::Ui, status: &mut SimulatedStatus) {
+        let mut roll = self.state.paper_roll.lock().unwrap();
+        ui.horizontal(|ui| {
+            ui.label("Paper roll");
+            ui.add(
+                egui::DragValue::new(&mut roll.length_m)
+                    .range(0.0..=500.0)
+                    .speed(0.5)
+                    .custom_formatter(|n, _| {
+                        if n == 0.0 {
+                            "endless".to_string()
+                        } else {
+                            format!("{} m", n)
+                        }
+                    }),
+            );
+            ui.label("near end at");
+            ui.add(
+                egui::DragValue::new(&mut roll.near_end_m)
+                    .range(0.0..=50.0)
+                    .speed(0.1)
+                    .suffix(" m left"),
+            );
+        });
+        if roll.is_tracked() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{:.2} m printed, {:.2} m left",
+                    roll.used_mm / 1000.0,
+                    roll.remaining_mm() / 1000.0
+                ));
+                if ui.button("New roll").clicked() {
+                    roll.used_mm = 0.0;
+                    status.paper_near_end = false;
+                    status.paper_out = false;
                 }
-            }
-            b'K' | b'L' | b'Y' | b'Z' => {
-                // ESC K/L/Y/Z - Select bit image mode
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as usize;
-                    let nh = data[i + 1] as usize;
-                    let width = nl + (nh << 8);
-                    i += 2;
-                    // Skip image data
-                    let bytes_needed = match cmd {
-                        b'K' | b'L' => width,
-                        b'Y' | b'Z' => width * 2,
-                        _ => width,
-                    };
-                    if i + bytes_needed <= data.len() {
-                        i += bytes_needed;
+            });
+        }
+    }
+
+    /// Flag changes that take effect after a delay
+    fn show_scheduled_status(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Set");
+            egui::ComboBox::from_id_salt("schedule_flag")
+                .selected_text(status::FLAGS[self.schedule_flag].1)
+                .show_ui(ui, |ui| {
+                    for (index, (_, label)) in status::FLAGS.iter().enumerate() {
+                        ui.selectable_value(&mut self.schedule_flag, index, *label);
                     }
-                }
+                });
+            ui.checkbox(&mut self.schedule_value, "on");
+            ui.label("after");
+            ui.add(
+                egui::DragValue::new(&mut self.schedule_delay)
+                    .range(1..=3600)
+                    .suffix(" s"),
+            );
+            if ui.button("Schedule").clicked() {
+                self.state
+                    .scheduled_status
+                    .lock()
+                    .unwrap()
+                    .push(ScheduledChange {
+                        due: std::time::Instant::now() + Duration::from_secs(self.schedule_delay),
+                        flag: status::FLAGS[self.schedule_flag].0,
+                        value: self.schedule_value,
+                    });
             }
-            b'D' => {
-                // ESC D - Set horizontal tab positions
-                i += 1;
-                // Read tab positions until NUL
-                while i < data.len() && data[i] != 0 {
-                    i += 1;
-                }
-                if i < data.len() {
-                    i += 1; // skip NUL
+        });
+
+        let now = std::time::Instant::now();
+        let mut scheduled = self.state.scheduled_status.lock().unwrap();
+        let mut cancel = None;
+        for (index, change) in scheduled.iter().enumerate() {
+            let label = status::FLAGS
+                .iter()
+                .find(|(name, _)| *name == change.flag)
+                .map_or(change.flag, |(_, label)| label);
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} {} in {:.0} s",
+                    label,
+                    if change.value { "on" } else { "off" },
+                    change
+                        .due
+                        .saturating_duration_since(now)
+                        .as_secs_f32()
+                        .ceil()
+                ));
+                if ui.small_button("Cancel").clicked() {
+                    cancel = Some(index);
                 }
-            }
-            b'S' | b'T' | b'U' | b'W' => {
-                // ESC S/T - Standard/page mode selection
-                // ESC U - Unidirectional printing
-                // ESC W - Set print area in page mode
-                i += 1;
-                if i < data.len() {
-                    if cmd == b'W' && i + 7 < data.len() {
-                        // W takes 8 parameters
-                        i += 8;
-                    } else {
-                        i += 1;
+            });
+        }
+        if let Some(index) = cancel {
+            scheduled.remove(index);
+        }
+    }
+
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_settings;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.strong("Listener");
+                egui::Grid::new("listener_settings")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Bind addresses");
+                        ui.text_edit_singleline(&mut self.listener_draft.bind_address)
+                            .on_hover_text(
+                                "IPv4 or IPv6 addresses separated by commas, e.g. \
+                                 0.0.0.0, ::1. On most systems :: alone also \
+                                 accepts IPv4 connections.",
+                            );
+                        ui.end_row();
+
+                        ui.label("Port");
+                        ui.add(
+                            egui::DragValue::new(&mut self.listener_draft.port).range(1..=65535),
+                        );
+                        ui.end_row();
+
+                        ui.label("Protocol");
+                        egui::ComboBox::from_id_salt("listener_protocol")
+                            .selected_text(self.listener_draft.protocol.label())
+                            .show_ui(ui, |ui| {
+                                for protocol in Protocol::ALL {
+                                    ui.selectable_value(
+                                        &mut self.listener_draft.protocol,
+                                        protocol,
+                                        protocol.label(),
+                                    );
+                                }
+                            })
+                            .response
+                            .on_hover_text(
+                                "RFC 2217 lets POS software set up for a networked \
+                                 serial printer connect through its COM port \
+                                 redirector. The baud rate it sets paces the data.",
+                            );
+                        ui.end_row();
+                    });
+
+                let current = self.state.listener.borrow().clone();
+                ui.horizontal(|ui| {
+                    let changed = self.listener_draft != current;
+                    if ui
+                        .add_enabled(changed, egui::Button::new("Apply"))
+                        .clicked()
+                    {
+                        self.listener_draft.bind_address =
+                            self.listener_draft.bind_address.trim().to_string();
+                        self.state
+                            .listener
+                            .send_replace(self.listener_draft.clone());
                     }
-                }
-            }
-            b'c' => {
-                // ESC c - Paper sensor commands
-                i += 1;
-                if i + 1 < data.len() {
-                    i += 2;
-                }
-            }
-            b'i' => {
-                // ESC i - Partial cut (obsolete)
-                i += 1;
-            }
-            b's' => {
-                // ESC s - Select paper sensor(s)
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            0x06 => {
-                // ESC ACK n - Enable/disable panel buttons (or ASB in some implementations)
-                i += 1;
-                if i < data.len() {
-                    let _n = data[i];
-                    self.log_debug(&format!(
-                        "ESC ACK: n=0x{:02X} (acknowledged, not implemented)",
-                        _n
-                    ));
-                    i += 1;
-                }
-            }
-            b'u' => {
-                // ESC u - Transmit peripheral device status (obsolete)
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b'v' => {
-                // ESC v - Transmit paper sensor status (obsolete)
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b't' => {
-                // ESC t - Select character code table (ESC/POS standard)
-                i += 1;
-                if i < data.len() {
-                    self.state.code_page = data[i];
-                    // Map codepage numbers to encoding_rs encodings
-                    // Note: CP437 (codepage 0) is handled specially in flush_line()
-                    self.state.encoding = match data[i] {
-                        0 => encoding_rs::WINDOWS_1252,  // CP437 (handled specially)
-                        1 => encoding_rs::WINDOWS_1252,  // Katakana (approximation)
-                        2 => encoding_rs::WINDOWS_1252,  // CP850
-                        3 => encoding_rs::WINDOWS_1252,  // CP860
-                        4 => encoding_rs::WINDOWS_1252,  // CP863
-                        5 => encoding_rs::WINDOWS_1252,  // CP865
-                        16 => encoding_rs::WINDOWS_1252, // Windows-1252 (Western European)
-                        17 => encoding_rs::WINDOWS_1251, // CP866 -> Windows-1251 (Cyrillic)
-                        18 => encoding_rs::WINDOWS_1250, // CP852 -> Windows-1250 (Central European)
-                        19 => encoding_rs::WINDOWS_1252, // CP858 (like CP850 with Euro)
-                        20 => encoding_rs::SHIFT_JIS,    // Shift JIS (Japanese)
-                        21 => encoding_rs::SHIFT_JIS,
-                        255 => encoding_rs::SHIFT_JIS,
-                        _ => encoding_rs::WINDOWS_1252, // Default fallback
-                    };
-                    if self.debug {
-                        self.log_debug(&format!("ESC t: selected codepage {}", data[i]));
+                    if ui
+                        .add_enabled(changed, egui::Button::new("Revert"))
+                        .clicked()
+                    {
+                        self.listener_draft = current;
                     }
-                    i += 1;
-                }
-            }
-            b'M' => {
-                // ESC M n - Select character font
-                // n=0: Font A, n=1: Font B, n=2: Font C (if supported)
-                i += 1;
-                if i < data.len() {
-                    self.state.font = data[i];
-                    self.log_debug(&format!("ESC M: font = {}", data[i]));
-                    i += 1;
-                }
-            }
-            b'R' | b'r' | b'%' => {
-                // Character set, region, user-defined char mode
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b'2' => {
-                // ESC 2 - Set default line spacing (1/6 inch = ~30 dots at 203 DPI)
-                self.state.line_spacing = 30;
-                self.log_debug("ESC 2: reset to default line spacing (30 dots)");
-                i += 1;
-            }
-            b'3' => {
-                // ESC 3 n - Set line spacing to n dots
-                i += 1;
-                if i < data.len() {
-                    self.state.line_spacing = data[i];
-                    self.log_debug(&format!("ESC 3: line spacing = {} dots", data[i]));
-                    i += 1;
-                }
-            }
-            b'{' => {
-                // Upside down mode
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b'G' => {
-                // ESC G n - Double-strike mode (makes text darker/bolder)
-                i += 1;
-                if i < data.len() {
-                    self.state.double_strike = data[i] != 0;
-                    self.log_debug(&format!(
-                        "ESC G: double-strike = {}",
-                        self.state.double_strike
-                    ));
-                    i += 1;
-                }
-            }
-            b'J' => {
-                // ESC J n - Print and feed n lines (used by zj-58 CUPS driver)
-                i += 1;
-                if i < data.len() {
-                    let lines = data[i];
-                    self.log_debug(&format!("ESC J: feed {} lines", lines));
-                    // Add line feeds as specified (each line is ~1/6 inch or ~4.23mm)
-                    // Display exactly as ESC/POS specifies for accurate virtual printer behavior
-                    for _ in 0..lines {
-                        self.elements.push(ReceiptElement::Separator);
+                });
+
+                let status = self.state.listener_status.lock().unwrap().clone();
+                let (color, text) = match status {
+                    ListenerStatus::Starting => (egui::Color32::GRAY, "Starting...".to_string()),
+                    ListenerStatus::Listening(addr) => (
+                        egui::Color32::from_rgb(0, 130, 0),
+                        format!("Listening on {}", addr),
+                    ),
+                    ListenerStatus::Draining(open) => (
+                        egui::Color32::from_rgb(200, 150, 0),
+                        format!("Restarting, waiting for {} connection(s)", open),
+                    ),
+                    ListenerStatus::Failed(err) => (egui::Color32::RED, err),
+                };
+                ui.colored_label(color, text);
+
+                // Unlike the address, limits apply right away
+                let mut limits = self.state.connection_limits.lock().unwrap();
+                egui::Grid::new("connection_limits")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Max connections");
+                        ui.add(
+                            egui::DragValue::new(&mut limits.max_connections)
+                                .range(0..=1000)
+                                .custom_formatter(|n, _| {
+                                    if n == 0.0 {
+                                        "unlimited".to_string()
+                                    } else {
+                                        n.to_string()
+                                    }
+                                }),
+                        );
+                        ui.end_row();
+
+                        ui.label("Idle timeout");
+                        ui.add(
+                            egui::DragValue::new(&mut limits.idle_timeout)
+                                .range(0..=3600)
+                                .custom_formatter(|n, _| {
+                                    if n == 0.0 {
+                                        "never".to_string()
+                                    } else {
+                                        format!("{} s", n)
+                                    }
+                                }),
+                        );
+                        ui.end_row();
+
+                        ui.label("Keepalive after");
+                        ui.add(
+                            egui::DragValue::new(&mut limits.keepalive)
+                                .range(0..=7200)
+                                .custom_formatter(|n, _| {
+                                    if n == 0.0 {
+                                        "off".to_string()
+                                    } else {
+                                        format!("{} s", n)
+                                    }
+                                }),
+                        )
+                        .on_hover_text(
+                            "Probe quiet connections to find clients that vanished \
+                             without closing them. Applies to new connections.",
+                        );
+                        ui.end_row();
+                    });
+                drop(limits);
+
+                let mut access = self.state.access.lock().unwrap();
+                let allowlist = access::parse_allowlist(&self.allowlist_draft);
+                egui::Grid::new("access").num_columns(2).show(ui, |ui| {
+                    ui.label("Allowed clients");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.allowlist_draft)
+                            .hint_text("everyone"),
+                    )
+                    .on_hover_text("Addresses or networks, e.g. 127.0.0.1, 192.168.1.0/24");
+                    ui.end_row();
+
+                    ui.label("Shared secret");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut access.secret)
+                            .password(true)
+                            .hint_text("none"),
+                    )
+                    .on_hover_text("Clients must send it, followed by LF, before their data");
+                    ui.end_row();
+                });
+                match allowlist {
+                    Ok(allowlist) => access.allowlist = allowlist,
+                    Err(e) => {
+                        ui.colored_label(egui::Color32::RED, e.to_string());
                     }
-                    i += 1;
-                }
-            }
-            b'V' => {
-                // 90-degree rotation
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b'(' => {
-                // ESC ( - Extended commands
-                i += 1;
-                if i + 2 < data.len() {
-                    let p_l = data[i + 1] as usize;
-                    let p_h = data[i + 2] as usize;
-                    let len = p_l + (p_h << 8);
-                    i += 3 + len;
-                }
-            }
-            b'&' => {
-                // ESC & - Define user-defined characters
-                i += 1;
-                if i + 2 < data.len() {
-                    let y = data[i] as usize;
-                    let c1 = data[i + 1] as usize;
-                    let c2 = data[i + 2] as usize;
-                    i += 3;
-                    let num_chars = if c2 >= c1 { c2 - c1 + 1 } else { 0 };
-                    let bytes_per_char = y * 12_usize.div_ceil(8);
-                    i += num_chars * bytes_per_char;
-                }
-            }
-            b'?' => {
-                // ESC ? - Cancel user-defined characters
-                i += 1;
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            b'=' => {
-                // ESC = - Select peripheral device
-                i += 1;
-                if i < data.len() {
-                    i += 1;
                 }
-            }
-            b'<' => {
-                // ESC < - Return home
-                i += 1;
-            }
-            _ => {
-                // Unknown ESC command - assume it has at least 1 parameter
-                if self.debug {
-                    self.log_debug(&format!("Unknown ESC command: 0x{:02X}", cmd));
-                }
-                i += 1;
-                // Try to consume 1 parameter byte to prevent leakage
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-        }
-        Ok(i)
-    }
-
-    fn handle_gs_command(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
-        let cmd = data[i];
-        match cmd {
-            b'8' => {
-                // GS 8 - Extended command (L = raster graphics)
-                let start_i = i - 1;
-                i += 1;
-                if i < data.len() {
-                    if data[i] == b'L' {
-                        i = self.handle_gs_8l(data, i)?;
-                    } else {
-                        // Other GS 8 subcommands (structure: GS 8 fn p1 p2 p3 p4 data...)
-                        let subcmd = data[i];
-                        i += 1; // skip subcommand
-
-                        // Read length bytes
-                        if i + 4 > data.len() {
-                            // Not enough data for length - wait for more
-                            if self.debug {
-                                self.log_debug(&format!(
-                                    "GS 8 0x{:02X}: waiting for length bytes",
-                                    subcmd
-                                ));
+                drop(access);
+
+                ui.separator();
+                ui.strong("Simulated status");
+                let current_status = *self.state.simulated_status.borrow();
+                let mut status = current_status;
+                egui::Grid::new("simulated_status")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.checkbox(&mut status.offline, "Offline");
+                        ui.checkbox(&mut status.cover_open, "Cover open");
+                        ui.end_row();
+                        ui.checkbox(&mut status.paper_near_end, "Paper near end");
+                        ui.checkbox(&mut status.paper_out, "Paper out");
+                        ui.end_row();
+                        ui.checkbox(&mut status.cutter_error, "Cutter error");
+                        ui.checkbox(&mut status.unrecoverable_error, "Unrecoverable error");
+                        ui.end_row();
+                        ui.checkbox(&mut status.drawer_open, "Drawer 1 open (pin 2)");
+                        ui.checkbox(&mut status.drawer2_open, "Drawer 2 open (pin 5)");
+                        ui.end_row();
+                        match status.profile {
+                            Profile::Receipt => {}
+                            Profile::Ink => {
+                                ui.checkbox(&mut status.ink_near_end, "Ink near end");
+                                ui.checkbox(&mut status.ink_out, "Ink out");
+                                ui.end_row();
                             }
-                            return Ok(start_i);
-                        }
-
-                        let p1 = data[i] as usize;
-                        let p2 = data[i + 1] as usize;
-                        let p3 = data[i + 2] as usize;
-                        let p4 = data[i + 3] as usize;
-                        let len = p1 | (p2 << 8) | (p3 << 16) | (p4 << 24);
-                        i += 4;
-
-                        // Check if we have all the data
-                        let skip = len.min(1_000_000);
-                        if i + skip > data.len() {
-                            // Not enough data - wait for more
-                            if self.debug {
-                                self.log_debug(&format!(
-                                    "GS 8 0x{:02X}: waiting for {} data bytes (have {})",
-                                    subcmd,
-                                    skip,
-                                    data.len() - i
-                                ));
+                            Profile::Peeler => {
+                                ui.checkbox(&mut status.label_waiting, "Label waiting in peeler");
+                                ui.end_row();
                             }
-                            return Ok(start_i);
                         }
-
-                        // Skip all the data
-                        i += skip;
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Printer profile");
+                    egui::ComboBox::from_id_salt("printer_profile")
+                        .selected_text(
+                            status::PROFILES
+                                .iter()
+                                .find(|(profile, _)| *profile == status.profile)
+                                .map_or("", |(_, label)| *label),
+                        )
+                        .show_ui(ui, |ui| {
+                            for (profile, label) in status::PROFILES {
+                                ui.selectable_value(&mut status.profile, profile, label);
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Default code page")
+                        .on_hover_text("Used until the client selects one with ESC t");
+                    let custom = self.state.custom_code_pages.lock().unwrap().clone();
+                    egui::ComboBox::from_id_salt("default_code_page")
+                        .selected_text(match custom.get(&status.code_page) {
+                            Some(page) => format!("{} (ESC t {})", page.name(), status.code_page),
+                            None => CODE_PAGES
+                                .iter()
+                                .find(|(n, _)| *n == status.code_page)
+                                .map_or_else(
+                                    || format!("ESC t {}", status.code_page),
+                                    |(_, label)| label.to_string(),
+                                ),
+                        })
+                        .show_ui(ui, |ui| {
+                            for &(n, label) in CODE_PAGES {
+                                if !custom.contains_key(&n) {
+                                    ui.selectable_value(&mut status.code_page, n, label);
+                                }
+                            }
+                            for (&n, page) in &custom {
+                                ui.selectable_value(
+                                    &mut status.code_page,
+                                    n,
+                                    format!("{} (ESC t {})", page.name(), n),
+                                );
+                            }
+                        });
+                });
+                self.show_custom_code_pages(ui);
+                ui.horizontal(|ui| {
+                    ui.label("Drawer sensor (pin 3)").on_hover_text(
+                        "Which drawer's open switch the DLE EOT 1, GS r 2 and ASB drawer bit report",
+                    );
+                    egui::ComboBox::from_id_salt("drawer_sensor")
+                        .selected_text(
+                            status::DRAWER_SENSORS
+                                .iter()
+                                .find(|(sensor, _)| *sensor == status.drawer_sensor)
+                                .map_or("", |(_, label)| *label),
+                        )
+                        .show_ui(ui, |ui| {
+                            for (sensor, label) in status::DRAWER_SENSORS {
+                                ui.selectable_value(&mut status.drawer_sensor, sensor, label);
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Columns per line").on_hover_text(
+                        "For printers that fit other counts than Epson's at their paper width, \
+                         like 42 instead of 48 in Font A. Auto is the paper width's count.",
+                    );
+                    let paper_size = *self.state.paper_size.lock().unwrap();
+                    for (font, name) in ["A", "B", "C"].into_iter().enumerate() {
+                        let own = paper_size.columns(font as u8);
+                        ui.add(
+                            egui::DragValue::new(&mut status.columns[font])
+                                .range(0..=255)
+                                .custom_formatter(move |n, _| {
+                                    if n == 0.0 {
+                                        format!("Font {} auto ({})", name, own)
+                                    } else {
+                                        format!("Font {} {}", name, n)
+                                    }
+                                }),
+                        );
+                    }
+                });
+                ui.checkbox(&mut status.so_double_width, "SO/SI double width")
+                    .on_hover_text(
+                        "Like legacy printers: SO prints the rest of the line in double \
+                         width, SI and DC4 cancel it. Otherwise these bytes are skipped.",
+                    );
+                self.show_paper_roll(ui, &mut status);
+                ui.collapsing("Advanced", |ui| {
+                    self.show_status_overrides(ui, &mut status);
+                    ui.separator();
+                    self.show_scheduled_status(ui);
+                });
+                // Only write back edits, so changes made meanwhile by the
+                // admin API or the scheduler aren't overwritten
+                if status != current_status {
+                    self.state.simulated_status.send_replace(status);
+                    // Lay the jobs out again with the new columns or widths
+                    if status.columns != current_status.columns
+                        || status.so_double_width != current_status.so_double_width
+                    {
+                        self.state.rerender_receipt();
                     }
                 }
-            }
-            b'V' => {
-                i += 1;
-                if i < data.len() {
-                    i = self.handle_paper_cut(data, i)?;
-                }
-            }
-            b'v' => {
-                i += 1;
-                if i < data.len() {
-                    i = self.handle_raster_graphics_gs(data, i)?;
-                }
-            }
-            b'!' => {
-                // GS ! - Select character size (width and height multipliers)
-                // Bits 0-2: width (0-7), Bits 4-6: height (0-7)
-                i += 1;
-                if i < data.len() {
-                    let mode = data[i];
-                    let width_mul = (mode & 0x07) + 1;
-                    let height_mul = ((mode >> 4) & 0x07) + 1;
-                    self.state.double_width = width_mul > 1;
-                    self.state.double_height = height_mul > 1;
-                    i += 1;
-                }
-            }
-            b'B' => {
-                i += 1;
-                if i < data.len() {
-                    self.state.inverted = data[i] == 1;
-                    i += 1;
-                }
-            }
-            b'L' => {
-                // GS L nL nH - Set left margin (in dots)
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as u16;
-                    let nh = data[i + 1] as u16;
-                    self.state.left_margin = nl + (nh << 8);
-                    self.log_debug(&format!(
-                        "GS L: left margin = {} dots",
-                        self.state.left_margin
-                    ));
-                    i += 2;
-                }
-            }
-            b'W' => {
-                // GS W nL nH - Set print area width (in dots)
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as u16;
-                    let nh = data[i + 1] as u16;
-                    self.state.print_area_width = nl + (nh << 8);
-                    self.log_debug(&format!(
-                        "GS W: print area width = {} dots",
-                        self.state.print_area_width
-                    ));
-                    i += 2;
+
+                ui.separator();
+                ui.strong("Maintenance counters");
+                let mut counters = self.state.maintenance.lock().unwrap();
+                egui::Grid::new("maintenance_counters")
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        ui.label("Since reset");
+                        ui.label("Total");
+                        ui.end_row();
+                        ui.label("Paper fed");
+                        ui.label(format!("{:.2} m", counters.fed_mm.since_reset / 1000.0));
+                        ui.label(format!("{:.2} m", counters.fed_mm.total / 1000.0));
+                        ui.end_row();
+                        ui.label("Lines printed");
+                        ui.label(counters.lines_printed.since_reset.to_string());
+                        ui.label(counters.lines_printed.total.to_string());
+                        ui.end_row();
+                        ui.label("Cuts");
+                        ui.label(counters.cuts.since_reset.to_string());
+                        ui.label(counters.cuts.total.to_string());
+                        ui.end_row();
+                    });
+                if ui
+                    .button("Reset")
+                    .on_hover_text("Clear the resettable counters, as GS g 0 does")
+                    .clicked()
+                {
+                    counters.reset_all();
                 }
-            }
-            b'H' | b'h' | b'w' | b'k' => {
-                // Barcode height, HRI position, barcode width, barcode print
-                i += 1;
-                if i < data.len() {
-                    if cmd == b'k' {
-                        // Barcode data follows
-                        let barcode_type = data[i];
-                        i += 1;
-                        if barcode_type < 6 {
-                            // Variable length barcode - find NUL terminator
-                            while i < data.len() && data[i] != 0 {
-                                i += 1;
-                            }
-                            if i < data.len() {
-                                i += 1; // skip NUL
+                drop(counters);
+
+                ui.separator();
+                ui.strong("Jobs");
+                ui.label("A job ends when");
+                let mut boundary = self.state.job_boundary.lock().unwrap();
+                let idle_secs = match *boundary {
+                    JobBoundary::Inactivity(secs) => secs,
+                    _ => 5,
+                };
+                ui.radio_value(
+                    &mut *boundary,
+                    JobBoundary::ConnectionClose,
+                    "the connection closes",
+                );
+                ui.radio_value(
+                    &mut *boundary,
+                    JobBoundary::FullCut,
+                    "the paper is fully cut",
+                );
+                ui.radio_value(
+                    &mut *boundary,
+                    JobBoundary::Initialize,
+                    "the printer is initialized (ESC @)",
+                );
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut *boundary,
+                        JobBoundary::Inactivity(idle_secs),
+                        "no data arrives for",
+                    );
+                    let mut secs = idle_secs;
+                    let selected = matches!(*boundary, JobBoundary::Inactivity(_));
+                    let drag = egui::DragValue::new(&mut secs).range(1..=3600).suffix(" s");
+                    if ui.add_enabled(selected, drag).changed() {
+                        *boundary = JobBoundary::Inactivity(secs);
+                    }
+                });
+                let init_is_boundary = *boundary == JobBoundary::Initialize;
+                drop(boundary);
+                let mut init_new_receipt = self.state.init_new_receipt.lock().unwrap();
+                ui.add_enabled(
+                    !init_is_boundary,
+                    egui::Checkbox::new(
+                        &mut init_new_receipt,
+                        "ESC @ also starts a new receipt",
+                    ),
+                )
+                .on_hover_text(
+                    "For clients that send ESC @ before every order. Otherwise ESC @ only \
+                     resets the formatting.",
+                );
+                drop(init_new_receipt);
+
+                ui.separator();
+                ui.strong("Receive buffer");
+                let mut receive_buffer = *self.state.receive_buffer.lock().unwrap();
+                egui::Grid::new("receive_buffer")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Capacity");
+                        ui.horizontal(|ui| {
+                            for (label, capacity) in receive_buffer::PRESETS {
+                                ui.selectable_value(&mut receive_buffer.capacity, capacity, label);
                             }
-                        } else {
-                            // Fixed length barcode
-                            if i < data.len() {
-                                let len = data[i] as usize;
-                                i += 1 + len;
+                            ui.add(
+                                egui::DragValue::new(&mut receive_buffer.capacity)
+                                    .range(0..=1024 * 1024)
+                                    .suffix(" bytes"),
+                            );
+                        });
+                        ui.end_row();
+
+                        ui.label("Print speed");
+                        ui.add_enabled(
+                            receive_buffer.capacity > 0,
+                            egui::DragValue::new(&mut receive_buffer.drain_rate)
+                                .range(1..=1024 * 1024)
+                                .suffix(" bytes/s"),
+                        );
+                        ui.end_row();
+
+                        ui.label("Line speed");
+                        ui.horizontal(|ui| {
+                            for baud_rate in receive_buffer::BAUD_RATES {
+                                let label = match baud_rate {
+                                    0 => "Network".to_string(),
+                                    baud => format!("{} baud", baud),
+                                };
+                                ui.selectable_value(
+                                    &mut receive_buffer.baud_rate,
+                                    baud_rate,
+                                    label,
+                                );
                             }
-                        }
-                    } else {
-                        i += 1;
+                        });
+                        ui.end_row();
+
+                        ui.label("Responses")
+                            .on_hover_text("Split replies for clients that expect one read");
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut receive_buffer.response_chunk)
+                                    .range(0..=64)
+                                    .custom_formatter(|n, _| {
+                                        if n == 0.0 {
+                                            "whole".to_string()
+                                        } else {
+                                            format!("{} bytes", n)
+                                        }
+                                    }),
+                            );
+                            ui.label("at a time, every");
+                            ui.add_enabled(
+                                receive_buffer.response_chunk > 0,
+                                egui::DragValue::new(&mut receive_buffer.response_delay_ms)
+                                    .range(0..=5000)
+                                    .suffix(" ms"),
+                            );
+                        });
+                        ui.end_row();
+                    });
+                *self.state.receive_buffer.lock().unwrap() = receive_buffer;
+
+                ui.separator();
+                ui.strong("Image dump");
+                let mut image_dump = self.state.image_dump.lock().unwrap();
+                let mut enabled = image_dump.is_some();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut enabled, "Save job images as PNG to");
+                    ui.text_edit_singleline(&mut self.image_dump_dir);
+                });
+                let dir = self.image_dump_dir.trim();
+                *image_dump = (enabled && !dir.is_empty()).then(|| dir.into());
+                drop(image_dump);
+                ui.label("Raster graphics, QR codes and barcodes, one folder per job");
+
+                ui.separator();
+                ui.strong("Raw capture");
+                let mut capture = self.state.capture.lock().unwrap();
+                let mut enabled = capture.is_some();
+                let mut dir = self.capture_settings.dir.display().to_string();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut enabled, "Save each job's bytes to");
+                    ui.text_edit_singleline(&mut dir);
+                });
+                self.capture_settings.dir = dir.trim().into();
+                ui.horizontal(|ui| {
+                    ui.label("Keep at most");
+                    ui.add(
+                        egui::DragValue::new(&mut self.capture_settings.max_mb)
+                            .range(1..=100_000)
+                            .suffix(" MB"),
+                    );
+                    ui.label("deleting the oldest captures");
+                });
+                *capture = (enabled && !dir.trim().is_empty())
+                    .then(|| self.capture_settings.clone());
+                drop(capture);
+
+                ui.separator();
+                ui.strong("Hooks script (Rhai)");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.script_path);
+                    if ui.button("Load").clicked() {
+                        self.load_script();
                     }
-                }
-            }
-            b'(' => {
-                // Extended commands
-                i += 1;
-                if i < data.len() {
-                    let subcmd = data[i];
-                    if subcmd == b'k' {
-                        // QR Code commands
-                        i = self.handle_qr_code(data, i)?;
-                    } else {
-                        // Other extended commands
-                        if i + 2 < data.len() {
-                            let p_l = data[i + 1] as usize;
-                            let p_h = data[i + 2] as usize;
-                            let len = p_l + (p_h << 8);
-                            i += 3 + len;
-                        }
+                    let loaded = self.state.hooks.lock().unwrap().is_some();
+                    if ui
+                        .add_enabled(loaded, egui::Button::new("Unload"))
+                        .clicked()
+                    {
+                        *self.state.hooks.lock().unwrap() = None;
+                        self.script_message = Some(Ok("Hooks unloaded".to_string()));
                     }
-                }
-            }
-            b'a' => {
-                // GS a n - Enable/disable Automatic Status Back (ASB)
-                // n bits specify which status types to report automatically
-                i += 1;
-                if i < data.len() {
-                    let asb_flags = data[i];
-                    self.log_debug(&format!("GS a: ASB flags=0x{:02X}", asb_flags));
-
-                    // If ASB is enabled (n != 0), send 4-byte ASB status immediately
-                    if asb_flags != 0 {
-                        // ASB format (4 bytes):
-                        // Byte 0: 0x10 = binary 00010000
-                        //   Bit 0,1 = 0 (fixed)
-                        //   Bit 2 = 0 (drawer pin LOW)
-                        //   Bit 3 = 0 (online)
-                        //   Bit 4 = 1 (fixed)
-                        //   Bit 5 = 0 (cover closed)
-                        //   Bit 6 = 0 (not feeding paper)
-                        //   Bit 7 = 0 (fixed)
-                        // Byte 1: 0x00 = all OK (no errors, not waiting)
-                        // Byte 2: 0x00 = paper sensors OK (paper present)
-                        // Byte 3: 0x00 = reserved
-                        self.response_queue.push(0x10);
-                        self.response_queue.push(0x00);
-                        self.response_queue.push(0x00);
-                        self.response_queue.push(0x00);
-                        self.log_debug("GS a: queued 4-byte ASB status (online, no errors)");
+                });
+                ui.label("Applies to new connections");
+                match &self.script_message {
+                    Some(Ok(message)) => {
+                        ui.colored_label(egui::Color32::from_rgb(0, 130, 0), message);
                     }
-                    i += 1;
-                }
-            }
-            b'I' => {
-                // GS I n - Transmit printer ID information
-                // Response format: 0x5f + "string" + 0x00 (block data format)
-                i += 1;
-                if i < data.len() {
-                    let n = data[i];
-                    self.log_debug(&format!("GS I: query type=0x{:02X}", n));
-
-                    // Queue response based on query type (block data format)
-                    match n {
-                        0x42 => {
-                            // Manufacturer name (0x42 = 66)
-                            // Send in block data format: 0x5f + "CITIZEN" + 0x00
-                            // (use CITIZEN not EPSON so receiptio switches to 'escpos' mode)
-                            self.response_queue.push(0x5f); // Block data start
-                            self.response_queue.extend_from_slice(b"CITIZEN");
-                            self.response_queue.push(0x00); // Null terminator
-                            self.log_debug("GS I 0x42: sent manufacturer 'CITIZEN' (block data)");
-                        }
-                        0x43 => {
-                            // Model name (0x43 = 67)
-                            // Send in block data format: 0x5f + "CT-S310" + 0x00
-                            self.response_queue.push(0x5f); // Block data start
-                            self.response_queue.extend_from_slice(b"CT-S310");
-                            self.response_queue.push(0x00); // Null terminator
-                            self.log_debug("GS I 0x43: sent model 'CT-S310' (block data)");
-                        }
-                        _ => {
-                            self.log_debug(&format!("GS I: unknown query type 0x{:02X}", n));
-                        }
+                    Some(Err(err)) => {
+                        ui.colored_label(egui::Color32::RED, err);
                     }
-                    i += 1;
-                }
-            }
-            b'r' => {
-                // GS r n - Transmit status
-                i += 1;
-                if i < data.len() {
-                    let _n = data[i];
-                    self.log_debug(&format!("GS r: transmit status n=0x{:02X}", _n));
-
-                    // Send 1-byte status response
-                    // Status byte format: bit pattern must have (value & 0x90) === 0
-                    // 0x08 = 00001000 (online, paper present, no errors)
-                    //   Bit 3 = 1: paper present
-                    //   Bit 4 = 0: online (not offline)
-                    //   Bit 7 = 0: (required by receiptio)
-                    self.response_queue.push(0x08);
-                    self.log_debug("GS r: queued status response 0x08 (online, paper OK)");
-                    i += 1;
+                    None => {}
                 }
-            }
-            b'$' => {
-                // GS $ nL nH - Set absolute vertical print position
-                // Used by receiptio for positioning each line
-                i += 1;
-                if i + 1 < data.len() {
-                    let nl = data[i] as u16;
-                    let nh = data[i + 1] as u16;
-                    let vertical_pos = nl + (nh << 8);
-                    self.log_debug(&format!("GS $: set vertical position to {}", vertical_pos));
-                    // VirtualESC renders sequentially, so we acknowledge but don't use this
-                    i += 2;
-                }
-            }
-            0x00 | 0x80 | 0xF7 => {
-                // Additional GS commands found in real data
-                i += 1;
-                // Consume likely parameter
-                if i < data.len() {
-                    i += 1;
-                }
-            }
-            _ => {
-                // Unknown GS command - assume it has at least 1 parameter
-                if self.debug {
-                    self.log_debug(&format!("Unknown GS command: 0x{:02X}", cmd));
+
+                ui.separator();
+                ui.strong("Display");
+                ui.checkbox(
+                    &mut self.wrap_print_area,
+                    "Wrap text at the print area edge (otherwise clip)",
+                );
+                ui.checkbox(
+                    &mut self.show_unknown_commands,
+                    "Mark unknown commands in the receipt",
+                );
+                ui.checkbox(
+                    &mut self.show_text_warnings,
+                    "Mark lines that overflow the print area or don't decode in the code page",
+                );
+                ui.checkbox(
+                    &mut self.show_guides,
+                    "Show print area (GS W) and left margin (GS L) guides",
+                );
+                ui.checkbox(
+                    &mut self.show_element_details,
+                    "Show element details and source bytes on hover",
+                );
+                ui.checkbox(
+                    &mut self.show_commands,
+                    "Show the commands of each job between its elements",
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Darkness");
+                    ui.add(
+                        egui::Slider::new(&mut self.darkness, DARKNESS_RANGE)
+                            .custom_formatter(|n, _| format!("{}%", 100.0 + n * 5.0)),
+                    )
+                    .on_hover_text(
+                        "The printer's print density setting, on top of the job's \
+                         ESC ~ / DC2 # density: lighter or darker text and graphics",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Downscale images over");
+                    ui.add(
+                        egui::DragValue::new(&mut self.raster_budget_mp)
+                            .range(0..=100)
+                            .custom_formatter(|n, _| {
+                                if n == 0.0 {
+                                    "never".to_string()
+                                } else {
+                                    format!("{} MP", n)
+                                }
+                            }),
+                    )
+                    .on_hover_text(
+                        "Very tall raster images are shown averaged down to this many \
+                         megapixels to bound texture memory. Exports and printing on \
+                         paper always use full resolution.",
+                    );
+                });
+                let mut merge_receipts = self.state.merge_receipts.lock().unwrap();
+                ui.checkbox(
+                    &mut merge_receipts,
+                    "Merge concurrent connections into one receipt",
+                );
+                drop(merge_receipts);
+                let mut auto_clear = self.state.auto_clear.lock().unwrap();
+                ui.checkbox(
+                    &mut auto_clear,
+                    "Archive and clear the receipt when a new connection prints",
+                )
+                .on_hover_text(format!(
+                    "The receipt and its jobs are saved to {} first, a folder per receipt \
+                     that opens as a session (ESCPRESSO_SESSION)",
+                    self.state.archive.lock().unwrap().dir.display()
+                ));
+                drop(auto_clear);
+                let mut limits = self.state.history_limits.lock().unwrap();
+                let HistoryLimits {
+                    max_receipts,
+                    max_elements,
+                } = &mut *limits;
+                ui.horizontal(|ui| {
+                    ui.label("Keep at most");
+                    for (value, unit) in [(max_receipts, " receipts"), (max_elements, " elements")] {
+                        ui.add(
+                            egui::DragValue::new(value)
+                                .range(0..=1_000_000)
+                                .custom_formatter(move |n, _| {
+                                    if n == 0.0 {
+                                        format!("all{}", unit)
+                                    } else {
+                                        format!("{}{}", n, unit)
+                                    }
+                                }),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text(
+                    "Beyond this the oldest receipts are archived and dropped as jobs end, \
+                     for soak tests that run for days",
+                );
+                drop(limits);
+                let mut control_glyphs = self.state.control_glyphs.lock().unwrap();
+                ui.checkbox(
+                    &mut control_glyphs,
+                    "Show stray control characters as symbols (␀, ␛, ...)",
+                )
+                .on_hover_text(
+                    "Control bytes that aren't commands are dropped by printers; \
+                     showing them helps spot corrupted data. Applies to data received from now on.",
+                );
+            });
+        self.show_settings = open;
+    }
+
+    /// Read files dropped on the window; they are replayed once the user
+    /// picks append or replace
+    fn collect_dropped_files(&mut self, ctx: &egui::Context) {
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            let (name, contents) = match (&file.path, &file.bytes) {
+                (_, Some(bytes)) => (file.name.clone(), Ok(bytes.to_vec())),
+                (Some(path), None) => (
+                    path.file_name().map_or_else(
+                        || path.display().to_string(),
+                        |n| n.to_string_lossy().into(),
+                    ),
+                    std::fs::read(path),
+                ),
+                (None, None) => continue,
+            };
+            match contents {
+                // Images are references to compare with, not captures
+                Ok(contents) if name.to_lowercase().ends_with(".png") => {
+                    self.load_reference(name, &contents);
+                    self.show_reference = true;
                 }
-                i += 1;
-                // Try to consume 1 parameter byte to prevent leakage
-                if i < data.len() {
-                    i += 1;
+                // Disassembly, possibly edited, is replayed as the bytes it stands for
+                Ok(contents) if disassembly::is_disassembly(&contents) => {
+                    match disassembly::assemble(&String::from_utf8_lossy(&contents)) {
+                        Ok(data) => self.dropped_files.push((name, data)),
+                        Err(e) => eprintln!("ERROR: Failed to assemble {}: {:#}", name, e),
+                    }
                 }
+                Ok(contents) => self.dropped_files.push((name, contents)),
+                Err(e) => eprintln!("ERROR: Failed to read dropped file {}: {}", name, e),
             }
         }
-        Ok(i)
     }
 
-    fn handle_raster_graphics(&mut self, data: &[u8], i: usize) -> Result<usize> {
-        let start_i = i - 2; // Point to ESC byte, not '*' byte (i-1=*, i-2=ESC)
-
-        if i + 3 > data.len() {
-            self.log_debug("ESC * incomplete: not enough header bytes");
-            return Ok(start_i);
-        }
-
-        let m = data[i];
-        let nl = data[i + 1] as usize;
-        let nh = data[i + 2] as usize;
-        let width = nl + (nh << 8);
-        let height = match m {
-            0 | 1 => 8,
-            32 | 33 => 24,
-            _ => 8,
-        };
-
-        let mut pos = i + 3;
-
-        // Validate dimensions
-        if width == 0 || width > 10000 {
-            self.log_debug(&format!("ESC * invalid width: {}", width));
-            return Ok(pos);
-        }
-
-        // ESC * uses COLUMN-based format, not raster!
-        // Each column is height/8 bytes (8-dot) or height/8*3 bytes (24-dot)
-        let bytes_per_column = height / 8;
-        let total_bytes = width * bytes_per_column;
-
-        self.log_debug(&format!(
-            "ESC * column-based: m={}, width={}, height={}, bytes_per_col={}, need {} bytes",
-            m, width, height, bytes_per_column, total_bytes
-        ));
-
-        if total_bytes > 1_000_000 {
-            self.log_debug("ESC * dimensions too large, skipping");
-            return Ok(pos);
-        }
-
-        if pos + total_bytes > data.len() {
-            self.log_debug(&format!(
-                "ESC * incomplete: have {}, need {}",
-                data.len() - pos,
-                total_bytes
-            ));
-            return Ok(start_i);
-        }
-
-        // Additional safety check before slicing
-        if pos >= data.len() || pos + total_bytes > data.len() {
-            self.log_debug("ESC * bounds check failed");
-            return Ok(start_i);
-        }
-
-        // Flush any pending text before image
-        if !self.current_line.is_empty() {
-            self.flush_line();
-            self.current_line.clear();
+    fn load_reference(&mut self, name: String, png: &[u8]) {
+        match Reference::from_png(name, png) {
+            Ok(reference) => {
+                self.reference = Some(reference);
+                self.reference_error = None;
+            }
+            Err(e) => self.reference_error = Some(format!("{:#}", e)),
         }
-
-        // Convert column-based data to row-based raster data for rendering
-        let column_data = &data[pos..pos + total_bytes];
-        let raster_data = self.column_to_raster(column_data, width, height);
-
-        self.elements.push(ReceiptElement::RasterImage {
-            width,
-            height,
-            data: raster_data,
-            offset: self.state.horizontal_offset,
-            density: self.state.print_density,
-            alignment: self.state.alignment.clone(),
-            bytes_per_line: width.div_ceil(8), // Calculate from pixel width
-            print_area_width: self.state.print_area_width,
-        });
-
-        // Reset offset after rendering
-        self.state.horizontal_offset = 0;
-
-        // Mark that we just processed binary data - don't treat following ASCII bytes as text
-        self.last_was_binary = true;
-
-        pos += total_bytes;
-
-        Ok(pos)
     }
 
-    fn column_to_raster(&self, column_data: &[u8], width: usize, height: usize) -> Vec<u8> {
-        let bytes_per_column = height / 8;
-        let bytes_per_row = width.div_ceil(8);
-        let mut raster_data = vec![0u8; bytes_per_row * height];
-
-        // Convert column format to raster format
-        // Column format: each byte represents 8 vertical pixels in a column
-        // Raster format: each byte represents 8 horizontal pixels in a row
-
-        for col in 0..width {
-            let column_offset = col * bytes_per_column;
-
-            for byte_in_col in 0..bytes_per_column {
-                if column_offset + byte_in_col >= column_data.len() {
-                    break;
+    /// Loading the reference image and choosing how it's shown
+    fn show_reference_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_reference;
+        let mut load = false;
+        egui::Window::new("Reference")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("An approved render (escpresso render, PNG export) to compare with");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.reference_path)
+                            .hint_text("reference.png, or drop one on the window"),
+                    );
+                    load = ui.button("Load").clicked();
+                    if ui
+                        .add_enabled(self.reference.is_some(), egui::Button::new("Remove"))
+                        .clicked()
+                    {
+                        self.reference = None;
+                    }
+                });
+                if let Some(error) = &self.reference_error {
+                    ui.colored_label(egui::Color32::RED, error);
                 }
-
-                let col_byte = column_data[column_offset + byte_in_col];
-
-                // Each bit in this byte represents a pixel at a different row
-                for bit in 0..8 {
-                    let y = byte_in_col * 8 + bit;
-                    if y >= height {
-                        break;
+                let Some(reference) = &self.reference else {
+                    return;
+                };
+                let [width, height] = reference.size();
+                ui.label(format!("{}: {}×{}", reference.name, width, height));
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.reference_mode, OverlayMode::Overlay, "Overlay");
+                    ui.radio_value(
+                        &mut self.reference_mode,
+                        OverlayMode::Heatmap,
+                        "Difference heatmap",
+                    );
+                });
+                match self.reference_mode {
+                    OverlayMode::Overlay => {
+                        ui.add(
+                            egui::Slider::new(&mut self.reference_opacity, 0.0..=1.0)
+                                .text("Opacity"),
+                        );
                     }
-
-                    // Extract the pixel value (1 = black, 0 = white)
-                    let pixel = (col_byte >> (7 - bit)) & 1;
-
-                    // Set the corresponding bit in the raster data
-                    let row_byte_idx = y * bytes_per_row + (col / 8);
-                    let row_bit_idx = 7 - (col % 8);
-
-                    if row_byte_idx < raster_data.len() {
-                        raster_data[row_byte_idx] |= pixel << row_bit_idx;
+                    OverlayMode::Heatmap => {
+                        let text = match reference.difference() {
+                            _ if reference.comparing() => "Comparing…".to_string(),
+                            Some(0.0) => "The receipt matches the reference".to_string(),
+                            Some(fraction) => {
+                                format!("{:.2}% of pixels differ", fraction * 100.0)
+                            }
+                            None => String::new(),
+                        };
+                        ui.label(text);
                     }
                 }
+            });
+        if load {
+            let path = self.reference_path.trim().to_string();
+            match std::fs::read(&path) {
+                Ok(png) => self.load_reference(path, &png),
+                Err(e) => self.reference_error = Some(format!("reading {}: {}", path, e)),
             }
         }
-
-        raster_data
+        self.show_reference = open;
     }
 
-    fn handle_raster_graphics_gs(&mut self, data: &[u8], i: usize) -> Result<usize> {
-        let start_i = i - 2; // Point to GS byte, not 'v' byte (i-1=v, i-2=GS)
-
-        self.log_debug(&format!("GS v: entered handler at position {}", i));
+    /// The texture to paint over the receipt, its size and tint
+    fn reference_overlay(
+        &mut self,
+        ctx: &egui::Context,
+    ) -> Option<(egui::TextureId, egui::Vec2, egui::Color32)> {
+        let reference = self.reference.as_mut()?;
+        let (texture, tint) = match self.reference_mode {
+            OverlayMode::Overlay => (
+                reference.texture(ctx),
+                egui::Color32::WHITE.gamma_multiply(self.reference_opacity),
+            ),
+            OverlayMode::Heatmap => {
+                let key = {
+                    let times = self.state.element_times.lock().unwrap();
+                    let paper_size = *self.state.paper_size.lock().unwrap();
+                    (times.len(), times.last().copied(), paper_size)
+                };
+                let elements = || self.state.elements.lock().unwrap().clone();
+                let (_, texture) = reference.heatmap(ctx, key, elements)?;
+                (texture, egui::Color32::WHITE)
+            }
+        };
+        Some((texture.id(), texture.size_vec2(), tint))
+    }
 
-        if i + 6 > data.len() {
-            self.log_debug(&format!(
-                "GS v incomplete: not enough header bytes (have {}, need {})",
-                data.len() - i,
-                6
-            ));
-            return Ok(start_i);
+    fn show_replay_window(&mut self, ctx: &egui::Context) {
+        let mut choice = None;
+        egui::Window::new("Replay capture")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                for (name, contents) in &self.dropped_files {
+                    ui.label(format!("{} ({} bytes)", name, contents.len()));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Append").clicked() {
+                        choice = Some(false);
+                    }
+                    if ui.button("Replace receipt").clicked() {
+                        choice = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.dropped_files.clear();
+                    }
+                });
+            });
+        let Some(replace) = choice else {
+            return;
+        };
+        if replace {
+            self.state.clear_receipt();
         }
-
-        // zj-58 format: GS v variant m xL xH yL yH [data]
-        // escRasterMode[] = "\x1dv0\0" sends: GS v '0' 0x00
-        // Then mputnum(width) and mputnum(height) send little-endian 2-byte values
-        let variant = data[i]; // '0' = 0x30
-        let _m = data[i + 1]; // 0x00 (mode)
-        let xl = data[i + 2] as usize;
-        let xh = data[i + 3] as usize;
-        let yl = data[i + 4] as usize;
-        let yh = data[i + 5] as usize;
-
-        self.log_debug(&format!(
-            "GS v: raw bytes at i: [{:02X} {:02X} {:02X} {:02X} {:02X} {:02X}]",
-            data[i],
-            data[i + 1],
-            data[i + 2],
-            data[i + 3],
-            data[i + 4],
-            data[i + 5]
-        ));
-        self.log_debug(&format!(
-            "GS v: variant=0x{:02X} m=0x{:02X}, xl=0x{:02X} xh=0x{:02X} yl=0x{:02X} yh=0x{:02X}",
-            variant, _m, xl, xh, yl, yh
-        ));
-
-        let mut pos = i + 6;
-
-        // GS v 0: xL/xH are width in BYTES, yL/yH are height in DOTS (pixels)
-        let width_in_bytes = xl + (xh << 8);
-        let height = yl + (yh << 8);
-        let width = width_in_bytes * 8; // Convert bytes to pixels for rendering
-
-        // Validate dimensions
-        if width_in_bytes == 0 || height == 0 {
-            self.log_debug(&format!(
-                "GS v invalid dimensions: {} bytes x {} pixels",
-                width_in_bytes, height
-            ));
-            return Ok(pos);
-        }
-
-        if width > 10000 || height > 10000 {
-            self.log_debug(&format!(
-                "GS v dimensions too large: {}x{} pixels, attempting to skip raster data",
-                width, height
-            ));
-            // Still need to skip the raster data even if dimensions seem wrong
-            // Otherwise the raster bytes will be processed as text
-            let total_bytes = width_in_bytes * height;
-            if total_bytes > 5_000_000 {
-                self.log_debug("GS v: calculated bytes too large, cannot skip safely");
-                return Ok(start_i); // Wait for correct data or give up
+        for (name, contents) in std::mem::take(&mut self.dropped_files) {
+            if let Err(e) = self.state.print_local(&format!("file: {}", name), contents) {
+                eprintln!("ERROR: Failed to replay {}: {:#}", name, e);
             }
-            if pos + total_bytes > data.len() {
-                self.log_debug(&format!(
-                    "GS v: not enough data to skip (need {} more bytes)",
-                    total_bytes - (data.len() - pos)
-                ));
-                return Ok(start_i); // Wait for more data
-            }
-            return Ok(pos + total_bytes); // Skip past the raster data
         }
+    }
 
-        let total_bytes = width_in_bytes * height;
-
-        self.log_debug(&format!(
-            "GS v raster: width={} pixels ({} bytes), height={} pixels, need {} bytes",
-            width, width_in_bytes, height, total_bytes
-        ));
-
-        if total_bytes > 5_000_000 {
-            self.log_debug("GS v raster: calculated bytes too large, skipping");
-            return Ok(pos);
-        }
+    /// Render the receipt and send it to the system printer, in the
+    /// background as rendering a long receipt takes a moment
+    fn print_receipt(&self, ctx: &egui::Context) {
+        let ctx = ctx.clone();
+        let elements = self.state.elements.lock().unwrap().clone();
+        let paper_size = *self.state.paper_size.lock().unwrap();
+        let status = self.print_status.clone();
+        *status.lock().unwrap() = Some(Ok(PRINTING.to_string()));
+        std::thread::spawn(move || {
+            let image = render::render_elements_to_image(&elements, paper_size, DOTS_PER_INCH);
+            let result = os_print::print_image(&image)
+                .map(|()| "Sent to the printer".to_string())
+                .map_err(|e| format!("Print failed: {:#}", e));
+            *status.lock().unwrap() = Some(result);
+            ctx.request_repaint();
+        });
+    }
 
-        if pos + total_bytes > data.len() {
-            self.log_debug(&format!(
-                "GS v incomplete: have {}, need {}",
-                data.len() - pos,
-                total_bytes
-            ));
-            return Ok(start_i);
-        }
+    /// Lint warnings of the recorded jobs
+    fn show_warnings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_warnings;
+        egui::Window::new("Warnings")
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                let jobs = self.state.jobs.lock().unwrap();
+                let mut any = false;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for job in jobs.iter().filter(|job| !job.warnings.is_empty()) {
+                        any = true;
+                        ui.strong(format!(
+                            "Job {} from {}, {}",
+                            job.id,
+                            job.source,
+                            format_timestamp(job.received_at)
+                        ));
+                        egui::Grid::new(("job_warnings", job.id))
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                for warning in &job.warnings {
+                                    ui.monospace(format!("{:>6}", warning.offset))
+                                        .on_hover_text("Byte offset in the job");
+                                    ui.label(&warning.message);
+                                    ui.end_row();
+                                }
+                            });
+                        ui.add_space(6.0);
+                    }
+                });
+                if !any {
+                    ui.label("No warnings for the jobs received so far");
+                }
+            });
+        self.show_warnings = open;
+    }
 
-        // Additional safety check before slicing
-        if pos >= data.len() || pos + total_bytes > data.len() {
-            self.log_debug("GS v bounds check failed");
-            return Ok(start_i);
-        }
+    /// Palette and editor for hand-written command sequences
+    fn show_composer_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_composer;
+        egui::Window::new("Composer")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                let parsed = composer::parse(&self.composer_source);
+                ui.horizontal_top(|ui| {
+                    ui.vertical(|ui| {
+                        ui.set_width(170.0);
+                        egui::ScrollArea::vertical()
+                            .id_salt("composer_palette")
+                            .max_height(320.0)
+                            .show(ui, |ui| {
+                                let mut groups: Vec<&str> =
+                                    composer::PALETTE.iter().map(|(g, _, _)| *g).collect();
+                                groups.dedup();
+                                for group in groups {
+                                    ui.collapsing(group, |ui| {
+                                        let entries = composer::PALETTE
+                                            .iter()
+                                            .filter(|(g, _, _)| *g == group);
+                                        for (_, name, sequence) in entries {
+                                            if ui.button(*name).on_hover_text(*sequence).clicked() {
+                                                if !self.composer_source.is_empty()
+                                                    && !self.composer_source.ends_with('\n')
+                                                {
+                                                    self.composer_source.push('\n');
+                                                }
+                                                self.composer_source.push_str(sequence);
+                                                self.composer_source.push('\n');
+                                            }
+                                        }
+                                    });
+                                }
+                            });
+                    });
+                    ui.vertical(|ui| {
+                        egui::ScrollArea::vertical()
+                            .id_salt("composer_source")
+                            .max_height(320.0)
+                            .show(ui, |ui| {
+                                ui.add(
+                                    egui::TextEdit::multiline(&mut self.composer_source)
+                                        .code_editor()
+                                        .desired_rows(14)
+                                        .desired_width(f32::INFINITY),
+                                );
+                            });
+                    });
+                });
+                ui.label(
+                    "Hex bytes (1B 40), names (ESC, GS, LF), single characters, \
+                     \"quoted text\" and // comments",
+                );
+                ui.horizontal(|ui| {
+                    let bytes = parsed.as_ref().ok();
+                    if ui
+                        .add_enabled(bytes.is_some(), egui::Button::new("Print"))
+                        .clicked()
+                    {
+                        self.print_composed(bytes.cloned().unwrap_or_default());
+                    }
+                    if ui
+                        .add_enabled(bytes.is_some(), egui::Button::new("Clear and print"))
+                        .clicked()
+                    {
+                        self.state.clear_receipt();
+                        self.print_composed(bytes.cloned().unwrap_or_default());
+                    }
+                    if ui.button("Clear editor").clicked() {
+                        self.composer_source.clear();
+                    }
+                    match &parsed {
+                        Ok(bytes) => {
+                            ui.colored_label(
+                                egui::Color32::DARK_GRAY,
+                                format!("{} bytes", bytes.len()),
+                            );
+                        }
+                        Err(e) => {
+                            ui.colored_label(egui::Color32::RED, e.to_string());
+                        }
+                    }
+                });
+            });
+        self.show_composer = open;
+    }
 
-        // Flush any pending text before image (already cleared by caller)
-        if !self.current_line.is_empty() {
-            self.flush_line();
-            self.current_line.clear();
+    fn print_composed(&self, data: Vec<u8>) {
+        if let Err(e) = self.state.print_local("composer", data) {
+            eprintln!("ERROR: Composed sequence failed: {:#}", e);
         }
+    }
 
-        // Debug: dump first 64 bytes of raster data to see the pattern
-        if self.debug {
-            let preview_len = std::cmp::min(64, total_bytes);
-            let mut hex_str = String::new();
-            for i in 0..preview_len {
-                hex_str.push_str(&format!("{:02X} ", data[pos + i]));
-                if (i + 1) % 16 == 0 {
-                    hex_str.push('\n');
+    /// Drawer kicks with their pulse timing, newest first
+    fn show_drawer_log_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_drawer_log;
+        egui::Window::new("Drawer kicks")
+            .open(&mut open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                let mut log = self.state.drawer_log.lock().unwrap();
+                ui.horizontal(|ui| {
+                    ui.label("Times are UTC");
+                    if ui
+                        .add_enabled(!log.is_empty(), egui::Button::new("Copy as CSV"))
+                        .clicked()
+                    {
+                        ui.ctx().copy_text(drawer_log_csv(&log));
+                    }
+                    if ui
+                        .add_enabled(!log.is_empty(), egui::Button::new("Clear"))
+                        .clicked()
+                    {
+                        log.clear();
+                    }
+                });
+                if log.is_empty() {
+                    ui.label("No drawer kicks so far");
+                    return;
                 }
-            }
-            self.log_debug(&format!(
-                "GS v raster data (first {} bytes):\n{}",
-                preview_len, hex_str
-            ));
-
-            // Also show bytes per line calculation
-            self.log_debug(&format!(
-                "Width={} pixels -> {} bytes per line, {} total lines",
-                width, width_in_bytes, height
-            ));
-
-            // Save raster data to a PBM file for inspection
-            use std::io::Write;
-            let filename = format!("raster_{}x{}.pbm", width, height);
-            if let Ok(mut file) = std::fs::File::create(&filename) {
-                // PBM format: P4 (binary)
-                writeln!(file, "P4").ok();
-                writeln!(file, "{} {}", width, height).ok();
-                file.write_all(&data[pos..pos + total_bytes]).ok();
-                self.log_debug(&format!("Saved raster to {}", filename));
-            }
-        }
-
-        // GS v data is in standard raster format (row-based), NOT column format
-        // Just use the data directly
-        self.elements.push(ReceiptElement::RasterImage {
-            width,
-            height,
-            data: data[pos..pos + total_bytes].to_vec(),
-            offset: self.state.horizontal_offset,
-            density: self.state.print_density,
-            alignment: self.state.alignment.clone(),
-            bytes_per_line: width_in_bytes, // Use actual bytes from command
-            print_area_width: self.state.print_area_width,
-        });
-
-        // Reset offset after rendering
-        self.state.horizontal_offset = 0;
-
-        // Mark that we just processed binary data - don't treat following ASCII bytes as text
-        self.last_was_binary = true;
-
-        pos += total_bytes;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("drawer_log")
+                        .num_columns(6)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for heading in ["Time", "Command", "Pin", "On", "Off", "Source"] {
+                                ui.strong(heading);
+                            }
+                            ui.end_row();
+                            for kick in log.iter().rev() {
+                                ui.monospace(format_timestamp(kick.at));
+                                ui.monospace(kick.pulse.command);
+                                ui.monospace(kick.pulse.pin.to_string());
+                                ui.monospace(format!("{} ms", kick.pulse.on_ms));
+                                ui.monospace(format!("{} ms", kick.pulse.off_ms));
+                                ui.label(&kick.source);
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+        self.show_drawer_log = open;
+    }
 
-        Ok(pos)
+    /// The receipt as a list of plain descriptions, one per element, for
+    /// screen readers and keyboard-only use: Up/Down, Page Up/Down and
+    /// Home/End move the selection, Ctrl+C copies the selected description
+    fn show_element_list_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_element_list;
+        egui::Window::new("Elements")
+            .open(&mut open)
+            .default_width(420.0)
+            .default_height(480.0)
+            .show(ctx, |ui| {
+                let elements = self.state.elements.lock().unwrap();
+                let descriptions: Vec<String> = elements
+                    .iter()
+                    .filter_map(|element| describe_element(element, self.show_unknown_commands))
+                    .collect();
+                drop(elements);
+                ui.label(format!("{} elements", descriptions.len()));
+                if descriptions.is_empty() {
+                    return;
+                }
+                let last = descriptions.len() - 1;
+                let previous = self.element_list_selected.min(last);
+                // Text fields elsewhere keep their own arrow keys
+                let selected = if ctx.wants_keyboard_input() {
+                    previous
+                } else {
+                    ctx.input(|i| {
+                        if i.key_pressed(egui::Key::Home) {
+                            0
+                        } else if i.key_pressed(egui::Key::End) {
+                            last
+                        } else if i.key_pressed(egui::Key::ArrowUp) {
+                            previous.saturating_sub(1)
+                        } else if i.key_pressed(egui::Key::ArrowDown) {
+                            (previous + 1).min(last)
+                        } else if i.key_pressed(egui::Key::PageUp) {
+                            previous.saturating_sub(ELEMENT_LIST_PAGE)
+                        } else if i.key_pressed(egui::Key::PageDown) {
+                            (previous + ELEMENT_LIST_PAGE).min(last)
+                        } else {
+                            previous
+                        }
+                    })
+                };
+                self.element_list_selected = selected;
+                if !ctx.wants_keyboard_input()
+                    && ctx.input(|i| i.events.contains(&egui::Event::Copy))
+                {
+                    ctx.copy_text(descriptions[selected].clone());
+                }
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false; 2])
+                    .show(ui, |ui| {
+                        for (index, description) in descriptions.iter().enumerate() {
+                            let response = ui.selectable_label(index == selected, description);
+                            if response.clicked() {
+                                self.element_list_selected = index;
+                            }
+                            if index == selected && selected != previous {
+                                // Focus makes the screen reader announce it
+                                response.request_focus();
+                                response.scroll_to_me(None);
+                            }
+                        }
+                    });
+            });
+        self.show_element_list = open;
     }
 
-    fn handle_gs_8l(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
-        let start_i = i - 1;
-
-        // GS 8 L p1 p2 p3 p4 m fn a bx by c xL xH yL yH d1...dk
-        if i + 10 > data.len() {
-            self.log_debug("GS 8 L incomplete: not enough header bytes");
-            return Ok(start_i);
+    /// Properties of the element under the pointer and the first bytes it
+    /// came from, once the pointer rests on it
+    fn show_element_tooltip(&mut self, ctx: &egui::Context, index: usize, layer: egui::LayerId) {
+        let delay = ctx.style().interaction.tooltip_delay;
+        let resting = ctx.input(|i| i.pointer.time_since_last_movement());
+        if resting < delay {
+            ctx.request_repaint_after(Duration::from_secs_f32(delay - resting));
+            return;
         }
-
-        i += 1; // skip 'L'
-
-        let p1 = data[i] as u32;
-        let p2 = data[i + 1] as u32;
-        let p3 = data[i + 2] as u32;
-        let p4 = data[i + 3] as u32;
-        let data_len = p1 | (p2 << 8) | (p3 << 16) | (p4 << 24);
-
-        let m = data[i + 4];
-        let _fn = data[i + 5];
-        let _a = data[i + 6];
-        let _bx = data[i + 7];
-        let _by = data[i + 8];
-        let _c = data[i + 9];
-
-        i += 10;
-
-        if m == 48 || m == 112 {
-            if i + 4 > data.len() {
-                self.log_debug("GS 8 L incomplete: not enough dimension bytes");
-                return Ok(start_i);
+        let Some(element) = self.state.elements.lock().unwrap().get(index).cloned() else {
+            return;
+        };
+        let Some(description) = describe_element(&element, self.show_unknown_commands) else {
+            return;
+        };
+        let key = (index, self.state.elements.lock().unwrap().len());
+        if !matches!(&self.hover_source, Some((cached, _)) if *cached == key) {
+            self.hover_source = Some((key, self.element_source(index)));
+        }
+        let source = self
+            .hover_source
+            .as_ref()
+            .and_then(|(_, source)| source.as_ref());
+        egui::show_tooltip_at_pointer(ctx, layer, egui::Id::new("element_details"), |ui| {
+            ui.set_max_width(ELEMENT_TOOLTIP_WIDTH);
+            ui.label(description);
+            if let Some((left_margin, print_area_width)) = element_layout(&element) {
+                let area = match print_area_width {
+                    0 => "full width".to_string(),
+                    dots => format!("{} dots", dots),
+                };
+                ui.label(format!(
+                    "Left margin {} dots, print area {}",
+                    left_margin, area
+                ));
             }
-
-            let xl = data[i] as usize;
-            let xh = data[i + 1] as usize;
-            let yl = data[i + 2] as usize;
-            let yh = data[i + 3] as usize;
-
-            let width = xl | (xh << 8);
-            let height = yl | (yh << 8);
-
-            i += 4;
-
-            let image_bytes = width.div_ceil(8) * height;
-
-            self.log_debug(&format!(
-                "GS 8 L raster: m={}, width={}, height={}, need {} bytes",
-                m, width, height, image_bytes
-            ));
-
-            if data_len as usize > 100_000 || image_bytes > 5_000_000 {
-                self.log_debug("GS 8 L: dimensions too large, skipping");
-                // data_len includes m,fn,a,bx,by,c (6 bytes) which we already consumed
-                // We need to skip the remaining data_len - 6 bytes
-                let skip = (data_len as usize).saturating_sub(6);
-                if i + skip <= data.len() {
-                    return Ok(i + skip);
-                } else {
-                    // Not enough data to skip - wait for more
-                    return Ok(start_i);
+            match source {
+                Some(source) => {
+                    ui.label(format!(
+                        "Job {}, bytes {}–{} of {}",
+                        source.job, source.start, source.end, source.job_len
+                    ));
+                    let mut dump = hex_dump(&source.bytes, source.start);
+                    if source.start + source.bytes.len() < source.end {
+                        dump.push_str("\n…");
+                    }
+                    ui.monospace(dump);
+                }
+                None => {
+                    ui.colored_label(egui::Color32::GRAY, "Source bytes not available");
                 }
             }
+        });
+    }
 
-            if i + image_bytes > data.len() {
-                self.log_debug(&format!(
-                    "GS 8 L incomplete: have {}, need {}",
-                    data.len() - i,
-                    image_bytes
-                ));
-                return Ok(start_i);
+    /// Where the element at `index` came from. Jobs are matched by the
+    /// element's receive time, and identical elements of a job, like blank
+    /// lines, by their order.
+    fn element_source(&mut self, index: usize) -> Option<ElementSource> {
+        let options = self.state.parse_options();
+        let jobs = self.state.jobs.lock().unwrap();
+        let elements = self.state.elements.lock().unwrap();
+        let times = self.state.element_times.lock().unwrap();
+        let wanted = serde_json::to_value(elements.get(index)?).ok()?;
+        let time = *times.get(index)?;
+        // Restored sessions may predate timestamps
+        if time == 0 {
+            return None;
+        }
+        let same =
+            |element: &ReceiptElement| serde_json::to_value(element).ok().as_ref() == Some(&wanted);
+        for job in jobs.iter() {
+            let in_job = |time: &u64| (job.received_at..=job.finished_at).contains(time);
+            if !in_job(&time) {
+                continue;
             }
-
-            if !self.current_line.is_empty() {
-                self.flush_line();
-                self.current_line.clear();
+            let same_before = elements[..index]
+                .iter()
+                .zip(times.iter())
+                .filter(|(element, time)| in_job(time) && same(element))
+                .count();
+            let cached = matches!(&self.hover_trace,
+                Some((id, paper_size, _)) if *id == job.id && *paper_size == options.paper_size);
+            if !cached {
+                let trace = trace_job(&job.raw, &options.for_source(&job.source)).ok()?;
+                self.hover_trace = Some((job.id, options.paper_size, trace));
+            }
+            let (_, _, trace) = self.hover_trace.as_ref()?;
+            let traced = trace
+                .elements
+                .iter()
+                .filter(|traced| same(&traced.element))
+                .nth(same_before);
+            if let Some(traced) = traced {
+                let (start, end) = (traced.start as usize, traced.end as usize);
+                return Some(ElementSource {
+                    job: job.id,
+                    start,
+                    end,
+                    job_len: job.raw.len(),
+                    bytes: job.raw[start..end.min(start + ELEMENT_TOOLTIP_BYTES)].to_vec(),
+                });
             }
-
-            self.elements.push(ReceiptElement::RasterImage {
-                width,
-                height,
-                data: data[i..i + image_bytes].to_vec(),
-                offset: self.state.horizontal_offset,
-                density: self.state.print_density,
-                alignment: self.state.alignment.clone(),
-                bytes_per_line: width.div_ceil(8), // Calculate from pixel width
-                print_area_width: self.state.print_area_width,
-            });
-
-            // Reset offset after rendering
-            self.state.horizontal_offset = 0;
-
-            // Mark that we just processed binary data
-            self.last_was_binary = true;
-
-            i += image_bytes;
-        } else {
-            let skip = (data_len as usize).saturating_sub(6);
-            i += skip.min(data.len() - i);
         }
-
-        Ok(i)
+        None
     }
 
-    fn handle_qr_code(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
-        let start_i = i - 1;
-
-        // GS ( k pL pH cn fn [parameters]
-        if i + 4 > data.len() {
-            self.log_debug("GS ( k incomplete: not enough header bytes");
-            return Ok(start_i);
-        }
-
-        i += 1; // skip 'k'
-
-        let p_l = data[i] as usize;
-        let p_h = data[i + 1] as usize;
-        let param_len = p_l | (p_h << 8);
-
-        let cn = data[i + 2];
-        let fn_code = data[i + 3];
-
-        i += 4;
-
-        if cn != 49 {
-            // Not a QR code command
-            let skip = param_len.saturating_sub(2);
-            i += skip.min(data.len() - i);
-            return Ok(i);
+    /// Build the command view again if jobs were added or removed or the
+    /// layout changed since
+    fn update_command_view(&mut self) {
+        let options = self.state.parse_options();
+        let merge = *self.state.merge_receipts.lock().unwrap();
+        let jobs = self.state.jobs.lock().unwrap();
+        let key = (
+            jobs.len(),
+            jobs.last().map(|job| job.id),
+            options.paper_size,
+            merge,
+        );
+        if !matches!(&self.command_view, Some((built, _)) if *built == key) {
+            self.command_view = Some((key, command_view::build(&jobs, &options, merge)));
         }
+    }
 
-        match fn_code {
-            65 | 67 => {
-                // 65: Set QR model, 67: Set module size
-                if i < data.len() {
-                    if fn_code == 67 {
-                        self.qr_size = data[i];
+    /// The customer display as a vacuum fluorescent panel: 2 lines of 20
+    /// characters, dimmed by its brightness setting
+    fn show_customer_display_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_customer_display;
+        egui::Window::new("Customer display")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let display = self.state.customer_display.lock().unwrap().clone();
+                let font = egui::FontId::monospace(22.0);
+                let cell = ui.fonts(|f| f.glyph_width(&font, 'M'));
+                let line_height = ui.fonts(|f| f.row_height(&font));
+                let size = egui::vec2(
+                    cell * customer_display::COLUMNS as f32,
+                    line_height * customer_display::ROWS as f32,
+                ) + egui::vec2(24.0, 20.0);
+                let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 4.0, egui::Color32::from_rgb(12, 18, 16));
+                // Blinking hides the text every other interval
+                let hidden = display.blink > 0 && {
+                    let interval = display.blink as f64 * 0.05;
+                    ctx.request_repaint_after(Duration::from_secs_f64(interval));
+                    (ui.input(|i| i.time) / interval) as u64 % 2 == 1
+                };
+                let alpha = 105 + 50 * display.brightness;
+                let color = egui::Color32::from_rgba_unmultiplied(90, 240, 200, alpha);
+                let origin = rect.min + egui::vec2(12.0, 10.0);
+                for (row, line) in display.lines().iter().enumerate() {
+                    for (column, ch) in line.chars().enumerate() {
+                        if hidden {
+                            break;
+                        }
+                        painter.text(
+                            origin + egui::vec2(cell * column as f32, line_height * row as f32),
+                            egui::Align2::LEFT_TOP,
+                            ch,
+                            font.clone(),
+                            color,
+                        );
                     }
-                    i += 1;
                 }
-            }
-            69 => {
-                // Set error correction level
-                if i < data.len() {
-                    self.qr_error_correction = data[i];
-                    i += 1;
-                }
-            }
-            80 => {
-                // Store QR data
-                let data_len = param_len.saturating_sub(3);
-                if i + data_len > data.len() {
-                    self.log_debug("GS ( k QR data incomplete");
-                    return Ok(start_i);
+                if display.cursor_visible {
+                    let (column, row) = display.cursor();
+                    let x = origin.x + cell * column as f32;
+                    let y = origin.y + line_height * (row + 1) as f32 - 2.0;
+                    painter.line_segment(
+                        [egui::pos2(x, y), egui::pos2(x + cell, y)],
+                        egui::Stroke::new(2.0, color),
+                    );
                 }
-                self.qr_data = data[i..i + data_len].to_vec();
-                i += data_len;
-            }
-            81 => {
-                // Print QR code
-                if !self.qr_data.is_empty() {
-                    if !self.current_line.is_empty() {
-                        self.flush_line();
-                        self.current_line.clear();
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{:?} mode, brightness {}",
+                        display.mode, display.brightness
+                    ));
+                    if ui.button("Reset").clicked() {
+                        *self.state.customer_display.lock().unwrap() = CustomerDisplay::default();
                     }
+                });
+            });
+        self.show_customer_display = open;
+    }
 
-                    let qr_string = String::from_utf8_lossy(&self.qr_data).to_string();
-                    let size = (self.qr_size as usize).clamp(1, 16);
-
-                    self.elements.push(ReceiptElement::QrCode {
-                        data: qr_string,
-                        size,
-                        alignment: self.state.alignment.clone(),
-                        offset: self.state.horizontal_offset,
-                        print_area_width: self.state.print_area_width,
-                    });
-
-                    // Reset horizontal offset after use
-                    self.state.horizontal_offset = 0;
-
-                    self.qr_data.clear();
+    /// History of the recorded jobs with their receive times
+    fn show_jobs_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_jobs;
+        let mut timeline_job = None;
+        let mut job_notes = None;
+        let mut export = None;
+        egui::Window::new("Jobs")
+            .open(&mut open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                let jobs = self.state.jobs.lock().unwrap();
+                if jobs.is_empty() {
+                    ui.label("No jobs received so far");
+                    return;
                 }
-            }
-            _ => {
-                // Unknown QR function
-                let skip = param_len.saturating_sub(2);
-                i += skip.min(data.len() - i);
-            }
+                self.job_selection
+                    .retain(|id| jobs.iter().any(|job| job.id == *id));
+                ui.horizontal(|ui| {
+                    ui.label("PDF");
+                    ui.add(egui::TextEdit::singleline(&mut self.pdf_path).desired_width(200.0));
+                    let status = self.pdf_status.lock().unwrap().clone();
+                    let exporting = matches!(&status, Some(Ok(message)) if message == EXPORTING);
+                    let selected = self.job_selection.len();
+                    if ui
+                        .add_enabled(
+                            selected > 0 && !exporting && !self.pdf_path.trim().is_empty(),
+                            egui::Button::new(format!("Export {} to PDF", selected)),
+                        )
+                        .on_hover_text("One page per ticked job, under its time, tags and note")
+                        .clicked()
+                    {
+                        export = Some(
+                            jobs.iter()
+                                .filter(|job| self.job_selection.contains(&job.id))
+                                .cloned()
+                                .collect::<Vec<_>>(),
+                        );
+                    }
+                    match &status {
+                        Some(Ok(message)) => {
+                            ui.colored_label(egui::Color32::DARK_GRAY, message);
+                        }
+                        Some(Err(err)) => {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+                        None => {}
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Text");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.disassembly_path).desired_width(200.0),
+                    );
+                    let selected = self.job_selection.len();
+                    if ui
+                        .add_enabled(
+                            selected > 0 && !self.disassembly_path.trim().is_empty(),
+                            egui::Button::new(format!("Disassemble {}", selected)),
+                        )
+                        .on_hover_text(
+                            "Every command of the ticked jobs as text, for support tickets",
+                        )
+                        .clicked()
+                    {
+                        let selected: Vec<&Job> = jobs
+                            .iter()
+                            .filter(|job| self.job_selection.contains(&job.id))
+                            .collect();
+                        self.disassembly_status = Some(Self::export_disassembly(
+                            &self.state.parse_options(),
+                            self.disassembly_path.trim(),
+                            &selected,
+                        ));
+                    }
+                    match &self.disassembly_status {
+                        Some(Ok(message)) => {
+                            ui.colored_label(egui::Color32::DARK_GRAY, message);
+                        }
+                        Some(Err(err)) => {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+                        None => {}
+                    }
+                });
+                ui.label("Times are UTC");
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("jobs")
+                        .num_columns(9)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            let mut all = self.job_selection.len() == jobs.len();
+                            if ui
+                                .checkbox(&mut all, "")
+                                .on_hover_text("Tick every job")
+                                .changed()
+                            {
+                                self.job_selection = if all {
+                                    jobs.iter().map(|job| job.id).collect()
+                                } else {
+                                    BTreeSet::new()
+                                };
+                            }
+                            ui.strong("Job");
+                            ui.strong("Received");
+                            ui.strong("Duration");
+                            ui.strong("Bytes");
+                            ui.strong("Source");
+                            ui.strong("Notes");
+                            ui.strong("Raw");
+                            ui.strong("");
+                            ui.end_row();
+                            for job in jobs.iter().rev() {
+                                let mut selected = self.job_selection.contains(&job.id);
+                                if ui.checkbox(&mut selected, "").changed() {
+                                    if selected {
+                                        self.job_selection.insert(job.id);
+                                    } else {
+                                        self.job_selection.remove(&job.id);
+                                    }
+                                }
+                                ui.monospace(job.id.to_string());
+                                ui.monospace(format_timestamp(job.received_at));
+                                let duration = if job.received_at == 0 {
+                                    String::new()
+                                } else {
+                                    let millis = job.finished_at.saturating_sub(job.received_at);
+                                    format!("{:.1} s", millis as f64 / 1000.0)
+                                };
+                                ui.monospace(duration)
+                                    .on_hover_text("From the first byte to the end of the job");
+                                ui.monospace(job.raw.len().to_string());
+                                ui.label(&job.source);
+                                let mut notes = job.tags.join(", ");
+                                if let Some(line) = job.note.lines().next() {
+                                    if !notes.is_empty() {
+                                        notes.push_str(" · ");
+                                    }
+                                    notes.push_str(line);
+                                }
+                                let notes = ui.add(
+                                    egui::Label::new(notes)
+                                        .truncate()
+                                        .sense(egui::Sense::click()),
+                                );
+                                let notes = if job.note.is_empty() {
+                                    notes.on_hover_text("Click to add a note or tags")
+                                } else {
+                                    notes.on_hover_text(&job.note)
+                                };
+                                if notes.clicked() {
+                                    job_notes =
+                                        Some((job.id, job.tags.join(", "), job.note.clone()));
+                                }
+                                match &job.capture {
+                                    Some(path) => {
+                                        if ui
+                                            .small_button("Show")
+                                            .on_hover_text(path.display().to_string())
+                                            .clicked()
+                                        {
+                                            if let Err(e) = capture::reveal(path) {
+                                                eprintln!("ERROR: {:#}", e);
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        ui.label("");
+                                    }
+                                }
+                                if ui
+                                    .small_button("Timing")
+                                    .on_hover_text("When the job's bytes arrived")
+                                    .clicked()
+                                {
+                                    timeline_job = Some(job.id);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+        self.show_jobs = open;
+        if timeline_job.is_some() {
+            self.timeline_job = timeline_job;
+        }
+        if job_notes.is_some() {
+            self.job_notes = job_notes;
+        }
+        if let Some(jobs) = export {
+            self.export_pdf(ctx, jobs);
         }
+    }
 
-        Ok(i)
+    /// Write the jobs to the PDF file, a page per receipt, in the background as
+    /// rendering them takes a moment
+    fn export_pdf(&self, ctx: &egui::Context, jobs: Vec<Job>) {
+        let ctx = ctx.clone();
+        let options = self.state.parse_options();
+        let path = std::path::PathBuf::from(self.pdf_path.trim());
+        let status = self.pdf_status.clone();
+        *status.lock().unwrap() = Some(Ok(EXPORTING.to_string()));
+        std::thread::spawn(move || {
+            let result = pdf::jobs_pdf(&jobs, &options)
+                .and_then(|pdf| {
+                    std::fs::write(&path, pdf)
+                        .map_err(|e| anyhow::anyhow!("writing {}: {}", path.display(), e))
+                })
+                .map(|()| format!("Wrote {} job(s) to {}", jobs.len(), path.display()))
+                .map_err(|e| format!("Export failed: {:#}", e));
+            *status.lock().unwrap() = Some(result);
+            ctx.request_repaint();
+        });
     }
 
-    fn handle_paper_cut(&mut self, data: &[u8], mut i: usize) -> Result<usize> {
-        let mode = data[i];
-        i += 1;
+    /// Write the jobs' disassembly to the text file, one after the other
+    fn export_disassembly(
+        options: &ParseOptions,
+        path: &str,
+        jobs: &[&Job],
+    ) -> Result<String, String> {
+        let path = std::path::PathBuf::from(path);
+        let texts = jobs
+            .iter()
+            .map(|job| {
+                let title = format!("job {} from {}", job.id, job.source);
+                let options = options.for_source(&job.source);
+                disassembly::disassemble(&title, &job.raw, &job.warnings, &options)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| format!("Disassembly failed: {:#}", e))?;
+        std::fs::write(&path, texts.join("\n"))
+            .map_err(|e| format!("Writing {} failed: {}", path.display(), e))?;
+        Ok(format!("Wrote {} job(s) to {}", jobs.len(), path.display()))
+    }
 
-        let cut_type = match mode {
-            0 | 48 => "FULL CUT",
-            1 | 49 => "PARTIAL CUT",
-            65 => "FEED & FULL CUT",
-            66 => "FEED & PARTIAL CUT",
-            _ => "UNKNOWN CUT",
+    /// Note and tags of one job, saved with the session and exported
+    fn show_job_notes_window(&mut self, ctx: &egui::Context) {
+        let Some((id, tags, note)) = &mut self.job_notes else {
+            return;
         };
+        let id = *id;
+        let mut open = true;
+        let mut save = false;
+        let mut close = false;
+        egui::Window::new(format!("Job {} notes", id))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.label("Tags");
+                ui.add(
+                    egui::TextEdit::singleline(tags)
+                        .hint_text("repro, approved layout v3")
+                        .desired_width(f32::INFINITY),
+                );
+                ui.label("Note");
+                ui.add(
+                    egui::TextEdit::multiline(note)
+                        .desired_rows(4)
+                        .desired_width(f32::INFINITY),
+                );
+                ui.horizontal(|ui| {
+                    save = ui.button("Save").clicked();
+                    close = ui.button("Cancel").clicked();
+                });
+            });
+        if save {
+            if let Some((_, tags, note)) = self.job_notes.take() {
+                self.state
+                    .annotate_job(id, note.trim().to_string(), parse_tags(&tags));
+            }
+        }
+        if !open || close {
+            self.job_notes = None;
+        }
+    }
 
-        self.flush_line();
-        self.elements.push(ReceiptElement::PaperCut {
-            cut_type: cut_type.to_string(),
-        });
-
-        Ok(i)
+    /// Bytes received over time for one job, with the pauses in between
+    fn show_timeline_window(&mut self, ctx: &egui::Context) {
+        let Some(id) = self.timeline_job else {
+            return;
+        };
+        let mut open = true;
+        egui::Window::new(format!("Job {} timing", id))
+            .open(&mut open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                let jobs = self.state.jobs.lock().unwrap();
+                let Some(job) = jobs.iter().find(|job| job.id == id) else {
+                    ui.label("The job has been cleared");
+                    return;
+                };
+                let Some(&(duration, _)) = job.arrivals.last() else {
+                    ui.label("No arrival times were recorded for this job");
+                    return;
+                };
+                // (start, end, bytes received before) of each pause
+                let pauses: Vec<(u64, u64, usize)> = job
+                    .arrivals
+                    .windows(2)
+                    .filter(|reads| reads[1].0 - reads[0].0 >= TIMING_PAUSE_MS)
+                    .map(|reads| (reads[0].0, reads[1].0, reads[0].1))
+                    .collect();
+                ui.label(format!(
+                    "{} bytes in {} read(s) over {:.3} s",
+                    job.raw.len(),
+                    job.arrivals.len(),
+                    duration as f64 / 1000.0
+                ));
+                paint_arrival_timeline(ui, &job.arrivals, &pauses);
+                if pauses.is_empty() {
+                    ui.label(format!("No pauses of {} ms or more", TIMING_PAUSE_MS));
+                    return;
+                }
+                ui.strong("Pauses");
+                egui::ScrollArea::vertical()
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        for (start, end, bytes) in &pauses {
+                            ui.monospace(format!(
+                                "{:>8.3} s  {:>6} ms after byte {}",
+                                *start as f64 / 1000.0,
+                                end - start,
+                                bytes
+                            ));
+                        }
+                    });
+            });
+        if !open {
+            self.timeline_job = None;
+        }
     }
-}
 
-#[derive(Clone)]
-struct AppState {
-    elements: Arc<Mutex<Vec<ReceiptElement>>>,
-    connections: Arc<Mutex<Vec<String>>>,
-    paper_size: Arc<Mutex<PaperSize>>,
-}
+    /// The receipt in a window of its own, so it can go on another monitor
+    /// (in a window inside the main one where the platform has just one).
+    /// Closing the window brings the receipt back.
+    fn show_receipt_viewport(&mut self, ctx: &egui::Context, paper_size_changed: bool) {
+        let width = self.state.paper_size.lock().unwrap().width_px() + 40.0;
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("receipt"),
+            egui::ViewportBuilder::default()
+                .with_title("escpresso receipt")
+                .with_inner_size([width, 800.0]),
+            |ctx, class| {
+                if class == egui::ViewportClass::Embedded {
+                    let mut open = true;
+                    egui::Window::new("Receipt")
+                        .open(&mut open)
+                        .show(ctx, |ui| self.show_receipt(ctx, ui, paper_size_changed));
+                    self.receipt_detached = open;
+                    return;
+                }
+                egui::CentralPanel::default()
+                    .frame(egui::Frame::none().fill(egui::Color32::from_gray(245)))
+                    .show(ctx, |ui| self.show_receipt(ctx, ui, paper_size_changed));
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    self.receipt_detached = false;
+                }
+            },
+        );
+    }
 
-impl AppState {
-    fn new() -> Self {
-        Self {
-            elements: Arc::new(Mutex::new(Vec::new())),
-            connections: Arc::new(Mutex::new(Vec::new())),
-            paper_size: Arc::new(Mutex::new(PaperSize::Size80mm)),
+    /// The receipt paper with the elements, or the command view
+    fn show_receipt(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, paper_size_changed: bool) {
+        let current_paper_size = *self.state.paper_size.lock().unwrap();
+        let listener_port = self.state.listener.borrow().port;
+        // Fixed width scroll area matching 80mm receipt paper
+        let printer_width_px = current_paper_size.width_px();
+        let wrap_print_area = self.wrap_print_area;
+        let show_unknown_commands = self.show_unknown_commands;
+        let show_text_warnings = self.show_text_warnings;
+        let show_guides = self.show_guides;
+        let darkness = self.darkness;
+        let max_raster_pixels = self.raster_budget_mp as usize * 1_000_000;
+        let mut hovered = None;
+        if self.show_commands {
+            self.update_command_view();
+        } else {
+            self.command_view = None;
         }
-    }
-}
+        let overlay = match self.command_view {
+            Some(_) => None,
+            None => self.reference_overlay(ctx),
+        };
 
-struct VirtualEscPosApp {
-    state: AppState,
-}
+        // Center the receipt area horizontally
+        ui.vertical_centered(|ui| {
+            ui.set_width(printer_width_px + 2.0); // +2 for border
+
+            // Receipt paper frame with border
+            egui::Frame::none()
+                .fill(egui::Color32::WHITE)
+                .stroke(egui::Stroke::new(1.0, egui::Color32::from_gray(200)))
+                .inner_margin(0.0)
+                .show(ui, |ui| {
+                    egui::ScrollArea::vertical()
+                        .auto_shrink([false; 2])
+                        .max_height(ui.available_height())
+                        .show(ui, |ui| {
+                            ui.set_width(printer_width_px);
+                            let origin = ui.cursor().min;
+                            let elements = self.state.elements.lock().unwrap();
+
+                            if elements.is_empty() {
+                                ui.add_space(100.0);
+                                ui.vertical_centered(|ui| {
+                                    ui.colored_label(egui::Color32::DARK_GRAY, "Receipt empty");
+                                    ui.add_space(10.0);
+                                    ui.colored_label(
+                                        egui::Color32::GRAY,
+                                        format!("Send print job to port {}", listener_port),
+                                    );
+                                    if paper_size_changed {
+                                        ui.add_space(5.0);
+                                        ui.colored_label(
+                                            egui::Color32::from_rgb(200, 150, 0),
+                                            format!(
+                                                "Paper size changed to {}",
+                                                current_paper_size.label()
+                                            ),
+                                        );
+                                    }
+                                });
+                            }
 
-impl VirtualEscPosApp {
-    fn new(_cc: &eframe::CreationContext, state: AppState) -> Self {
-        Self { state }
+                            let Some((_, items)) = &self.command_view else {
+                                hovered = show_receipt_elements(
+                                    ui,
+                                    &elements,
+                                    current_paper_size,
+                                    wrap_print_area,
+                                    show_unknown_commands,
+                                    show_text_warnings,
+                                    show_guides,
+                                    darkness,
+                                    max_raster_pixels,
+                                )
+                                .map(|index| (index, ui.layer_id()));
+                                if let Some((texture, size, tint)) = overlay {
+                                    paint_reference(ui, origin, texture, size, tint);
+                                }
+                                return;
+                            };
+                            for item in items {
+                                match item {
+                                    command_view::ViewItem::Chips(chips) => {
+                                        command_view::show_chips(ui, chips);
+                                    }
+                                    command_view::ViewItem::Elements(elements) => {
+                                        show_receipt_elements(
+                                            ui,
+                                            elements,
+                                            current_paper_size,
+                                            wrap_print_area,
+                                            show_unknown_commands,
+                                            show_text_warnings,
+                                            show_guides,
+                                            darkness,
+                                            max_raster_pixels,
+                                        );
+                                    }
+                                }
+                            }
+                        });
+                });
+        });
+        if let Some((index, layer)) = hovered {
+            if self.show_element_details {
+                self.show_element_tooltip(ctx, index, layer);
+            }
+        }
     }
 }
 
 impl eframe::App for VirtualEscPosApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint();
-
-        // Force light mode, ignoring OS dark mode
-        ctx.set_visuals(egui::Visuals::light());
-
-        let mut style = (*ctx.style()).clone();
-        style.visuals.panel_fill = egui::Color32::WHITE;
-        style.visuals.window_fill = egui::Color32::WHITE;
-        style.visuals.popup_shadow = egui::epaint::Shadow::NONE;
-        style.visuals.widgets.noninteractive.fg_stroke.color = egui::Color32::BLACK;
-        style.visuals.widgets.noninteractive.bg_fill = egui::Color32::WHITE;
-        style.visuals.widgets.inactive.fg_stroke.color = egui::Color32::BLACK;
-        style.visuals.widgets.inactive.bg_fill = egui::Color32::from_gray(245);
-        style.visuals.widgets.active.fg_stroke.color = egui::Color32::BLACK;
-        style.visuals.widgets.active.bg_fill = egui::Color32::from_gray(230);
-        style.visuals.widgets.hovered.fg_stroke.color = egui::Color32::BLACK;
-        style.visuals.widgets.hovered.bg_fill = egui::Color32::from_gray(250);
-        style.visuals.widgets.open.fg_stroke.color = egui::Color32::BLACK;
-        style.visuals.widgets.open.bg_fill = egui::Color32::from_gray(250);
-        style.visuals.extreme_bg_color = egui::Color32::WHITE;
-        style.visuals.faint_bg_color = egui::Color32::from_gray(250);
-        style.visuals.selection.bg_fill = egui::Color32::from_gray(248);
-        style.visuals.selection.stroke.color = egui::Color32::BLACK;
-        ctx.set_style(style);
+        // Changes from connections and the API request a repaint themselves;
+        // this catches the rest, like timers and elapsed times shown
+        ctx.request_repaint_after(IDLE_REPAINT_INTERVAL);
 
         let mut current_paper_size = *self.state.paper_size.lock().unwrap();
         let mut paper_size_changed = false;
+        let listener_port = self.state.listener.borrow().port;
 
         egui::TopBottomPanel::top("menu_bar")
             .frame(
@@ -1884,22 +3245,176 @@ impl eframe::App for VirtualEscPosApp {
                             egui::Color32::from_rgb(210, 210, 210);
 
                         if ui.button("Clear").clicked() {
-                            self.state.elements.lock().unwrap().clear();
+                            self.state.clear_receipt();
+                        }
+                        if ui.button("Settings").clicked() {
+                            self.show_settings = !self.show_settings;
+                        }
+                        if ui.button("Composer").clicked() {
+                            self.show_composer = !self.show_composer;
+                        }
+                        if ui.button("Jobs").clicked() {
+                            self.show_jobs = !self.show_jobs;
+                        }
+                        let kicks = self.state.drawer_log.lock().unwrap().len();
+                        let label = match kicks {
+                            0 => "Drawer".to_string(),
+                            n => format!("Drawer ({})", n),
+                        };
+                        if ui.button(label).clicked() {
+                            self.show_drawer_log = !self.show_drawer_log;
+                        }
+                        let warnings: usize = {
+                            let jobs = self.state.jobs.lock().unwrap();
+                            jobs.iter().map(|job| job.warnings.len()).sum()
+                        };
+                        let label = match warnings {
+                            0 => "Warnings".to_string(),
+                            n => format!("Warnings ({})", n),
+                        };
+                        if ui.button(label).clicked() {
+                            self.show_warnings = !self.show_warnings;
+                        }
+                        if ui
+                            .button("Display")
+                            .on_hover_text("The customer display selected with ESC = 2")
+                            .clicked()
+                        {
+                            self.show_customer_display = !self.show_customer_display;
+                        }
+                        if ui
+                            .button("Elements")
+                            .on_hover_text("The receipt as a list of text descriptions (Ctrl+L)")
+                            .clicked()
+                        {
+                            self.show_element_list = !self.show_element_list;
+                        }
+                        if ui
+                            .button("Pop out")
+                            .on_hover_text(
+                                "Show the receipt in a window of its own, e.g. on another monitor",
+                            )
+                            .clicked()
+                        {
+                            self.receipt_detached = !self.receipt_detached;
+                        }
+                        if ui
+                            .button("Reference")
+                            .on_hover_text("Compare the receipt with an approved render")
+                            .clicked()
+                        {
+                            self.show_reference = !self.show_reference;
+                        }
+                        ui.menu_button("Demo", |ui| {
+                            for job in demo::jobs() {
+                                if ui.button(job.name).clicked() {
+                                    self.print_demo(job);
+                                    ui.close_menu();
+                                }
+                            }
+                            ui.separator();
+                            if ui.button("All").clicked() {
+                                demo::jobs()
+                                    .into_iter()
+                                    .for_each(|job| self.print_demo(job));
+                                ui.close_menu();
+                            }
+                        });
+                        if ui
+                            .button("Self test")
+                            .on_hover_text(
+                                "Print the self-test page, like holding FEED at power-up",
+                            )
+                            .clicked()
+                        {
+                            self.print_self_test();
+                        }
+                        let print_status = self.print_status.lock().unwrap().clone();
+                        let printing =
+                            matches!(&print_status, Some(Ok(message)) if message == PRINTING);
+                        if ui
+                            .add_enabled(!printing, egui::Button::new("Print…"))
+                            .on_hover_text("Print the receipt on a system printer")
+                            .clicked()
+                        {
+                            self.print_receipt(ctx);
+                        }
+                        match &print_status {
+                            Some(Ok(message)) => {
+                                ui.colored_label(egui::Color32::DARK_GRAY, message);
+                            }
+                            Some(Err(err)) => {
+                                ui.colored_label(egui::Color32::RED, err);
+                            }
+                            None => {}
                         }
                     });
 
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        let columns = self.state.simulated_status.borrow().columns;
                         ui.colored_label(
                             egui::Color32::DARK_GRAY,
-                            format!("{}cpl | :9100", current_paper_size.chars_per_line()),
+                            format!(
+                                "{}cpl (Font B {}) | :{}",
+                                current_paper_size.font_columns(0, &columns),
+                                current_paper_size.font_columns(1, &columns),
+                                listener_port
+                            ),
                         );
                     });
                 });
             });
 
-        // Clear receipt when paper size changes
+        // Lay the jobs out again at the new width
         if paper_size_changed {
-            self.state.elements.lock().unwrap().clear();
+            self.state.rerender_receipt();
+        }
+
+        if self.show_settings {
+            self.show_settings_window(ctx);
+        }
+        if self.show_warnings {
+            self.show_warnings_window(ctx);
+        }
+        if self.show_jobs {
+            self.show_jobs_window(ctx);
+        }
+        if self.show_composer {
+            self.show_composer_window(ctx);
+        }
+        if self.show_drawer_log {
+            self.show_drawer_log_window(ctx);
+        }
+        if self.show_customer_display {
+            self.show_customer_display_window(ctx);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::L)) {
+            self.show_element_list = !self.show_element_list;
+        }
+        if self.show_element_list {
+            self.show_element_list_window(ctx);
+        }
+        if self.timeline_job.is_some() {
+            self.show_timeline_window(ctx);
+        }
+        if self.job_notes.is_some() {
+            self.show_job_notes_window(ctx);
+        }
+        if self.show_reference {
+            self.show_reference_window(ctx);
+        }
+        self.collect_dropped_files(ctx);
+        if !self.dropped_files.is_empty() {
+            self.show_replay_window(ctx);
+        }
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            egui::Area::new(egui::Id::new("drop_hint"))
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.heading("Drop a capture file to replay it");
+                    });
+                });
         }
 
         egui::CentralPanel::default()
@@ -1909,551 +3424,434 @@ impl eframe::App for VirtualEscPosApp {
                 if !connections.is_empty() {
                     ui.label(format!("Active connections: {}", connections.len()));
                     for conn in connections.iter() {
-                        ui.label(conn);
+                        ui.label(format!("Connected: {}", conn.addr));
+                        if let Some((command, received, size)) = conn.receiving {
+                            // Stalled uploads look finished unless told apart
+                            let idle = conn.last_data.elapsed().as_secs();
+                            let stalled = if idle >= 2 {
+                                format!(", no data for {} s", idle)
+                            } else {
+                                String::new()
+                            };
+                            ui.add(egui::ProgressBar::new(received as f32 / size as f32).text(
+                                format!(
+                                    "{}: {} of {} KB{}",
+                                    command,
+                                    received / 1024,
+                                    size.div_ceil(1024),
+                                    stalled
+                                ),
+                            ));
+                        }
                     }
                     ui.separator();
                 }
                 drop(connections);
 
-                // Fixed width scroll area matching 80mm receipt paper
-                let printer_width_px = current_paper_size.width_px();
-                let printer_chars_per_line = current_paper_size.chars_per_line();
-
-                // Center the receipt area horizontally
-                ui.vertical_centered(|ui| {
-                    ui.set_width(printer_width_px + 2.0); // +2 for border
-
-                    // Receipt paper frame with border
-                    egui::Frame::none()
-                        .fill(egui::Color32::WHITE)
-                        .stroke(egui::Stroke::new(1.0, egui::Color32::from_gray(200)))
-                        .inner_margin(0.0)
-                        .show(ui, |ui| {
-                            egui::ScrollArea::vertical()
-                                .auto_shrink([false; 2])
-                                .max_height(ui.available_height())
-                                .show(ui, |ui| {
-                                    ui.set_width(printer_width_px);
-                                    let elements = self.state.elements.lock().unwrap();
-
-                                    if elements.is_empty() {
-                                        ui.add_space(100.0);
-                                        ui.vertical_centered(|ui| {
-                                            ui.colored_label(
-                                                egui::Color32::DARK_GRAY,
-                                                "Receipt empty",
-                                            );
-                                            ui.add_space(10.0);
-                                            ui.colored_label(
-                                                egui::Color32::GRAY,
-                                                "Send print job to port 9100",
-                                            );
-                                            if paper_size_changed {
-                                                ui.add_space(5.0);
-                                                ui.colored_label(
-                                                    egui::Color32::from_rgb(200, 150, 0),
-                                                    format!(
-                                                        "Paper size changed to {}",
-                                                        current_paper_size.label()
-                                                    ),
-                                                );
-                                            }
-                                        });
-                                    }
-
-                                    for element in elements.iter() {
-                                        match element {
-                                            ReceiptElement::Text {
-                                                content,
-                                                bold,
-                                                underline,
-                                                double_width,
-                                                double_height,
-                                                inverted,
-                                                alignment,
-                                                density,
-                                                offset,
-                                                left_margin,
-                                                character_spacing,
-                                                double_strike,
-                                                font,
-                                                print_area_width,
-                                            } => {
-                                                let mut job = egui::text::LayoutJob::default();
-
-                                                // Use print_area_width (GS W) for content sizing
-                                                // when set, otherwise fall back to full printer width
-                                                let effective_width = if *print_area_width > 0 {
-                                                    *print_area_width as f32
-                                                } else {
-                                                    printer_width_px
-                                                };
-
-                                                // Calculate font size to fit chars per line
-                                                // Measure actual monospace advance width ratio
-                                                let char_width =
-                                                    effective_width / printer_chars_per_line as f32;
-                                                let ref_size = 20.0_f32;
-                                                let ref_galley = ui.fonts(|f| {
-                                                    f.layout_job(
-                                                        egui::text::LayoutJob::simple_singleline(
-                                                            "M".to_string(),
-                                                            egui::FontId::monospace(ref_size),
-                                                            egui::Color32::BLACK,
-                                                        ),
-                                                    )
-                                                });
-                                                let mono_ratio = ref_galley.size().x / ref_size;
-                                                let base_font_size = char_width / mono_ratio;
-
-                                                // Apply font selection (Font B is ~75% of Font A size)
-                                                let font_multiplier = match font {
-                                                    1 => 0.75, // Font B - smaller
-                                                    2 => 0.65, // Font C - even smaller (if used)
-                                                    _ => 1.0,  // Font A - standard
-                                                };
-
-                                                let mut size = base_font_size * font_multiplier;
-                                                if *double_width || *double_height {
-                                                    size = base_font_size * font_multiplier * 1.5;
-                                                }
-
-                                                // Always use monospace for consistent character widths
-                                                // ESC/POS printers use fixed-width fonts
-                                                // Bold will be rendered by egui's text rendering (stroke weight)
-                                                let font_id = egui::FontId::monospace(size);
-
-                                                // Apply bold, double-strike, and density
-                                                let color = if *inverted {
-                                                    egui::Color32::WHITE
-                                                } else {
-                                                    // Bold or double-strike makes text darker
-                                                    if *bold || *double_strike {
-                                                        egui::Color32::BLACK
-                                                    } else {
-                                                        match density {
-                                                            0 => egui::Color32::LIGHT_GRAY,
-                                                            1 => egui::Color32::GRAY,
-                                                            2 => egui::Color32::DARK_GRAY,
-                                                            _ => egui::Color32::BLACK, // 3-8: normal black
-                                                        }
-                                                    }
-                                                };
-
-                                                let bg_color = if *inverted {
-                                                    egui::Color32::BLACK
-                                                } else {
-                                                    egui::Color32::TRANSPARENT
-                                                };
-
-                                                // Apply character spacing (ESC SP)
-                                                let extra_letter_spacing =
-                                                    *character_spacing as f32;
-
-                                                job.append(
-                                                    content,
-                                                    0.0,
-                                                    egui::TextFormat {
-                                                        font_id,
-                                                        color,
-                                                        background: bg_color,
-                                                        underline: if *underline {
-                                                            egui::Stroke::new(1.0, color)
-                                                        } else {
-                                                            egui::Stroke::NONE
-                                                        },
-                                                        extra_letter_spacing,
-                                                        ..Default::default()
-                                                    },
-                                                );
-
-                                                let galley = ui.fonts(|f| f.layout_job(job));
-
-                                                // Allocate full width for 80mm receipt paper
-                                                let line_height = galley.size().y;
-
-                                                let (rect, _) = ui.allocate_exact_size(
-                                                    egui::vec2(printer_width_px, line_height),
-                                                    egui::Sense::hover(),
-                                                );
-
-                                                // Apply left margin (GS L)
-                                                let margin_offset = *left_margin as f32;
-
-                                                // Center the printable area within the paper
-                                                let area_offset = if *print_area_width > 0 {
-                                                    (printer_width_px - *print_area_width as f32)
-                                                        / 2.0
-                                                } else {
-                                                    0.0
-                                                };
-
-                                                // Calculate base position from alignment
-                                                // All alignments use area_offset so content
-                                                // stays within the GS W print area
-                                                let base_x = match alignment {
-                                                    Alignment::Left => {
-                                                        rect.left() + area_offset + margin_offset
-                                                    }
-                                                    Alignment::Center => {
-                                                        rect.left()
-                                                            + area_offset
-                                                            + margin_offset
-                                                            + (effective_width
-                                                                - galley.size().x
-                                                                - margin_offset)
-                                                                / 2.0
-                                                    }
-                                                    Alignment::Right => {
-                                                        rect.left() + area_offset + effective_width
-                                                            - galley.size().x
-                                                    }
-                                                };
-
-                                                // Apply horizontal offset (from ESC $ / ESC \ commands)
-                                                // Offset is in pixels, add to base position
-                                                let final_x = if *offset > 0 {
-                                                    rect.left() + margin_offset + *offset as f32
-                                                } else {
-                                                    base_x
-                                                };
-
-                                                let pos = egui::pos2(final_x, rect.top());
-
-                                                ui.painter().galley(pos, galley, color);
-                                            }
-                                            ReceiptElement::RasterImage {
-                                                width,
-                                                height,
-                                                data,
-                                                offset,
-                                                density,
-                                                alignment,
-                                                bytes_per_line,
-                                                print_area_width,
-                                            } => {
-                                                render_raster_image(
-                                                    ui,
-                                                    *width,
-                                                    *height,
-                                                    data,
-                                                    *offset,
-                                                    *density,
-                                                    alignment,
-                                                    printer_width_px,
-                                                    *bytes_per_line,
-                                                    *print_area_width,
-                                                );
-                                            }
-                                            ReceiptElement::QrCode {
-                                                data,
-                                                size,
-                                                alignment,
-                                                offset,
-                                                print_area_width,
-                                            } => {
-                                                render_qr_code(
-                                                    ui,
-                                                    data,
-                                                    *size,
-                                                    alignment,
-                                                    *offset,
-                                                    *print_area_width,
-                                                    printer_width_px,
-                                                );
-                                            }
-                                            ReceiptElement::PaperCut { cut_type } => {
-                                                ui.separator();
-                                                ui.horizontal(|ui| {
-                                                    ui.label("✂");
-                                                    ui.strong(format!("PAPER CUT: {}", cut_type));
-                                                });
-                                                ui.separator();
-                                            }
-                                            ReceiptElement::CashDrawer {
-                                                pin,
-                                                on_time,
-                                                off_time,
-                                            } => {
-                                                ui.separator();
-                                                ui.horizontal(|ui| {
-                                                    ui.label("💰");
-                                                    ui.strong("CASH DRAWER OPEN");
-                                                });
-                                                ui.label(format!(
-                                                    "Pin: {}  On: {}ms  Off: {}ms",
-                                                    pin,
-                                                    *on_time as u32 * 2,
-                                                    *off_time as u32 * 2
-                                                ));
-                                                ui.separator();
-                                            }
-                                            ReceiptElement::Separator => {
-                                                ui.add_space(4.0);
-                                            }
-                                            ReceiptElement::FormFeed => {
-                                                // Don't add artificial spacing - only show protocol breaks
-                                            }
-                                        }
-                                    }
-                                });
-                        });
-                });
-            });
-    }
-}
-
-#[allow(clippy::too_many_arguments)]
-fn render_raster_image(
-    ui: &mut egui::Ui,
-    width: usize,
-    height: usize,
-    data: &[u8],
-    offset: u16,
-    density: u8,
-    alignment: &Alignment,
-    printer_width_px: f32,
-    bytes_per_line: usize,
-    print_area_width: u16,
-) {
-    // Use the actual bytes_per_line from the command, not recalculated
-    let mut pixels = Vec::with_capacity(width * height);
-
-    // Apply density/darkness control to raster images
-    // Density 0-8 maps to different gray levels for lighter/darker printing
-    let ink_color = match density {
-        0 => egui::Color32::from_gray(180), // Very light
-        1 => egui::Color32::from_gray(130), // Light
-        2 => egui::Color32::from_gray(80),  // Slightly light
-        _ => egui::Color32::BLACK,          // 3-8: normal black
-    };
-
-    for y in 0..height {
-        for x in 0..width {
-            let byte_idx = y * bytes_per_line + (x / 8);
-            // MSB-first bit order: bit 7 (0x80) is leftmost pixel, bit 0 (0x01) is rightmost
-            let bit_idx = 7 - (x % 8);
-
-            if byte_idx < data.len() {
-                let bit = (data[byte_idx] >> bit_idx) & 1;
-                // Standard ESC/POS: 1=black (printed), 0=white (not printed)
-                if bit == 1 {
-                    pixels.push(ink_color); // Bit 1 = black
+                if self.receipt_detached {
+                    ui.add_space(100.0);
+                    ui.vertical_centered(|ui| {
+                        ui.colored_label(
+                            egui::Color32::DARK_GRAY,
+                            "The receipt is shown in its own window",
+                        );
+                        if ui.button("Bring it back").clicked() {
+                            self.receipt_detached = false;
+                        }
+                    });
                 } else {
-                    pixels.push(egui::Color32::WHITE); // Bit 0 = white
+                    self.show_receipt(ctx, ui, paper_size_changed);
                 }
-            } else {
-                pixels.push(egui::Color32::WHITE);
-            }
-        }
-    }
-
-    let image = egui::ColorImage {
-        size: [width, height],
-        pixels,
-    };
-
-    let texture = ui.ctx().load_texture(
-        format!("raster_{}x{}_{}", width, height, offset),
-        image,
-        egui::TextureOptions::NEAREST,
-    );
-
-    // Use print_area_width (GS W) for alignment when set,
-    // otherwise fall back to full printer width
-    let effective_width = if print_area_width > 0 {
-        print_area_width as f32
-    } else {
-        printer_width_px
-    };
-
-    // Scale up the image for better visibility (thermal printers are 203 DPI, screens are ~96 DPI)
-    // Use adaptive scaling: small images (text) get 3x, large images (logos) get 1x
-    // Clamp so the image never exceeds the printable area
-    let scale_factor = if width > 300 || height > 150 {
-        1.0
-    } else {
-        3.0_f32.min(effective_width / width as f32)
-    };
-    let display_width = width as f32 * scale_factor;
-    let display_height = height as f32 * scale_factor;
-
-    // Allocate full printer width for proper alignment
-    let (rect, _) = ui.allocate_exact_size(
-        egui::vec2(printer_width_px, display_height),
-        egui::Sense::hover(),
-    );
-
-    // Center the printable area within the paper width
-    let area_offset = if print_area_width > 0 {
-        (printer_width_px - print_area_width as f32) / 2.0
-    } else {
-        0.0
-    };
+            });
 
-    // Calculate horizontal position based on alignment and offset
-    // For CENTER/RIGHT, center the printable area within the paper.
-    // For LEFT, use left edge only.
-    let x_offset = match alignment {
-        Alignment::Left => offset as f32 * scale_factor,
-        Alignment::Center => {
-            area_offset + (effective_width - display_width) / 2.0 + offset as f32 * scale_factor
+        if self.receipt_detached {
+            self.show_receipt_viewport(ctx, paper_size_changed);
         }
-        Alignment::Right => {
-            area_offset + effective_width - display_width - offset as f32 * scale_factor
-        }
-    };
-
-    let pos = egui::pos2(rect.left() + x_offset, rect.top());
-    let size = egui::vec2(display_width, display_height);
-
-    ui.painter().image(
-        texture.id(),
-        egui::Rect::from_min_size(pos, size),
-        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-        egui::Color32::WHITE,
-    );
+    }
 }
 
-fn render_qr_code(
+/// Paint the reference or heatmap over the receipt from its top left, making
+/// room to scroll to its end when it's the taller
+fn paint_reference(
     ui: &mut egui::Ui,
-    data: &str,
-    size: usize,
-    alignment: &Alignment,
-    offset: u16,
-    print_area_width: u16,
-    printer_width_px: f32,
+    origin: egui::Pos2,
+    texture: egui::TextureId,
+    size: egui::Vec2,
+    tint: egui::Color32,
 ) {
-    match QrCode::new(data.as_bytes()) {
-        Ok(qr) => {
-            let colors = qr.to_colors();
-            let width = qr.width();
-            let module_size = size.clamp(1, 8);
-            let pixel_size = width * module_size;
-
-            let mut pixels = Vec::with_capacity(pixel_size * pixel_size);
-
-            for y in 0..width {
-                for _ in 0..module_size {
-                    for x in 0..width {
-                        let idx = y * width + x;
-                        let color = match colors[idx] {
-                            QrColor::Dark => egui::Color32::BLACK,
-                            QrColor::Light => egui::Color32::WHITE,
-                        };
-                        for _ in 0..module_size {
-                            pixels.push(color);
-                        }
-                    }
-                }
-            }
+    let rect = egui::Rect::from_min_size(origin, size);
+    let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+    ui.painter().image(texture, rect, uv, tint);
+    let below = rect.bottom() - ui.cursor().min.y;
+    if below > 0.0 {
+        ui.add_space(below);
+    }
+}
 
-            let image = egui::ColorImage {
-                size: [pixel_size, pixel_size],
-                pixels,
-            };
+/// Reads this long after the previous one are highlighted as pauses
+const TIMING_PAUSE_MS: u64 = 200;
 
-            let texture = ui.ctx().load_texture(
-                format!("qr_{}", data.chars().take(20).collect::<String>()),
-                image,
-                egui::TextureOptions::NEAREST,
-            );
+/// Bytes received so far against time, flat while waiting and rising at
+/// each read, with the pauses shaded
+fn paint_arrival_timeline(
+    ui: &mut egui::Ui,
+    arrivals: &[(u64, usize)],
+    pauses: &[(u64, u64, usize)],
+) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width().max(300.0), 160.0),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(250));
+    let (duration, total) = arrivals.last().copied().unwrap_or_default();
+    let x = |at: u64| rect.left() + rect.width() * at as f32 / duration.max(1) as f32;
+    let y = |bytes: usize| rect.bottom() - rect.height() * bytes as f32 / total.max(1) as f32;
+    for &(start, end, _) in pauses {
+        painter.rect_filled(
+            egui::Rect::from_x_y_ranges(x(start)..=x(end), rect.y_range()),
+            0.0,
+            egui::Color32::from_rgba_unmultiplied(255, 160, 0, 60),
+        );
+    }
+    let mut points = vec![rect.left_bottom()];
+    let mut previous = 0;
+    for &(at, bytes) in arrivals {
+        points.push(egui::pos2(x(at), y(previous)));
+        points.push(egui::pos2(x(at), y(bytes)));
+        previous = bytes;
+    }
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, egui::Color32::from_rgb(40, 90, 200)),
+    ));
+    let font = egui::FontId::proportional(11.0);
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        egui::Align2::LEFT_TOP,
+        format!("{} bytes", total),
+        font.clone(),
+        egui::Color32::GRAY,
+    );
+    painter.text(
+        rect.right_bottom() - egui::vec2(4.0, 2.0),
+        egui::Align2::RIGHT_BOTTOM,
+        format!("{:.3} s", duration as f64 / 1000.0),
+        font,
+        egui::Color32::GRAY,
+    );
+}
 
-            let (rect, _) = ui.allocate_exact_size(
-                egui::vec2(printer_width_px, pixel_size as f32),
-                egui::Sense::hover(),
-            );
+/// Elements the element list moves by with Page Up/Down
+const ELEMENT_LIST_PAGE: usize = 10;
+
+/// Source bytes shown in the element tooltip, and its width
+const ELEMENT_TOOLTIP_BYTES: usize = 48;
+const ELEMENT_TOOLTIP_WIDTH: f32 = 420.0;
+
+/// Hex dump with 16 bytes per line, each line starting with the offset of
+/// its first byte: "0000007B  1B 61 01 ..."
+fn hex_dump(bytes: &[u8], offset: usize) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:08X}  {}", offset + line * 16, hex.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-            // Use print_area_width (GS W) for alignment when set,
-            // otherwise fall back to full printer width
-            let effective_width = if print_area_width > 0 {
-                print_area_width as f32
+/// What an element prints, in words, for the element list. None for
+/// elements that print nothing, like separators.
+fn describe_element(element: &ReceiptElement, show_unknown_commands: bool) -> Option<String> {
+    let alignment = |alignment: &Alignment| match alignment {
+        Alignment::Left => "",
+        Alignment::Center => ", centered",
+        Alignment::Right => ", right aligned",
+    };
+    Some(match element {
+        ReceiptElement::Text {
+            content,
+            bold,
+            underline,
+            double_width,
+            double_height,
+            inverted,
+            alignment: align,
+            runs,
+            ..
+        } => {
+            let content = if runs.is_empty() {
+                content.clone()
             } else {
-                printer_width_px
+                runs.iter()
+                    .map(|run| run.content.trim())
+                    .collect::<Vec<_>>()
+                    .join(" ")
             };
-
-            // Center the printable area within the paper width
-            let area_offset = if print_area_width > 0 {
-                (printer_width_px - print_area_width as f32) / 2.0
+            if content.trim().is_empty() {
+                return Some("Blank line".to_string());
+            }
+            let style: Vec<&str> = [
+                (*bold, "bold"),
+                (*underline, "underlined"),
+                (*double_width && *double_height, "double size"),
+                (*double_width && !*double_height, "double width"),
+                (*double_height && !*double_width, "double height"),
+                (*inverted, "inverted"),
+            ]
+            .into_iter()
+            .filter_map(|(set, name)| set.then_some(name))
+            .collect();
+            let style = if style.is_empty() {
+                String::new()
             } else {
-                0.0
-            };
-
-            // Calculate base position from alignment
-            // For CENTER/RIGHT, center the printable area within the paper.
-            // For LEFT, use left edge only.
-            let base_x = match alignment {
-                Alignment::Left => 0.0,
-                Alignment::Center => area_offset + (effective_width - pixel_size as f32) / 2.0,
-                Alignment::Right => area_offset + effective_width - pixel_size as f32,
+                format!(" ({})", style.join(", "))
             };
+            format!("Text{}{}: {}", style, alignment(align), content.trim())
+        }
+        ReceiptElement::RasterImage {
+            width,
+            height,
+            alignment: align,
+            ..
+        } => format!("Image, {} by {} dots{}", width, height, alignment(align)),
+        ReceiptElement::QrCode {
+            data,
+            alignment: align,
+            ..
+        } => format!("QR code{}: {}", alignment(align), data),
+        ReceiptElement::Barcode {
+            symbology,
+            text,
+            alignment: align,
+            ..
+        } => format!("Barcode {}{}: {}", symbology, alignment(align), text),
+        ReceiptElement::PaperCut { cut_type } => format!("Paper cut: {}", cut_type.to_lowercase()),
+        ReceiptElement::CashDrawer { pin, .. } => {
+            format!("Cash drawer kick, pin {}", if pin & 1 == 0 { 2 } else { 5 })
+        }
+        ReceiptElement::FormFeed => "Form feed".to_string(),
+        ReceiptElement::JobTags { tags } => format!("Tags: {}", tags.join(", ")),
+        ReceiptElement::UnknownCommand { bytes } if show_unknown_commands => {
+            format!("Unknown command: {}", command_name(bytes))
+        }
+        ReceiptElement::ReceiptStart { id, source } => format!("Receipt {} from {}", id, source),
+        ReceiptElement::Separator | ReceiptElement::UnknownCommand { .. } => return None,
+    })
+}
 
-            // Apply horizontal offset (from ESC $ / ESC \ commands)
-            let final_x = if offset > 0 { offset as f32 } else { base_x };
+/// Applies scheduled simulated status changes when they fall due
+async fn run_status_scheduler(state: AppState) {
+    let mut tick = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        tick.tick().await;
+        state.apply_due_status_changes();
+    }
+}
 
-            let pos = egui::pos2(rect.left() + final_x, rect.top());
-            let size = egui::vec2(pixel_size as f32, pixel_size as f32);
+struct ConnectionEntry {
+    state: AppState,
+    addr: std::net::SocketAddr,
+}
 
-            ui.painter().image(
-                texture.id(),
-                egui::Rect::from_min_size(pos, size),
-                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                egui::Color32::WHITE,
-            );
-        }
-        Err(e) => {
-            ui.colored_label(egui::Color32::RED, format!("QR Code Error: {:?}", e));
+impl Drop for ConnectionEntry {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.state.connections.lock() {
+            connections.retain(|c| c.addr != self.addr);
         }
+        self.state.request_repaint();
     }
 }
 
+/// Serve a connection until it closes, or until `closing` is set, which
+/// finishes the job it has open and closes it
 async fn handle_client(
     mut socket: tokio::net::TcpStream,
     addr: std::net::SocketAddr,
     state: AppState,
     debug: bool,
+    mut closing: watch::Receiver<bool>,
 ) -> Result<()> {
+    let secret = state.access.lock().unwrap().secret.clone();
+    if !secret.is_empty() && !access::check_preamble(&mut socket, &secret).await {
+        println!("Rejected connection from {}: wrong or missing secret", addr);
+        state.audit(AuditEvent::Rejected {
+            source: &addr.to_string(),
+            reason: "wrong or missing secret",
+        });
+        return Ok(());
+    }
+    let connected_at = unix_millis();
     {
         let mut connections = state.connections.lock().unwrap();
-        connections.push(format!("Connected: {}", addr));
+        connections.push(Connection {
+            addr,
+            receiving: None,
+            last_data: std::time::Instant::now(),
+        });
+    }
+    state.request_repaint();
+    // Removes the entry however the connection ends, including being aborted
+    // by a listener restart
+    let _connection_entry = ConnectionEntry {
+        state: state.clone(),
+        addr,
+    };
+
+    let mut com_port = match state.listener.borrow().protocol {
+        Protocol::Raw => None,
+        Protocol::Rfc2217 => Some(rfc2217::ComPortServer::new()),
+    };
+    if let Some(com_port) = &mut com_port {
+        socket.write_all(&com_port.take_replies()).await?;
     }
+    // Printer responses with telnet's 0xFF bytes doubled
+    let telnet = com_port.is_some();
+    let escape = |data: Vec<u8>| if telnet { rfc2217::escape(data) } else { data };
 
+    let hooks = state.hooks.lock().unwrap().clone();
     let mut renderer = EscPosRenderer::new(debug);
+    renderer.set_hooks(hooks.clone());
+    renderer.set_nv_memory(state.nv_memory.clone());
+    renderer.set_custom_values(state.custom_values.clone());
+    renderer.set_customer_display(state.customer_display.clone());
+    renderer.set_maintenance_counters(state.maintenance.clone());
     let mut buffer = vec![0u8; 8192];
-
-    // Open file for raw data capture if debug enabled
-    let mut raw_file = if debug {
-        std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open("escpos_capture.raw")
-            .ok()
-    } else {
-        None
-    };
+    let source = addr.to_string();
+    state.audit(AuditEvent::Connected { source: &source });
+    let mut jobs = JobSplitter::default();
+    let mut job = OpenJob::default();
+    let mut status_rx = state.simulated_status.subscribe();
+    let mut receive_buffer = ReceiveBuffer::new(*state.receive_buffer.lock().unwrap());
+    let mut last_activity = tokio::time::Instant::now();
 
     loop {
-        match socket.read(&mut buffer).await {
+        // A full receive buffer stops reading until enough has been printed,
+        // leaving the client's writes to block
+        let mut buffer_settings = *state.receive_buffer.lock().unwrap();
+        // The client's serial line speed, over RFC 2217
+        if let Some(com_port) = com_port.as_ref().filter(|port| port.baud_rate_set) {
+            buffer_settings.baud_rate = com_port.line.baud_rate;
+        }
+        receive_buffer.configure(buffer_settings);
+        let space = receive_buffer.space();
+        let full = space == Some(0);
+        if let Some(frame) = renderer.set_buffer_full(full) {
+            if debug {
+                eprintln!("[DEBUG] Sending ASB frame: {:02X?}", frame);
+            }
+            let settings = *state.receive_buffer.lock().unwrap();
+            let frame = escape(frame.to_vec());
+            if let Err(e) = receive_buffer::write_responses(&mut socket, &frame, &settings).await {
+                eprintln!("Error sending ASB status: {}", e);
+            }
+        }
+        let limit = space.map_or(buffer.len(), |space| space.min(buffer.len()));
+        // At serial speed, the next read waits for the line to carry the last
+        // one
+        let line_delay = receive_buffer.line_delay();
+        let line_busy = !line_delay.is_zero();
+
+        // Clients that open a connection and never close it would otherwise
+        // stay connected, with their job unfinished, forever. Waiting on a
+        // full receive buffer or the serial line doesn't count as idle.
+        let idle_timeout = state.connection_limits.lock().unwrap().idle_timeout;
+        if full || line_busy {
+            last_activity = tokio::time::Instant::now();
+        }
+        let idle_deadline = last_activity + Duration::from_secs(idle_timeout);
+        let job_boundary = *state.job_boundary.lock().unwrap();
+        let job_timeout = match job_boundary {
+            JobBoundary::Inactivity(secs) if jobs.job_started() && !full && !line_busy => {
+                Some(secs)
+            }
+            _ => None,
+        };
+        let job_deadline = last_activity + Duration::from_secs(job_timeout.unwrap_or(0));
+
+        let read = tokio::select! {
+            read = socket.read(&mut buffer[..limit]), if !full && !line_busy => read,
+            _ = tokio::time::sleep(receive_buffer.time_until_space()), if full => continue,
+            _ = tokio::time::sleep(line_delay), if !full && line_busy => continue,
+            _ = tokio::time::sleep_until(idle_deadline), if idle_timeout > 0 && !full => {
+                println!("Closing {} after {}s without data", addr, idle_timeout);
+                break;
+            }
+            _ = tokio::time::sleep_until(job_deadline), if job_timeout.is_some() => {
+                if let Err(e) = jobs.finish(&mut renderer) {
+                    eprintln!("Error processing data: {}", e);
+                }
+                job.record(&state, hooks.as_deref(), &source, jobs.take_events());
+                continue;
+            }
+            Ok(()) = closing.changed() => {
+                println!("Closing {}: the listener is restarting", addr);
+                break;
+            }
+            Ok(()) = status_rx.changed() => {
+                // Automatic Status Back: tell the client without being asked
+                let status = *status_rx.borrow_and_update();
+                if let Some(frame) = renderer.status_changed(status) {
+                    if debug {
+                        eprintln!("[DEBUG] Sending ASB frame: {:02X?}", frame);
+                    }
+                    let settings = *state.receive_buffer.lock().unwrap();
+                    let frame = escape(frame.to_vec());
+                    if let Err(e) =
+                        receive_buffer::write_responses(&mut socket, &frame, &settings).await
+                    {
+                        eprintln!("Error sending ASB status: {}", e);
+                    }
+                }
+                continue;
+            }
+        };
+        match read {
             Ok(0) => {
-                let mut connections = state.connections.lock().unwrap();
-                connections.retain(|c| !c.contains(&addr.to_string()));
                 break;
             }
             Ok(n) => {
-                // Save raw data if debug enabled
-                if let Some(ref mut file) = raw_file {
-                    use std::io::Write;
-                    let _ = file.write_all(&buffer[..n]);
+                last_activity = tokio::time::Instant::now();
+                let n = match &mut com_port {
+                    Some(com_port) => {
+                        let n = com_port.receive(&mut buffer[..n]);
+                        socket.write_all(&com_port.take_replies()).await?;
+                        if com_port.take_line_changed() {
+                            println!("Serial line of {}: {}", addr, com_port.line);
+                        }
+                        n
+                    }
+                    None => n,
+                };
+                if n == 0 {
+                    continue;
                 }
+                receive_buffer.fill(n);
 
                 if debug {
                     eprintln!("[DEBUG] Received {} bytes: {:02X?}", n, &buffer[..n]);
                 }
 
-                if let Err(e) = renderer.process_data(&buffer[..n]) {
+                renderer.set_status(*state.simulated_status.borrow());
+                renderer.set_paper_size(*state.paper_size.lock().unwrap());
+                renderer.set_custom_code_pages(state.custom_code_pages.lock().unwrap().clone());
+                renderer.set_forced_code_page(codepages::forced_code_page(
+                    &state.forced_code_pages.lock().unwrap(),
+                    &source,
+                ));
+                renderer.set_control_glyphs(*state.control_glyphs.lock().unwrap());
+                renderer.set_job_boundary(job_boundary);
+                renderer.set_init_starts_job(*state.init_new_receipt.lock().unwrap());
+                if let Err(e) = jobs.receive(&mut renderer, &buffer[..n]) {
                     eprintln!("Error processing data: {}", e);
                 }
+                state.connection_progress(addr, renderer.receiving());
+                if renderer.take_display_updated() {
+                    state.request_repaint();
+                }
 
                 // Send any queued responses (status queries, etc.)
                 let responses = renderer.take_responses();
@@ -2465,19 +3863,26 @@ async fn handle_client(
                             responses
                         );
                     }
-                    if let Err(e) = socket.write_all(&responses).await {
+                    let settings = *state.receive_buffer.lock().unwrap();
+                    let responses = escape(responses);
+                    if let Err(e) =
+                        receive_buffer::write_responses(&mut socket, &responses, &settings).await
+                    {
                         eprintln!("Error sending responses: {}", e);
                     }
-                    if let Err(e) = socket.flush().await {
-                        eprintln!("Error flushing socket: {}", e);
-                    }
                 }
 
-                let new_elements = renderer.take_elements();
-                if !new_elements.is_empty() {
-                    let mut elements = state.elements.lock().unwrap();
-                    elements.extend(new_elements);
+                state.log_drawer_pulses(&source, renderer.take_drawer_pulses());
+                if renderer.take_error_recovery() {
+                    state.recover_error(&source);
                 }
+                job.record(&state, hooks.as_deref(), &source, jobs.take_events());
+            }
+            // Keepalive probes went unanswered: the client is gone, and
+            // what it sent so far is finished as its job
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                println!("Closing {}: the client stopped responding", addr);
+                break;
             }
             Err(e) => {
                 eprintln!("Error reading from socket: {}", e);
@@ -2486,41 +3891,192 @@ async fn handle_client(
         }
     }
 
+    if let Err(e) = jobs.finish(&mut renderer) {
+        eprintln!("Error processing data: {}", e);
+    }
+    job.record(&state, hooks.as_deref(), &source, jobs.take_events());
+    state.audit(AuditEvent::Disconnected {
+        source: &source,
+        bytes: jobs.bytes(),
+        duration_ms: unix_millis().saturating_sub(connected_at),
+    });
+
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let debug = std::env::var("DEBUG").is_ok();
-    let state = AppState::new();
-    let state_clone = state.clone();
+/// What the app keeps of the job a connection is receiving, beside what
+/// the JobSplitter does
+#[derive(Default)]
+struct OpenJob {
+    // Kept for AppState::save_job_images while image dumping is enabled
+    images: Vec<ReceiptElement>,
+    // See AppState::append_elements
+    receipt: Option<u64>,
+    // Whether the connection has printed anything yet (see
+    // AppState::auto_clear); not reset between its jobs
+    printed: bool,
+}
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            let listener = match TcpListener::bind("0.0.0.0:9100").await {
-                Ok(listener) => listener,
-                Err(e) => {
-                    eprintln!("ERROR: Failed to bind to port 9100: {}", e);
-                    eprintln!("Port 9100 is already in use. Please:");
-                    eprintln!("  1. Stop any other escpresso instances");
-                    eprintln!("  2. Check for other applications using port 9100:");
-                    eprintln!("     lsof -i :9100");
-                    eprintln!("     netstat -tulpn | grep 9100");
-                    std::process::exit(1);
-                }
-            };
-            println!("TCP Server listening on 0.0.0.0:9100");
-            if debug {
-                eprintln!("[DEBUG] Debug mode enabled");
+impl OpenJob {
+    /// Add the elements printed to the receipt and record the jobs finished
+    fn record(
+        &mut self,
+        state: &AppState,
+        hooks: Option<&ScriptHooks>,
+        source: &str,
+        events: Vec<JobEvent>,
+    ) {
+        for event in events {
+            match event {
+                JobEvent::Elements(elements) => self.add_elements(state, source, elements),
+                JobEvent::Finished(job) => self.finish(state, hooks, source, job),
+            }
+        }
+    }
+
+    fn add_elements(&mut self, state: &AppState, source: &str, elements: Vec<ReceiptElement>) {
+        if !std::mem::replace(&mut self.printed, true) && *state.auto_clear.lock().unwrap() {
+            state.archive_and_clear();
+        }
+        if state.image_dump_enabled() {
+            let images = elements.iter().filter(|e| dump::is_image(e)).cloned();
+            self.images.extend(images);
+        }
+        state.append_elements(&mut self.receipt, source, elements);
+    }
+
+    /// Run the end-of-job hook, record the job and start the next one
+    fn finish(
+        &mut self,
+        state: &AppState,
+        hooks: Option<&ScriptHooks>,
+        source: &str,
+        job: FinishedJob,
+    ) {
+        let mut tags = Vec::new();
+        if let Some(hooks) = hooks {
+            tags = hooks.on_job_end(&JobSummary {
+                source,
+                bytes: job.raw.len(),
+                elements: job.elements,
+            });
+            if !tags.is_empty() {
+                println!("Job from {} tagged: {}", source, tags.join(", "));
+                state.append_elements(
+                    &mut self.receipt,
+                    source,
+                    vec![ReceiptElement::JobTags { tags: tags.clone() }],
+                );
+            }
+        }
+
+        let images = std::mem::take(&mut self.images);
+        self.receipt = None;
+        if !job.raw.is_empty() {
+            let id = state.add_job(
+                source.to_string(),
+                job.raw,
+                job.warnings,
+                job.received_at,
+                job.arrivals,
+            );
+            if !tags.is_empty() {
+                state.annotate_job(id, String::new(), tags);
             }
+            let images = state.save_job_images(id, &images);
+            state.audit_job(id, job.elements, &job.commands, images.as_deref());
+        }
+    }
+}
+
+/// Accept connections until the listener settings change, then stop accepting,
+/// drain the open connections and rebind with the new settings
+async fn run_server(state: AppState, debug: bool) {
+    let mut settings_rx = state.listener.subscribe();
+    let mut first_bind = true;
+
+    loop {
+        let settings = settings_rx.borrow_and_update().clone();
+        let bind_addrs = settings.socket_addrs();
+        let mut bind_addr = bind_addrs.join(", ");
+        if settings.protocol == Protocol::Rfc2217 {
+            bind_addr.push_str(" (RFC 2217)");
+        }
 
-            loop {
-                match listener.accept().await {
+        let listeners = match bind_all(&bind_addrs).await {
+            Ok(listeners) => listeners,
+            Err((addr, e)) if first_bind => {
+                eprintln!("ERROR: Failed to bind to {}: {}", addr, e);
+                eprintln!("Port {} is already in use. Please:", settings.port);
+                eprintln!("  1. Stop any other escpresso instances");
+                eprintln!(
+                    "  2. Check for other applications using port {}:",
+                    settings.port
+                );
+                eprintln!("     lsof -i :{}", settings.port);
+                eprintln!("     netstat -tulpn | grep {}", settings.port);
+                std::process::exit(1);
+            }
+            Err((addr, e)) => {
+                eprintln!("ERROR: Failed to bind to {}: {}", addr, e);
+                state.set_listener_status(ListenerStatus::Failed(format!(
+                    "Failed to bind to {}: {}",
+                    addr, e
+                )));
+                // Nothing to serve until the user picks different settings
+                if settings_rx.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        println!("TCP Server listening on {}", bind_addr);
+        if debug && first_bind {
+            eprintln!("[DEBUG] Debug mode enabled");
+        }
+        first_bind = false;
+        state.set_listener_status(ListenerStatus::Listening(bind_addr.clone()));
+
+        let mut clients = JoinSet::new();
+        // Set when draining, for the connections to finish their job and close
+        let (closing, _) = watch::channel(false);
+        loop {
+            tokio::select! {
+                accepted = accept_any(&listeners) => match accepted {
                     Ok((socket, addr)) => {
-                        let state = state_clone.clone();
-                        let debug_flag = debug;
-                        tokio::spawn(async move {
-                            if let Err(e) = handle_client(socket, addr, state, debug_flag).await {
+                        if !state.access.lock().unwrap().allows(addr.ip()) {
+                            println!("Rejected connection from {}: not in the allowlist", addr);
+                            state.audit(AuditEvent::Rejected {
+                                source: &addr.to_string(),
+                                reason: "not in the allowlist",
+                            });
+                            drop(socket);
+                            continue;
+                        }
+                        let limits = *state.connection_limits.lock().unwrap();
+                        let max_connections = limits.max_connections;
+                        let open = state.connections.lock().unwrap().len();
+                        if max_connections > 0 && open >= max_connections {
+                            println!(
+                                "Rejected connection from {}: {} connection(s) open (limit {})",
+                                addr, open, max_connections
+                            );
+                            state.audit(AuditEvent::Rejected {
+                                source: &addr.to_string(),
+                                reason: "connection limit reached",
+                            });
+                            drop(socket);
+                            continue;
+                        }
+                        if limits.keepalive > 0 {
+                            if let Err(e) = set_keepalive(&socket, limits.keepalive) {
+                                eprintln!("ERROR: Failed to enable keepalive for {}: {}", addr, e);
+                            }
+                        }
+                        let state = state.clone();
+                        let closing = closing.subscribe();
+                        clients.spawn(async move {
+                            if let Err(e) = handle_client(socket, addr, state, debug, closing).await {
                                 eprintln!("Error handling client {}: {}", addr, e);
                             }
                         });
@@ -2528,23 +4084,475 @@ fn main() -> Result<()> {
                     Err(e) => {
                         eprintln!("Error accepting connection: {}", e);
                     }
+                },
+                // Reap finished connections so the set doesn't grow forever
+                Some(_) = clients.join_next(), if !clients.is_empty() => {}
+                changed = settings_rx.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    break;
+                }
+            }
+        }
+
+        // Stop accepting before draining so the port is released for the rebind
+        drop(listeners);
+        println!(
+            "Listener settings changed, closing {} ({} open connection(s))",
+            bind_addr,
+            clients.len()
+        );
+        state.set_listener_status(ListenerStatus::Draining(clients.len()));
+
+        // Connections end their job with what they received so far; only
+        // those that don't respond in time, like ones stuck writing to a
+        // client that stopped reading, are aborted
+        closing.send_replace(true);
+        let drain = async { while clients.join_next().await.is_some() {} };
+        if tokio::time::timeout(LISTENER_DRAIN_TIMEOUT, drain)
+            .await
+            .is_err()
+        {
+            eprintln!(
+                "Aborting {} connection(s) still open after {}s",
+                clients.len(),
+                LISTENER_DRAIN_TIMEOUT.as_secs()
+            );
+            clients.shutdown().await;
+        }
+    }
+}
+
+/// Open the session directory and restore whatever the previous run left there
+fn open_session(state: &AppState, dir: &std::path::Path) -> Result<Session> {
+    let session = Session::open(dir)?;
+    if let Some(restored) = session.restore()? {
+        println!(
+            "Restored {} job(s) and {} element(s) from {}",
+            restored.jobs.len(),
+            restored.elements.len(),
+            session.dir().display()
+        );
+        *state.paper_size.lock().unwrap() = restored.paper_size;
+        *state.jobs.lock().unwrap() = restored.jobs;
+        state.job_ids.store(restored.last_job_id, Ordering::Relaxed);
+        // New receipts must not reuse the ids of restored ones
+        let last_receipt = restored
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                ReceiptElement::ReceiptStart { id, .. } => Some(*id),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        state.receipt_ids.store(last_receipt, Ordering::Relaxed);
+        *state.elements.lock().unwrap() = restored.elements;
+        *state.element_times.lock().unwrap() = restored.element_times;
+    }
+    Ok(session)
+}
+
+/// The drawer log as CSV, oldest kick first
+fn drawer_log_csv(log: &[DrawerKick]) -> String {
+    let mut csv = String::from("time_utc,command,pin,on_ms,off_ms,source\n");
+    for kick in log {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            format_timestamp(kick.at),
+            kick.pulse.command,
+            kick.pulse.pin,
+            kick.pulse.on_ms,
+            kick.pulse.off_ms,
+            kick.source
+        ));
+    }
+    csv
+}
+
+/// "YYYY-MM-DD HH:MM:SS" in UTC, for times from unix_millis (0 = unknown)
+fn format_timestamp(millis: u64) -> String {
+    if millis == 0 {
+        return "unknown".to_string();
+    }
+    let secs = millis / 1000;
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Columns per line of Fonts A, B and C written "A/B/C", e.g. "42/56".
+/// Fonts left out or given 0 keep their own count.
+fn parse_columns(text: &str) -> Result<[u16; 3]> {
+    let mut columns = [0; 3];
+    let parts: Vec<&str> = text.trim().split('/').collect();
+    if parts.len() > columns.len() {
+        anyhow::bail!(
+            "expected at most 3 column counts (Font A/B/C), got '{}'",
+            text
+        );
+    }
+    for (column, part) in columns.iter_mut().zip(parts) {
+        *column = match part.trim() {
+            "" => 0,
+            part => part.parse().ok().filter(|n| *n <= 255).ok_or_else(|| {
+                anyhow::anyhow!("invalid column count '{}', expected 0 to 255", part)
+            })?,
+        };
+    }
+    Ok(columns)
+}
+
+/// Numeric setting from the environment, if set and valid
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let text = std::env::var(name).ok()?;
+    let value = text.trim().parse().ok();
+    if value.is_none() {
+        eprintln!("ERROR: Ignoring {}={}, expected a number", name, text);
+    }
+    value
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let watch = cli::window_watch(&args);
+    if !args.is_empty() && watch.is_none() {
+        std::process::exit(cli::run(&args));
+    }
+
+    let debug = std::env::var("DEBUG").is_ok();
+    let mut state = AppState::new();
+
+    if let Ok(dir) = std::env::var("ESCPRESSO_SESSION") {
+        match open_session(&state, std::path::Path::new(&dir)) {
+            Ok(session) => state.session = Some(session.spawn_saver()),
+            Err(e) => eprintln!("ERROR: Session persistence disabled: {:#}", e),
+        }
+    }
+
+    // The audit log is kept in its own file or the session
+    let audit_path = std::env::var_os("ESCPRESSO_AUDIT_LOG")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            let session = state.session.as_ref()?;
+            Some(session.dir().join("audit.jsonl"))
+        });
+    if let Some(path) = audit_path {
+        match AuditLog::open(&path) {
+            Ok(audit) => {
+                println!("Audit log: {}", audit.path().display());
+                state.audit = Some(Arc::new(audit));
+            }
+            Err(e) => eprintln!("ERROR: Audit log disabled: {:#}", e),
+        }
+    }
+
+    if let Ok(url) = std::env::var("ESCPRESSO_EVENTS") {
+        match url.parse::<events::Target>() {
+            Ok(target) => _ = state.event_target.send_replace(Some(target)),
+            Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_EVENTS: {:#}", e),
+        }
+    }
+    // The configuration file can name a target later on
+    let config_path = std::env::var_os("ESCPRESSO_CONFIG").map(std::path::PathBuf::from);
+    let publish_events = state.event_target.borrow().is_some() || config_path.is_some();
+    let event_receiver = publish_events.then(|| {
+        let (bus, receiver) = EventBus::new();
+        state.events = Some(bus);
+        receiver
+    });
+
+    // NV user memory survives restarts in its own file or the session
+    let nv_memory_path = std::env::var_os("ESCPRESSO_NV_MEMORY")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            let session = state.session.as_ref()?;
+            Some(session.dir().join("nv-memory.json"))
+        });
+    if let Some(path) = nv_memory_path {
+        match NvMemory::open(&path) {
+            Ok(memory) => *state.nv_memory.lock().unwrap() = memory,
+            Err(e) => eprintln!("ERROR: NV user memory won't be saved: {:#}", e),
+        }
+    }
+
+    // So do the settings saved with GS ( M
+    let custom_values_path = std::env::var_os("ESCPRESSO_CUSTOM_VALUES")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            let session = state.session.as_ref()?;
+            Some(session.dir().join("custom-values.json"))
+        });
+    if let Some(path) = custom_values_path {
+        match CustomValues::open(&path) {
+            Ok(values) => *state.custom_values.lock().unwrap() = values,
+            Err(e) => eprintln!("ERROR: Customized values won't be saved: {:#}", e),
+        }
+    }
+
+    if let Ok(path) = std::env::var("ESCPRESSO_SCRIPT") {
+        match ScriptHooks::load(std::path::Path::new(&path)) {
+            Ok(hooks) => {
+                println!("Loaded hooks script {}", path);
+                *state.hooks.lock().unwrap() = Some(Arc::new(hooks));
+            }
+            Err(e) => eprintln!("ERROR: Failed to load hooks script: {}", e),
+        }
+    }
+
+    if let Some(code_page) = env_number("ESCPRESSO_CODE_PAGE") {
+        state
+            .simulated_status
+            .send_modify(|status| status.code_page = code_page);
+    }
+    if let Ok(text) = std::env::var("ESCPRESSO_FORCE_CODE_PAGE") {
+        match codepages::parse_forced_code_pages(&text) {
+            Ok(rules) => *state.forced_code_pages.lock().unwrap() = rules,
+            Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_FORCE_CODE_PAGE: {:#}", e),
+        }
+    }
+    // Clone printers' own tables, ESCPRESSO_CODE_PAGE_<n>=<mapping file>
+    for (name, path) in std::env::vars_os() {
+        let Some(number) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("ESCPRESSO_CODE_PAGE_"))
+        else {
+            continue;
+        };
+        let Ok(number) = number.parse::<u8>() else {
+            eprintln!(
+                "ERROR: Ignoring {:?}: expected a code page number 0 to 255",
+                name
+            );
+            continue;
+        };
+        if let Err(e) = state.load_code_page(number, std::path::Path::new(&path)) {
+            eprintln!("ERROR: Ignoring {:?}: {:#}", name, e);
+        }
+    }
+    if let Ok(text) = std::env::var("ESCPRESSO_COLUMNS") {
+        match parse_columns(&text) {
+            Ok(columns) => state
+                .simulated_status
+                .send_modify(|status| status.columns = columns),
+            Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_COLUMNS: {:#}", e),
+        }
+    }
+    match std::env::var("ESCPRESSO_SO_DOUBLE_WIDTH").as_deref() {
+        Ok("on") => state
+            .simulated_status
+            .send_modify(|status| status.so_double_width = true),
+        Ok("off") | Err(_) => {}
+        Ok(other) => eprintln!(
+            "ERROR: Ignoring ESCPRESSO_SO_DOUBLE_WIDTH: expected on or off, got '{}'",
+            other
+        ),
+    }
+
+    if let Some(length) = env_number("ESCPRESSO_PAPER_ROLL") {
+        state.paper_roll.lock().unwrap().length_m = length;
+    }
+
+    {
+        let mut capture = state.capture.lock().unwrap();
+        match std::env::var("ESCPRESSO_CAPTURE").as_deref() {
+            Ok("off") => *capture = None,
+            Ok(dir) => {
+                *capture = Some(RawCapture {
+                    dir: dir.into(),
+                    ..RawCapture::default()
+                })
+            }
+            Err(_) => {}
+        }
+        if let (Some(capture), Some(max_mb)) =
+            (capture.as_mut(), env_number("ESCPRESSO_CAPTURE_MAX_MB"))
+        {
+            capture.max_mb = max_mb;
+        }
+    }
+
+    if let Ok(dir) = std::env::var("ESCPRESSO_ARCHIVE") {
+        state.archive.lock().unwrap().dir = dir.into();
+    }
+    match std::env::var("ESCPRESSO_AUTO_CLEAR").as_deref() {
+        Ok("on") => *state.auto_clear.lock().unwrap() = true,
+        Ok("off") | Err(_) => {}
+        Ok(other) => eprintln!(
+            "ERROR: Ignoring ESCPRESSO_AUTO_CLEAR: expected on or off, got '{}'",
+            other
+        ),
+    }
+
+    if let Ok(dir) = std::env::var("ESCPRESSO_IMAGE_DUMP") {
+        *state.image_dump.lock().unwrap() = Some(dir.into());
+    }
+
+    {
+        let mut limits = state.connection_limits.lock().unwrap();
+        if let Some(max) = env_number("ESCPRESSO_MAX_CONNECTIONS") {
+            limits.max_connections = max;
+        }
+        if let Some(secs) = env_number("ESCPRESSO_IDLE_TIMEOUT") {
+            limits.idle_timeout = secs;
+        }
+        if let Some(secs) = env_number("ESCPRESSO_KEEPALIVE") {
+            limits.keepalive = secs;
+        }
+    }
+
+    {
+        let mut limits = state.history_limits.lock().unwrap();
+        if let Some(max) = env_number("ESCPRESSO_MAX_RECEIPTS") {
+            limits.max_receipts = max;
+        }
+        if let Some(max) = env_number("ESCPRESSO_MAX_ELEMENTS") {
+            limits.max_elements = max;
+        }
+    }
+
+    {
+        let mut access = state.access.lock().unwrap();
+        if let Ok(text) = std::env::var("ESCPRESSO_ALLOW") {
+            match access::parse_allowlist(&text) {
+                Ok(allowlist) => access.allowlist = allowlist,
+                Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_ALLOW: {}", e),
+            }
+        }
+        if let Ok(secret) = std::env::var("ESCPRESSO_SECRET") {
+            access.secret = secret;
+        }
+    }
+
+    if let Ok(addresses) = std::env::var("ESCPRESSO_BIND") {
+        state
+            .listener
+            .send_modify(|listener| listener.bind_address = addresses.trim().to_string());
+    }
+
+    if let Ok(text) = std::env::var("ESCPRESSO_PROTOCOL") {
+        match text.parse() {
+            Ok(protocol) => state
+                .listener
+                .send_modify(|listener| listener.protocol = protocol),
+            Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_PROTOCOL: {}", e),
+        }
+    }
+
+    if let Ok(text) = std::env::var("ESCPRESSO_JOB_BOUNDARY") {
+        match text.parse() {
+            Ok(boundary) => *state.job_boundary.lock().unwrap() = boundary,
+            Err(e) => eprintln!("ERROR: Ignoring ESCPRESSO_JOB_BOUNDARY: {}", e),
+        }
+    }
+    match std::env::var("ESCPRESSO_INIT").as_deref() {
+        Ok("receipt") => *state.init_new_receipt.lock().unwrap() = true,
+        Ok("reset") | Err(_) => {}
+        Ok(other) => eprintln!(
+            "ERROR: Ignoring ESCPRESSO_INIT: expected reset or receipt, got '{}'",
+            other
+        ),
+    }
+
+    if let Some((path, paper_size)) = watch {
+        *state.paper_size.lock().unwrap() = paper_size;
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let name = path.display().to_string();
+            cli::watch_file(&path, |data| {
+                // The file replaces the receipt each time it's saved
+                state.clear_receipt();
+                if let Err(e) = state.print_local(&format!("file: {}", name), data) {
+                    eprintln!("ERROR: Failed to show {}: {:#}", name, e);
                 }
+            })
+        });
+    }
+
+    // Applied over the environment variables, then again on every change
+    if let Some(path) = config_path {
+        let config = config::Config::load(&state, path);
+        let state = state.clone();
+        std::thread::spawn(move || config.watch(state));
+    }
+
+    let state_clone = state.clone();
+    let admin_addr = std::env::var("ESCPRESSO_ADMIN").ok();
+    let headless = match std::env::var("ESCPRESSO_HEADLESS").as_deref() {
+        Ok("on") => true,
+        Ok("off") | Err(_) => false,
+        Ok(other) => {
+            eprintln!(
+                "ERROR: Ignoring ESCPRESSO_HEADLESS: expected on or off, got '{}'",
+                other
+            );
+            false
+        }
+    };
+
+    let server = std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            if let Some(addr) = admin_addr {
+                tokio::spawn(admin_api::run(addr, state_clone.clone()));
+            }
+            tokio::spawn(run_status_scheduler(state_clone.clone()));
+            if let (Some(receiver), Some(bus)) = (event_receiver, state_clone.events.clone()) {
+                tokio::spawn(events::run(state_clone.event_target.subscribe(), receiver));
+                tokio::spawn(events::publish_errors(
+                    bus,
+                    state_clone.simulated_status.subscribe(),
+                ));
             }
+            run_server(state_clone, debug).await;
         });
     });
 
+    // Containers and CI have no display: serve until killed
+    if headless {
+        println!("Running without a window");
+        return server
+            .join()
+            .map_err(|_| anyhow::anyhow!("The server stopped unexpectedly"));
+    }
+
     let default_width = PaperSize::Size80mm.width_px();
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([default_width + 40.0, 800.0]) // Receipt width + padding
-            .with_title("escpresso"),
+            .with_title("escpresso")
+            .with_drag_and_drop(true),
         ..Default::default()
     };
 
-    eframe::run_native(
+    let session = state.session.clone();
+    let result = eframe::run_native(
         "escpresso",
         options,
         Box::new(move |cc| Ok(Box::new(VirtualEscPosApp::new(cc, state)))),
     )
-    .map_err(|e| anyhow::anyhow!("Failed to run app: {}", e))
+    .map_err(|e| anyhow::anyhow!("Failed to run app: {}", e));
+    // The last changes may still be on their way to the session
+    if let Some(session) = session {
+        session.flush();
+    }
+    result
 }
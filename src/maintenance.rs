@@ -0,0 +1,88 @@
+// Maintenance counters, as printers keep them for service tooling: paper fed,
+// lines printed and autocutter operations. Each has a value that GS g 0 (or
+// the Reset button) clears and a cumulative one that is never cleared.
+//
+// GS g 2 counter numbers (cumulative = resettable + 128):
+//
+//   20 / 148   paper fed, in lines of 1/6 inch
+//   21 / 149   lines printed
+//   50 / 178   autocutter operations
+
+use crate::{paper_roll, ReceiptElement};
+use serde::Serialize;
+
+/// Millimeters in a line of the default 1/6 inch spacing
+const LINE_MM: f32 = 25.4 / 6.0;
+
+/// Counter numbers that GS g 0 can reset
+const RESETTABLE: [u16; 3] = [20, 21, 50];
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Counter<T> {
+    pub since_reset: T,
+    pub total: T,
+}
+
+impl<T: Copy + Default + std::ops::AddAssign> Counter<T> {
+    fn add(&mut self, n: T) {
+        self.since_reset += n;
+        self.total += n;
+    }
+
+    /// The resettable value (n < 128) or the cumulative one
+    fn get(&self, n: u16) -> T {
+        if n < 128 {
+            self.since_reset
+        } else {
+            self.total
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MaintenanceCounters {
+    pub fed_mm: Counter<f32>,
+    pub lines_printed: Counter<u64>,
+    pub cuts: Counter<u64>,
+}
+
+impl MaintenanceCounters {
+    /// Count what printing the elements takes
+    pub fn count(&mut self, elements: &[ReceiptElement]) {
+        self.fed_mm.add(paper_roll::feed_mm(elements));
+        for element in elements {
+            match element {
+                ReceiptElement::Text { .. } => self.lines_printed.add(1),
+                ReceiptElement::PaperCut { .. } => self.cuts.add(1),
+                _ => {}
+            }
+        }
+    }
+
+    /// Value of GS g 2 counter n, None for counters that aren't kept
+    pub fn value(&self, n: u16) -> Option<u64> {
+        match n {
+            20 | 148 => Some((self.fed_mm.get(n) / LINE_MM) as u64),
+            21 | 149 => Some(self.lines_printed.get(n)),
+            50 | 178 => Some(self.cuts.get(n)),
+            _ => None,
+        }
+    }
+
+    /// GS g 0: clear resettable counter n. Returns false for other numbers.
+    pub fn reset(&mut self, n: u16) -> bool {
+        match n {
+            20 => self.fed_mm.since_reset = 0.0,
+            21 => self.lines_printed.since_reset = 0,
+            50 => self.cuts.since_reset = 0,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn reset_all(&mut self) {
+        for n in RESETTABLE {
+            self.reset(n);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// NV user memory (GS ( C): records of bytes under a two-character key code
+// that POS applications use to keep settings on the printer. Like the
+// printer's flash, the memory is shared by all connections and, given a
+// file, survives restarts.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// User NV memory size of a TM-T88 class printer
+pub const CAPACITY: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct NvMemory {
+    /// Records by key code (c1 c2), kept as text since both are printable
+    records: BTreeMap<String, Vec<u8>>,
+    /// Saved after every change when set
+    path: Option<PathBuf>,
+}
+
+impl NvMemory {
+    /// Memory backed by a JSON file, loaded if it exists
+    pub fn open(path: &Path) -> Result<Self> {
+        let records = if path.exists() {
+            serde_json::from_slice(&std::fs::read(path)?)
+                .with_context(|| format!("parsing {}", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            records,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn records(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.records
+    }
+
+    pub fn used(&self) -> usize {
+        self.records.values().map(Vec::len).sum()
+    }
+
+    pub fn get(&self, key: [u8; 2]) -> Option<&[u8]> {
+        self.records.get(&key_name(key)).map(Vec::as_slice)
+    }
+
+    /// Store a record, replacing the one with the same key. Data that
+    /// doesn't fit is not stored, returning false.
+    pub fn store(&mut self, key: [u8; 2], data: &[u8]) -> bool {
+        let name = key_name(key);
+        let replaced = self.records.get(&name).map_or(0, Vec::len);
+        if self.used() - replaced + data.len() > CAPACITY {
+            return false;
+        }
+        self.records.insert(name, data.to_vec());
+        self.save();
+        true
+    }
+
+    pub fn delete(&mut self, key: [u8; 2]) {
+        if self.records.remove(&key_name(key)).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(&self.records)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                std::fs::write(path, json).with_context(|| format!("writing {}", path.display()))
+            });
+        if let Err(e) = result {
+            eprintln!("ERROR: Failed to save NV memory: {:#}", e);
+        }
+    }
+}
+
+fn key_name(key: [u8; 2]) -> String {
+    String::from_utf8_lossy(&key).into_owned()
+}
@@ -0,0 +1,48 @@
+// Printing the previewed receipt on a real printer through the operating
+// system: the receipt is rendered to a PNG (see headless) and handed to the
+// platform's print command.
+//
+//   Windows        mspaint /p, which shows the print dialog
+//   macOS, Linux   lp (CUPS), to the default printer
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static PRINT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Save the image to a temporary file and send it to the printer
+pub fn print_image(image: &RgbaImage) -> Result<()> {
+    let path = std::env::temp_dir().join(format!(
+        "escpresso-print-{}-{}.png",
+        std::process::id(),
+        PRINT_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    image
+        .save(&path)
+        .with_context(|| format!("writing {}", path.display()))?;
+
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("mspaint");
+        command.arg("/p").arg(&path);
+        command
+    } else {
+        let mut command = Command::new("lp");
+        command.args(["-o", "fit-to-page"]).arg(&path);
+        command
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output();
+    // Spooled (or printed) by now
+    let _ = std::fs::remove_file(&path);
+    let output = output.with_context(|| format!("running {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
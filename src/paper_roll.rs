@@ -0,0 +1,113 @@
+// Paper roll consumption: the length of what's printed is estimated from the
+// receipt elements and used up from a roll of configurable length. The paper
+// near-end and paper-out sensors trip as a real roll runs out, so long-running
+// tests reach paper exhaustion without toggling the flags by hand.
+
+use crate::ReceiptElement;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+
+/// 203 dpi
+const DOTS_PER_MM: f32 = 8.0;
+
+/// Feed of a text line or blank line: the default 1/6 inch line spacing
+const LINE_DOTS: f32 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaperRoll {
+    /// Roll length in meters, 0 = endless (not tracked)
+    pub length_m: f32,
+    /// Paper left on the roll when the near-end sensor trips, in meters
+    pub near_end_m: f32,
+    /// Printed since the roll was loaded, in millimeters
+    pub used_mm: f32,
+}
+
+impl Default for PaperRoll {
+    fn default() -> Self {
+        Self {
+            length_m: 0.0,
+            near_end_m: 2.0,
+            used_mm: 0.0,
+        }
+    }
+}
+
+/// Sensor states after printing on a tracked roll
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sensors {
+    pub near_end: bool,
+    pub paper_out: bool,
+}
+
+impl PaperRoll {
+    pub fn is_tracked(&self) -> bool {
+        self.length_m > 0.0
+    }
+
+    /// Paper left on the roll, in millimeters
+    pub fn remaining_mm(&self) -> f32 {
+        (self.length_m * 1000.0 - self.used_mm).max(0.0)
+    }
+
+    pub fn sensors(&self) -> Sensors {
+        let remaining = self.remaining_mm();
+        Sensors {
+            near_end: remaining <= self.near_end_m * 1000.0,
+            paper_out: remaining <= 0.0,
+        }
+    }
+
+    /// Use up the paper the elements take, returning the sensor states if
+    /// the roll is tracked
+    pub fn consume(&mut self, elements: &[ReceiptElement]) -> Option<Sensors> {
+        if !self.is_tracked() {
+            return None;
+        }
+        self.used_mm += feed_mm(elements);
+        Some(self.sensors())
+    }
+}
+
+/// Paper the elements take, in millimeters
+pub fn feed_mm(elements: &[ReceiptElement]) -> f32 {
+    elements.iter().map(element_length).sum::<f32>() / DOTS_PER_MM
+}
+
+/// Paper feed of an element in dots, roughly as a printer would feed it
+fn element_length(element: &ReceiptElement) -> f32 {
+    match element {
+        ReceiptElement::Text {
+            double_height,
+            height_multiplier,
+            ..
+        } => f32::from((*height_multiplier).max(1 + u8::from(*double_height))) * LINE_DOTS,
+        ReceiptElement::Separator => LINE_DOTS,
+        ReceiptElement::RasterImage { height, .. } => *height as f32,
+        ReceiptElement::QrCode { data, size, .. } => QrCode::new(data.as_bytes())
+            .map_or(0.0, |qr| (qr.width() * (*size).max(1)) as f32 + LINE_DOTS),
+        ReceiptElement::Barcode {
+            widths,
+            module_width,
+            height,
+            hri_position,
+            rotated,
+            ..
+        } => {
+            let bars = if *rotated {
+                // Printed along the paper
+                widths.iter().map(|&w| f32::from(w)).sum::<f32>() * f32::from(*module_width) / 2.0
+            } else {
+                f32::from(*height)
+            };
+            let hri_lines = match hri_position {
+                1 | 2 => 1.0,
+                3 => 2.0,
+                _ => 0.0,
+            };
+            bars + hri_lines * LINE_DOTS
+        }
+        _ => 0.0,
+    }
+}
@@ -0,0 +1,318 @@
+// PDF export: a page per receipt, each under a header of metadata lines
+// (job, source, time, tags and note), for review packets. Written by hand, as
+// the pages hold nothing but an image and a few lines in a standard font:
+//
+//   1 catalog, 2 page tree, 3 Helvetica, 4 Helvetica-Bold,
+//   then per page: the page, its content stream and its image
+//
+// Pages are as wide as the receipt plus margins and as tall as it needs. A
+// job that cuts the paper gets a page per piece, as it comes out of the
+// printer: pages break at full cuts, and partial cuts, which leave the
+// receipts attached, are drawn as perforations within a page.
+// Header text is encoded as WinAnsi; characters outside it print as "?".
+
+use crate::{
+    format_timestamp, parse_job, Job, PaperSize, ParseOptions, ReceiptElement, DOTS_PER_INCH,
+};
+use anyhow::Result;
+use encoding_rs::WINDOWS_1252;
+use flate2::write::ZlibEncoder;
+use image::RgbaImage;
+use std::io::Write;
+
+/// Around the header and the receipt, in points
+const MARGIN: f64 = 18.0;
+const FONT_SIZE: f64 = 8.0;
+const LEADING: f64 = 10.0;
+/// Pages are at least this wide so headers stay readable next to narrow
+/// receipts: 80 mm
+const MIN_WIDTH: f64 = 226.8;
+/// Average Helvetica character width, in font sizes, to wrap headers
+const CHAR_WIDTH: f64 = 0.55;
+
+const FIRST_PAGE_OBJECT: usize = 5;
+
+pub struct Page {
+    /// The first line is set in bold
+    pub header: Vec<String>,
+    pub image: RgbaImage,
+}
+
+/// The jobs, each parsed again on its own, a page per receipt
+pub fn jobs_pdf(jobs: &[Job], options: &ParseOptions) -> Result<Vec<u8>> {
+    let mut pages = Vec::new();
+    for job in jobs {
+        pages.extend(job_pages(job, options)?);
+    }
+    write_pdf(&pages, DOTS_PER_INCH)
+}
+
+/// A recorded job parsed again on its own, with its metadata as header
+fn job_pages(job: &Job, options: &ParseOptions) -> Result<Vec<Page>> {
+    let parsed = parse_job(&job.raw, &options.for_source(&job.source))?;
+    let mut header = vec![
+        format!("Job {} from {}", job.id, job.source),
+        format!(
+            "{} UTC, {} bytes, {} warning(s)",
+            format_timestamp(job.received_at),
+            job.raw.len(),
+            job.warnings.len()
+        ),
+    ];
+    if !job.tags.is_empty() {
+        header.push(format!("Tags: {}", job.tags.join(", ")));
+    }
+    header.extend(job.note.lines().map(str::to_string));
+    Ok(receipt_pages(&header, &parsed.elements, options.paper_size))
+}
+
+/// A page per receipt the elements' full cuts separate, each under the
+/// header, its first line numbering the receipts when there are several
+pub fn receipt_pages(
+    header: &[String],
+    elements: &[ReceiptElement],
+    paper_size: PaperSize,
+) -> Vec<Page> {
+    let receipts = split_at_full_cuts(elements);
+    let count = receipts.len();
+    receipts
+        .into_iter()
+        .enumerate()
+        .map(|(index, receipt)| {
+            let mut header = header.to_vec();
+            if count > 1 {
+                if let Some(first) = header.first_mut() {
+                    first.push_str(&format!(", receipt {} of {}", index + 1, count));
+                }
+            }
+            let image = crate::render::render_elements_to_image(receipt, paper_size, DOTS_PER_INCH);
+            Page { header, image }
+        })
+        .collect()
+}
+
+/// The elements between full cuts, without the cuts and the empty pieces;
+/// at least one, so a job that prints nothing still gets its page
+fn split_at_full_cuts(elements: &[ReceiptElement]) -> Vec<&[ReceiptElement]> {
+    let full_cut = |element: &ReceiptElement| matches!(element, ReceiptElement::PaperCut { cut_type } if !cut_type.contains("PARTIAL"));
+    let receipts: Vec<_> = elements
+        .split(full_cut)
+        .filter(|receipt| !receipt.is_empty())
+        .collect();
+    if receipts.is_empty() {
+        vec![elements]
+    } else {
+        receipts
+    }
+}
+
+/// The pages as a PDF document, images rendered at `dpi`
+pub fn write_pdf(pages: &[Page], dpi: u32) -> Result<Vec<u8>> {
+    let mut pdf = Writer::default();
+    pdf.out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", FIRST_PAGE_OBJECT + 3 * i))
+        .collect();
+    pdf.object(1, b"<< /Type /Catalog /Pages 2 0 R >>");
+    pdf.object(
+        2,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .as_bytes(),
+    );
+    for (number, font) in [(3, "Helvetica"), (4, "Helvetica-Bold")] {
+        pdf.object(
+            number,
+            format!(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                font
+            )
+            .as_bytes(),
+        );
+    }
+
+    let points = |pixels: u32| f64::from(pixels) * 72.0 / f64::from(dpi);
+    for (i, page) in pages.iter().enumerate() {
+        let number = FIRST_PAGE_OBJECT + 3 * i;
+        let (image_width, image_height) = (points(page.image.width()), points(page.image.height()));
+        let width = (image_width + 2.0 * MARGIN).max(MIN_WIDTH);
+        let columns = ((width - 2.0 * MARGIN) / (FONT_SIZE * CHAR_WIDTH)) as usize;
+        let lines: Vec<(usize, String)> = page
+            .header
+            .iter()
+            .enumerate()
+            .flat_map(|(index, line)| {
+                let font = if index == 0 { 4 } else { 3 };
+                wrap(line, columns)
+                    .into_iter()
+                    .map(move |line| (font, line))
+            })
+            .collect();
+        let header_height = lines.len() as f64 * LEADING;
+        let height = MARGIN + header_height + MARGIN / 2.0 + image_height + MARGIN;
+
+        let mut content = String::from("BT\n");
+        let mut y = height - MARGIN - FONT_SIZE;
+        for (font, line) in &lines {
+            content.push_str(&format!(
+                "/F{} {} Tf 1 0 0 1 {:.2} {:.2} Tm ({}) Tj\n",
+                font,
+                FONT_SIZE,
+                MARGIN,
+                y,
+                escape(line)
+            ));
+            y -= LEADING;
+        }
+        content.push_str("ET\n");
+        // The receipt, centered, with a hairline around its paper
+        let x = (width - image_width) / 2.0;
+        content.push_str(&format!(
+            "q {w:.2} 0 0 {h:.2} {x:.2} {y:.2} cm /Im0 Do Q\n\
+             0.75 G 0.5 w {x:.2} {y:.2} {w:.2} {h:.2} re S\n",
+            w = image_width,
+            h = image_height,
+            x = x,
+            y = MARGIN,
+        ));
+
+        pdf.object(
+            number,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+                 /Resources << /Font << /F3 3 0 R /F4 4 0 R >> /XObject << /Im0 {} 0 R >> >> \
+                 /Contents {} 0 R >>",
+                width,
+                height,
+                number + 2,
+                number + 1
+            )
+            .as_bytes(),
+        );
+        pdf.stream(number + 1, "", content.as_bytes())?;
+        // RGB over white paper; the receipt has no transparency worth keeping
+        let rgb: Vec<u8> = page
+            .image
+            .pixels()
+            .flat_map(|pixel| {
+                let [r, g, b, a] = pixel.0.map(u32::from);
+                let over_white = |c: u32| ((c * a + 255 * (255 - a)) / 255) as u8;
+                [over_white(r), over_white(g), over_white(b)]
+            })
+            .collect();
+        pdf.stream(
+            number + 2,
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} \
+                 /ColorSpace /DeviceRGB /BitsPerComponent 8",
+                page.image.width(),
+                page.image.height()
+            ),
+            &rgb,
+        )?;
+    }
+    Ok(pdf.finish())
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    /// Byte offset of each object, by number - 1
+    offsets: Vec<usize>,
+}
+
+impl Writer {
+    fn object(&mut self, number: usize, body: &[u8]) {
+        if self.offsets.len() < number {
+            self.offsets.resize(number, 0);
+        }
+        self.offsets[number - 1] = self.out.len();
+        self.out
+            .extend_from_slice(format!("{} 0 obj\n", number).as_bytes());
+        self.out.extend_from_slice(body);
+        self.out.extend_from_slice(b"\nendobj\n");
+    }
+
+    /// A compressed stream object, `dictionary` holding the entries besides
+    /// its length and filter
+    fn stream(&mut self, number: usize, dictionary: &str, data: &[u8]) -> Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        let mut body = format!(
+            "<< {} /Length {} /Filter /FlateDecode >>\nstream\n",
+            dictionary,
+            compressed.len()
+        )
+        .into_bytes();
+        body.extend_from_slice(&compressed);
+        body.extend_from_slice(b"\nendstream");
+        self.object(number, &body);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let xref = self.out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            table.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        table.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            xref
+        ));
+        self.out.extend_from_slice(table.as_bytes());
+        self.out
+    }
+}
+
+/// Break a line at spaces so it fits `columns` characters, cutting words
+/// longer than a line
+fn wrap(line: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(1);
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in line.split(' ') {
+        let length = current.chars().count();
+        if length > 0 && length + 1 + word.chars().count() > columns {
+            lines.push(std::mem::take(&mut current));
+        } else if length > 0 {
+            current.push(' ');
+        }
+        for c in word.chars() {
+            if current.chars().count() >= columns {
+                lines.push(std::mem::take(&mut current));
+            }
+            current.push(c);
+        }
+    }
+    lines.push(current);
+    lines
+}
+
+/// A PDF literal string body in WinAnsi
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        let mut buffer = [0; 4];
+        let (bytes, _, unmappable) = WINDOWS_1252.encode(c.encode_utf8(&mut buffer));
+        match bytes.first() {
+            _ if unmappable => escaped.push('?'),
+            Some(&byte @ (b'(' | b')' | b'\\')) => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            Some(&byte) if byte.is_ascii() && !byte.is_ascii_control() => {
+                escaped.push(byte as char)
+            }
+            Some(&byte) => escaped.push_str(&format!("\\{:03o}", byte)),
+            None => {}
+        }
+    }
+    escaped
+}
@@ -0,0 +1,113 @@
+// PJL (Printer Job Language) wrapping, as sent by Windows drivers that
+// treat the printer like an office one: the job starts with the Universal
+// Exit Language sequence and a few PJL command lines, the ESC/POS payload
+// follows, and another UEL with more PJL ends it:
+//
+//   ESC %-12345X @PJL JOB NAME="Receipt" LF
+//   @PJL INFO STATUS LF
+//   @PJL ENTER LANGUAGE=ESCPOS LF
+//   ...ESC/POS...
+//   ESC %-12345X @PJL EOJ LF ESC %-12345X
+//
+// After a UEL, lines starting with @PJL are PJL commands; ENTER LANGUAGE or
+// anything else starts the payload. INFO and ECHO are answered the way HP
+// printers do: the query line echoed, the answer, then FF. Other commands
+// are skipped.
+
+use crate::SimulatedStatus;
+
+/// Universal Exit Language: leaves the printer language for PJL
+pub const UEL: &[u8] = b"\x1b%-12345X";
+
+const PREFIX: &[u8] = b"@PJL";
+
+/// What the data at the start of the buffer is, after a UEL
+pub enum Next<'a> {
+    /// Not all there yet
+    Incomplete,
+    /// Another UEL, `UEL.len()` bytes
+    Uel,
+    /// A PJL command line of `len` bytes, its line end included
+    Command { line: &'a str, len: usize },
+    /// The printer language starts here
+    Payload,
+}
+
+pub fn next(data: &[u8]) -> Next<'_> {
+    if data.starts_with(UEL) {
+        return Next::Uel;
+    }
+    if is_prefix(data, UEL) || is_prefix(data, PREFIX) {
+        return Next::Incomplete;
+    }
+    if !data[..PREFIX.len().min(data.len())].eq_ignore_ascii_case(PREFIX) {
+        return Next::Payload;
+    }
+    match data.iter().position(|&byte| byte == b'\n') {
+        Some(end) => Next::Command {
+            line: std::str::from_utf8(&data[..end])
+                .unwrap_or_default()
+                .trim_end_matches('\r'),
+            len: end + 1,
+        },
+        None => Next::Incomplete,
+    }
+}
+
+/// Whether `data` is shorter than `full` and its start
+fn is_prefix(data: &[u8], full: &[u8]) -> bool {
+    data.len() < full.len() && full[..data.len()].eq_ignore_ascii_case(data)
+}
+
+/// Whether the command hands over to the printer language
+pub fn enters_language(line: &str) -> bool {
+    words(line).get(1).is_some_and(|word| word == "ENTER")
+}
+
+/// The answer to an INFO or ECHO command, if it has one
+pub fn response(line: &str, status: &SimulatedStatus) -> Option<Vec<u8>> {
+    let words = words(line);
+    let body = match words.get(1).map(String::as_str)? {
+        "ECHO" => String::new(),
+        "INFO" => match words.get(2).map(String::as_str)? {
+            "ID" => "\"escpresso ESC/POS\"\r\n".to_string(),
+            "STATUS" => {
+                let (code, display) = status_code(status);
+                format!(
+                    "CODE={}\r\nDISPLAY=\"{}\"\r\nONLINE={}\r\n",
+                    code,
+                    display,
+                    if status.offline { "FALSE" } else { "TRUE" }
+                )
+            }
+            "CONFIG" => "LANGUAGES [1 ENUMERATED]\r\n\tESCPOS\r\n".to_string(),
+            "MEMORY" => "TOTAL=4096\r\nLARGEST=4096\r\n".to_string(),
+            "PAGECOUNT" => "0\r\n".to_string(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(format!("{}\r\n{}\x0c", line, body).into_bytes())
+}
+
+/// HP status code and display text for the printer's condition
+fn status_code(status: &SimulatedStatus) -> (u32, &'static str) {
+    if status.cover_open {
+        (40021, "COVER OPEN")
+    } else if status.paper_out {
+        (41000, "LOAD PAPER")
+    } else if status.cutter_error || status.unrecoverable_error {
+        (40079, "PRINTER ERROR")
+    } else if status.offline {
+        (10002, "OFFLINE")
+    } else {
+        (10001, "READY")
+    }
+}
+
+/// The command's words in upper case, without the @PJL prefix's case
+fn words(line: &str) -> Vec<String> {
+    line.split_whitespace()
+        .map(|word| word.to_ascii_uppercase())
+        .collect()
+}
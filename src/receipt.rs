@@ -0,0 +1,180 @@
+// Receipt builder: ESC/POS bytes written as method calls instead of by hand,
+// for the demo jobs and for tests that feed the parser input they know the
+// rendering of.
+//
+//   let bytes = Receipt::new()
+//       .center()
+//       .bold()
+//       .line("escpresso")
+//       .normal()
+//       .qr("https://github.com/jflaflamme/escpresso")
+//       .cut()
+//       .build();
+//
+// Styles stay in effect until `normal()`, like on the printer.
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+
+/// ESC ! bits
+const FONT_B: u8 = 0x01;
+const BOLD: u8 = 0x08;
+const DOUBLE_HEIGHT: u8 = 0x10;
+const DOUBLE_WIDTH: u8 = 0x20;
+const UNDERLINE: u8 = 0x80;
+
+pub struct Receipt {
+    bytes: Vec<u8>,
+    /// Print mode last sent with ESC !
+    mode: u8,
+    inverted: bool,
+}
+
+impl Default for Receipt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receipt {
+    /// Starts with ESC @, like drivers do
+    pub fn new() -> Self {
+        Self {
+            bytes: vec![ESC, b'@'],
+            mode: 0,
+            inverted: false,
+        }
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Bytes sent as they are, for commands the builder has no method for
+    pub fn raw(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    /// Text without a line feed. Only ASCII prints the same in every code
+    /// page; see `encoded_line` for the rest.
+    pub fn text(self, text: &str) -> Self {
+        self.raw(text.as_bytes())
+    }
+
+    pub fn line(self, text: &str) -> Self {
+        self.text(text).raw(b"\n")
+    }
+
+    /// A line of text in a code page, selected with ESC t
+    pub fn encoded_line(
+        self,
+        table: u8,
+        encoding: &'static encoding_rs::Encoding,
+        text: &str,
+    ) -> Self {
+        let (bytes, _, _) = encoding.encode(text);
+        self.raw(&[ESC, b't', table]).raw(&bytes).raw(b"\n")
+    }
+
+    pub fn left(self) -> Self {
+        self.raw(&[ESC, b'a', 0])
+    }
+
+    pub fn center(self) -> Self {
+        self.raw(&[ESC, b'a', 1])
+    }
+
+    pub fn right(self) -> Self {
+        self.raw(&[ESC, b'a', 2])
+    }
+
+    /// Continue the line at a distance from the left margin (ESC $)
+    pub fn position(self, dots: u16) -> Self {
+        let [low, high] = dots.to_le_bytes();
+        self.raw(&[ESC, b'$', low, high])
+    }
+
+    pub fn bold(self) -> Self {
+        self.mode(BOLD)
+    }
+
+    pub fn underline(self) -> Self {
+        self.mode(UNDERLINE)
+    }
+
+    pub fn double_width(self) -> Self {
+        self.mode(DOUBLE_WIDTH)
+    }
+
+    pub fn double_height(self) -> Self {
+        self.mode(DOUBLE_HEIGHT)
+    }
+
+    pub fn font_b(self) -> Self {
+        self.mode(FONT_B)
+    }
+
+    /// White on black (GS B)
+    pub fn inverted(mut self) -> Self {
+        self.inverted = true;
+        self.raw(&[GS, b'B', 1])
+    }
+
+    /// Back to plain text
+    pub fn normal(mut self) -> Self {
+        self.mode = 0;
+        if std::mem::take(&mut self.inverted) {
+            self = self.raw(&[GS, b'B', 0]);
+        }
+        self.raw(&[ESC, b'!', 0])
+    }
+
+    fn mode(mut self, bit: u8) -> Self {
+        self.mode |= bit;
+        let mode = self.mode;
+        self.raw(&[ESC, b'!', mode])
+    }
+
+    /// QR code, model 2 with module size 6 and error correction M, on a
+    /// line of its own
+    pub fn qr(self, data: &str) -> Self {
+        let [low, high] = (data.len() as u16 + 3).to_le_bytes();
+        self.raw(&[GS, b'(', b'k', 4, 0, 49, 65, 50, 0])
+            .raw(&[GS, b'(', b'k', 3, 0, 49, 67, 6])
+            .raw(&[GS, b'(', b'k', 3, 0, 49, 69, 49])
+            .raw(&[GS, b'(', b'k', low, high, 49, 80, 48])
+            .raw(data.as_bytes())
+            .raw(&[GS, b'(', b'k', 3, 0, 49, 81, 48])
+            .raw(b"\n")
+    }
+
+    /// Barcode in a system of GS k's second form (65 UPC-A to 73 CODE128),
+    /// on a line of its own. Height, module width and HRI position are set
+    /// beforehand with `raw` (GS h, GS w, GS H).
+    pub fn barcode(self, system: u8, data: &[u8]) -> Self {
+        self.raw(&[GS, b'k', system, data.len() as u8])
+            .raw(data)
+            .raw(b"\n")
+    }
+
+    /// Raster image (GS v 0), `width_bytes` bytes a row with the leftmost
+    /// dot in the high bit, on a line of its own
+    pub fn raster(self, width_bytes: u16, height: u16, data: &[u8]) -> Self {
+        let [xl, xh] = width_bytes.to_le_bytes();
+        let [yl, yh] = height.to_le_bytes();
+        self.raw(&[GS, b'v', b'0', 0, xl, xh, yl, yh])
+            .raw(data)
+            .raw(b"\n")
+    }
+
+    /// Feed 3 lines and cut
+    pub fn cut(self) -> Self {
+        self.raw(&[GS, b'V', 65, 3])
+    }
+
+    /// Feed 3 lines and cut, leaving one point uncut
+    pub fn partial_cut(self) -> Self {
+        self.raw(&[GS, b'V', 66, 3])
+    }
+}
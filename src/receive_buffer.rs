@@ -0,0 +1,175 @@
+// Simulated receive buffer
+//
+// Real printers store incoming bytes in a fixed-size buffer that empties as
+// they print. Once it's full they stop reading and the host's writes block
+// until a good part of it has been printed. Each connection gets one of these
+// so clients' chunking and back-pressure handling can be tested; with a
+// capacity of 0 data is read as fast as it arrives.
+//
+// Independently, the data can be read at the pace of a serial line, so slow
+// printers expose client timeouts and progress reporting that assume a
+// fast network.
+//
+// In the other direction, responses can be written in small pieces with
+// pauses in between, for clients that expect a whole status reply in one
+// read.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Buffer sizes offered in the settings: (label, bytes)
+pub const PRESETS: [(&str, usize); 3] = [("Unlimited", 0), ("4 KB", 4096), ("45 KB", 45 * 1024)];
+
+/// Serial line speeds offered in the settings, 0 for network speed
+pub const BAUD_RATES: [u32; 5] = [0, 9600, 19200, 38400, 115200];
+
+/// Bits on the line per byte: start bit, 8 data bits, stop bit (8N1)
+const BITS_PER_BYTE: f64 = 10.0;
+
+/// Reads are limited to this much of the line's time so data trickles in
+/// rather than arriving in bursts
+const LINE_CHUNK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReceiveBufferSettings {
+    /// Buffer size in bytes, 0 for no limit
+    pub capacity: usize,
+    /// Bytes printed (removed from the buffer) per second
+    pub drain_rate: usize,
+    /// Serial line speed data is read at, 0 for as fast as it arrives
+    pub baud_rate: u32,
+    /// Responses are written this many bytes at a time, 0 for all at once
+    pub response_chunk: usize,
+    /// Pause between the pieces of a response
+    pub response_delay_ms: u64,
+}
+
+impl Default for ReceiveBufferSettings {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            drain_rate: 8192,
+            baud_rate: 0,
+            response_chunk: 0,
+            response_delay_ms: 0,
+        }
+    }
+}
+
+pub struct ReceiveBuffer {
+    settings: ReceiveBufferSettings,
+    level: f64,
+    drained_at: Instant,
+    busy: bool,
+    // When the serial line has finished carrying the bytes received so far
+    line_free_at: Instant,
+}
+
+impl ReceiveBuffer {
+    pub fn new(settings: ReceiveBufferSettings) -> Self {
+        Self {
+            settings,
+            level: 0.0,
+            drained_at: Instant::now(),
+            busy: false,
+            line_free_at: Instant::now(),
+        }
+    }
+
+    /// Apply changed settings; what's already buffered is kept
+    pub fn configure(&mut self, settings: ReceiveBufferSettings) {
+        self.drain();
+        self.settings = settings;
+        if settings.baud_rate == 0 {
+            self.line_free_at = Instant::now();
+        }
+    }
+
+    fn drain(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.drained_at).as_secs_f64();
+        self.level = (self.level - elapsed * self.settings.drain_rate as f64).max(0.0);
+        self.drained_at = now;
+    }
+
+    /// Free space at which a full buffer accepts data again
+    fn resume_threshold(&self) -> usize {
+        (self.settings.capacity / 4).max(1)
+    }
+
+    fn bytes_per_second(&self) -> f64 {
+        self.settings.baud_rate as f64 / BITS_PER_BYTE
+    }
+
+    /// Bytes that can be received now (0 while busy), or None if there's no
+    /// limit
+    pub fn space(&mut self) -> Option<usize> {
+        let line_chunk = (self.settings.baud_rate > 0)
+            .then(|| ((self.bytes_per_second() * LINE_CHUNK.as_secs_f64()) as usize).max(1));
+        if self.settings.capacity == 0 {
+            self.busy = false;
+            return line_chunk;
+        }
+        self.drain();
+        let free = self
+            .settings
+            .capacity
+            .saturating_sub(self.level.ceil() as usize);
+        if self.busy {
+            self.busy = free < self.resume_threshold();
+        } else {
+            self.busy = free == 0;
+        }
+        let free = line_chunk.map_or(free, |chunk| free.min(chunk));
+        Some(if self.busy { 0 } else { free })
+    }
+
+    pub fn fill(&mut self, bytes: usize) {
+        if self.settings.capacity > 0 {
+            self.level += bytes as f64;
+        }
+        if self.settings.baud_rate > 0 {
+            let transfer = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second());
+            self.line_free_at = self.line_free_at.max(Instant::now()) + transfer;
+        }
+    }
+
+    /// How long until the serial line has carried what was received, zero at
+    /// network speed
+    pub fn line_delay(&self) -> Duration {
+        self.line_free_at.saturating_duration_since(Instant::now())
+    }
+
+    /// How long until a full buffer accepts data again
+    pub fn time_until_space(&self) -> Duration {
+        let target = self
+            .settings
+            .capacity
+            .saturating_sub(self.resume_threshold());
+        let excess = self.level - target as f64;
+        let rate = self.settings.drain_rate.max(1) as f64;
+        Duration::from_secs_f64((excess / rate).max(0.001))
+    }
+}
+
+/// Write responses to the client, in pieces if the settings ask for it
+pub async fn write_responses(
+    socket: &mut (impl AsyncWrite + Unpin),
+    responses: &[u8],
+    settings: &ReceiveBufferSettings,
+) -> std::io::Result<()> {
+    let chunk = match settings.response_chunk {
+        0 => responses.len().max(1),
+        chunk => chunk,
+    };
+    for (i, piece) in responses.chunks(chunk).enumerate() {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_millis(settings.response_delay_ms)).await;
+        }
+        socket.write_all(piece).await?;
+        socket.flush().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+// Reference overlay: an approved render of a receipt (a PNG, such as one
+// written by `escpresso render`) laid over the receipt in the window, so
+// layout drift against the approved design shows right away. Either the
+// reference is drawn on top with adjustable opacity, or a heatmap marks the
+// pixels where the current receipt, rendered like the PNG export, differs:
+// yellow for slight differences, red for strong ones. Beyond the shorter of
+// the two images, the other is compared against blank paper.
+
+use crate::{render, PaperSize, ReceiptElement, DOTS_PER_INCH};
+use anyhow::{Context, Result};
+use eframe::egui;
+use image::{Rgba, RgbaImage};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlayMode {
+    /// The reference over the receipt
+    Overlay,
+    /// Where the receipt differs from the reference
+    Heatmap,
+}
+
+/// What the heatmap was computed for: element count, time of the last
+/// element and paper size, which change whenever the receipt does
+pub type ReceiptKey = (usize, Option<u64>, PaperSize);
+
+/// A heatmap: the receipt it's for, the fraction of pixels that differ and
+/// the image
+type Heatmap = (ReceiptKey, f64, RgbaImage);
+
+pub struct Reference {
+    /// File name, for display
+    pub name: String,
+    image: Arc<RgbaImage>,
+    texture: Option<egui::TextureHandle>,
+    heatmap: Option<(ReceiptKey, f64, egui::TextureHandle)>,
+    /// Filled in by the thread computing a heatmap
+    computed: Arc<Mutex<Option<Heatmap>>>,
+    pending: Option<ReceiptKey>,
+}
+
+impl Reference {
+    pub fn from_png(name: String, bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)
+            .with_context(|| format!("reading {}", name))?
+            .to_rgba8();
+        Ok(Self {
+            name,
+            image: Arc::new(image),
+            texture: None,
+            heatmap: None,
+            computed: Arc::default(),
+            pending: None,
+        })
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.image.width(), self.image.height()]
+    }
+
+    /// The reference as a texture, uploaded the first time
+    pub fn texture(&mut self, ctx: &egui::Context) -> &egui::TextureHandle {
+        let image = &self.image;
+        self.texture
+            .get_or_insert_with(|| ctx.load_texture("reference", color_image(image), NEAREST))
+    }
+
+    /// The heatmap for the receipt identified by `key` and the fraction of
+    /// pixels that differ. A new one is computed in the background when the
+    /// receipt changes (`elements` is only called then); until it's done the
+    /// previous one is returned.
+    pub fn heatmap(
+        &mut self,
+        ctx: &egui::Context,
+        key: ReceiptKey,
+        elements: impl FnOnce() -> Vec<ReceiptElement>,
+    ) -> Option<(f64, &egui::TextureHandle)> {
+        if let Some((computed_key, fraction, image)) = self.computed.lock().unwrap().take() {
+            if self.pending == Some(computed_key) {
+                self.pending = None;
+            }
+            let texture = ctx.load_texture("reference_heatmap", color_image(&image), NEAREST);
+            self.heatmap = Some((computed_key, fraction, texture));
+        }
+        let current = self.heatmap.as_ref().map(|(key, _, _)| *key);
+        if current != Some(key) && self.pending != Some(key) {
+            self.pending = Some(key);
+            let (elements, reference) = (elements(), self.image.clone());
+            let (computed, ctx) = (self.computed.clone(), ctx.clone());
+            std::thread::spawn(move || {
+                let receipt = render::render_elements_to_image(&elements, key.2, DOTS_PER_INCH);
+                let (fraction, image) = heatmap(&receipt, &reference);
+                *computed.lock().unwrap() = Some((key, fraction, image));
+                ctx.request_repaint();
+            });
+        }
+        self.heatmap
+            .as_ref()
+            .map(|(_, fraction, texture)| (*fraction, texture))
+    }
+
+    /// Fraction of pixels that differ, as of the last heatmap
+    pub fn difference(&self) -> Option<f64> {
+        self.heatmap.as_ref().map(|(_, fraction, _)| *fraction)
+    }
+
+    /// Whether the heatmap shown is out of date
+    pub fn comparing(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+/// Pixels stay sharp when the window scale isn't 1
+const NEAREST: egui::TextureOptions = egui::TextureOptions::NEAREST;
+
+fn color_image(image: &RgbaImage) -> egui::ColorImage {
+    egui::ColorImage::from_rgba_unmultiplied(
+        [image.width() as usize, image.height() as usize],
+        image.as_raw(),
+    )
+}
+
+/// Fraction of pixels that differ, and the heatmap: transparent where the
+/// images match, yellow to red by how much they differ elsewhere
+fn heatmap(receipt: &RgbaImage, reference: &RgbaImage) -> (f64, RgbaImage) {
+    const PAPER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    let width = receipt.width().max(reference.width());
+    let height = receipt.height().max(reference.height());
+    let pixel = |image: &RgbaImage, x, y| *image.get_pixel_checked(x, y).unwrap_or(&PAPER);
+    let mut differing = 0usize;
+    let map = RgbaImage::from_fn(width, height, |x, y| {
+        let (a, b) = (pixel(receipt, x, y), pixel(reference, x, y));
+        let difference = a.0[..3]
+            .iter()
+            .zip(&b.0[..3])
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0);
+        if difference <= render::PIXEL_TOLERANCE {
+            return Rgba([0, 0, 0, 0]);
+        }
+        differing += 1;
+        // Yellow just past the tolerance, red at full contrast
+        let tolerance = u32::from(render::PIXEL_TOLERANCE);
+        let green = 220 * (255 - u32::from(difference)) / (255 - tolerance);
+        Rgba([255, green as u8, 0, 200])
+    });
+    let total = (width as usize * height as usize).max(1);
+    (differing as f64 / total as f64, map)
+}
@@ -0,0 +1,1261 @@
+// Painting receipts with egui, and rendering them to images without a window
+//
+// show_receipt_elements lays the elements out and paints them the way the
+// printer prints them, in the window's receipt view. render_elements_to_image
+// runs the same code in a context of its own, then rasterizes the triangles
+// egui produces in software. Images match the preview and no GPU or display
+// is needed, so this works in CI. The CLI, the PNG export and printing on
+// paper all render through render_elements_to_image.
+//
+// Needs the `render` feature, which brings in egui.
+
+use crate::{
+    command_name, fill_rows, font_width_dots, rtl, Alignment, PaperSize, ReceiptElement,
+    DOTS_PER_INCH,
+};
+use egui::epaint::{ImageData, Primitive, TextureId};
+use egui::{Color32, Pos2, Rect};
+use image::{Rgba, RgbaImage};
+use qrcode::{Color as QrColor, QrCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// System fonts tried, in order, for glyphs egui's bundled fonts lack
+// (Hebrew, Arabic, control pictures, ...)
+const FALLBACK_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+    "/usr/share/fonts/dejavu-sans-mono-fonts/DejaVuSansMono.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSansArabic-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSansHebrew-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSansSymbols2-Regular.ttf",
+    "/System/Library/Fonts/Apple Symbols.ttf",
+    "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+    "/Library/Fonts/Arial Unicode.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+    "C:\\Windows\\Fonts\\seguisym.ttf",
+];
+
+pub fn install_fallback_fonts(ctx: &egui::Context) {
+    let mut fonts = egui::FontDefinitions::default();
+    for path in FALLBACK_FONTS {
+        let Ok(bytes) = std::fs::read(path) else {
+            continue;
+        };
+        let name = path.to_string();
+        fonts
+            .font_data
+            .insert(name.clone(), egui::FontData::from_owned(bytes));
+        for family in [egui::FontFamily::Monospace, egui::FontFamily::Proportional] {
+            fonts.families.entry(family).or_default().push(name.clone());
+        }
+    }
+    ctx.set_fonts(fonts);
+}
+
+/// Light theme with white paper, used by the window and headless rendering
+pub fn apply_style(ctx: &egui::Context) {
+    // Force light mode, ignoring OS dark mode, also when the OS switches
+    // later as the style is only set once
+    ctx.set_theme(egui::Theme::Light);
+    ctx.set_visuals(egui::Visuals::light());
+
+    let mut style = (*ctx.style()).clone();
+    style.visuals.panel_fill = egui::Color32::WHITE;
+    style.visuals.window_fill = egui::Color32::WHITE;
+    style.visuals.popup_shadow = egui::epaint::Shadow::NONE;
+    style.visuals.widgets.noninteractive.fg_stroke.color = egui::Color32::BLACK;
+    style.visuals.widgets.noninteractive.bg_fill = egui::Color32::WHITE;
+    style.visuals.widgets.inactive.fg_stroke.color = egui::Color32::BLACK;
+    style.visuals.widgets.inactive.bg_fill = egui::Color32::from_gray(245);
+    style.visuals.widgets.active.fg_stroke.color = egui::Color32::BLACK;
+    style.visuals.widgets.active.bg_fill = egui::Color32::from_gray(230);
+    style.visuals.widgets.hovered.fg_stroke.color = egui::Color32::BLACK;
+    style.visuals.widgets.hovered.bg_fill = egui::Color32::from_gray(250);
+    style.visuals.widgets.open.fg_stroke.color = egui::Color32::BLACK;
+    style.visuals.widgets.open.bg_fill = egui::Color32::from_gray(250);
+    style.visuals.extreme_bg_color = egui::Color32::WHITE;
+    style.visuals.faint_bg_color = egui::Color32::from_gray(250);
+    style.visuals.selection.bg_fill = egui::Color32::from_gray(248);
+    style.visuals.selection.stroke.color = egui::Color32::BLACK;
+    ctx.set_style(style);
+}
+
+/// Warning sign in the right margin of a line the printer had to wrap or
+/// that didn't decode cleanly, explaining why on hover
+fn text_warning_badge(
+    ui: &egui::Ui,
+    rect: egui::Rect,
+    response: egui::Response,
+    overflow: bool,
+    decoding_problem: &Option<String>,
+) {
+    let mut reasons = Vec::new();
+    if overflow {
+        reasons.push(
+            "Wider than the print area: the printer wraps the rest onto the next line".to_string(),
+        );
+    }
+    reasons.extend(decoding_problem.clone());
+    if reasons.is_empty() {
+        return;
+    }
+    ui.painter().text(
+        rect.right_top(),
+        egui::Align2::RIGHT_TOP,
+        "⚠",
+        egui::FontId::proportional(12.0),
+        egui::Color32::from_rgb(200, 120, 0),
+    );
+    response.on_hover_text(reasons.join("\n"));
+}
+
+/// Draw receipt elements one below the other at the paper width, returning
+/// the index of the one under the pointer. Shared by the window and headless
+/// rendering. Raster images over `max_raster_pixels` (0 for no limit) are
+/// drawn from a downscaled texture.
+#[allow(clippy::too_many_arguments)]
+pub fn show_receipt_elements(
+    ui: &mut egui::Ui,
+    elements: &[ReceiptElement],
+    paper_size: PaperSize,
+    wrap_print_area: bool,
+    show_unknown_commands: bool,
+    show_text_warnings: bool,
+    show_guides: bool,
+    darkness: i8,
+    max_raster_pixels: usize,
+) -> Option<usize> {
+    let printer_width_px = paper_size.width_px();
+    let printer_chars_per_line = paper_size.chars_per_line();
+    // The element drawn last and where it started
+    let mut drawn: Option<(usize, f32)> = None;
+    let mut hovered = None;
+
+    for (index, element) in elements.iter().enumerate() {
+        if let Some((last, top)) = drawn.take() {
+            if finish_element(ui, &elements[last], top, printer_width_px, show_guides) {
+                hovered = Some(last);
+            }
+        }
+        drawn = Some((index, ui.cursor().top()));
+        match element {
+            ReceiptElement::Text {
+                content,
+                bold,
+                underline,
+                double_width,
+                double_height,
+                inverted,
+                alignment,
+                density,
+                offset,
+                left_margin,
+                character_spacing,
+                double_strike,
+                font,
+                print_area_width,
+                runs,
+                overflow,
+                decoding_problem,
+                cell_width,
+                width_multiplier,
+                height_multiplier,
+            } => {
+                let mut job = egui::text::LayoutJob::default();
+
+                // Use print_area_width (GS W) for content sizing
+                // when set, otherwise fall back to full printer width
+                let effective_width = if *print_area_width > 0 {
+                    *print_area_width as f32
+                } else {
+                    printer_width_px
+                };
+
+                // Calculate font size to fit chars per line
+                // Measure actual monospace advance width ratio
+                let char_width = effective_width / printer_chars_per_line as f32;
+                let ref_size = 20.0_f32;
+                let ref_galley = ui.fonts(|f| {
+                    f.layout_job(egui::text::LayoutJob::simple_singleline(
+                        "M".to_string(),
+                        egui::FontId::monospace(ref_size),
+                        egui::Color32::BLACK,
+                    ))
+                });
+                let mono_ratio = ref_galley.size().x / ref_size;
+                let base_font_size = char_width / mono_ratio;
+
+                // Apply font selection: glyphs shrink with the
+                // cell width, so Font B fits 64 columns on 80mm
+                let font_multiplier = font_width_dots(*font) as f32 / font_width_dots(0) as f32;
+
+                let size = base_font_size * font_multiplier;
+
+                // Cells widened or narrowed to fit the columns set for the
+                // font keep the font's height
+                let stretch = if *cell_width > 0 {
+                    *cell_width as f32 / font_width_dots(*font) as f32
+                } else {
+                    1.0
+                };
+
+                // Character sizes stretch the glyphs in one direction
+                // each, like the print head does. Elements saved before
+                // sizes were kept only say whether they're doubled.
+                let width = (*width_multiplier).max(1 + u8::from(*double_width));
+                let height = (*height_multiplier).max(1 + u8::from(*double_height));
+                let scale = egui::vec2(stretch * f32::from(width), f32::from(height));
+
+                // Always use monospace for consistent character widths
+                // ESC/POS printers use fixed-width fonts
+                // Bold will be rendered by egui's text rendering (stroke weight)
+                let font_id = egui::FontId::monospace(size);
+
+                // Apply bold, double-strike, and density
+                let (ink, bleed) = apply_darkness(
+                    // Bold or double-strike makes text darker
+                    if *bold || *double_strike || *inverted {
+                        egui::Color32::BLACK
+                    } else {
+                        match density {
+                            0 => egui::Color32::LIGHT_GRAY,
+                            1 => egui::Color32::GRAY,
+                            2 => egui::Color32::DARK_GRAY,
+                            _ => egui::Color32::BLACK, // 3-8: normal black
+                        }
+                    },
+                    darkness,
+                );
+                let (color, bg_color) = if *inverted {
+                    (egui::Color32::WHITE, ink)
+                } else {
+                    (ink, egui::Color32::TRANSPARENT)
+                };
+                // Ink spilling past black thickens the strokes, up to
+                // about a third of a dot
+                let bleed = if *inverted { 0.0 } else { bleed };
+
+                // Apply character spacing (ESC SP), in dots whatever the
+                // cell stretch
+                let extra_letter_spacing = *character_spacing as f32 / stretch;
+
+                let text_format = egui::TextFormat {
+                    font_id,
+                    color,
+                    background: bg_color,
+                    underline: if *underline {
+                        egui::Stroke::new(1.0, color)
+                    } else {
+                        egui::Stroke::NONE
+                    },
+                    extra_letter_spacing,
+                    ..Default::default()
+                };
+
+                // Lines split by mid-line ESC $ / ESC \ are
+                // drawn run by run at their dot positions
+                if !runs.is_empty() {
+                    let galleys: Vec<_> = runs
+                        .iter()
+                        .map(|run| {
+                            let job = egui::text::LayoutJob::single_section(
+                                rtl::to_visual(&run.content).unwrap_or_else(|| run.content.clone()),
+                                text_format.clone(),
+                            );
+                            (run.x, ui.fonts(|f| f.layout_job(job)))
+                        })
+                        .collect();
+                    let line_height = galleys
+                        .iter()
+                        .map(|(_, galley)| galley.size().y * scale.y)
+                        .fold(0.0, f32::max);
+                    let (rect, response) = ui.allocate_exact_size(
+                        egui::vec2(printer_width_px, line_height),
+                        egui::Sense::hover(),
+                    );
+                    let area = print_area(rect, printer_width_px, *print_area_width);
+                    let painter = ui.painter().with_clip_rect(area);
+                    for (x, galley) in galleys {
+                        let pos =
+                            egui::pos2(area.left() + *left_margin as f32 + x as f32, rect.top());
+                        paint_scaled_galley(&painter, pos, galley, color, scale, bleed);
+                    }
+                    if show_text_warnings {
+                        text_warning_badge(ui, rect, response, *overflow, decoding_problem);
+                    }
+                    continue;
+                }
+
+                // Hebrew/Arabic lines are painted in visual order
+                let visual = rtl::to_visual(content);
+                job.append(visual.as_deref().unwrap_or(content), 0.0, text_format);
+
+                // Text past the print area edge is clipped
+                // below unless wrapping is enabled
+                if wrap_print_area {
+                    job.wrap.max_width = ((effective_width - *left_margin as f32 - *offset as f32)
+                        / scale.x)
+                        .max(char_width);
+                }
+
+                let galley = ui.fonts(|f| f.layout_job(job));
+
+                // Allocate full width for 80mm receipt paper
+                let line_height = galley.size().y * scale.y;
+                let text_width = galley.size().x * scale.x;
+
+                let (rect, response) = ui.allocate_exact_size(
+                    egui::vec2(printer_width_px, line_height),
+                    egui::Sense::hover(),
+                );
+
+                // Apply left margin (GS L)
+                let margin_offset = *left_margin as f32;
+
+                // The GS W print area, centered within the paper
+                let area = print_area(rect, printer_width_px, *print_area_width);
+
+                // Calculate base position from alignment
+                // All alignments are relative to the print area
+                // so content stays within it
+                let base_x = match alignment {
+                    Alignment::Left => area.left() + margin_offset,
+                    Alignment::Center => {
+                        area.left()
+                            + margin_offset
+                            + (area.width() - text_width - margin_offset) / 2.0
+                    }
+                    Alignment::Right => area.right() - text_width,
+                };
+
+                // Apply horizontal offset (from ESC $ / ESC \ commands)
+                // Offset is in pixels, add to base position
+                let final_x = if *offset > 0 {
+                    area.left() + margin_offset + *offset as f32
+                } else {
+                    base_x
+                };
+
+                let pos = egui::pos2(final_x, rect.top());
+
+                paint_scaled_galley(
+                    &ui.painter().with_clip_rect(area),
+                    pos,
+                    galley,
+                    color,
+                    scale,
+                    bleed,
+                );
+                if show_text_warnings {
+                    text_warning_badge(ui, rect, response, *overflow, decoding_problem);
+                }
+            }
+            ReceiptElement::RasterImage {
+                width,
+                height,
+                data,
+                offset,
+                density,
+                alignment,
+                bytes_per_line,
+                print_area_width,
+                left_margin,
+            } => {
+                render_raster_image(
+                    ui,
+                    *width,
+                    *height,
+                    data,
+                    *offset,
+                    *density,
+                    alignment,
+                    printer_width_px,
+                    *bytes_per_line,
+                    *print_area_width,
+                    *left_margin,
+                    darkness,
+                    max_raster_pixels,
+                );
+            }
+            ReceiptElement::QrCode {
+                data,
+                size,
+                alignment,
+                offset,
+                print_area_width,
+                left_margin,
+            } => {
+                render_qr_code(
+                    ui,
+                    data,
+                    *size,
+                    alignment,
+                    *offset,
+                    *print_area_width,
+                    *left_margin,
+                    printer_width_px,
+                );
+            }
+            ReceiptElement::PaperCut { cut_type } => {
+                render_paper_cut(ui, cut_type, printer_width_px);
+            }
+            ReceiptElement::CashDrawer {
+                pin,
+                on_time,
+                off_time,
+            } => {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("💰");
+                    ui.strong("CASH DRAWER OPEN");
+                });
+                ui.label(format!(
+                    "Pin: {}  On: {}ms  Off: {}ms",
+                    pin,
+                    *on_time as u32 * 2,
+                    *off_time as u32 * 2
+                ));
+                ui.separator();
+            }
+            ReceiptElement::Separator => {
+                ui.add_space(4.0);
+            }
+            ReceiptElement::FormFeed => {
+                // Don't add artificial spacing - only show protocol breaks
+            }
+            ReceiptElement::Barcode {
+                text,
+                widths,
+                module_width,
+                height,
+                hri_position,
+                rotated,
+                alignment,
+                left_margin,
+                print_area_width,
+                ..
+            } => {
+                render_barcode(
+                    ui,
+                    &BarcodeView {
+                        text,
+                        widths,
+                        module_width: *module_width,
+                        height: *height,
+                        hri_position: *hri_position,
+                        rotated: *rotated,
+                        alignment,
+                        left_margin: *left_margin,
+                        print_area_width: *print_area_width,
+                    },
+                    printer_width_px,
+                );
+            }
+            ReceiptElement::JobTags { tags } => {
+                ui.horizontal(|ui| {
+                    ui.label("🏷");
+                    ui.colored_label(egui::Color32::DARK_GRAY, tags.join(", "));
+                });
+            }
+            ReceiptElement::UnknownCommand { bytes } => {
+                if show_unknown_commands {
+                    ui.label(
+                        egui::RichText::new(format!("[{}]", command_name(bytes)))
+                            .small()
+                            .color(egui::Color32::GRAY),
+                    )
+                    .on_hover_text(format!("Unknown command, skipped: {:02X?}", bytes));
+                }
+            }
+            ReceiptElement::ReceiptStart { source, .. } => {
+                // Receipts the previous one wasn't cut from still look torn
+                // off; after a cut, the cut shows whether they're attached
+                let cut = elements[..index]
+                    .iter()
+                    .rev()
+                    .take_while(|e| !matches!(e, ReceiptElement::ReceiptStart { .. }))
+                    .any(|e| matches!(e, ReceiptElement::PaperCut { .. }));
+                if index > 0 && !cut {
+                    paper_gap(ui, printer_width_px);
+                }
+                ui.horizontal(|ui| {
+                    ui.label("🧾");
+                    ui.colored_label(egui::Color32::DARK_GRAY, source);
+                });
+            }
+        }
+    }
+    if let Some((last, top)) = drawn {
+        if finish_element(ui, &elements[last], top, printer_width_px, show_guides) {
+            hovered = Some(last);
+        }
+    }
+    hovered
+}
+
+/// Paint the guides over an element drawn from `top` down to the cursor,
+/// and tell whether the pointer is over it
+fn finish_element(
+    ui: &egui::Ui,
+    element: &ReceiptElement,
+    top: f32,
+    printer_width_px: f32,
+    show_guides: bool,
+) -> bool {
+    let left = ui.min_rect().left();
+    let rect = egui::Rect::from_x_y_ranges(left..=left + printer_width_px, top..=ui.cursor().top());
+    if show_guides {
+        if let Some(layout) = element_layout(element) {
+            paint_guides(ui, rect, printer_width_px, layout);
+        }
+    }
+    ui.rect_contains_pointer(rect)
+}
+
+/// Left margin and print area width of the elements laid out in them
+pub fn element_layout(element: &ReceiptElement) -> Option<(u16, u16)> {
+    match element {
+        ReceiptElement::Text {
+            left_margin,
+            print_area_width,
+            ..
+        }
+        | ReceiptElement::RasterImage {
+            left_margin,
+            print_area_width,
+            ..
+        }
+        | ReceiptElement::QrCode {
+            left_margin,
+            print_area_width,
+            ..
+        }
+        | ReceiptElement::Barcode {
+            left_margin,
+            print_area_width,
+            ..
+        } => Some((*left_margin, *print_area_width)),
+        _ => None,
+    }
+}
+
+/// Dashed lines down an element's rect: blue at both edges of the GS W
+/// print area, green at the GS L left margin
+fn paint_guides(
+    ui: &egui::Ui,
+    rect: egui::Rect,
+    printer_width_px: f32,
+    (left_margin, print_area_width): (u16, u16),
+) {
+    let area = print_area(rect, printer_width_px, print_area_width);
+    let vertical = |x: f32| [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())];
+    let mut lines = vec![
+        (area.left() + 0.5, egui::Color32::from_rgb(70, 130, 220)),
+        (area.right() - 0.5, egui::Color32::from_rgb(70, 130, 220)),
+    ];
+    if left_margin > 0 {
+        let x = (area.left() + left_margin as f32).min(area.right());
+        lines.push((x, egui::Color32::from_rgb(0, 160, 90)));
+    }
+    for (x, color) in lines {
+        ui.painter().extend(egui::Shape::dashed_line(
+            &vertical(x),
+            egui::Stroke::new(1.0, color),
+            4.0,
+            3.0,
+        ));
+    }
+}
+
+/// Printer darkness setting, in steps of 5% of the normal heat: -6 prints at
+/// 70%, +6 at 130%, like the print density setting of real printers
+pub const DARKNESS_RANGE: std::ops::RangeInclusive<i8> = -6..=6;
+
+/// Ink color after the darkness setting, and how far past black the ink
+/// goes (0 to 0.3), which spreads the dots like excess heat does
+fn apply_darkness(color: egui::Color32, darkness: i8) -> (egui::Color32, f32) {
+    let ink = f32::from(255 - color.r()) / 255.0 * (1.0 + f32::from(darkness) * 0.05);
+    let gray = (255.0 * (1.0 - ink.min(1.0))).round() as u8;
+    (egui::Color32::from_gray(gray), (ink - 1.0).max(0.0))
+}
+
+/// Paint a galley stretched by `scale` from its top-left corner. Glyphs
+/// are scaled independently horizontally and vertically, which font sizes
+/// alone can't do. With `bleed`, the text is painted a second time that
+/// many points to the right, for darker, thicker strokes.
+fn paint_scaled_galley(
+    painter: &egui::Painter,
+    pos: egui::Pos2,
+    galley: Arc<egui::Galley>,
+    color: egui::Color32,
+    scale: egui::Vec2,
+    bleed: f32,
+) {
+    if bleed > 0.0 {
+        paint_scaled_galley(painter, pos, galley.clone(), color, scale, 0.0);
+        let pos = pos + egui::vec2(bleed, 0.0);
+        return paint_scaled_galley(painter, pos, galley, color, scale, 0.0);
+    }
+    if scale == egui::Vec2::splat(1.0) {
+        painter.galley(pos, galley, color);
+        return;
+    }
+    let ctx = painter.ctx();
+    let mut tessellator = egui::epaint::Tessellator::new(
+        ctx.pixels_per_point(),
+        Default::default(),
+        ctx.fonts(|f| f.font_image_size()),
+        Vec::new(),
+    );
+    let mut mesh = egui::Mesh::default();
+    tessellator.tessellate_text(&egui::epaint::TextShape::new(pos, galley, color), &mut mesh);
+    for vertex in &mut mesh.vertices {
+        vertex.pos = pos + (vertex.pos - pos) * scale;
+    }
+    painter.add(mesh);
+}
+
+/// The GS W print area within a line allocated at full paper width.
+/// The area is centered on the paper; 0 means the full width.
+fn print_area(rect: egui::Rect, printer_width_px: f32, print_area_width: u16) -> egui::Rect {
+    let width = if print_area_width > 0 {
+        (print_area_width as f32).min(printer_width_px)
+    } else {
+        printer_width_px
+    };
+    let left = rect.left() + (printer_width_px - width) / 2.0;
+    egui::Rect::from_x_y_ranges(left..=left + width, rect.y_range())
+}
+
+/// Raster images over this many megapixels are shown downscaled unless the
+/// display settings change it
+pub const DEFAULT_RASTER_BUDGET_MP: u32 = 4;
+
+/// Smallest whole factor that shrinks a width x height raster to at most
+/// `max_pixels` pixels (0 for no limit) and `max_side` on either side
+fn raster_downscale_factor(
+    width: usize,
+    height: usize,
+    max_pixels: usize,
+    max_side: usize,
+) -> usize {
+    let mut factor = width.max(height).div_ceil(max_side.max(1)).max(1);
+    if max_pixels > 0 {
+        while width.div_ceil(factor) * height.div_ceil(factor) > max_pixels {
+            factor += 1;
+        }
+    }
+    factor
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_raster_image(
+    ui: &mut egui::Ui,
+    width: usize,
+    height: usize,
+    data: &[u8],
+    offset: u16,
+    density: u8,
+    alignment: &Alignment,
+    printer_width_px: f32,
+    bytes_per_line: usize,
+    print_area_width: u16,
+    left_margin: u16,
+    darkness: i8,
+    max_pixels: usize,
+) {
+    // Apply density/darkness control to raster images
+    // Density 0-8 maps to different gray levels for lighter/darker printing
+    let (ink_color, bleed) = apply_darkness(
+        match density {
+            0 => egui::Color32::from_gray(180), // Very light
+            1 => egui::Color32::from_gray(130), // Light
+            2 => egui::Color32::from_gray(80),  // Slightly light
+            _ => egui::Color32::BLACK,          // 3-8: normal black
+        },
+        darkness,
+    );
+    // Dots spread into the next one to the right when printed hot enough
+    let spread = usize::from(bleed >= 0.15);
+
+    // Use the actual bytes_per_line from the command, not recalculated.
+    // MSB-first bit order: bit 7 (0x80) is leftmost pixel, bit 0 (0x01) is rightmost
+    let bit_at = |x: usize, y: usize| {
+        data.get(y * bytes_per_line + x / 8)
+            .is_some_and(|byte| byte & (0x80 >> (x % 8)) != 0)
+    };
+    // Standard ESC/POS: 1=black (printed), 0=white (not printed)
+    let printed =
+        |x: usize, y: usize| bit_at(x, y) || (spread > 0 && x >= spread && bit_at(x - spread, y));
+
+    // Each texel is the average of a factor x factor block of dots, so a
+    // tall raster doesn't need a texture as big as itself
+    let max_side = if max_pixels > 0 {
+        ui.ctx().input(|i| i.max_texture_side)
+    } else {
+        usize::MAX
+    };
+    let factor = raster_downscale_factor(width, height, max_pixels, max_side);
+    let texture_size = [width.div_ceil(factor), height.div_ceil(factor)];
+    let mut pixels = vec![egui::Color32::WHITE; texture_size[0] * texture_size[1]];
+    fill_rows(&mut pixels, texture_size[0], |ty, texels| {
+        let rows = ty * factor..((ty + 1) * factor).min(height);
+        for (tx, texel) in texels.iter_mut().enumerate() {
+            let columns = tx * factor..((tx + 1) * factor).min(width);
+            let dots = rows.len() * columns.len();
+            let inked = rows
+                .clone()
+                .flat_map(|y| columns.clone().map(move |x| (x, y)))
+                .filter(|&(x, y)| printed(x, y))
+                .count();
+            *texel = egui::Color32::WHITE.lerp_to_gamma(ink_color, inked as f32 / dots as f32);
+        }
+    });
+
+    let image = egui::ColorImage {
+        size: texture_size,
+        pixels,
+    };
+
+    let texture = ui.ctx().load_texture(
+        format!("raster_{}x{}_{}", width, height, offset),
+        image,
+        if factor > 1 {
+            egui::TextureOptions::LINEAR
+        } else {
+            egui::TextureOptions::NEAREST
+        },
+    );
+
+    // Use print_area_width (GS W) for alignment when set,
+    // otherwise fall back to full printer width
+    let effective_width = if print_area_width > 0 {
+        print_area_width as f32
+    } else {
+        printer_width_px
+    };
+
+    // Scale up the image for better visibility (thermal printers are 203 DPI, screens are ~96 DPI)
+    // Use adaptive scaling: small images (text) get 3x, large images (logos) get 1x
+    // Clamp so the image never exceeds the printable area
+    let scale_factor = if width > 300 || height > 150 {
+        1.0
+    } else {
+        3.0_f32.min(effective_width / width as f32)
+    };
+    let display_width = width as f32 * scale_factor;
+    let display_height = height as f32 * scale_factor;
+
+    // Allocate full printer width for proper alignment
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(printer_width_px, display_height),
+        egui::Sense::hover(),
+    );
+
+    // The GS W print area, centered within the paper width
+    let area = print_area(rect, printer_width_px, print_area_width);
+
+    // Apply left margin (GS L) the same way as for text
+    let margin_offset = left_margin as f32;
+
+    // Calculate horizontal position within the print area from alignment and offset
+    let x_offset = match alignment {
+        Alignment::Left => margin_offset + offset as f32 * scale_factor,
+        Alignment::Center => {
+            margin_offset
+                + (area.width() - display_width - margin_offset) / 2.0
+                + offset as f32 * scale_factor
+        }
+        Alignment::Right => area.width() - display_width - offset as f32 * scale_factor,
+    };
+
+    let pos = egui::pos2(area.left() + x_offset, rect.top());
+    let size = egui::vec2(display_width, display_height);
+
+    // Anything past the print area edge is not printed
+    ui.painter().with_clip_rect(area).image(
+        texture.id(),
+        egui::Rect::from_min_size(pos, size),
+        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+        egui::Color32::WHITE,
+    );
+}
+
+/// Uncut paper left in the middle of a partial cut, in dots
+const PARTIAL_CUT_TAB: f32 = 64.0;
+
+/// A full cut as a gap in the background color, the receipts on either side
+/// separate; a partial cut as a perforation across the paper with a tab in
+/// the middle, so chained copies stay attached the way they come out
+fn render_paper_cut(ui: &mut egui::Ui, cut_type: &str, printer_width_px: f32) {
+    let partial = cut_type.contains("PARTIAL");
+    let rect = if partial {
+        ui.allocate_exact_size(egui::vec2(printer_width_px, 24.0), egui::Sense::hover())
+            .0
+    } else {
+        paper_gap(ui, printer_width_px)
+    };
+    let label = ui.painter().text(
+        egui::pos2(rect.left() + 4.0, rect.center().y),
+        egui::Align2::LEFT_CENTER,
+        format!("✂ {}", cut_type.to_lowercase()),
+        egui::TextStyle::Small.resolve(ui.style()),
+        egui::Color32::GRAY,
+    );
+    if partial {
+        let y = rect.center().y;
+        let stroke = egui::Stroke::new(1.0, egui::Color32::GRAY);
+        let tab = rect.center().x - PARTIAL_CUT_TAB / 2.0..=rect.center().x + PARTIAL_CUT_TAB / 2.0;
+        for (from, to) in [
+            (label.right() + 4.0, *tab.start()),
+            (*tab.end(), rect.right()),
+        ] {
+            if from < to {
+                ui.painter().extend(egui::Shape::dashed_line(
+                    &[egui::pos2(from, y), egui::pos2(to, y)],
+                    stroke,
+                    4.0,
+                    3.0,
+                ));
+            }
+        }
+    }
+}
+
+/// A gap in the background color across the paper, so what's above and
+/// below look like separate receipts
+fn paper_gap(ui: &mut egui::Ui, printer_width_px: f32) -> egui::Rect {
+    let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(printer_width_px, 24.0), egui::Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 0.0, egui::Color32::from_gray(245));
+    rect
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_qr_code(
+    ui: &mut egui::Ui,
+    data: &str,
+    size: usize,
+    alignment: &Alignment,
+    offset: u16,
+    print_area_width: u16,
+    left_margin: u16,
+    printer_width_px: f32,
+) {
+    match QrCode::new(data.as_bytes()) {
+        Ok(qr) => {
+            let colors = qr.to_colors();
+            let width = qr.width();
+            let module_size = size.clamp(1, 8);
+            let pixel_size = width * module_size;
+
+            let mut pixels = Vec::with_capacity(pixel_size * pixel_size);
+
+            for y in 0..width {
+                for _ in 0..module_size {
+                    for x in 0..width {
+                        let idx = y * width + x;
+                        let color = match colors[idx] {
+                            QrColor::Dark => egui::Color32::BLACK,
+                            QrColor::Light => egui::Color32::WHITE,
+                        };
+                        for _ in 0..module_size {
+                            pixels.push(color);
+                        }
+                    }
+                }
+            }
+
+            let image = egui::ColorImage {
+                size: [pixel_size, pixel_size],
+                pixels,
+            };
+
+            let texture = ui.ctx().load_texture(
+                format!("qr_{}", data.chars().take(20).collect::<String>()),
+                image,
+                egui::TextureOptions::NEAREST,
+            );
+
+            let (rect, _) = ui.allocate_exact_size(
+                egui::vec2(printer_width_px, pixel_size as f32),
+                egui::Sense::hover(),
+            );
+
+            // The GS W print area, centered within the paper width
+            let area = print_area(rect, printer_width_px, print_area_width);
+
+            // Apply left margin (GS L) the same way as for text
+            let margin_offset = left_margin as f32;
+
+            // Calculate base position within the print area from alignment
+            let base_x = match alignment {
+                Alignment::Left => margin_offset,
+                Alignment::Center => {
+                    margin_offset + (area.width() - pixel_size as f32 - margin_offset) / 2.0
+                }
+                Alignment::Right => area.width() - pixel_size as f32,
+            };
+
+            // Apply horizontal offset (from ESC $ / ESC \ commands)
+            let final_x = if offset > 0 {
+                margin_offset + offset as f32
+            } else {
+                base_x
+            };
+
+            let pos = egui::pos2(area.left() + final_x, rect.top());
+            let size = egui::vec2(pixel_size as f32, pixel_size as f32);
+
+            // Anything past the print area edge is not printed
+            ui.painter().with_clip_rect(area).image(
+                texture.id(),
+                egui::Rect::from_min_size(pos, size),
+                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                egui::Color32::WHITE,
+            );
+        }
+        Err(e) => {
+            ui.colored_label(egui::Color32::RED, format!("QR Code Error: {:?}", e));
+        }
+    }
+}
+
+struct BarcodeView<'a> {
+    text: &'a str,
+    widths: &'a [u8],
+    module_width: u8,
+    height: u8,
+    hri_position: u8,
+    rotated: bool,
+    alignment: &'a Alignment,
+    left_margin: u16,
+    print_area_width: u16,
+}
+
+fn render_barcode(ui: &mut egui::Ui, barcode: &BarcodeView, printer_width_px: f32) {
+    // Lay the barcode out unrotated in dots: `u` along the bars, `v` down
+    let half_module = barcode.module_width as f32 / 2.0;
+    let length: f32 = barcode.widths.iter().map(|&w| w as f32 * half_module).sum();
+    // HRI characters are Font A sized, about 12 dots wide
+    let font_id = egui::FontId::monospace(20.0);
+    let hri_galley =
+        ui.fonts(|f| f.layout_no_wrap(barcode.text.to_string(), font_id, egui::Color32::BLACK));
+    let hri_height = hri_galley.size().y;
+    let hri_above = matches!(barcode.hri_position, 1 | 3);
+    let hri_below = matches!(barcode.hri_position, 2 | 3);
+    let bars_top = if hri_above { hri_height } else { 0.0 };
+    let depth = bars_top + barcode.height as f32 + if hri_below { hri_height } else { 0.0 };
+
+    // Rotated 90° clockwise, the bars run down the paper
+    let (width, line_height) = if barcode.rotated {
+        (depth, length)
+    } else {
+        (length, depth)
+    };
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(printer_width_px, line_height),
+        egui::Sense::hover(),
+    );
+    let area = print_area(rect, printer_width_px, barcode.print_area_width);
+    let margin_offset = barcode.left_margin as f32;
+    let x_offset = match barcode.alignment {
+        Alignment::Left => margin_offset,
+        Alignment::Center => margin_offset + (area.width() - width - margin_offset) / 2.0,
+        Alignment::Right => area.width() - width,
+    };
+    let origin = egui::pos2(area.left() + x_offset, rect.top());
+
+    // Map a point of the unrotated layout onto the paper
+    let to_screen = |u: f32, v: f32| {
+        if barcode.rotated {
+            origin + egui::vec2(depth - v, u)
+        } else {
+            origin + egui::vec2(u, v)
+        }
+    };
+
+    let painter = ui.painter().with_clip_rect(area);
+    let mut u = 0.0;
+    for (i, &w) in barcode.widths.iter().enumerate() {
+        let bar_width = w as f32 * half_module;
+        if i % 2 == 0 {
+            let bar = egui::Rect::from_two_pos(
+                to_screen(u, bars_top),
+                to_screen(u + bar_width, bars_top + barcode.height as f32),
+            );
+            painter.rect_filled(bar, 0.0, egui::Color32::BLACK);
+        }
+        u += bar_width;
+    }
+
+    // HRI text, centered under (or over) the bars and rotated with them
+    let text_u = (length - hri_galley.size().x) / 2.0;
+    let angle = if barcode.rotated {
+        std::f32::consts::FRAC_PI_2
+    } else {
+        0.0
+    };
+    for (show, v) in [
+        (hri_above, 0.0),
+        (hri_below, bars_top + barcode.height as f32),
+    ] {
+        if show {
+            let text = egui::epaint::TextShape::new(
+                to_screen(text_u, v),
+                hri_galley.clone(),
+                egui::Color32::BLACK,
+            )
+            .with_angle(angle);
+            painter.add(text);
+        }
+    }
+}
+
+/// Longest receipt that can be rendered, in pixels
+const MAX_HEIGHT: f32 = 100_000.0;
+
+/// Channel difference above which two pixels count as different when
+/// comparing, so anti-aliasing noise doesn't fail a comparison
+pub const PIXEL_TOLERANCE: u8 = 32;
+
+struct Texture {
+    size: [usize; 2],
+    pixels: Vec<Color32>,
+}
+
+impl Texture {
+    fn sample(&self, uv: Pos2) -> Color32 {
+        let [w, h] = self.size;
+        let x = ((uv.x * w as f32) as usize).min(w - 1);
+        let y = ((uv.y * h as f32) as usize).min(h - 1);
+        self.pixels[y * w + x]
+    }
+}
+
+/// Render elements on paper of the given size, as tall as they need, at
+/// `dpi` pixels per inch (one pixel per dot at the printer's 203)
+pub fn render_elements_to_image(
+    elements: &[ReceiptElement],
+    paper_size: PaperSize,
+    dpi: u32,
+) -> RgbaImage {
+    let ctx = egui::Context::default();
+    install_fallback_fonts(&ctx);
+    // Layout is in dots; text is rasterized at the output resolution
+    let scale = dpi.max(1) as f32 / DOTS_PER_INCH as f32;
+    let width = paper_size.width_px();
+
+    let mut textures: HashMap<TextureId, Texture> = HashMap::new();
+    let mut height = 0.0;
+    let mut output = None;
+    // Fonts are only available from the second pass on
+    for _ in 0..2 {
+        let input = egui::RawInput {
+            screen_rect: Some(Rect::from_min_size(
+                Pos2::ZERO,
+                egui::vec2(width, MAX_HEIGHT),
+            )),
+            // Textures are only sampled here, never uploaded to a GPU, so a
+            // raster as tall as the receipt is fine
+            max_texture_side: Some(MAX_HEIGHT as usize),
+            viewports: std::iter::once((
+                egui::ViewportId::ROOT,
+                egui::ViewportInfo {
+                    native_pixels_per_point: Some(scale),
+                    ..Default::default()
+                },
+            ))
+            .collect(),
+            ..Default::default()
+        };
+        let full_output = ctx.run(input, |ctx| {
+            apply_style(ctx);
+            egui::CentralPanel::default()
+                .frame(egui::Frame::none().fill(Color32::WHITE))
+                .show(ctx, |ui| {
+                    ui.set_width(width);
+                    let receipt = ui.vertical(|ui| {
+                        // Full resolution, however big
+                        show_receipt_elements(
+                            ui, elements, paper_size, false, false, false, false, 0, 0,
+                        );
+                    });
+                    height = receipt.response.rect.bottom();
+                });
+        });
+        for (id, delta) in &full_output.textures_delta.set {
+            update_texture(&mut textures, *id, delta);
+        }
+        output = Some(full_output);
+    }
+    let output = output.expect("rendered at least once");
+
+    let width = (width * scale).ceil() as usize;
+    let height = ((height * scale).ceil() as usize).max(1);
+    let mut pixels = vec![[1.0f32; 4]; width * height];
+    for clipped in ctx.tessellate(output.shapes, output.pixels_per_point) {
+        if let Primitive::Mesh(mesh) = clipped.primitive {
+            let Some(texture) = textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            for triangle in mesh.indices.chunks_exact(3) {
+                let vertices = [0, 1, 2].map(|k| {
+                    let mut vertex = mesh.vertices[triangle[k] as usize];
+                    vertex.pos = vertex.pos * scale;
+                    vertex
+                });
+                fill_triangle(
+                    &mut pixels,
+                    [width, height],
+                    clipped.clip_rect * scale,
+                    vertices,
+                    texture,
+                );
+            }
+        }
+    }
+
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        let [r, g, b, _] = pixels[y as usize * width + x as usize];
+        Rgba([to_u8(r), to_u8(g), to_u8(b), 255])
+    })
+}
+
+fn update_texture(
+    textures: &mut HashMap<TextureId, Texture>,
+    id: TextureId,
+    delta: &egui::epaint::ImageDelta,
+) {
+    let (size, pixels): ([usize; 2], Vec<Color32>) = match &delta.image {
+        ImageData::Color(image) => (image.size, image.pixels.clone()),
+        ImageData::Font(image) => (image.size, image.srgba_pixels(None).collect()),
+    };
+    match delta.pos {
+        None => {
+            textures.insert(id, Texture { size, pixels });
+        }
+        Some([x0, y0]) => {
+            let Some(texture) = textures.get_mut(&id) else {
+                return;
+            };
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let index = (y0 + y) * texture.size[0] + x0 + x;
+                    if let Some(pixel) = texture.pixels.get_mut(index) {
+                        *pixel = pixels[y * size[0] + x];
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn premultiplied(color: Color32) -> [f32; 4] {
+    color.to_array().map(|c| c as f32 / 255.0)
+}
+
+/// Blend one textured, vertex-colored triangle over the pixels, covering the
+/// pixels whose centers are inside it (top-left rule for shared edges)
+fn fill_triangle(
+    pixels: &mut [[f32; 4]],
+    [width, height]: [usize; 2],
+    clip_rect: Rect,
+    vertices: [egui::epaint::Vertex; 3],
+    texture: &Texture,
+) {
+    let [a, b, c] = vertices.map(|v| v.pos);
+    let area = (b - a).x * (c - a).y - (b - a).y * (c - a).x;
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+    let bounds = Rect::from_points(&[a, b, c]).intersect(clip_rect);
+    let x_range = (bounds.left().floor().max(0.0) as usize)
+        ..(bounds.right().ceil().min(width as f32).max(0.0) as usize);
+    let y_range = (bounds.top().floor().max(0.0) as usize)
+        ..(bounds.bottom().ceil().min(height as f32).max(0.0) as usize);
+
+    // Edge function of p against the edge from -> to, normalized so the
+    // inside of the triangle is positive whatever its winding
+    let edge = |from: Pos2, to: Pos2, p: Pos2| {
+        ((to - from).x * (p - from).y - (to - from).y * (p - from).x) / area
+    };
+    let is_top_left = |from: Pos2, to: Pos2| {
+        let d = (to - from) * area.signum();
+        (d.y == 0.0 && d.x < 0.0) || d.y > 0.0
+    };
+    let edges = [(b, c), (c, a), (a, b)];
+    let top_left = edges.map(|(from, to)| is_top_left(from, to));
+    let colors = vertices.map(|v| premultiplied(v.color));
+
+    for y in y_range {
+        for x in x_range.clone() {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            if !clip_rect.contains(p) {
+                continue;
+            }
+            let weights = edges.map(|(from, to)| edge(from, to, p));
+            let inside = weights
+                .iter()
+                .zip(top_left)
+                .all(|(&w, top_left)| w > 0.0 || (w == 0.0 && top_left));
+            if !inside {
+                continue;
+            }
+            let uv = Pos2::new(
+                weights[0] * vertices[0].uv.x
+                    + weights[1] * vertices[1].uv.x
+                    + weights[2] * vertices[2].uv.x,
+                weights[0] * vertices[0].uv.y
+                    + weights[1] * vertices[1].uv.y
+                    + weights[2] * vertices[2].uv.y,
+            );
+            let texel = premultiplied(texture.sample(uv));
+            let dst = &mut pixels[y * width + x];
+            let mut src = [0.0; 4];
+            for (i, channel) in src.iter_mut().enumerate() {
+                let vertex_color = weights[0] * colors[0][i]
+                    + weights[1] * colors[1][i]
+                    + weights[2] * colors[2][i];
+                *channel = vertex_color * texel[i];
+            }
+            let src_alpha = src[3];
+            for (dst, src) in dst.iter_mut().zip(src) {
+                *dst = src + *dst * (1.0 - src_alpha);
+            }
+        }
+    }
+}
+
+/// Fraction of pixels that differ between two images (1.0 if their sizes
+/// differ), and an image of the differences in red over a faded `actual`
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage) -> (f64, RgbaImage) {
+    if actual.dimensions() != expected.dimensions() {
+        return (1.0, actual.clone());
+    }
+    let mut differing = 0usize;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let different =
+            a.0.iter()
+                .zip(e.0)
+                .any(|(&a, e)| a.abs_diff(e) > PIXEL_TOLERANCE);
+        if different {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let gray = 255 - (255 - a.0[0]) / 4;
+            Rgba([gray, gray, gray, 255])
+        }
+    });
+    let total = (actual.width() as usize * actual.height() as usize).max(1);
+    (differing as f64 / total as f64, diff)
+}
@@ -0,0 +1,326 @@
+// RFC 2217: a serial port over telnet, as spoken by serial device servers.
+// POS software set up for a networked serial printer connects, negotiates the
+// COM-PORT-OPTION and sets the line up (baud rate, data bits, parity, stop
+// bits, DTR/RTS, flow control) before sending print data. The telnet layer is
+// peeled off here:
+//
+//   IAC IAC                      a 0xFF data byte
+//   IAC WILL/WONT/DO/DONT opt    agreed to for BINARY, SGA and COM-PORT-OPTION
+//   IAC SB 44 command value IAC SE
+//                                a COM-PORT-OPTION command, answered with
+//                                command + 100 and the value now in effect
+//   IAC other                    ignored
+//
+// Printer responses going the other way have their 0xFF bytes doubled.
+// The modem lines report CTS, DSR and CD on, like a printer that's ready.
+
+const IAC: u8 = 0xFF;
+const DONT: u8 = 0xFE;
+const DO: u8 = 0xFD;
+const WONT: u8 = 0xFC;
+const WILL: u8 = 0xFB;
+const SB: u8 = 0xFA;
+const SE: u8 = 0xF0;
+
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+/// COM-PORT-OPTION commands from the client; the server answers with
+/// command + 100
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const FLOWCONTROL_SUSPEND: u8 = 8;
+const FLOWCONTROL_RESUME: u8 = 9;
+const SERVER_OFFSET: u8 = 100;
+
+/// CTS, DSR and CD on
+const MODEM_STATE: u8 = 0x10 | 0x20 | 0x80;
+
+/// Subnegotiations longer than this are cut short
+const MAX_SUBNEGOTIATION: usize = 64;
+
+/// Where in a telnet sequence the next byte falls
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Parse {
+    Data,
+    Iac,
+    /// After IAC WILL/WONT/DO/DONT
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Serial line settings, as last set by the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    pub data_size: u8,
+    /// 1 none, 2 odd, 3 even, 4 mark, 5 space
+    pub parity: u8,
+    /// 1, 2, or 3 for 1.5
+    pub stop_size: u8,
+    pub dtr: bool,
+    pub rts: bool,
+    /// SET-CONTROL value: 1 none, 2 XON/XOFF, 3 hardware
+    pub flow_control: u8,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            baud_rate: 9600,
+            data_size: 8,
+            parity: 1,
+            stop_size: 1,
+            dtr: true,
+            rts: true,
+            flow_control: 1,
+        }
+    }
+}
+
+impl std::fmt::Display for LineSettings {
+    /// "9600 8N1, DTR on, RTS on, hardware flow control"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parity = match self.parity {
+            2 => 'O',
+            3 => 'E',
+            4 => 'M',
+            5 => 'S',
+            _ => 'N',
+        };
+        let stop = match self.stop_size {
+            2 => "2",
+            3 => "1.5",
+            _ => "1",
+        };
+        let on = |on: bool| if on { "on" } else { "off" };
+        let flow = match self.flow_control {
+            2 => "XON/XOFF",
+            3 => "hardware",
+            _ => "no",
+        };
+        write!(
+            f,
+            "{} {}{}{}, DTR {}, RTS {}, {} flow control",
+            self.baud_rate,
+            self.data_size,
+            parity,
+            stop,
+            on(self.dtr),
+            on(self.rts),
+            flow
+        )
+    }
+}
+
+pub struct ComPortServer {
+    parse: Parse,
+    subnegotiation: Vec<u8>,
+    /// Option negotiations already sent, so agreeing isn't repeated forever
+    sent: Vec<(u8, u8)>,
+    replies: Vec<u8>,
+    pub line: LineSettings,
+    /// Whether the client has set the baud rate, which then paces the data
+    pub baud_rate_set: bool,
+    line_changed: bool,
+}
+
+impl ComPortServer {
+    /// A server whose first replies offer the options it supports
+    pub fn new() -> Self {
+        let mut server = Self {
+            parse: Parse::Data,
+            subnegotiation: Vec::new(),
+            sent: Vec::new(),
+            replies: Vec::new(),
+            line: LineSettings::default(),
+            baud_rate_set: false,
+            line_changed: false,
+        };
+        server.negotiate(DO, COM_PORT_OPTION);
+        server.negotiate(WILL, BINARY);
+        server.negotiate(DO, BINARY);
+        server.negotiate(WILL, SGA);
+        server
+    }
+
+    /// Remove the telnet sequences from `data`, acting on them, and return
+    /// how many bytes of print data are left at its start
+    pub fn receive(&mut self, data: &mut [u8]) -> usize {
+        let mut kept = 0;
+        for i in 0..data.len() {
+            let byte = data[i];
+            match self.parse {
+                Parse::Data if byte == IAC => self.parse = Parse::Iac,
+                Parse::Data => {
+                    data[kept] = byte;
+                    kept += 1;
+                }
+                Parse::Iac => {
+                    self.parse = match byte {
+                        IAC => {
+                            data[kept] = IAC;
+                            kept += 1;
+                            Parse::Data
+                        }
+                        WILL | WONT | DO | DONT => Parse::Option(byte),
+                        SB => {
+                            self.subnegotiation.clear();
+                            Parse::Subnegotiation
+                        }
+                        // NOP, AYT, BREAK and the like
+                        _ => Parse::Data,
+                    }
+                }
+                Parse::Option(verb) => {
+                    self.option(verb, byte);
+                    self.parse = Parse::Data;
+                }
+                Parse::Subnegotiation if byte == IAC => self.parse = Parse::SubnegotiationIac,
+                Parse::Subnegotiation => self.push_subnegotiation(byte),
+                Parse::SubnegotiationIac => match byte {
+                    SE => {
+                        self.subnegotiation_end();
+                        self.parse = Parse::Data;
+                    }
+                    _ => {
+                        self.push_subnegotiation(byte);
+                        self.parse = Parse::Subnegotiation;
+                    }
+                },
+            }
+        }
+        kept
+    }
+
+    /// Bytes to send the client: negotiation and COM-PORT-OPTION answers
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Whether the client changed the line settings since the last call
+    pub fn take_line_changed(&mut self) -> bool {
+        std::mem::take(&mut self.line_changed)
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        if !self.sent.contains(&(verb, option)) {
+            self.sent.push((verb, option));
+            self.replies.extend_from_slice(&[IAC, verb, option]);
+        }
+    }
+
+    fn option(&mut self, verb: u8, option: u8) {
+        let supported = matches!(option, BINARY | SGA | COM_PORT_OPTION);
+        match verb {
+            WILL if supported => self.negotiate(DO, option),
+            WILL => self.negotiate(DONT, option),
+            // Only the client sends COM-PORT-OPTION commands
+            DO if supported && option != COM_PORT_OPTION => self.negotiate(WILL, option),
+            DO => self.negotiate(WONT, option),
+            // WONT and DONT need no answer beyond stopping, and nothing here
+            // depends on them
+            _ => {}
+        }
+    }
+
+    fn push_subnegotiation(&mut self, byte: u8) {
+        if self.subnegotiation.len() < MAX_SUBNEGOTIATION {
+            self.subnegotiation.push(byte);
+        }
+    }
+
+    fn subnegotiation_end(&mut self) {
+        let sub = std::mem::take(&mut self.subnegotiation);
+        let [COM_PORT_OPTION, command, ref value @ ..] = sub[..] else {
+            return;
+        };
+        let before = self.line;
+        let answer: Vec<u8> = match command {
+            SIGNATURE => b"escpresso".to_vec(),
+            SET_BAUDRATE => {
+                if let Ok(bytes) = value.try_into() {
+                    let baud_rate = u32::from_be_bytes(bytes);
+                    // 0 asks for the current value
+                    if baud_rate > 0 {
+                        self.line.baud_rate = baud_rate;
+                        self.baud_rate_set = true;
+                    }
+                }
+                self.line.baud_rate.to_be_bytes().to_vec()
+            }
+            SET_DATASIZE => vec![set(&mut self.line.data_size, value, 5..=8)],
+            SET_PARITY => vec![set(&mut self.line.parity, value, 1..=5)],
+            SET_STOPSIZE => vec![set(&mut self.line.stop_size, value, 1..=3)],
+            SET_CONTROL => vec![self.control(value.first().copied().unwrap_or(0))],
+            NOTIFY_MODEMSTATE => vec![MODEM_STATE],
+            FLOWCONTROL_SUSPEND | FLOWCONTROL_RESUME => return,
+            // NOTIFY-LINESTATE, SET-LINESTATE-MASK, SET-MODEMSTATE-MASK,
+            // PURGE-DATA: acknowledged with the value received
+            _ => value.to_vec(),
+        };
+        self.line_changed |= self.line != before;
+        self.replies
+            .extend_from_slice(&[IAC, SB, COM_PORT_OPTION, command + SERVER_OFFSET]);
+        self.replies.extend_from_slice(&escape(answer));
+        self.replies.extend_from_slice(&[IAC, SE]);
+    }
+
+    /// SET-CONTROL: set or ask for flow control, DTR and RTS, returning the
+    /// value now in effect
+    fn control(&mut self, value: u8) -> u8 {
+        let on_off = |on: bool, on_value: u8| if on { on_value } else { on_value + 1 };
+        match value {
+            0 => self.line.flow_control,
+            1..=3 => {
+                self.line.flow_control = value;
+                value
+            }
+            // BREAK state: asked, on or off, it stays off
+            4..=6 => 6,
+            7 => on_off(self.line.dtr, 8),
+            8 | 9 => {
+                self.line.dtr = value == 8;
+                value
+            }
+            10 => on_off(self.line.rts, 11),
+            11 | 12 => {
+                self.line.rts = value == 11;
+                value
+            }
+            // Inbound flow control: taken as set
+            _ => value,
+        }
+    }
+}
+
+/// Set a one-byte setting unless the value is 0 (a query) or out of range,
+/// returning the setting now in effect
+fn set(setting: &mut u8, value: &[u8], range: std::ops::RangeInclusive<u8>) -> u8 {
+    if let Some(value) = value.first().filter(|value| range.contains(value)) {
+        *setting = *value;
+    }
+    *setting
+}
+
+/// Double the 0xFF bytes of data sent to the client
+pub fn escape(data: Vec<u8>) -> Vec<u8> {
+    if !data.contains(&IAC) {
+        return data;
+    }
+    let mut escaped = Vec::with_capacity(data.len() + 4);
+    for byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
@@ -0,0 +1,290 @@
+// Right-to-left display for Hebrew and Arabic lines
+//
+// Text is kept in logical order in the receipt elements (so exports and the
+// admin API see what the client sent) and converted to visual order only for
+// painting: Arabic letters are shaped into their joined presentation forms,
+// then the line is reordered with a simplified version of the Unicode
+// bidirectional algorithm (no embeddings or isolates).
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Left,
+    Right,
+    Number,
+    Neutral,
+}
+
+fn is_rtl(c: char) -> bool {
+    matches!(c,
+        '\u{0590}'..='\u{05FF}'
+        | '\u{0600}'..='\u{065F}'
+        | '\u{066A}'..='\u{06EF}'
+        | '\u{06FA}'..='\u{06FF}'
+        | '\u{FB1D}'..='\u{FDFF}'
+        | '\u{FE70}'..='\u{FEFF}')
+}
+
+fn classify(c: char) -> Class {
+    if c.is_ascii_digit() || matches!(c, '\u{0660}'..='\u{0669}' | '\u{06F0}'..='\u{06F9}') {
+        Class::Number
+    } else if is_rtl(c) {
+        Class::Right
+    } else if c.is_alphabetic() {
+        Class::Left
+    } else {
+        Class::Neutral
+    }
+}
+
+/// Visual form of a line, or None if it has no right-to-left characters
+pub fn to_visual(line: &str) -> Option<String> {
+    if !line.chars().any(is_rtl) {
+        return None;
+    }
+    let chars = shape_arabic(&line.chars().collect::<Vec<_>>());
+    Some(reorder(&chars).into_iter().collect())
+}
+
+fn reorder(chars: &[char]) -> Vec<char> {
+    let mut classes: Vec<Class> = chars.iter().map(|&c| classify(c)).collect();
+
+    // Paragraph direction comes from the first strong character
+    let rtl_paragraph = classes
+        .iter()
+        .find(|c| matches!(c, Class::Left | Class::Right))
+        .is_some_and(|c| *c == Class::Right);
+
+    // A single separator between digits belongs to the number ("12.50")
+    for i in 1..chars.len().saturating_sub(1) {
+        if classes[i] == Class::Neutral
+            && matches!(chars[i], '.' | ',' | ':' | '/')
+            && classes[i - 1] == Class::Number
+            && classes[i + 1] == Class::Number
+        {
+            classes[i] = Class::Number;
+        }
+    }
+
+    // Numbers following left-to-right text are part of it
+    let mut last_strong = if rtl_paragraph {
+        Class::Right
+    } else {
+        Class::Left
+    };
+    for class in classes.iter_mut() {
+        match *class {
+            Class::Left | Class::Right => last_strong = *class,
+            Class::Number if last_strong == Class::Left => *class = Class::Left,
+            _ => {}
+        }
+    }
+
+    // Neutrals between two characters of the same direction take that
+    // direction (numbers count as right-to-left), otherwise the paragraph's
+    let direction = |class: Class| match class {
+        Class::Left => Some(Class::Left),
+        Class::Right | Class::Number => Some(Class::Right),
+        Class::Neutral => None,
+    };
+    let paragraph = if rtl_paragraph {
+        Class::Right
+    } else {
+        Class::Left
+    };
+    let mut i = 0;
+    while i < classes.len() {
+        if classes[i] != Class::Neutral {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < classes.len() && classes[i] == Class::Neutral {
+            i += 1;
+        }
+        let before = start
+            .checked_sub(1)
+            .and_then(|j| direction(classes[j]))
+            .unwrap_or(paragraph);
+        let after = classes
+            .get(i)
+            .and_then(|&c| direction(c))
+            .unwrap_or(paragraph);
+        let resolved = if before == after { before } else { paragraph };
+        classes[start..i].fill(resolved);
+    }
+
+    let levels: Vec<u8> = classes
+        .iter()
+        .map(|class| match (rtl_paragraph, class) {
+            (false, Class::Left) => 0,
+            (false, Class::Right) => 1,
+            (true, Class::Right) => 1,
+            _ => 2,
+        })
+        .collect();
+
+    // Reverse every run at or above each level, from the highest down
+    let mut visual: Vec<(char, u8)> = chars.iter().copied().zip(levels).collect();
+    let max_level = visual.iter().map(|&(_, level)| level).max().unwrap_or(0);
+    for level in (1..=max_level).rev() {
+        let mut i = 0;
+        while i < visual.len() {
+            if visual[i].1 < level {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < visual.len() && visual[i].1 >= level {
+                i += 1;
+            }
+            visual[start..i].reverse();
+        }
+    }
+
+    visual
+        .into_iter()
+        .map(|(c, level)| if level % 2 == 1 { mirror(c) } else { c })
+        .collect()
+}
+
+fn mirror(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        _ => c,
+    }
+}
+
+/// Presentation forms of a letter: the isolated form and whether the letter
+/// also joins to the following one (4 consecutive forms: isolated, final,
+/// initial, medial) or only to the preceding one (isolated, final)
+fn forms(c: char) -> Option<(u32, bool)> {
+    let forms = match c {
+        '\u{0621}' => (0xFE80, false),
+        '\u{0622}' => (0xFE81, false),
+        '\u{0623}' => (0xFE83, false),
+        '\u{0624}' => (0xFE85, false),
+        '\u{0625}' => (0xFE87, false),
+        '\u{0626}' => (0xFE89, true),
+        '\u{0627}' => (0xFE8D, false),
+        '\u{0628}' => (0xFE8F, true),
+        '\u{0629}' => (0xFE93, false),
+        '\u{062A}' => (0xFE95, true),
+        '\u{062B}' => (0xFE99, true),
+        '\u{062C}' => (0xFE9D, true),
+        '\u{062D}' => (0xFEA1, true),
+        '\u{062E}' => (0xFEA5, true),
+        '\u{062F}' => (0xFEA9, false),
+        '\u{0630}' => (0xFEAB, false),
+        '\u{0631}' => (0xFEAD, false),
+        '\u{0632}' => (0xFEAF, false),
+        '\u{0633}' => (0xFEB1, true),
+        '\u{0634}' => (0xFEB5, true),
+        '\u{0635}' => (0xFEB9, true),
+        '\u{0636}' => (0xFEBD, true),
+        '\u{0637}' => (0xFEC1, true),
+        '\u{0638}' => (0xFEC5, true),
+        '\u{0639}' => (0xFEC9, true),
+        '\u{063A}' => (0xFECD, true),
+        '\u{0641}' => (0xFED1, true),
+        '\u{0642}' => (0xFED5, true),
+        '\u{0643}' => (0xFED9, true),
+        '\u{0644}' => (0xFEDD, true),
+        '\u{0645}' => (0xFEE1, true),
+        '\u{0646}' => (0xFEE5, true),
+        '\u{0647}' => (0xFEE9, true),
+        '\u{0648}' => (0xFEED, false),
+        '\u{0649}' => (0xFEEF, false),
+        '\u{064A}' => (0xFEF1, true),
+        // Persian letters in Windows-1256
+        '\u{067E}' => (0xFB56, true),
+        '\u{0686}' => (0xFB7A, true),
+        '\u{0698}' => (0xFB8A, false),
+        '\u{06A9}' => (0xFB8E, true),
+        '\u{06AF}' => (0xFB92, true),
+        '\u{06CC}' => (0xFBFC, true),
+        _ => return None,
+    };
+    Some(forms)
+}
+
+/// Harakat and other marks don't affect joining
+fn is_transparent(c: char) -> bool {
+    matches!(c, '\u{064B}'..='\u{065F}' | '\u{0670}')
+}
+
+/// Whether `c` connects to the letter after it
+fn joins_next(c: char) -> bool {
+    c == '\u{0640}' || forms(c).is_some_and(|(_, dual)| dual)
+}
+
+/// Whether `c` connects to the letter before it
+fn joins_previous(c: char) -> bool {
+    c == '\u{0640}' || (forms(c).is_some() && c != '\u{0621}')
+}
+
+/// Lam-alef ligature (isolated form) for the alef variant following a lam
+fn lam_alef(alef: char) -> Option<u32> {
+    match alef {
+        '\u{0622}' => Some(0xFEF5),
+        '\u{0623}' => Some(0xFEF7),
+        '\u{0625}' => Some(0xFEF9),
+        '\u{0627}' => Some(0xFEFB),
+        _ => None,
+    }
+}
+
+fn shape_arabic(chars: &[char]) -> Vec<char> {
+    let neighbour = |from: usize, step: isize| -> Option<char> {
+        let mut i = from as isize + step;
+        while i >= 0 && (i as usize) < chars.len() {
+            let c = chars[i as usize];
+            if !is_transparent(c) {
+                return Some(c);
+            }
+            i += step;
+        }
+        None
+    };
+
+    let mut shaped = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let Some((isolated, dual)) = forms(c) else {
+            shaped.push(c);
+            i += 1;
+            continue;
+        };
+        let prev_joins = neighbour(i, -1).is_some_and(joins_next) && c != '\u{0621}';
+
+        // Lam followed by alef becomes a single ligature
+        if c == '\u{0644}' {
+            if let Some(ligature) = chars.get(i + 1).and_then(|&next| lam_alef(next)) {
+                let form = ligature + u32::from(prev_joins);
+                shaped.extend(char::from_u32(form));
+                i += 2;
+                continue;
+            }
+        }
+
+        let next_joins = dual && neighbour(i, 1).is_some_and(joins_previous);
+        let offset = match (prev_joins, next_joins) {
+            (false, false) => 0,
+            (true, false) => 1,
+            (false, true) => 2,
+            (true, true) => 3,
+        };
+        shaped.extend(char::from_u32(isolated + offset));
+        i += 1;
+    }
+    shaped
+}
@@ -0,0 +1,152 @@
+// Rhai scripting hooks
+//
+// A hooks script can define any of these functions:
+//
+//   fn on_command(cmd)                  // cmd = #{ name: "ESC a", bytes: [0x1B, 0x61, 1] }
+//   fn on_status_query(query, response) // both are byte arrays
+//   fn on_job_end(job)                  // job = #{ source, bytes, elements }
+//
+// on_command may return a byte array that is sent back to the client.
+// on_status_query may return a byte array that replaces the response
+// (e.g. to inject error bits); returning nothing keeps the default.
+// on_job_end may return a string or an array of strings to tag the job.
+
+use anyhow::Result;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use std::path::{Path, PathBuf};
+
+// Keeps a runaway script (e.g. an infinite loop) from stalling a connection
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: AST,
+    path: PathBuf,
+    has_on_command: bool,
+    has_on_status_query: bool,
+    has_on_job_end: bool,
+}
+
+/// Summary of a finished job, passed to `on_job_end`
+pub struct JobSummary<'a> {
+    pub source: &'a str,
+    pub bytes: usize,
+    pub elements: usize,
+}
+
+impl ScriptHooks {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+        let defines = |name: &str| ast.iter_functions().any(|f| f.name == name);
+        let has_on_command = defines("on_command");
+        let has_on_status_query = defines("on_status_query");
+        let has_on_job_end = defines("on_job_end");
+
+        Ok(Self {
+            engine,
+            ast,
+            path: path.to_path_buf(),
+            has_on_command,
+            has_on_status_query,
+            has_on_job_end,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of the hook functions the script defines, for display
+    pub fn defined_hooks(&self) -> Vec<&'static str> {
+        let mut hooks = Vec::new();
+        if self.has_on_command {
+            hooks.push("on_command");
+        }
+        if self.has_on_status_query {
+            hooks.push("on_status_query");
+        }
+        if self.has_on_job_end {
+            hooks.push("on_job_end");
+        }
+        hooks
+    }
+
+    /// Run `on_command`; returns extra bytes to send to the client, if any
+    pub fn on_command(&self, name: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        if !self.has_on_command {
+            return None;
+        }
+        let mut cmd = Map::new();
+        cmd.insert("name".into(), name.into());
+        cmd.insert("bytes".into(), to_array(bytes).into());
+
+        let result = self.call("on_command", (cmd,))?;
+        from_array(result)
+    }
+
+    /// Run `on_status_query`; returns a replacement response, if any
+    pub fn on_status_query(&self, query: &[u8], response: &[u8]) -> Option<Vec<u8>> {
+        if !self.has_on_status_query {
+            return None;
+        }
+        let result = self.call("on_status_query", (to_array(query), to_array(response)))?;
+        from_array(result)
+    }
+
+    /// Run `on_job_end`; returns the tags the script attached to the job
+    pub fn on_job_end(&self, job: &JobSummary) -> Vec<String> {
+        if !self.has_on_job_end {
+            return Vec::new();
+        }
+        let mut map = Map::new();
+        map.insert("source".into(), job.source.into());
+        map.insert("bytes".into(), (job.bytes as i64).into());
+        map.insert("elements".into(), (job.elements as i64).into());
+
+        let Some(result) = self.call("on_job_end", (map,)) else {
+            return Vec::new();
+        };
+        if result.is_string() {
+            return vec![result.to_string()];
+        }
+        match result.try_cast::<Array>() {
+            Some(tags) => tags.into_iter().map(|t| t.to_string()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        match self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, args)
+        {
+            Ok(result) if result.is_unit() => None,
+            Ok(result) => Some(result),
+            Err(e) => {
+                eprintln!("Script error in {}: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+fn to_array(bytes: &[u8]) -> Array {
+    bytes.iter().map(|&b| Dynamic::from(b as i64)).collect()
+}
+
+fn from_array(value: Dynamic) -> Option<Vec<u8>> {
+    let array = value.try_cast::<Array>()?;
+    Some(
+        array
+            .into_iter()
+            .filter_map(|v| v.as_int().ok())
+            .map(|v| v as u8)
+            .collect(),
+    )
+}
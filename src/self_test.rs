@@ -0,0 +1,114 @@
+// Test prints, as GS ( A and the Self test button print them: the self-test
+// page a printer prints when FEED is held at power-up (model, firmware, the
+// settings a real printer takes from its DIP switches and the characters of
+// the default code page) and the rolling pattern.
+//
+// Both are ESC/POS jobs, parsed like any other so they show what the emulator
+// actually renders with the current settings.
+
+use crate::status::{SimulatedStatus, PROFILES};
+use crate::{code_page_name, CustomCodePages, PaperSize, ESC, GS, LF};
+
+/// Lines of the rolling pattern
+const ROLLING_LINES: usize = 12;
+
+/// The self-test page for the printer's current settings. Text is printed
+/// in the default code page, so parse it with that code page.
+pub fn page(
+    status: &SimulatedStatus,
+    paper_size: PaperSize,
+    custom_code_pages: &CustomCodePages,
+) -> Vec<u8> {
+    let columns = paper_size.font_columns(0, &status.columns);
+    let profile = PROFILES
+        .iter()
+        .find(|(profile, _)| *profile == status.profile)
+        .map_or("", |(_, label)| *label);
+    let code_page = code_page_name(status.code_page, custom_code_pages);
+
+    let mut job = vec![ESC, b'@', ESC, b'a', 1, ESC, b'!', 0x30];
+    line(&mut job, "escpresso");
+    job.extend_from_slice(&[ESC, b'!', 0]);
+    line(&mut job, &format!("Ver {}", env!("CARGO_PKG_VERSION")));
+    line(&mut job, "");
+    job.extend_from_slice(&[ESC, b'a', 0]);
+    heading(&mut job, "Settings", columns);
+    for (name, value) in [
+        ("Printer profile", profile.to_string()),
+        ("Paper width", paper_size.label().to_string()),
+        (
+            "Columns (Font A/B)",
+            format!(
+                "{} / {}",
+                paper_size.font_columns(0, &status.columns),
+                paper_size.font_columns(1, &status.columns)
+            ),
+        ),
+        ("Code page", code_page),
+        ("Auto cutter", "Installed".to_string()),
+        ("Resident fonts", "Font A, Font B".to_string()),
+    ] {
+        // Value right-aligned after the name, on its own line if too long
+        let padding = columns.saturating_sub(name.len() + value.chars().count());
+        if padding > 0 {
+            line(
+                &mut job,
+                &format!("{}{}{}", name, " ".repeat(padding), value),
+            );
+        } else {
+            line(&mut job, name);
+            line(&mut job, &format!("  {}", value));
+        }
+    }
+    line(&mut job, "");
+    heading(&mut job, "Character set", columns);
+    // Rows of 16 characters, spaced out where the paper is wide enough
+    let spaced = columns >= 4 + 2 * 16;
+    for row in (0x20..=0xF0u8).step_by(16) {
+        job.extend_from_slice(format!("{:X}x  ", row >> 4).as_bytes());
+        for byte in row..=row + 15 {
+            // DEL isn't a character
+            job.push(if byte == 0x7F { b' ' } else { byte });
+            if spaced {
+                job.push(b' ');
+            }
+        }
+        job.push(LF);
+    }
+    line(&mut job, "");
+    job.extend_from_slice(&[ESC, b'a', 1]);
+    line(&mut job, "*** completed ***");
+    job.extend_from_slice(&[GS, b'V', 66, 3]);
+    job
+}
+
+/// Lines of printable ASCII, each starting one character further along
+pub fn rolling_pattern(paper_size: PaperSize) -> Vec<u8> {
+    let printable: Vec<u8> = (0x20..0x7F).collect();
+    let mut job = vec![ESC, b'@'];
+    for start in 0..ROLLING_LINES {
+        job.extend(
+            printable
+                .iter()
+                .cycle()
+                .skip(start)
+                .take(paper_size.chars_per_line()),
+        );
+        job.push(LF);
+    }
+    job.extend_from_slice(&[GS, b'V', 66, 3]);
+    job
+}
+
+fn line(job: &mut Vec<u8>, text: &str) {
+    job.extend_from_slice(text.as_bytes());
+    job.push(LF);
+}
+
+/// Bold title over a rule
+fn heading(job: &mut Vec<u8>, title: &str, columns: usize) {
+    job.extend_from_slice(&[ESC, b'E', 1]);
+    line(job, title);
+    job.extend_from_slice(&[ESC, b'E', 0]);
+    line(job, &"-".repeat(columns));
+}
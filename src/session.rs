@@ -0,0 +1,254 @@
+// Session persistence: received jobs (raw bytes) and the parsed receipt are
+// saved to a directory after every change and restored on the next launch.
+// Saving happens on a thread of its own (see Saver), from a copy of the
+// state, so the receipt isn't locked while the files are written.
+//
+// Layout:
+//   <dir>/session.json       paper size, job list and parsed elements
+//   <dir>/job-00001.bin      raw bytes of each job
+
+use crate::{Job, LintWarning, PaperSize, ReceiptElement};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+const SESSION_FILE: &str = "session.json";
+
+#[derive(Serialize, Deserialize)]
+struct JobEntry {
+    id: u64,
+    source: String,
+    raw_file: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<LintWarning>,
+    #[serde(default)]
+    received_at: u64,
+    #[serde(default)]
+    finished_at: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    arrivals: Vec<(u64, usize)>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    note: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+// Generic over the element lists so saving can borrow them instead of cloning
+#[derive(Serialize, Deserialize)]
+struct SessionFile<E, T> {
+    paper_size: PaperSize,
+    jobs: Vec<JobEntry>,
+    // Last job id handed out, so ids aren't reused after the jobs are
+    // cleared. Sessions saved before it was kept have none.
+    #[serde(default)]
+    last_job_id: u64,
+    elements: E,
+    // Sessions saved before elements were timestamped have none
+    #[serde(default)]
+    element_times: T,
+}
+
+/// What was restored from a previous run
+pub struct RestoredSession {
+    pub paper_size: PaperSize,
+    pub jobs: Vec<Job>,
+    pub last_job_id: u64,
+    pub elements: Vec<ReceiptElement>,
+    pub element_times: Vec<u64>,
+}
+
+pub struct Session {
+    dir: PathBuf,
+}
+
+impl Session {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("creating session directory {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Load the previous session, if one was saved
+    pub fn restore(&self) -> Result<Option<RestoredSession>> {
+        let path = self.dir.join(SESSION_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file: SessionFile<Vec<ReceiptElement>, Vec<u64>> =
+            serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("parsing {}", path.display()))?;
+
+        let mut jobs = Vec::with_capacity(file.jobs.len());
+        for entry in file.jobs {
+            let raw_path = self.dir.join(&entry.raw_file);
+            let raw = std::fs::read(&raw_path)
+                .with_context(|| format!("reading {}", raw_path.display()))?;
+            jobs.push(Job {
+                id: entry.id,
+                source: entry.source,
+                raw,
+                warnings: entry.warnings,
+                received_at: entry.received_at,
+                finished_at: entry.finished_at,
+                capture: Some(raw_path),
+                arrivals: entry.arrivals,
+                note: entry.note,
+                tags: entry.tags,
+            });
+        }
+
+        let last_job_id = jobs
+            .iter()
+            .map(|job| job.id)
+            .fold(file.last_job_id, u64::max);
+        Ok(Some(RestoredSession {
+            paper_size: file.paper_size,
+            jobs,
+            last_job_id,
+            elements: file.elements,
+            element_times: file.element_times,
+        }))
+    }
+
+    pub fn save(
+        &self,
+        paper_size: PaperSize,
+        jobs: &[Job],
+        last_job_id: u64,
+        elements: &[ReceiptElement],
+        element_times: &[u64],
+    ) -> Result<()> {
+        let mut entries = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            let raw_file = format!("job-{:05}.bin", job.id);
+            let raw_path = self.dir.join(&raw_file);
+            // Raw bytes never change once a job is finished, and ids aren't
+            // reused
+            if !raw_path.exists() {
+                std::fs::write(&raw_path, &job.raw)?;
+            }
+            entries.push(JobEntry {
+                id: job.id,
+                source: job.source.clone(),
+                raw_file,
+                warnings: job.warnings.clone(),
+                received_at: job.received_at,
+                finished_at: job.finished_at,
+                arrivals: job.arrivals.clone(),
+                note: job.note.clone(),
+                tags: job.tags.clone(),
+            });
+        }
+        self.remove_stale_jobs(&entries)?;
+
+        let file = SessionFile {
+            paper_size,
+            jobs: entries,
+            last_job_id,
+            elements,
+            element_times,
+        };
+        // Write then rename so a crash mid-save never leaves a truncated session
+        let tmp = self.dir.join(format!("{}.tmp", SESSION_FILE));
+        std::fs::write(&tmp, serde_json::to_vec(&file)?)?;
+        std::fs::rename(&tmp, self.dir.join(SESSION_FILE))?;
+        Ok(())
+    }
+
+    /// Hand the session to a saving thread
+    pub fn spawn_saver(self) -> Saver {
+        let (sender, receiver) = mpsc::channel();
+        let dir = self.dir.clone();
+        std::thread::spawn(move || self.save_snapshots(receiver));
+        Saver { dir, sender }
+    }
+
+    /// Save the snapshots sent until the senders are gone. Those queued up
+    /// while one was being written are outdated by the last of them.
+    fn save_snapshots(self, receiver: mpsc::Receiver<Request>) {
+        while let Ok(request) = receiver.recv() {
+            let mut latest = None;
+            let mut flushed = Vec::new();
+            for request in std::iter::once(request).chain(receiver.try_iter()) {
+                match request {
+                    Request::Save(snapshot) => latest = Some(snapshot),
+                    Request::Flush(done) => flushed.push(done),
+                }
+            }
+            if let Some(snapshot) = latest {
+                if let Err(e) = self.save(
+                    snapshot.paper_size,
+                    &snapshot.jobs,
+                    snapshot.last_job_id,
+                    &snapshot.elements,
+                    &snapshot.element_times,
+                ) {
+                    eprintln!("ERROR: Failed to save session: {}", e);
+                }
+            }
+            for done in flushed {
+                _ = done.send(());
+            }
+        }
+    }
+
+    fn remove_stale_jobs(&self, keep: &[JobEntry]) -> Result<()> {
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let is_job_file = name.starts_with("job-") && name.ends_with(".bin");
+            if is_job_file && !keep.iter().any(|job| job.raw_file == name) {
+                std::fs::remove_file(self.dir.join(&name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a session is saved from, copied out of the app state
+pub struct Snapshot {
+    pub paper_size: PaperSize,
+    pub jobs: Vec<Job>,
+    pub last_job_id: u64,
+    pub elements: Vec<ReceiptElement>,
+    pub element_times: Vec<u64>,
+}
+
+enum Request {
+    Save(Snapshot),
+    /// Answered once the saves sent before it are written
+    Flush(mpsc::Sender<()>),
+}
+
+/// Sends snapshots to the session's saving thread without waiting for them
+/// to be written
+#[derive(Debug, Clone)]
+pub struct Saver {
+    dir: PathBuf,
+    sender: mpsc::Sender<Request>,
+}
+
+impl Saver {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn save(&self, snapshot: Snapshot) {
+        // Fails only once the saving thread is gone
+        _ = self.sender.send(Request::Save(snapshot));
+    }
+
+    /// Wait for the saves sent so far to be written, before quitting
+    pub fn flush(&self) {
+        let (done, written) = mpsc::channel();
+        if self.sender.send(Request::Flush(done)).is_ok() {
+            _ = written.recv();
+        }
+    }
+}
@@ -0,0 +1,400 @@
+// Simulated printer status and the status bytes reported for it
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Error and sensor conditions the emulated printer reports to clients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulatedStatus {
+    pub offline: bool,
+    pub cover_open: bool,
+    pub paper_near_end: bool,
+    pub paper_out: bool,
+    pub cutter_error: bool,
+    pub unrecoverable_error: bool,
+    /// Drawer 1, kicked on connector pin 2
+    pub drawer_open: bool,
+    /// Drawer 2, kicked on connector pin 5
+    pub drawer2_open: bool,
+    /// Whose open switch drives connector pin 3, the level status reports
+    pub drawer_sensor: DrawerSensor,
+    pub profile: Profile,
+    /// Code page (ESC t number) in effect until the client selects one, like
+    /// the DIP switch setting of a real printer
+    pub code_page: u8,
+    /// Columns per line of Fonts A, B and C, 0 for the Epson count at the
+    /// paper width (48 and 64 on 80 mm), for clones that fit other counts
+    pub columns: [u16; 3],
+    /// Legacy firmware: SO selects double width until the line is printed,
+    /// SI and DC4 cancel it. Epson printers ignore these bytes.
+    pub so_double_width: bool,
+    /// Ink A of ink models (Profile::Ink)
+    pub ink_near_end: bool,
+    pub ink_out: bool,
+    /// A printed label waits in the peeler to be taken (Profile::Peeler)
+    pub label_waiting: bool,
+    /// Set per connection while its receive buffer is full, not configurable
+    #[serde(skip)]
+    pub buffer_full: bool,
+    /// Raw DLE EOT bytes that replace the computed ones
+    pub overrides: StatusOverrides,
+}
+
+/// Printer family, deciding which extended DLE EOT n a requests are answered.
+/// Printers ignore the requests their family doesn't support.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// DLE EOT 1-4 only
+    #[default]
+    Receipt,
+    /// Also DLE EOT 7 1/2: ink A and ink B status
+    Ink,
+    /// Also DLE EOT 8 3: peeler status
+    Peeler,
+}
+
+/// Profiles offered in the settings, with their labels
+pub const PROFILES: [(Profile, &str); 3] = [
+    (Profile::Receipt, "Receipt"),
+    (Profile::Ink, "Ink (DLE EOT 7)"),
+    (Profile::Peeler, "Label peeler (DLE EOT 8)"),
+];
+
+/// Drawer whose open switch is wired to connector pin 3. Dual-drawer
+/// cables share the single sensor input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawerSensor {
+    #[default]
+    Drawer1,
+    Drawer2,
+    /// Both switches in parallel: high while either drawer is open
+    Either,
+}
+
+/// Drawer sensor wirings offered in the settings, with their labels
+pub const DRAWER_SENSORS: [(DrawerSensor, &str); 3] = [
+    (DrawerSensor::Drawer1, "Drawer 1 (pin 2)"),
+    (DrawerSensor::Drawer2, "Drawer 2 (pin 5)"),
+    (DrawerSensor::Either, "Either drawer"),
+];
+
+/// Manually set DLE EOT status bytes, for combinations the flags can't express
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusOverrides {
+    pub printer: Option<u8>,
+    pub offline_cause: Option<u8>,
+    pub error_cause: Option<u8>,
+    pub paper_sensor: Option<u8>,
+}
+
+impl StatusOverrides {
+    /// Override for DLE EOT n (1-4)
+    pub fn get(&self, n: u8) -> Option<u8> {
+        match n {
+            1 => self.printer,
+            2 => self.offline_cause,
+            3 => self.error_cause,
+            4 => self.paper_sensor,
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, n: u8) -> Option<&mut Option<u8>> {
+        match n {
+            1 => Some(&mut self.printer),
+            2 => Some(&mut self.offline_cause),
+            3 => Some(&mut self.error_cause),
+            4 => Some(&mut self.paper_sensor),
+            _ => None,
+        }
+    }
+}
+
+/// Names of the DLE EOT n status bits, from bit 0 to bit 7 (empty = fixed)
+pub const STATUS_BITS: [(&str, [&str; 8]); 4] = [
+    (
+        "Printer",
+        [
+            "",
+            "",
+            "Drawer pin 3 high",
+            "Offline",
+            "",
+            "Waiting for recovery",
+            "Feed button",
+            "",
+        ],
+    ),
+    (
+        "Offline cause",
+        [
+            "",
+            "",
+            "Cover open",
+            "Paper fed by button",
+            "",
+            "Paper end stop",
+            "Error",
+            "",
+        ],
+    ),
+    (
+        "Error cause",
+        [
+            "",
+            "",
+            "Recoverable error",
+            "Autocutter error",
+            "",
+            "Unrecoverable error",
+            "Auto-recoverable error",
+            "",
+        ],
+    ),
+    (
+        "Paper sensor",
+        [
+            "",
+            "",
+            "Near end",
+            "Near end",
+            "",
+            "Paper end",
+            "Paper end",
+            "",
+        ],
+    ),
+];
+
+/// Flags that can be changed on a schedule: (field name, label)
+pub const FLAGS: [(&str, &str); 11] = [
+    ("offline", "Offline"),
+    ("cover_open", "Cover open"),
+    ("paper_near_end", "Paper near end"),
+    ("paper_out", "Paper out"),
+    ("cutter_error", "Cutter error"),
+    ("unrecoverable_error", "Unrecoverable error"),
+    ("drawer_open", "Drawer 1 open"),
+    ("drawer2_open", "Drawer 2 open"),
+    ("ink_near_end", "Ink near end"),
+    ("ink_out", "Ink out"),
+    ("label_waiting", "Label waiting"),
+];
+
+/// A flag change that takes effect at a later time
+#[derive(Debug, Clone)]
+pub struct ScheduledChange {
+    pub due: Instant,
+    pub flag: &'static str,
+    pub value: bool,
+}
+
+impl SimulatedStatus {
+    /// The status with the fields present in `changes` set to their values,
+    /// the others left untouched
+    pub fn merged(
+        self,
+        changes: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Self, String> {
+        let mut current = serde_json::json!(self);
+        for (name, value) in changes {
+            match current.get_mut(&name) {
+                Some(field) => *field = value,
+                None => return Err(format!("unknown status flag '{}'", name)),
+            }
+        }
+        serde_json::from_value(current).map_err(|e| format!("invalid status: {}", e))
+    }
+
+    /// Flag by field name, see [`FLAGS`]
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "offline" => Some(&mut self.offline),
+            "cover_open" => Some(&mut self.cover_open),
+            "paper_near_end" => Some(&mut self.paper_near_end),
+            "paper_out" => Some(&mut self.paper_out),
+            "cutter_error" => Some(&mut self.cutter_error),
+            "unrecoverable_error" => Some(&mut self.unrecoverable_error),
+            "drawer_open" => Some(&mut self.drawer_open),
+            "drawer2_open" => Some(&mut self.drawer2_open),
+            "ink_near_end" => Some(&mut self.ink_near_end),
+            "ink_out" => Some(&mut self.ink_out),
+            "label_waiting" => Some(&mut self.label_waiting),
+            _ => None,
+        }
+    }
+
+    /// A kick-out pulse on connector pin 2 or 5 opens that pin's drawer.
+    /// Returns whether it was closed.
+    pub fn kick_drawer(&mut self, pin: u8) -> bool {
+        let drawer = match pin {
+            2 => &mut self.drawer_open,
+            5 => &mut self.drawer2_open,
+            _ => return false,
+        };
+        !std::mem::replace(drawer, true)
+    }
+
+    /// Connector pin 3 level, reported as the drawer status
+    fn drawer_pin_high(&self) -> bool {
+        match self.drawer_sensor {
+            DrawerSensor::Drawer1 => self.drawer_open,
+            DrawerSensor::Drawer2 => self.drawer2_open,
+            DrawerSensor::Either => self.drawer_open || self.drawer2_open,
+        }
+    }
+
+    /// DLE ENQ 1/2: clear the recoverable (cutter) error, returning false
+    /// if there was none. Unrecoverable errors need a power cycle.
+    pub fn recover(&mut self) -> bool {
+        std::mem::take(&mut self.cutter_error)
+    }
+
+    fn has_error(&self) -> bool {
+        self.cutter_error || self.unrecoverable_error
+    }
+
+    fn ink_out(&self) -> bool {
+        self.profile == Profile::Ink && self.ink_out
+    }
+
+    /// Real printers go offline while the cover is open, paper or ink is out
+    /// or an error is pending, and are busy while their receive buffer is full
+    fn is_offline(&self) -> bool {
+        self.offline
+            || self.cover_open
+            || self.paper_out
+            || self.ink_out()
+            || self.has_error()
+            || self.buffer_full
+    }
+
+    /// DLE EOT n response (bits 1 and 4 are fixed high)
+    pub fn real_time_status(&self, n: u8) -> u8 {
+        if let Some(value) = self.overrides.get(n) {
+            return value;
+        }
+        let mut status = 0x12;
+        match n {
+            // Printer status
+            1 => {
+                if self.drawer_pin_high() {
+                    status |= 0x04;
+                }
+                if self.is_offline() {
+                    status |= 0x08;
+                }
+            }
+            // Offline cause
+            2 => {
+                if self.cover_open {
+                    status |= 0x04;
+                }
+                if self.paper_out {
+                    status |= 0x20;
+                }
+                if self.has_error() {
+                    status |= 0x40;
+                }
+            }
+            // Error cause
+            3 => {
+                if self.cutter_error {
+                    status |= 0x08;
+                }
+                if self.unrecoverable_error {
+                    status |= 0x20;
+                }
+            }
+            // Roll paper sensor
+            4 => {
+                if self.paper_near_end {
+                    status |= 0x0C;
+                }
+                if self.paper_out {
+                    status |= 0x60;
+                }
+            }
+            _ => {}
+        }
+        status
+    }
+
+    /// DLE EOT n a response (n = 7, 8 or 18), or None if the profile's
+    /// printers don't answer it
+    pub fn extended_status(&self, n: u8, a: u8) -> Option<u8> {
+        let mut status = 0x12;
+        match (self.profile, n, a) {
+            // Ink A status, same layout as the roll paper sensor
+            (Profile::Ink, 7, 1) => {
+                if self.ink_near_end {
+                    status |= 0x0C;
+                }
+                if self.ink_out {
+                    status |= 0x60;
+                }
+            }
+            // Ink B status
+            (Profile::Ink, 7, 2) => {}
+            // Peeler status: bit 2 set while a label waits to be removed
+            (Profile::Peeler, 8, 3) => {
+                if self.label_waiting {
+                    status |= 0x04;
+                }
+            }
+            _ => return None,
+        }
+        Some(status)
+    }
+
+    /// GS r n response
+    pub fn transmit_status(&self, n: u8) -> u8 {
+        match n {
+            // Drawer kick-out connector status
+            2 | 50 => u8::from(self.drawer_pin_high()),
+            // Paper sensor status. 0x08 is what receiptio expects from a
+            // healthy printer ((value & 0x90) == 0)
+            _ => {
+                let mut status = 0x08;
+                if self.paper_near_end {
+                    status |= 0x03;
+                }
+                if self.paper_out {
+                    status |= 0x04;
+                }
+                status
+            }
+        }
+    }
+
+    /// 4-byte Automatic Status Back frame
+    pub fn asb(&self) -> [u8; 4] {
+        let mut frame = [0x10, 0x00, 0x00, 0x00];
+        if self.drawer_pin_high() {
+            frame[0] |= 0x04;
+        }
+        if self.is_offline() {
+            frame[0] |= 0x08;
+        }
+        if self.cover_open {
+            frame[0] |= 0x20;
+        }
+        if self.cutter_error {
+            frame[1] |= 0x08;
+        }
+        if self.unrecoverable_error {
+            frame[1] |= 0x20;
+        }
+        if self.paper_near_end {
+            frame[2] |= 0x03;
+        }
+        if self.paper_out {
+            frame[2] |= 0x0C;
+        }
+        frame
+    }
+}
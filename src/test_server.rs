@@ -0,0 +1,95 @@
+// In-process emulator for integration tests: a listener on a free local port
+// whose jobs come back parsed over a channel, so a test can print through
+// its real client code and check the receipt without a running escpresso.
+//
+//   let mut server = escpresso::test_server::spawn().await?;
+//   my_pos::print_receipt(("127.0.0.1", server.port)).await?;
+//   let job = server.jobs.recv().await.unwrap();
+//   assert!(job.elements.iter().any(|e| matches!(e, ReceiptElement::PaperCut { .. })));
+//
+// Each connection is one job, delivered when the client closes it. Status
+// queries are answered as by a ready printer. The listener stops when the
+// TestServer is dropped.
+
+use crate::connection::{JobEvent, JobSplitter};
+use crate::{EscPosRenderer, LintWarning, ReceiptElement};
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+pub struct TestServer {
+    /// Port on 127.0.0.1 to print to
+    pub port: u16,
+    /// Jobs in the order their connections closed
+    pub jobs: mpsc::UnboundedReceiver<TestJob>,
+    listener: JoinHandle<()>,
+}
+
+/// What one connection printed
+#[derive(Debug, Clone, Default)]
+pub struct TestJob {
+    pub raw: Vec<u8>,
+    pub elements: Vec<ReceiptElement>,
+    pub warnings: Vec<LintWarning>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Start an emulator on a port picked by the OS. Needs a Tokio runtime.
+pub async fn spawn() -> Result<TestServer> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let (sender, jobs) = mpsc::unbounded_channel();
+    let listener = tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match receive_job(socket).await {
+                    // The test may have stopped listening
+                    Ok(job) => drop(sender.send(job)),
+                    Err(e) => eprintln!("Test server connection error: {:#}", e),
+                }
+            });
+        }
+    });
+    Ok(TestServer {
+        port,
+        jobs,
+        listener,
+    })
+}
+
+async fn receive_job(mut socket: TcpStream) -> Result<TestJob> {
+    let mut renderer = EscPosRenderer::new(false);
+    let mut jobs = JobSplitter::default();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        jobs.receive(&mut renderer, &buffer[..n])?;
+        let responses = renderer.take_responses();
+        if !responses.is_empty() {
+            socket.write_all(&responses).await?;
+        }
+    }
+    jobs.finish(&mut renderer)?;
+    let mut job = TestJob::default();
+    for event in jobs.take_events() {
+        match event {
+            JobEvent::Elements(elements) => job.elements.extend(elements),
+            JobEvent::Finished(finished) => {
+                job.raw = finished.raw;
+                job.warnings = finished.warnings;
+            }
+        }
+    }
+    Ok(job)
+}
@@ -1,109 +1,158 @@
-// Unit tests for ESC/POS command parsing
-// Note: These tests would work better as #[cfg(test)] modules in main.rs
-// to access private functions. This file shows what should be tested.
+// ESC/POS command parsing, checked on the elements the parser produces
 
-#[cfg(test)]
-mod tests {
-    // These tests are examples of what should be tested once the code is refactored
-    // to expose the command parsing logic
+use escpresso::status::SimulatedStatus;
+use escpresso::{parse_bytes, Alignment, EscPosRenderer, ParseOptions, ReceiptElement};
 
-    #[test]
-    fn test_esc_init_command() {
-        // ESC @ should initialize printer
-        let data = b"\x1B\x40";
-        // Expected: reset all formatting state
-    }
+fn parse(data: &[u8]) -> Vec<ReceiptElement> {
+    parse_bytes(data, &ParseOptions::default())
+}
 
-    #[test]
-    fn test_bold_on_off() {
-        // ESC E 1 = bold on, ESC E 0 = bold off
-        let data = b"\x1B\x45\x01\x1B\x45\x00";
-        // Expected: state.bold = true, then false
-    }
+/// The text lines, without their formatting
+fn lines(elements: &[ReceiptElement]) -> Vec<&str> {
+    elements
+        .iter()
+        .filter_map(|element| match element {
+            ReceiptElement::Text { content, .. } => Some(content.as_str()),
+            _ => None,
+        })
+        .collect()
+}
 
-    #[test]
-    fn test_alignment() {
-        // ESC a 0 = left, 1 = center, 2 = right
-        let data_left = b"\x1B\x61\x00";
-        let data_center = b"\x1B\x61\x01";
-        let data_right = b"\x1B\x61\x02";
-        // Expected: alignment state changes
-    }
+#[test]
+fn test_text_with_formatting() {
+    let elements = parse(b"\x1B\x40\x1B\x45\x01Bold\n\x1B\x45\x00\x1B\x61\x01Normal\n");
+    assert!(matches!(
+        elements.as_slice(),
+        [
+            ReceiptElement::Text { content: bold, bold: true, alignment: Alignment::Left, .. },
+            ReceiptElement::Text { content: normal, bold: false, alignment: Alignment::Center, .. },
+        ] if bold == "Bold" && normal == "Normal"
+    ));
+}
 
-    #[test]
-    fn test_double_width_height() {
-        // ESC ! with bits 4 and 5
-        let data_double = b"\x1B\x21\x30"; // 0x30 = 0b00110000
-                                           // Expected: double_width = true, double_height = true
-    }
+#[test]
+fn test_partial_and_invalid_commands() {
+    // ESC alone waits for more data; ESC 0xFF is skipped with the parameter
+    // byte unknown commands are assumed to have
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.process_data(b"Hi\n\x1B").unwrap();
+    renderer.process_data(b"\xFF\x01There\n").unwrap();
+    assert_eq!(lines(&renderer.take_elements()), ["Hi", "There"]);
+}
 
-    #[test]
-    fn test_underline() {
-        // ESC - 1 = underline on, ESC - 0 = underline off
-        let data = b"\x1B\x2D\x01\x1B\x2D\x00";
-        // Expected: state.underline = true, then false
-    }
+#[test]
+fn test_barcode_functions() {
+    // Function A: NUL terminated; Function B: length prefixed
+    let elements = parse(b"\x1Dk\x04ABC-1\x00\x1Dk\x49\x05{B123\n");
+    let barcodes: Vec<(&str, &str)> = elements
+        .iter()
+        .filter_map(|element| match element {
+            ReceiptElement::Barcode {
+                symbology, text, ..
+            } => Some((symbology.as_str(), text.as_str())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(barcodes, [("CODE39", "*ABC-1*"), ("CODE128", "123")]);
+}
 
-    #[test]
-    fn test_qr_code_store_data() {
-        // GS ( k - QR code store command
-        let url = "https://test.com";
-        let len = (url.len() + 3) as u16;
-        let mut data = Vec::new();
-        data.extend_from_slice(b"\x1D\x28\x6B");
-        data.extend_from_slice(&len.to_le_bytes());
-        data.extend_from_slice(b"\x31\x50\x30");
-        data.extend_from_slice(url.as_bytes());
+#[test]
+fn test_barcode_waits_for_data() {
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.process_data(b"\x1Dk\x04AB").unwrap();
+    renderer.process_data(b"C\x00").unwrap();
+    renderer.process_data(b"\x1Dk\x49\x04{B").unwrap();
+    assert!(matches!(
+        renderer.take_elements().as_slice(),
+        [ReceiptElement::Barcode { text, .. }] if text == "*ABC*"
+    ));
+    renderer.process_data(b"12\n").unwrap();
+    assert!(matches!(
+        renderer.take_elements().as_slice(),
+        [ReceiptElement::Barcode { text, .. }, ..] if text == "12"
+    ));
+}
 
-        // Expected: QR data stored in state
-    }
+#[test]
+fn test_tab_stops() {
+    // Stops at columns 4 and 8 of Font A
+    let elements = parse(b"\x1BD\x04\x08\x00AB\tC\tD\n\tE\n");
+    assert_eq!(lines(&elements), ["AB  C   D", "    E"]);
 
-    #[test]
-    fn test_raster_graphics_esc_star() {
-        // ESC * m nL nH d1...dk
-        let data = b"\x1B\x2A\x00\x08\x00\xAA\x55\xAA\x55\xAA\x55\xAA\x55";
-        // Expected: raster image element created
-    }
+    // Without a stop further on the line HT is ignored
+    let elements = parse(b"\x1BD\x02\x00ABC\tD\n");
+    assert_eq!(lines(&elements), ["ABCD"]);
+}
 
-    #[test]
-    fn test_line_feed() {
-        // LF (0x0A) should advance to next line
-        let data = b"\x0A";
-        // Expected: y position increases
-    }
+#[test]
+fn test_tab_stops_past_print_area() {
+    // 255 dots of character spacing: the stop is far past the line, and set
+    // at its end
+    let elements = parse(b"\x1B \xFF\x1BD\xFA\x00\x1B \x00\tA\n");
+    assert!(lines(&elements).concat().ends_with('A'));
+}
 
-    #[test]
-    fn test_carriage_return() {
-        // CR (0x0D) should reset x position
-        let data = b"\x0D";
-        // Expected: x position resets to 0
-    }
+#[test]
+fn test_error_recovery() {
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.set_status(SimulatedStatus {
+        cutter_error: true,
+        ..Default::default()
+    });
+    // DLE ENQ 2 recovers, dropping the buffered line
+    renderer.process_data(b"LOST\x10\x05\x02KEPT\n").unwrap();
+    assert!(renderer.take_error_recovery());
+    assert_eq!(lines(&renderer.take_elements()), ["KEPT"]);
 
-    #[test]
-    fn test_text_with_formatting() {
-        // Complete sequence: init, bold on, text, bold off
-        let data = b"\x1B\x40\x1B\x45\x01Bold\x1B\x45\x00Normal";
-        // Expected: "Bold" in bold, "Normal" in regular
-    }
+    // Once recovered there's nothing to recover from
+    renderer.process_data(b"\x10\x05\x01").unwrap();
+    assert!(!renderer.take_error_recovery());
+}
 
-    #[test]
-    fn test_partial_command() {
-        // Test that incomplete commands don't crash
-        let data = b"\x1B"; // ESC without following command
-                            // Expected: no panic, waits for more data
-    }
+#[test]
+fn test_character_size() {
+    let elements = parse(b"\x1D!\x11Big\n\x1D!\x01Wide\n\x1D!\x70Tall\n\x1D!\x00Normal\n");
+    let sizes: Vec<(&str, bool, bool, u8, u8)> = elements
+        .iter()
+        .filter_map(|element| match element {
+            ReceiptElement::Text {
+                content,
+                double_width,
+                double_height,
+                width_multiplier,
+                height_multiplier,
+                ..
+            } => Some((
+                content.as_str(),
+                *double_width,
+                *double_height,
+                *width_multiplier,
+                *height_multiplier,
+            )),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        sizes,
+        [
+            ("Big", true, true, 2, 2),
+            ("Wide", true, false, 2, 1),
+            ("Tall", false, true, 1, 8),
+            ("Normal", false, false, 1, 1),
+        ]
+    );
 
-    #[test]
-    fn test_invalid_command() {
-        // Test that invalid commands are handled gracefully
-        let data = b"\x1B\xFF"; // ESC with invalid command byte
-                                // Expected: no panic, command ignored or logged
-    }
+    // Three times as wide, 16 Font A characters fill 80mm paper
+    let elements = parse(b"\x1D!\x22ABCDEFGHIJKLMNOPQRSTUVWXYZ\n");
+    assert_eq!(lines(&elements), ["ABCDEFGHIJKLMNOP", "QRSTUVWXYZ"]);
+}
 
-    #[test]
-    fn test_mixed_content() {
-        // Test text mixed with commands
-        let data = b"Hello \x1B\x45\x01World\x1B\x45\x00!";
-        // Expected: "Hello " normal, "World" bold, "!" normal
-    }
+#[test]
+fn test_print_position_past_print_area() {
+    // ESC $ and ESC \ past the print area are ignored, the text following
+    // them prints on where it was
+    assert_eq!(lines(&parse(b"A\x1B$\xFF\xFFB\n")), ["AB"]);
+    assert_eq!(lines(&parse(b"\x1DP\x01\x01\x1B$\xFF\xFFAB\n")), ["AB"]);
+    assert_eq!(lines(&parse(b"A\x1B\\\xFF\x7FB\n")), ["AB"]);
+    assert_eq!(lines(&parse(b"A\x1B\\\x00\x80B\n")), ["AB"]);
 }
@@ -0,0 +1,402 @@
+// Tests of the parser through the library API, the way other tools embed it
+
+use escpresso::connection::{JobEvent, JobSplitter};
+use escpresso::disassembly;
+use escpresso::receipt::Receipt;
+use escpresso::{
+    parse_bytes, parse_job, Alignment, EscPosRenderer, JobBoundary, PaperSize, ParseOptions,
+    ReceiptElement,
+};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+#[test]
+fn test_formatted_line() {
+    let data = Receipt::new().center().bold().line("TOTAL").build();
+    let elements = parse_bytes(&data, &ParseOptions::default());
+    assert!(matches!(
+        elements.as_slice(),
+        [ReceiptElement::Text {
+            content,
+            bold: true,
+            alignment: Alignment::Center,
+            ..
+        }] if content == "TOTAL"
+    ));
+}
+
+#[test]
+fn test_command_split_across_reads() {
+    let data = Receipt::new().qr("https://example.com").cut().build();
+    let mut renderer = EscPosRenderer::new(false);
+    for chunk in data.chunks(3) {
+        renderer.process_data(chunk).unwrap();
+    }
+    let elements = renderer.take_elements();
+    assert!(matches!(
+        elements.as_slice(),
+        [ReceiptElement::QrCode { data, .. }, .., ReceiptElement::PaperCut { .. }]
+            if data == "https://example.com"
+    ));
+}
+
+#[test]
+fn test_barcode_split_across_reads() {
+    let data = Receipt::new()
+        .barcode(73, b"{BHELLO-123")
+        .raw(b"\x1dk\x04ABC123\x00")
+        .build();
+    let mut renderer = EscPosRenderer::new(false);
+    for chunk in data.chunks(3) {
+        renderer.process_data(chunk).unwrap();
+    }
+    let texts: Vec<String> = renderer
+        .take_elements()
+        .into_iter()
+        .filter_map(|element| match element {
+            ReceiptElement::Barcode { text, .. } => Some(text),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(texts, ["HELLO-123", "*ABC123*"]);
+}
+
+/// CUPS raster page header: 1 bit black (K) pixels, cut after the page
+fn raster_header(width: u32, height: u32, bits_per_color: u32) -> Vec<u8> {
+    let mut header = vec![0u8; 1796];
+    let bytes_per_line = (width * bits_per_color).div_ceil(8);
+    for (offset, value) in [
+        (268, 1),
+        (372, width),
+        (376, height),
+        (384, bits_per_color),
+        (388, bits_per_color),
+        (392, bytes_per_line),
+        (400, 3),
+    ] {
+        header[offset..offset + 4].copy_from_slice(&u32::to_be_bytes(value));
+    }
+    header
+}
+
+#[test]
+fn test_cups_raster_page() {
+    let mut data = b"RaS2".to_vec();
+    data.extend(raster_header(16, 4, 1));
+    // Two lines of 8 dots and 8 blank, then two blank lines
+    data.extend([1, 255, 0xFF, 0x00, 1, 128]);
+    let mut renderer = EscPosRenderer::new(false);
+    for chunk in data.chunks(7) {
+        renderer.process_data(chunk).unwrap();
+    }
+    assert!(matches!(
+        renderer.take_elements().as_slice(),
+        [ReceiptElement::RasterImage { width: 16, height: 4, data, .. }, ReceiptElement::PaperCut { .. }]
+            if data == &[0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00]
+    ));
+
+    let mut data = b"RaS2".to_vec();
+    data.extend(raster_header(16, 4, 16));
+    data.extend([0; 64]);
+    let job = parse_job(&data, &ParseOptions::default()).unwrap();
+    assert!(job.elements.is_empty());
+    assert!(job.warnings[0]
+        .message
+        .contains("CUPS raster page not printed"));
+}
+
+#[test]
+fn test_pjl_wrapped_job() {
+    let mut data = b"\x1b%-12345X@PJL JOB NAME=\"Receipt\"\r\n@PJL INFO STATUS\r\n".to_vec();
+    data.extend(b"@PJL ENTER LANGUAGE=ESCPOS\n");
+    data.extend(Receipt::new().line("PAID").cut().build());
+    data.extend(b"\x1b%-12345X@PJL EOJ\n\x1b%-12345X");
+    let mut renderer = EscPosRenderer::new(false);
+    for chunk in data.chunks(5) {
+        renderer.process_data(chunk).unwrap();
+    }
+    assert!(matches!(
+        renderer.take_elements().as_slice(),
+        [ReceiptElement::Text { content, .. }, ReceiptElement::PaperCut { .. }]
+            if content == "PAID"
+    ));
+    let responses = renderer.take_responses();
+    let response = String::from_utf8_lossy(&responses);
+    assert!(response.starts_with("@PJL INFO STATUS\r\nCODE=10001"));
+    assert_eq!(response.find('\x0c'), Some(response.len() - 1));
+}
+
+#[test]
+fn test_jobs_split_at_cuts() {
+    let first = Receipt::new().line("ONE").cut().build();
+    let second = Receipt::new().line("TWO").build();
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.set_job_boundary(JobBoundary::FullCut);
+    let mut jobs = JobSplitter::default();
+    jobs.receive(&mut renderer, &[first.clone(), second.clone()].concat())
+        .unwrap();
+    jobs.finish(&mut renderer).unwrap();
+    let finished: Vec<Vec<u8>> = jobs
+        .take_events()
+        .into_iter()
+        .filter_map(|event| match event {
+            JobEvent::Finished(job) => Some(job.raw),
+            JobEvent::Elements(_) => None,
+        })
+        .collect();
+    assert_eq!(finished, [first, second]);
+}
+
+#[test]
+fn test_status_query_answered() {
+    let mut renderer = EscPosRenderer::new(false);
+    renderer.process_data(b"\x10\x04\x01").unwrap();
+    assert_eq!(renderer.take_responses().len(), 1);
+}
+
+#[cfg(feature = "render")]
+#[test]
+fn test_render_to_image() {
+    let data = Receipt::new()
+        .line("Hello")
+        .raster(1, 8, &[0xFF; 8])
+        .build();
+    let elements = parse_bytes(&data, &ParseOptions::default());
+    let image = escpresso::render::render_elements_to_image(
+        &elements,
+        PaperSize::Size80mm,
+        escpresso::DOTS_PER_INCH,
+    );
+    assert_eq!(image.width(), 576);
+    assert!(image.pixels().any(|pixel| pixel.0[0] < 128));
+}
+
+#[tokio::test]
+async fn test_server_delivers_job() {
+    let mut server = escpresso::test_server::spawn().await.unwrap();
+    let mut client = TcpStream::connect(("127.0.0.1", server.port))
+        .await
+        .unwrap();
+    client
+        .write_all(&Receipt::new().line("Hello").cut().build())
+        .await
+        .unwrap();
+    drop(client);
+    let job = server.jobs.recv().await.unwrap();
+    assert!(job
+        .elements
+        .iter()
+        .any(|e| matches!(e, ReceiptElement::PaperCut { .. })));
+}
+
+#[test]
+fn test_text_at_end_of_job() {
+    // The last byte isn't lost to the wait for more data
+    let elements = parse_bytes(b"Hello", &ParseOptions::default());
+    assert!(matches!(
+        elements.as_slice(),
+        [ReceiptElement::Text { content, .. }] if content == "Hello"
+    ));
+    let trace = escpresso::trace_job(b"Hi\nA", &ParseOptions::default()).unwrap();
+    assert!(matches!(
+        trace.elements.as_slice(),
+        [_, last] if matches!(&last.element, ReceiptElement::Text { content, .. } if content == "A")
+            && last.start == 3
+    ));
+
+    // Nor when a connection's job is finished
+    let mut renderer = EscPosRenderer::new(false);
+    let mut jobs = JobSplitter::default();
+    jobs.receive(&mut renderer, b"Hi").unwrap();
+    jobs.finish(&mut renderer).unwrap();
+    let printed: Vec<ReceiptElement> = jobs
+        .take_events()
+        .into_iter()
+        .flat_map(|event| match event {
+            JobEvent::Elements(elements) => elements,
+            JobEvent::Finished(_) => Vec::new(),
+        })
+        .collect();
+    assert!(matches!(
+        printed.as_slice(),
+        [ReceiptElement::Text { content, .. }] if content == "Hi"
+    ));
+}
+
+/// The text lines, without their formatting
+fn lines(elements: &[ReceiptElement]) -> Vec<&str> {
+    elements
+        .iter()
+        .filter_map(|element| match element {
+            ReceiptElement::Text { content, .. } => Some(content.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Width and height of each image
+fn image_sizes(elements: &[ReceiptElement]) -> Vec<(usize, usize)> {
+    elements
+        .iter()
+        .filter_map(|element| match element {
+            ReceiptElement::RasterImage { width, height, .. } => Some((*width, *height)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_builder_round_trip() {
+    let data = Receipt::new()
+        .center()
+        .bold()
+        .line("SHOP")
+        .normal()
+        .left()
+        .underline()
+        .line("Item")
+        .normal()
+        .barcode(73, b"{B42")
+        .qr("order-42")
+        .raster(1, 8, &[0x80; 8])
+        .cut()
+        .build();
+    let elements = parse_bytes(&data, &ParseOptions::default());
+    assert!(matches!(
+        elements.as_slice(),
+        [
+            ReceiptElement::Text { content: shop, bold: true, alignment: Alignment::Center, .. },
+            ReceiptElement::Text { content: item, bold: false, underline: true, alignment: Alignment::Left, .. },
+            ReceiptElement::Barcode { text: barcode, .. },
+            ..,
+            ReceiptElement::PaperCut { .. },
+        ] if shop == "SHOP" && item == "Item" && barcode == "42"
+    ));
+    assert!(elements.iter().any(
+        |element| matches!(element, ReceiptElement::QrCode { data, .. } if data == "order-42")
+    ));
+    assert_eq!(image_sizes(&elements), [(8, 8)]);
+}
+
+#[test]
+fn test_two_column_layout() {
+    // A label, ESC $ to dot 400, then the amount: one line of two runs
+    let data = Receipt::new()
+        .text("TOTAL")
+        .position(400)
+        .line("9.99")
+        .build();
+    let elements = parse_bytes(&data, &ParseOptions::default());
+    assert!(matches!(
+        elements.as_slice(),
+        [ReceiptElement::Text { runs, .. }]
+            if runs.iter().map(|run| (run.x, run.content.as_str())).collect::<Vec<_>>()
+                == [(0, "TOTAL"), (400, "9.99")]
+    ));
+}
+
+#[test]
+fn test_wrap_columns() {
+    // Font A and B, selected with ESC M or ESC ! bit 0, in normal and double
+    // width, on both paper widths
+    for (paper_size, font, columns) in [
+        (PaperSize::Size58mm, b"\x1BM\x00".as_slice(), 32),
+        (PaperSize::Size58mm, b"\x1BM\x01", 42),
+        (PaperSize::Size58mm, b"\x1B!\x01", 42),
+        (PaperSize::Size58mm, b"\x1B!\x20", 16),
+        (PaperSize::Size80mm, b"\x1BM\x00", 48),
+        (PaperSize::Size80mm, b"\x1BM\x01", 64),
+        (PaperSize::Size80mm, b"\x1B!\x01", 64),
+        (PaperSize::Size80mm, b"\x1B!\x21", 32),
+        (PaperSize::Size80mm, b"\x1D!\x22", 16),
+    ] {
+        let text = "0123456789".repeat(7);
+        let data = Receipt::new().raw(font).line(&text).build();
+        let options = ParseOptions {
+            paper_size,
+            ..Default::default()
+        };
+        let elements = parse_bytes(&data, &options);
+        assert_eq!(
+            lines(&elements)[0],
+            &text[..columns],
+            "{:?} {:02X?}",
+            paper_size,
+            font
+        );
+    }
+}
+
+#[test]
+fn test_columns_override() {
+    // A clone printer with 35 Font A columns on 80mm paper
+    let text = "0123456789".repeat(4);
+    let data = Receipt::new().line(&text).build();
+    let options = ParseOptions {
+        columns: [35, 0, 0],
+        ..Default::default()
+    };
+    let elements = parse_bytes(&data, &options);
+    assert_eq!(lines(&elements), [&text[..35], &text[35..]]);
+}
+
+#[test]
+fn test_tab_stops_expand() {
+    // Stops at columns 4 and 8 of Font A
+    let data = Receipt::new()
+        .raw(b"\x1BD\x04\x08\x00")
+        .line("AB\tC\tD")
+        .build();
+    let elements = parse_bytes(&data, &ParseOptions::default());
+    assert_eq!(lines(&elements), ["AB  C   D"]);
+}
+
+#[test]
+fn test_bit_image_density() {
+    // ESC * with 4 columns: single density modes print each column twice as
+    // wide
+    let image = |mode: u8, bytes_per_column: usize| {
+        let data = Receipt::new()
+            .raw(&[0x1B, b'*', mode, 4, 0])
+            .raw(&vec![0xFF; 4 * bytes_per_column])
+            .raw(b"\n")
+            .build();
+        image_sizes(&parse_bytes(&data, &ParseOptions::default()))
+    };
+    assert_eq!(image(0, 1)[0].0, 8);
+    assert_eq!(image(1, 1)[0].0, 4);
+    assert_eq!(image(32, 3), [(8, 24)]);
+    assert_eq!(image(33, 3), [(4, 24)]);
+}
+
+#[test]
+fn test_raster_image_scaling() {
+    // GS v 0 modes: normal, double width, double height, quadruple
+    for (mode, size) in [(0, (8, 2)), (1, (16, 2)), (2, (8, 4)), (3, (16, 4))] {
+        let data = Receipt::new()
+            .raw(&[0x1D, b'v', b'0', mode, 1, 0, 2, 0, 0xAA, 0x55])
+            .raw(b"\n")
+            .build();
+        let elements = parse_bytes(&data, &ParseOptions::default());
+        assert_eq!(image_sizes(&elements), [size], "mode {}", mode);
+    }
+}
+
+#[test]
+fn test_disassembly_round_trip() {
+    let data = Receipt::new()
+        .center()
+        .bold()
+        .line("Hello \"there\"\\")
+        .normal()
+        .qr("https://example.com")
+        .raster(2, 3, &[0x00, 0xFF, 0x7E, 0x81, 0xAA, 0x55])
+        .raw(b"\x1Dk\x04ABC\x00")
+        .encoded_line(16, encoding_rs::WINDOWS_1252, "Café")
+        .cut()
+        .build();
+    let options = ParseOptions::default();
+    let text = disassembly::disassemble("job", &data, &[], &options).unwrap();
+    assert!(disassembly::is_disassembly(text.as_bytes()));
+    assert_eq!(disassembly::assemble(&text).unwrap(), data);
+}